@@ -2,58 +2,64 @@ use std::error::Error;
 use std::fs;
 use std::path::Path;
 
+/// Shaders to compile, written next to the source as `<name>.spv`
+const SHADERS: &[(&str, shaderc::ShaderKind)] = &[
+    ("tile.vert", shaderc::ShaderKind::Vertex),        // Mercator
+    ("tile_simple.vert", shaderc::ShaderKind::Vertex), // Simple linear projection
+    ("tile_debug.vert", shaderc::ShaderKind::Vertex),  // Debug
+    ("tile.frag", shaderc::ShaderKind::Fragment),
+    ("tile_srgb.frag", shaderc::ShaderKind::Fragment), // sRGB color attachment
+];
+
 fn main() -> Result<(), Box<dyn Error>> {
     // Tell Cargo to rerun build.rs if shaders change
     println!("cargo:rerun-if-changed=shaders/");
 
-    let mut compiler = shaderc::Compiler::new().ok_or("Failed to create shader compiler")?;
+    let compiler = shaderc::Compiler::new().ok_or("Failed to create shader compiler")?;
     let shader_dir = Path::new("shaders");
     let out_dir = shader_dir;
 
-    // Compile vertex shader (Mercator)
-    let vert_source = fs::read_to_string(shader_dir.join("tile.vert"))?;
-    let vert_spirv = compiler.compile_into_spirv(
-        &vert_source,
-        shaderc::ShaderKind::Vertex,
-        "tile.vert",
-        "main",
-        None,
-    )?;
-    fs::write(out_dir.join("tile.vert.spv"), vert_spirv.as_binary_u8())?;
-
-    // Compile simple vertex shader
-    let vert_simple_source = fs::read_to_string(shader_dir.join("tile_simple.vert"))?;
-    let vert_simple_spirv = compiler.compile_into_spirv(
-        &vert_simple_source,
-        shaderc::ShaderKind::Vertex,
-        "tile_simple.vert",
-        "main",
-        None,
-    )?;
-    fs::write(out_dir.join("tile_simple.vert.spv"), vert_simple_spirv.as_binary_u8())?;
-
-    // Compile debug vertex shader
-    let vert_debug_source = fs::read_to_string(shader_dir.join("tile_debug.vert"))?;
-    let vert_debug_spirv = compiler.compile_into_spirv(
-        &vert_debug_source,
-        shaderc::ShaderKind::Vertex,
-        "tile_debug.vert",
-        "main",
-        None,
-    )?;
-    fs::write(out_dir.join("tile_debug.vert.spv"), vert_debug_spirv.as_binary_u8())?;
+    // Compile every shader before failing, so all errors show up at once
+    let mut failed = Vec::new();
+    for &(name, kind) in SHADERS {
+        let spirv_path = out_dir.join(format!("{}.spv", name));
+        if let Err(e) = compile_shader(&compiler, &shader_dir.join(name), &spirv_path, kind) {
+            // Cargo only shows build script output on failure; warnings are always shown
+            println!("cargo:warning={}: {}", name, e.to_string().lines().next().unwrap_or_default());
+            eprintln!("error: failed to compile shaders/{}\n{}\n", name, e);
+            failed.push(name);
+        }
+    }
 
-    // Compile fragment shader
-    let frag_source = fs::read_to_string(shader_dir.join("tile.frag"))?;
-    let frag_spirv = compiler.compile_into_spirv(
-        &frag_source,
-        shaderc::ShaderKind::Fragment,
-        "tile.frag",
-        "main",
-        None,
-    )?;
-    fs::write(out_dir.join("tile.frag.spv"), frag_spirv.as_binary_u8())?;
+    if !failed.is_empty() {
+        return Err(format!(
+            "{} of {} shaders failed to compile: {}",
+            failed.len(),
+            SHADERS.len(),
+            failed.join(", ")
+        )
+        .into());
+    }
 
     println!("Shaders compiled successfully");
     Ok(())
 }
+
+/// Compile one GLSL shader to SPIR-V, returning the full shaderc log on error
+fn compile_shader(
+    compiler: &shaderc::Compiler,
+    source_path: &Path,
+    spirv_path: &Path,
+    kind: shaderc::ShaderKind,
+) -> Result<(), Box<dyn Error>> {
+    let file_name = source_path.display().to_string();
+    let source = fs::read_to_string(source_path).map_err(|e| format!("can't read {}: {}", file_name, e))?;
+    let spirv = compiler.compile_into_spirv(&source, kind, &file_name, "main", None)?;
+    if spirv.get_num_warnings() > 0 {
+        for line in spirv.get_warning_messages().lines() {
+            println!("cargo:warning={}", line);
+        }
+    }
+    fs::write(spirv_path, spirv.as_binary_u8())?;
+    Ok(())
+}
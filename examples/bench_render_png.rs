@@ -0,0 +1,57 @@
+use rust_osm_renderer::data::loader::load_osm_data;
+use rust_osm_renderer::data::mmap::MappedData;
+use rust_osm_renderer::data::types::Tile;
+use rust_osm_renderer::encoding::png::encode_png;
+use rust_osm_renderer::renderer::renderer::MAX_INDEXED_ZOOM;
+use rust_osm_renderer::renderer::{RendererConfig, VulkanRenderer};
+use std::env;
+use std::time::{Duration, Instant};
+use tempfile::NamedTempFile;
+
+/// Renders per path; the first of each is discarded as warm-up
+const ROUNDS: u32 = 50;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// Compare `render_tile` + `encode_png` with `render_tile_to_png` on one
+/// tile, e.g. a dense city center: `prepared.osm.pbf 14 8653 5293`
+fn main() -> Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 5 {
+        eprintln!("Usage: {} <osm-file.pbf> <z> <x> <y>", args[0]);
+        std::process::exit(1);
+    }
+    let tile = Tile::new(args[3].parse()?, args[4].parse()?, args[2].parse()?);
+
+    let mut temp_file = NamedTempFile::new()?;
+    let tile_index = load_osm_data(&args[1], tile.z.min(MAX_INDEXED_ZOOM), temp_file.as_file_mut())?;
+    let mmap_data = MappedData::new(temp_file.path())?;
+    let mut renderer = VulkanRenderer::with_config(RendererConfig {
+        max_points: tile_index.max_points,
+        ..RendererConfig::default()
+    })?;
+
+    let mut time = |render: &mut dyn FnMut(&mut VulkanRenderer) -> Result<Vec<u8>>| -> Result<(Duration, usize)> {
+        render(&mut renderer)?;
+        let start = Instant::now();
+        let mut bytes = 0;
+        for _ in 1..ROUNDS {
+            bytes = render(&mut renderer)?.len();
+        }
+        Ok((start.elapsed() / (ROUNDS - 1), bytes))
+    };
+    let (via_image, image_bytes) = time(&mut |renderer| Ok(encode_png(&renderer.render_tile(&tile, &tile_index, &mmap_data)?)?))?;
+    let (direct, direct_bytes) = time(&mut |renderer| Ok(renderer.render_tile_to_png(&tile, &tile_index, &mmap_data)?))?;
+    assert_eq!(image_bytes, direct_bytes);
+
+    println!("\n{}", "=".repeat(60));
+    println!("Tile {}: {} objects, {} byte PNG", tile, renderer.last_object_count(), direct_bytes);
+    println!("{:>24} {:>12?}", "render_tile + encode_png", via_image);
+    println!("{:>24} {:>12?}", "render_tile_to_png", direct);
+    println!("{:>24} {:>12?}", "saved", via_image.saturating_sub(direct));
+    println!("{}", "=".repeat(60));
+
+    Ok(())
+}
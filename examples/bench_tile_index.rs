@@ -0,0 +1,60 @@
+use rust_osm_renderer::data::loader::load_osm_data;
+use rust_osm_renderer::data::spatial::{TileIndex, TileKey, TileMapKind};
+use rust_osm_renderer::data::types::MapObjectOffset;
+use std::env;
+use std::hint::black_box;
+use std::time::Instant;
+use tempfile::NamedTempFile;
+
+/// Lookup passes over all tile keys per hasher
+const LOOKUP_ROUNDS: usize = 10;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage: {} <osm-file.pbf>", args[0]);
+        std::process::exit(1);
+    }
+
+    // Load OSM data once and record every (tile, offset) insert
+    let mut temp_file = NamedTempFile::new()?;
+    let tile_index = load_osm_data(&args[1], 15, temp_file.as_file_mut())?;
+    let entries: Vec<(TileKey, MapObjectOffset)> = tile_index
+        .tiles
+        .iter()
+        .flat_map(|(&key, offsets)| offsets.iter().map(move |&offset| (key, offset)))
+        .collect();
+    let mut keys: Vec<TileKey> = tile_index.tiles.iter().map(|(&key, _)| key).collect();
+    // Scatter lookups instead of walking keys in order
+    keys.sort_unstable_by_key(|key| key.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+
+    println!("\n{}", "=".repeat(60));
+    println!("Tiles: {}, entries: {}", keys.len(), entries.len());
+    println!("{:>8} {:>14} {:>14}", "map", "build", "lookup/key");
+
+    for kind in [TileMapKind::Fx, TileMapKind::SipHash, TileMapKind::BTree] {
+        let start = Instant::now();
+        let mut index = TileIndex::with_kind(kind);
+        for &(key, offset) in &entries {
+            index.tiles.get_or_default(key).push(offset);
+        }
+        let build_time = start.elapsed();
+
+        let start = Instant::now();
+        let mut found = 0;
+        for _ in 0..LOOKUP_ROUNDS {
+            for key in &keys {
+                found += black_box(index.tiles.get(key)).map_or(0, |offsets| offsets.len());
+            }
+        }
+        let lookup_time = start.elapsed() / (LOOKUP_ROUNDS * keys.len().max(1)) as u32;
+        assert_eq!(found, entries.len() * LOOKUP_ROUNDS);
+
+        println!("{:>8} {:>14?} {:>14?}", format!("{:?}", kind), build_time, lookup_time);
+    }
+    println!("{}", "=".repeat(60));
+
+    Ok(())
+}
@@ -0,0 +1,71 @@
+use rust_osm_renderer::data::compressed::{compress_data_file, CompressedData, DEFAULT_BLOCK_SIZE};
+use rust_osm_renderer::data::loader::load_osm_data;
+use rust_osm_renderer::data::mmap::MappedData;
+use std::collections::BTreeSet;
+use std::env;
+use std::time::Instant;
+use tempfile::NamedTempFile;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage: {} <osm-file.pbf> [block-size-bytes]", args[0]);
+        std::process::exit(1);
+    }
+
+    let osm_path = &args[1];
+    let block_size = match args.get(2) {
+        Some(s) => s.parse()?,
+        None => DEFAULT_BLOCK_SIZE,
+    };
+
+    // Load OSM data
+    let mut temp_file = NamedTempFile::new()?;
+    let tile_index = load_osm_data(osm_path, 15, temp_file.as_file_mut())?;
+
+    // Compress the data file
+    let compressed_file = NamedTempFile::new()?;
+    let start = Instant::now();
+    let stats = compress_data_file(temp_file.path(), compressed_file.path(), block_size)?;
+    let compress_time = start.elapsed();
+
+    // Every distinct object offset, in file order
+    let offsets: BTreeSet<u64> = tile_index.tiles.values().flatten().copied().collect();
+
+    // Read all objects from the uncompressed mmap
+    let mmap_data = MappedData::new(temp_file.path())?;
+    let start = Instant::now();
+    let mut mmap_points = 0;
+    for &offset in &offsets {
+        mmap_points += mmap_data.read_map_object(offset).num_points();
+    }
+    let mmap_time = start.elapsed();
+
+    // Read all objects from the compressed file
+    let compressed_data = CompressedData::new(compressed_file.path())?;
+    let start = Instant::now();
+    let mut compressed_points = 0;
+    for &offset in &offsets {
+        compressed_points += compressed_data.read_map_object(offset)?.points.len();
+    }
+    let compressed_time = start.elapsed();
+
+    assert_eq!(mmap_points, compressed_points);
+
+    println!("\n{}", "=".repeat(60));
+    println!("Objects: {}, points: {}", offsets.len(), mmap_points);
+    println!("Block size: {} bytes, blocks: {}", block_size, stats.blocks);
+    println!(
+        "Size: {} -> {} bytes ({:.1}% of original), compressed in {:?}",
+        stats.uncompressed_bytes,
+        stats.compressed_bytes,
+        stats.ratio() * 100.0,
+        compress_time
+    );
+    println!("Read all objects: mmap {:?}, compressed {:?}", mmap_time, compressed_time);
+    println!("{}", "=".repeat(60));
+
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use clap::Parser;
+use rust_osm_renderer::cli::ShaderArgs;
+use rust_osm_renderer::data::loader::load_osm_data;
+use rust_osm_renderer::data::mmap::MappedData;
+use rust_osm_renderer::data::types::{BoundingBox, Point};
+use rust_osm_renderer::projection::get_tiles_for_bounding_box;
+use rust_osm_renderer::renderer::renderer::MAX_INDEXED_ZOOM;
+use rust_osm_renderer::renderer::{RendererConfig, VulkanRenderer};
+use rust_osm_renderer::server::handlers::MAX_ZOOM;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Instant;
+use tempfile::NamedTempFile;
+
+/// Tiles between progress messages
+const PROGRESS_TILES: usize = 1000;
+
+/// Render all tiles of a bounding box and zoom range to `<out-dir>/z/x/y.png`
+/// for offline use, e.g. `prepared.osm.pbf tiles --max-zoom 14`
+///
+/// Tiles without anything to draw are skipped, so viewers fall back to
+/// their blank background for them.
+#[derive(Parser)]
+struct Args {
+    osm_path: PathBuf,
+    out_dir: PathBuf,
+    #[arg(long, default_value_t = 0)]
+    min_zoom: u32,
+    #[arg(long, default_value_t = 12)]
+    max_zoom: u32,
+    /// Area to render as min_lon,min_lat,max_lon,max_lat; the data bounds by default
+    #[arg(long, value_name = "BBOX", value_parser = parse_bbox)]
+    bbox: Option<BoundingBox>,
+    #[command(flatten)]
+    shader: ShaderArgs,
+}
+
+fn parse_bbox(s: &str) -> Result<BoundingBox, String> {
+    let coords: Vec<f64> = s
+        .split(',')
+        .map(|c| c.trim().parse().map_err(|e| format!("invalid coordinate {:?}: {}", c, e)))
+        .collect::<Result<_, _>>()?;
+    let [min_lon, min_lat, max_lon, max_lat] = coords[..] else {
+        return Err(format!("expected min_lon,min_lat,max_lon,max_lat, got {:?}", s));
+    };
+    if min_lat > max_lat {
+        return Err(format!("min_lat {} is north of max_lat {}", min_lat, max_lat));
+    }
+    Ok(BoundingBox::new(Point::new(min_lon, min_lat), Point::new(max_lon, max_lat)))
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn,render_pyramid=info")).init();
+
+    let Args { osm_path, out_dir, min_zoom, max_zoom, bbox, shader } = Args::parse();
+    if min_zoom > max_zoom || max_zoom > MAX_ZOOM {
+        return Err(format!("zoom range {}..={} must be ascending and at most {}", min_zoom, max_zoom, MAX_ZOOM).into());
+    }
+
+    // Higher zooms render from their ancestor at the deepest indexed zoom
+    let mut temp_file = NamedTempFile::new()?;
+    let tile_index = load_osm_data(&osm_path, max_zoom.min(MAX_INDEXED_ZOOM), temp_file.as_file_mut())?;
+    let mmap_data = MappedData::new(temp_file.path())?;
+    let Some(bbox) = bbox.or(tile_index.bounds) else {
+        return Err("no --bbox given and the data has no bounds".into());
+    };
+
+    let mut renderer = VulkanRenderer::with_config(RendererConfig {
+        max_points: tile_index.max_points,
+        shader_type: shader.shader_type(),
+        ..RendererConfig::default()
+    })?;
+
+    let tiles = get_tiles_for_bounding_box(&bbox, min_zoom, max_zoom);
+    log::info!("Rendering {} tiles at zoom {} to {} into {}", tiles.len(), min_zoom, max_zoom, out_dir.display());
+    let start = Instant::now();
+    let mut written = 0;
+    for (i, tile) in tiles.iter().enumerate() {
+        let png = renderer.render_tile_to_png(tile, &tile_index, &mmap_data)?;
+        if renderer.last_vertex_count() > 0 {
+            let dir = out_dir.join(tile.z.to_string()).join(tile.x.to_string());
+            fs::create_dir_all(&dir)?;
+            fs::write(dir.join(format!("{}.png", tile.y)), png)?;
+            written += 1;
+        }
+        if (i + 1) % PROGRESS_TILES == 0 {
+            log::info!("Rendered {} of {} tiles...", i + 1, tiles.len());
+        }
+    }
+
+    log::info!(
+        "Wrote {} tiles, skipped {} empty ones, in {:.1?}",
+        written,
+        tiles.len() - written,
+        start.elapsed()
+    );
+
+    Ok(())
+}
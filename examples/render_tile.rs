@@ -1,41 +1,46 @@
+use clap::Parser;
+use rust_osm_renderer::cli::ShaderArgs;
 use rust_osm_renderer::data::loader::load_osm_data;
 use rust_osm_renderer::data::mmap::MappedData;
 use rust_osm_renderer::data::types::Tile;
-use rust_osm_renderer::renderer::{VulkanRenderer, ShaderType};
-use std::env;
+use rust_osm_renderer::renderer::pipeline::TILE_SIZE;
+use rust_osm_renderer::renderer::renderer::MAX_INDEXED_ZOOM;
+use rust_osm_renderer::renderer::{RendererConfig, VulkanRenderer};
+use std::path::PathBuf;
 use tempfile::NamedTempFile;
 
+/// Render one tile to a PNG file, e.g. `prepared.osm.pbf 11 1081 660 hamburg.png`
+#[derive(Parser)]
+struct Args {
+    osm_path: PathBuf,
+    z: u32,
+    x: u32,
+    y: u32,
+    #[arg(default_value = "output.png")]
+    output_path: PathBuf,
+    /// Highest zoom to index; higher zooms render from their ancestor at this zoom
+    #[arg(long, default_value_t = MAX_INDEXED_ZOOM)]
+    max_zoom: u32,
+    /// Tile size in pixels
+    #[arg(long, default_value_t = TILE_SIZE)]
+    tile_size: u32,
+    #[command(flatten)]
+    shader: ShaderArgs,
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 5 {
-        eprintln!("Usage: {} <osm-file.pbf> <z> <x> <y> [output.png] [--simple-shader|--debug-shader]", args[0]);
-        eprintln!("Example: {} prepared.osm.pbf 11 1081 660 hamburg.png", args[0]);
-        std::process::exit(1);
-    }
-
-    let osm_path = &args[1];
-    let z: u32 = args[2].parse()?;
-    let x: u32 = args[3].parse()?;
-    let y: u32 = args[4].parse()?;
-    let output_path = args.get(5).map(|s| s.as_str()).unwrap_or("output.png");
-
-    let shader_type = if args.iter().any(|s| s == "--simple-shader") {
-        ShaderType::Simple
-    } else if args.iter().any(|s| s == "--debug-shader") {
-        ShaderType::Debug
-    } else {
-        ShaderType::Mercator
-    };
+    let Args { osm_path, z, x, y, output_path, max_zoom, tile_size, shader } = Args::parse();
+    let shader_type = shader.shader_type();
 
-    log::info!("Rendering tile {}/{}/{} from {}", z, x, y, osm_path);
+    log::info!("Rendering tile {}/{}/{} from {}", z, x, y, osm_path.display());
 
     // Load OSM data
     let mut temp_file = NamedTempFile::new()?;
     log::info!("Loading OSM data...");
-    // Index up to zoom 15, higher zooms will use parent tile data
-    let tile_index = load_osm_data(osm_path, 15, temp_file.as_file_mut())?;
+    // Index up to --max-zoom, higher zooms will use parent tile data
+    let tile_index = load_osm_data(&osm_path, max_zoom, temp_file.as_file_mut())?;
     log::info!("Loaded {} tiles", tile_index.len());
 
     // Memory-map the data
@@ -43,7 +48,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Create renderer
     log::info!("Creating {:?} shader renderer...", shader_type);
-    let mut renderer = VulkanRenderer::new(tile_index.max_points, shader_type)?;
+    let mut renderer = VulkanRenderer::with_config(RendererConfig {
+        max_points: tile_index.max_points,
+        shader_type,
+        tile_size,
+        ..RendererConfig::default()
+    })?;
 
     // Render tile
     let tile = Tile::new(x, y, z);
@@ -51,8 +61,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let image = renderer.render_tile(&tile, &tile_index, &mmap_data)?;
 
     // Save
-    image.save(output_path)?;
-    log::info!("Saved to {}", output_path);
+    image.save(&output_path)?;
+    log::info!("Saved to {}", output_path.display());
 
     // Check if it has content
     let non_white = image.pixels().filter(|p| p[0] != 255 || p[1] != 255 || p[2] != 255).count();
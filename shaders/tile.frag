@@ -1,8 +1,10 @@
 #version 450
 
+layout(location = 0) in vec4 fragColor;
+
 layout(location = 0) out vec4 outColor;
 
 void main() {
-    // Black lines with full opacity
-    outColor = vec4(0.0, 0.0, 0.0, 1.0);
+    // Per-vertex line color
+    outColor = fragColor;
 }
@@ -1,37 +1,49 @@
 #version 450
 
 layout(location = 0) in vec2 position; // lon, lat
+layout(location = 1) in vec4 color;    // RGBA line color
+
+layout(location = 0) out vec4 fragColor;
 
 layout(set = 0, binding = 0) uniform UniformBufferObject {
     vec4 bbox;        // minLon, minLat, maxLon, maxLat
     float tileSize;   // 256.0
+    float pointSize;  // Side of POINT_LIST points in pixels
     mat4 projection;  // Orthographic projection
 } ubo;
 
 const float PI = 3.14159265359;
 const float MAX_LAT = 85.0511287798; // Maximum latitude in Web Mercator
 
-float lat2y_mercator(float lat) {
-    // Clamp latitude to valid Mercator range
-    lat = clamp(lat, -MAX_LAT, MAX_LAT);
+float lat2y_mercator_unclamped(float lat) {
     float latRad = lat * PI / 180.0;
     return log(tan(PI/4.0 + latRad/2.0));
 }
 
+float lat2y_mercator(float lat) {
+    // Clamp latitude to valid Mercator range
+    return lat2y_mercator_unclamped(clamp(lat, -MAX_LAT, MAX_LAT));
+}
+
 void main() {
+    fragColor = color;
+    gl_PointSize = ubo.pointSize;
+
     // Convert longitude to x coordinate (linear)
     float x = (position.x - ubo.bbox.x) / (ubo.bbox.z - ubo.bbox.x);
 
     // Convert latitude to y coordinate (Mercator)
     float y_mercator = lat2y_mercator(position.y);
-    float min_y_mercator = lat2y_mercator(ubo.bbox.y);
-    float max_y_mercator = lat2y_mercator(ubo.bbox.w);
+    // Buffered tiles at the poles extend past MAX_LAT, so the bbox isn't clamped
+    float min_y_mercator = lat2y_mercator_unclamped(ubo.bbox.y);
+    float max_y_mercator = lat2y_mercator_unclamped(ubo.bbox.w);
     float y = (y_mercator - min_y_mercator) / (max_y_mercator - min_y_mercator);
 
-    // Map 0-1 normalized coordinates directly to NDC -1 to 1
+    // Pixel coordinates with y down from the north edge; the projection maps
+    // them to NDC and sets the image orientation (--tile-origin)
     // No clamping - let GPU viewport clipping handle lines extending beyond tile
-    float ndc_x = x * 2.0 - 1.0;
-    float ndc_y = (1.0 - y) * 2.0 - 1.0;  // Flip Y for correct orientation
+    float px = x * ubo.tileSize;
+    float py = (1.0 - y) * ubo.tileSize;
 
-    gl_Position = vec4(ndc_x, ndc_y, 0.0, 1.0);
+    gl_Position = vec4(px, py, 0.0, 1.0) * ubo.projection;
 }
@@ -1,14 +1,21 @@
 #version 450
 
 layout(location = 0) in vec2 position; // lon, lat
+layout(location = 1) in vec4 color;    // RGBA line color
+
+layout(location = 0) out vec4 fragColor;
 
 layout(set = 0, binding = 0) uniform UniformBufferObject {
     vec4 bbox;
     float tileSize;
+    float pointSize;
     mat4 projection;
 } ubo;
 
 void main() {
+    fragColor = color;
+    gl_PointSize = ubo.pointSize;
+
     // DEBUG: Draw an X pattern across the screen
     // Alternate between corners to create visible lines
     int idx = gl_VertexIndex % 4;
@@ -1,14 +1,21 @@
 #version 450
 
 layout(location = 0) in vec2 position; // lon, lat
+layout(location = 1) in vec4 color;    // RGBA line color
+
+layout(location = 0) out vec4 fragColor;
 
 layout(set = 0, binding = 0) uniform UniformBufferObject {
     vec4 bbox;        // minLon, minLat, maxLon, maxLat
     float tileSize;   // 256.0
+    float pointSize;  // Side of POINT_LIST points in pixels
     mat4 projection;  // Orthographic projection
 } ubo;
 
 void main() {
+    fragColor = color;
+    gl_PointSize = ubo.pointSize;
+
     // Simple linear transformation for debugging
     // Maps lon/lat directly to NDC space without Mercator projection
     float x = position.x / 90.0;  // -180..180 -> -2..2 (clipped to -1..1)
@@ -0,0 +1,15 @@
+#version 450
+
+layout(location = 0) in vec4 fragColor;
+
+layout(location = 0) out vec4 outColor;
+
+// Vertex colors are sRGB encoded, the sRGB attachment expects linear values
+vec3 srgb_to_linear(vec3 c) {
+    return mix(c / 12.92, pow((c + 0.055) / 1.055, vec3(2.4)), greaterThan(c, vec3(0.04045)));
+}
+
+void main() {
+    // Per-vertex line color, alpha is linear already
+    outColor = vec4(srgb_to_linear(fragColor.rgb), fragColor.a);
+}
@@ -0,0 +1,485 @@
+//! Command line of the tile server, and options shared with the examples
+
+use crate::data::loader::DETAIL_ZOOM;
+use crate::data::spatial::TileKeyScheme;
+use crate::data::types::AffineTransform;
+use crate::encoding::format::FormatPreference;
+use crate::encoding::vector::MAX_DECIMALS;
+use crate::filter::{RetainTags, TagFilter, TagList};
+use crate::projection::{TileOrigin, TileScheme};
+use crate::renderer::color_format::ColorFormat;
+use crate::renderer::downscale::{DownscaleFilter, MAX_SUPERSAMPLE};
+use crate::renderer::lod::LodThresholds;
+use crate::renderer::renderer::{DEFAULT_RENDER_TIMEOUT, MAX_INDEXED_ZOOM, MAX_VERTEX_BUFFER_CAPACITY};
+use crate::renderer::vertex_budget::VertexBudgets;
+use crate::renderer::vulkan::{parse_api_version, ContextOptions};
+use crate::renderer::ShaderType;
+use crate::server::cache::{DEFAULT_TILE_CACHE_ENTRIES, TILE_CACHE_ENV};
+use crate::server::handlers::{DEFAULT_MAX_AGE_SECS, MAX_ZOOM};
+use crate::server::OutOfCoverage;
+use crate::style::parse_color;
+use clap::{Args as ClapArgs, Parser};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+/// Address the server listens on without `--bind` or `BIND_ADDR_ENV`
+pub const DEFAULT_BIND_ADDR: &str = "0.0.0.0:8080";
+
+/// Environment variable with the address to listen on, like `--bind`
+pub const BIND_ADDR_ENV: &str = "BIND_ADDR";
+
+/// Environment variable with the Vulkan device to use, like `--gpu`
+pub const DEVICE_INDEX_ENV: &str = "RENDERER_DEVICE_INDEX";
+
+/// Shader selection (`--shader`)
+#[derive(Debug, Clone, ClapArgs)]
+pub struct ShaderArgs {
+    /// Projection shader: mercator, simple (linear, better for debugging) or debug (all vertices at the center, pipeline test)
+    #[arg(long, default_value = "mercator")]
+    pub shader: ShaderType,
+
+    /// Same as --shader simple
+    #[arg(long, hide = true, conflicts_with_all = ["shader", "debug_shader"])]
+    pub simple_shader: bool,
+
+    /// Same as --shader debug
+    #[arg(long, hide = true, conflicts_with = "shader")]
+    pub debug_shader: bool,
+}
+
+impl ShaderArgs {
+    pub fn shader_type(&self) -> ShaderType {
+        if self.simple_shader {
+            ShaderType::Simple
+        } else if self.debug_shader {
+            ShaderType::Debug
+        } else {
+            self.shader
+        }
+    }
+}
+
+/// Vulkan device selection (`--gpu`, `--vulkan-version`)
+#[derive(Debug, Clone, ClapArgs)]
+pub struct VulkanArgs {
+    /// Use this Vulkan device only (disables device fallback)
+    #[arg(long, value_name = "INDEX", env = DEVICE_INDEX_ENV)]
+    pub gpu: Option<usize>,
+
+    /// Highest Vulkan API version to request (default 1.2)
+    #[arg(long, value_name = "MAJOR.MINOR", value_parser = api_version)]
+    pub vulkan_version: Option<u32>,
+}
+
+impl VulkanArgs {
+    pub fn context(&self) -> ContextOptions {
+        ContextOptions {
+            device_index: self.gpu,
+            api_version: self.vulkan_version,
+        }
+    }
+}
+
+/// Fast OSM raster tile server rendering with Vulkan
+#[derive(Debug, Clone, Parser)]
+#[command(version, about)]
+pub struct Args {
+    /// OSM extracts to load and merge, comma separated; each way once per file it is in
+    #[arg(value_name = "OSM_FILE.PBF", value_delimiter = ',', required_unless_present = "selftest")]
+    pub osm_files: Vec<PathBuf>,
+
+    /// Render synthetic data through the whole pipeline, print a pass/fail summary and exit
+    #[arg(long)]
+    pub selftest: bool,
+
+    #[command(flatten)]
+    pub shader: ShaderArgs,
+
+    #[command(flatten)]
+    pub vulkan: VulkanArgs,
+
+    /// Address to listen on
+    #[arg(long, value_name = "ADDR:PORT", env = BIND_ADDR_ENV, default_value = DEFAULT_BIND_ADDR)]
+    pub bind: SocketAddr,
+
+    /// Listen on this port, overriding the port of --bind
+    #[arg(long)]
+    pub port: Option<u16>,
+
+    /// Highest zoom to index; higher zooms render from their ancestor at this zoom
+    #[arg(long, value_name = "Z", default_value_t = MAX_INDEXED_ZOOM, value_parser = clap::value_parser!(u32).range(0..=MAX_INDEXED_ZOOM as i64))]
+    pub max_zoom: u32,
+
+    /// Lowest zoom to index minor ways at without a style; below it only major roads are loaded
+    #[arg(long, value_name = "Z", default_value_t = DETAIL_ZOOM, value_parser = clap::value_parser!(u32).range(0..=MAX_INDEXED_ZOOM as i64))]
+    pub detail_zoom: u32,
+
+    /// Directory for the data files built from the OSM files, and their saved index
+    #[arg(long, value_name = "DIR", default_value_os_t = std::env::temp_dir())]
+    pub tmp_dir: PathBuf,
+
+    /// Load at most this many of the extracts at once (each needs its own memory)
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = positive)]
+    pub max_concurrent_loads: usize,
+
+    /// Render diff tiles against this base (added green, removed red, unchanged gray)
+    #[arg(long, value_name = "BASE.PBF")]
+    pub diff_against: Option<PathBuf>,
+
+    /// Render this many tile widths (0-1) of surrounding data on each side, uncropped
+    #[arg(long, value_name = "F", default_value_t = 0.0, value_parser = fraction)]
+    pub buffer_fraction: f64,
+
+    /// Response for tiles outside the data: notfound, nodata or render
+    #[arg(long, value_name = "POLICY", default_value = "render")]
+    pub out_of_coverage: OutOfCoverage,
+
+    /// Print per-zoom tile index statistics and exit
+    #[arg(long)]
+    pub index_report: bool,
+
+    /// Check tile keys, zooms and object offsets of the index against the data file, exit on anomalies
+    #[arg(long)]
+    pub verify_index: bool,
+
+    /// Re-parse the OSM files even if the saved index (.idx next to the data file) is up to date
+    #[arg(long)]
+    pub rebuild_index: bool,
+
+    /// Cap index memory while loading by spilling every this many tile entries to disk
+    #[arg(long, value_name = "ENTRIES", value_parser = positive)]
+    pub spill_index: Option<usize>,
+
+    /// Tile index key numbering: quadtree (as the Go version) or morton (keeps nearby tiles close)
+    #[arg(long, value_name = "SCHEME", default_value = "quadtree")]
+    pub tile_keys: TileKeyScheme,
+
+    /// Only load ways matching a tag filter, e.g. "highway=primary OR (waterway AND name)"
+    #[arg(long, value_name = "EXPR")]
+    pub filter: Option<TagFilter>,
+
+    /// Only load ways with one of these tags (comma separated key=value, or key for any value)
+    #[arg(long, value_name = "TAGS")]
+    pub include_tags: Option<TagList>,
+
+    /// Don't load ways with any of these tags (comma separated key=value, or key for any value)
+    #[arg(long, value_name = "TAGS")]
+    pub exclude_tags: Option<TagList>,
+
+    /// Keep these tags of every way (comma separated keys, or *) for per-request ?filter= expressions
+    #[arg(long, value_name = "KEYS")]
+    pub retain_tags: Option<RetainTags>,
+
+    /// Index low zooms with ways simplified (Douglas-Peucker) to this many pixels of tolerance
+    #[arg(long, value_name = "PX", value_parser = pixels)]
+    pub simplify_px: Option<f64>,
+
+    /// Map source coordinates to lon*sx+ox, lat*sy+oy before indexing
+    #[arg(long, value_name = "SX,SY,OX,OY", allow_hyphen_values = true)]
+    pub transform: Option<AffineTransform>,
+
+    /// Skip objects spanning fewer pixels than this in the tile
+    #[arg(long, value_name = "PX", value_parser = pixels)]
+    pub lod_skip_px: Option<f64>,
+
+    /// Draw objects spanning fewer pixels than this as a single segment
+    #[arg(long, value_name = "PX", value_parser = pixels)]
+    pub lod_simplify_px: Option<f64>,
+
+    /// Cap vertices and objects per tile from each zoom up (zoom=vertices[/objects],...), * for no cap
+    #[arg(long, value_name = "TABLE")]
+    pub vertex_budget: Option<VertexBudgets>,
+
+    /// Most vertices the vertex buffer grows to for dense tiles before truncating them
+    #[arg(long, value_name = "VERTICES", default_value_t = MAX_VERTEX_BUFFER_CAPACITY, value_parser = positive)]
+    pub max_vertex_buffer: usize,
+
+    /// Maximum number of Vulkan renderers, and so of tiles rendering at once (default: CPU count)
+    #[arg(long, value_name = "N", value_parser = positive)]
+    pub renderer_pool_size: Option<usize>,
+
+    /// Fail requests with 503 after waiting this long for a renderer (default: wait)
+    #[arg(long, value_name = "MS")]
+    pub renderer_timeout_ms: Option<u64>,
+
+    /// Fail renders with 503 and replace their renderer when the GPU takes longer than this
+    #[arg(long, value_name = "MS", default_value_t = DEFAULT_RENDER_TIMEOUT.as_millis() as u64, value_parser = clap::value_parser!(u64).range(1..))]
+    pub render_timeout_ms: u64,
+
+    /// Threads encoding tiles; rendering waits while their queue is full (default: CPU count)
+    #[arg(long, value_name = "N", value_parser = positive)]
+    pub encode_threads: Option<usize>,
+
+    /// Log and count tiles whose render and encode take longer than this (see /metrics)
+    #[arg(long, value_name = "MS")]
+    pub render_budget_ms: Option<u64>,
+
+    /// Keep up to this many encoded tiles in memory, 0 to disable
+    #[arg(long, value_name = "ENTRIES", env = TILE_CACHE_ENV, default_value_t = DEFAULT_TILE_CACHE_ENTRIES)]
+    pub tile_cache: usize,
+
+    /// Serve cached tiles older than the data immediately and re-render them in the background
+    #[arg(long)]
+    pub stale_while_revalidate: bool,
+
+    /// Let browsers and CDNs reuse tiles this long before revalidating their ETag
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_MAX_AGE_SECS)]
+    pub max_age: u32,
+
+    /// Reject tile requests above this zoom with 400 instead of rendering them
+    #[arg(long, value_name = "Z", default_value_t = MAX_ZOOM, value_parser = clap::value_parser!(u32).range(0..=MAX_ZOOM as i64))]
+    pub max_request_zoom: u32,
+
+    /// Classify, filter and color ways by a style file (see mapstyle.toml)
+    #[arg(long, value_name = "MAPSTYLE.TOML")]
+    pub style: Option<PathBuf>,
+
+    /// Color unclassed ways by highway tag (motorway orange, primary yellow, residential grey)
+    #[arg(long)]
+    pub highway_colors: bool,
+
+    /// Color of unclassed ways without a highway color, #rrggbb or #rgb (default black)
+    #[arg(long, value_name = "COLOR", value_parser = color)]
+    pub default_line_color: Option<[u8; 4]>,
+
+    /// Render only inside the polygons of this GeoJSON file, transparent elsewhere
+    #[arg(long, value_name = "GEOJSON")]
+    pub clip_region: Option<PathBuf>,
+
+    /// Round GeoJSON coordinates to this many decimals (MVT is always on the 4096 grid)
+    #[arg(long, value_name = "DECIMALS", value_parser = clap::value_parser!(u32).range(0..=MAX_DECIMALS as i64))]
+    pub vector_precision: Option<u32>,
+
+    /// Draw features across the antimeridian in the first and last tile columns
+    #[arg(long)]
+    pub wrap_antimeridian: bool,
+
+    /// Encode tiles in the first of these formats (avif,webp,png) the client accepts (default png)
+    #[arg(long, value_name = "FORMATS")]
+    pub format_preference: Option<FormatPreference>,
+
+    /// Enable POST /cache/pin and /cache/unpin for requests with this bearer token
+    #[arg(long, value_name = "TOKEN", value_parser = clap::builder::NonEmptyStringValueParser::new())]
+    pub admin_token: Option<String>,
+
+    /// Answer failed renders with a tile showing the error instead of an empty 500 (for debugging)
+    #[arg(long)]
+    pub debug_error_tiles: bool,
+
+    /// Multisample antialiasing samples per pixel (1, 2, 4 or 8), lowered to what the GPU supports
+    #[arg(long, value_name = "SAMPLES", default_value_t = 1, value_parser = msaa_samples)]
+    pub msaa: u32,
+
+    /// Fill closed ways (areas) in their color instead of drawing their outline, under all lines
+    #[arg(long)]
+    pub fill_polygons: bool,
+
+    /// Multisample antialiasing samples of polygon fills (1, 2, 4 or 8), --msaa by default; differing from it needs --msaa 1
+    #[arg(long, value_name = "SAMPLES", value_parser = msaa_samples, requires = "fill_polygons")]
+    pub fill_msaa: Option<u32>,
+
+    /// Draw polygons as outlines on tiles with more objects than this, skipping triangulation (default: always fill)
+    #[arg(long, value_name = "OBJECTS", requires = "fill_polygons")]
+    pub fill_max_objects: Option<usize>,
+
+    /// Render tiles at this multiple of their size and downscale them (antialiasing)
+    #[arg(long, value_name = "FACTOR", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..=MAX_SUPERSAMPLE as i64))]
+    pub supersample: u32,
+
+    /// Filter for --supersample: box (fastest), triangle or lanczos3 (sharpest)
+    #[arg(long, value_name = "FILTER", default_value = "box")]
+    pub downscale_filter: DownscaleFilter,
+
+    /// Snap lines to pixel centers for sharp 1px lines (Mercator shader only)
+    #[arg(long)]
+    pub crisp_lines: bool,
+
+    /// Draw lines in their style class width, or wider for major roads by highway tag and zoom (Mercator shader only)
+    #[arg(long)]
+    pub line_widths: bool,
+
+    /// Draw a darker casing around wide lines, under the lines of their layer
+    #[arg(long, requires = "line_widths")]
+    pub casing: bool,
+
+    /// Draw at most one POI node per cell of this many pixels (default 0, all)
+    #[arg(long, value_name = "PX", default_value_t = 0.0, value_parser = pixels)]
+    pub point_decimation_px: f64,
+
+    /// Skip ways whose bounding box overlaps a tile but none of whose segments do (costs CPU)
+    #[arg(long)]
+    pub precise_overlap: bool,
+
+    /// Encode tiles with at most 256 colors as palette PNGs (smaller, lossless)
+    #[arg(long)]
+    pub png_indexed: bool,
+
+    /// Color image format: unorm, or srgb to blend in linear light and mark PNGs as sRGB
+    #[arg(long, value_name = "FORMAT", default_value = "unorm")]
+    pub color_format: ColorFormat,
+
+    /// Transparent background with opaque features, as PNG tiles for layering over another basemap
+    #[arg(long, visible_alias = "transparent", conflicts_with = "format_preference")]
+    pub overlay: bool,
+
+    /// Tile row 0 and image row 0 at the north (top-left, XYZ) or south edge (bottom-left)
+    #[arg(long, value_name = "ORIGIN", default_value = "top-left")]
+    pub tile_origin: TileOrigin,
+
+    /// Tile row numbering in request paths: xyz (row 0 north) or tms (row 0 south, images north up)
+    #[arg(long, value_name = "SCHEME", default_value = "xyz", conflicts_with = "tile_origin")]
+    pub scheme: TileScheme,
+}
+
+impl Args {
+    /// Address to listen on, `--bind` with `--port`
+    pub fn bind_addr(&self) -> SocketAddr {
+        let mut addr = self.bind;
+        if let Some(port) = self.port {
+            addr.set_port(port);
+        }
+        addr
+    }
+
+    /// Filter ways are loaded with: `--filter`, `--include-tags` and `--exclude-tags` combined
+    pub fn load_filter(&self) -> Option<TagFilter> {
+        [
+            self.filter.clone(),
+            self.include_tags.as_ref().map(TagList::any),
+            self.exclude_tags.as_ref().map(|tags| TagFilter::Not(Box::new(tags.any()))),
+        ]
+        .into_iter()
+        .flatten()
+        .reduce(|a, b| TagFilter::And(Box::new(a), Box::new(b)))
+    }
+
+    pub fn lod(&self) -> LodThresholds {
+        let default = LodThresholds::default();
+        LodThresholds {
+            skip_below_px: self.lod_skip_px.unwrap_or(default.skip_below_px),
+            simplify_below_px: self.lod_simplify_px.unwrap_or(default.simplify_below_px),
+        }
+    }
+
+    /// Tile cache capacity, `None` if disabled
+    pub fn tile_cache_entries(&self) -> Option<usize> {
+        (self.tile_cache > 0).then_some(self.tile_cache)
+    }
+}
+
+fn positive(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err("expected a positive number".to_string()),
+    }
+}
+
+fn fraction(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(f) if (0.0..=1.0).contains(&f) => Ok(f),
+        _ => Err("expected a value between 0 and 1".to_string()),
+    }
+}
+
+fn pixels(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(px) if px >= 0.0 => Ok(px),
+        _ => Err("expected a non-negative pixel size".to_string()),
+    }
+}
+
+fn msaa_samples(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(samples) if [1, 2, 4, 8].contains(&samples) => Ok(samples),
+        _ => Err("expected a sample count of 1, 2, 4 or 8".to_string()),
+    }
+}
+
+fn api_version(s: &str) -> Result<u32, String> {
+    parse_api_version(s).ok_or_else(|| "expected a version such as 1.0 or 1.2".to_string())
+}
+
+fn color(s: &str) -> Result<[u8; 4], String> {
+    parse_color(s)
+        .map(|[r, g, b]| [r, g, b, 255])
+        .ok_or_else(|| "expected a color like #rrggbb or #rgb".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, clap::Error> {
+        Args::try_parse_from(std::iter::once("rust-osm-renderer").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = parse(&["a.pbf,b.pbf"]).unwrap();
+        assert_eq!(args.osm_files, vec![PathBuf::from("a.pbf"), PathBuf::from("b.pbf")]);
+        assert_eq!(args.shader.shader_type(), ShaderType::Mercator);
+        assert_eq!(args.max_zoom, MAX_INDEXED_ZOOM);
+        assert_eq!(args.detail_zoom, DETAIL_ZOOM);
+        assert_eq!(args.tmp_dir, std::env::temp_dir());
+        assert_eq!(args.out_of_coverage, OutOfCoverage::default());
+        assert_eq!(args.tile_origin, TileOrigin::default());
+        assert_eq!(args.scheme, TileScheme::Xyz);
+        assert_eq!(args.max_vertex_buffer, MAX_VERTEX_BUFFER_CAPACITY);
+        assert_eq!(args.render_timeout_ms, 5000);
+        assert_eq!(args.color_format, ColorFormat::Unorm);
+
+        let args = parse(&["a.pbf", "--shader", "simple", "--bind", "127.0.0.1:9000", "--max-zoom", "12", "--tmp-dir", "/var/tmp"]).unwrap();
+        assert_eq!(args.shader.shader_type(), ShaderType::Simple);
+        assert_eq!(args.bind_addr(), "127.0.0.1:9000".parse().unwrap());
+        assert_eq!(args.max_zoom, 12);
+        assert_eq!(args.tmp_dir, PathBuf::from("/var/tmp"));
+        assert_eq!(parse(&["a.pbf", "--scheme", "tms"]).unwrap().scheme, TileScheme::Tms);
+        assert!(parse(&["a.pbf", "--transparent"]).unwrap().overlay);
+        assert!(parse(&["a.pbf", "--line-widths", "--casing"]).unwrap().casing);
+        assert_eq!(parse(&["a.pbf", "--color-format", "srgb"]).unwrap().color_format, ColorFormat::Srgb);
+        let args = parse(&["a.pbf", "--fill-polygons", "--fill-msaa", "4"]).unwrap();
+        assert!(args.fill_polygons);
+        assert_eq!(args.fill_msaa, Some(4));
+        assert_eq!(parse(&["a.pbf", "--fill-polygons", "--fill-max-objects", "5000"]).unwrap().fill_max_objects, Some(5000));
+        let args = parse(&["a.pbf", "--bind", "127.0.0.1:9000", "--port", "9001"]).unwrap();
+        assert_eq!(args.bind_addr(), "127.0.0.1:9001".parse().unwrap());
+
+        // The old shader flags still work
+        assert_eq!(parse(&["a.pbf", "--debug-shader"]).unwrap().shader.shader_type(), ShaderType::Debug);
+        assert_eq!(parse(&["--selftest", "--simple-shader"]).unwrap().shader.shader_type(), ShaderType::Simple);
+
+        let args = parse(&["a.pbf", "--lod-skip-px", "2", "--transform", "-1,1,0,0", "--default-line-color", "#f00"]).unwrap();
+        assert_eq!(args.lod(), LodThresholds { skip_below_px: 2.0, simplify_below_px: 0.0 });
+        assert!(args.transform.is_some());
+        assert_eq!(args.default_line_color, Some([255, 0, 0, 255]));
+
+        assert_eq!(parse(&["a.pbf"]).unwrap().load_filter(), None);
+        let args = parse(&["a.pbf", "--include-tags", "waterway,natural=water", "--exclude-tags", "intermittent=yes"]).unwrap();
+        assert_eq!(args.load_filter(), Some("(waterway OR natural=water) AND NOT intermittent=yes".parse().unwrap()));
+        let args = parse(&["a.pbf", "--filter", "name", "--exclude-tags", "building"]).unwrap();
+        assert_eq!(args.load_filter(), Some("name AND NOT building".parse().unwrap()));
+
+        for invalid in [
+            &[][..],
+            &["a.pbf", "--shader", "fast"],
+            &["a.pbf", "--max-zoom", "16"],
+            &["a.pbf", "--bind", "localhost"],
+            &["a.pbf", "--msaa", "3"],
+            &["a.pbf", "--buffer-fraction", "2"],
+            &["a.pbf", "--overlay", "--format-preference", "webp"],
+            &["a.pbf", "--scheme", "tms", "--tile-origin", "bottom-left"],
+            &["a.pbf", "--detail-zoom", "16"],
+            &["a.pbf", "--max-vertex-buffer", "0"],
+            &["a.pbf", "--include-tags", ""],
+            &["a.pbf", "--casing"],
+            &["a.pbf", "--render-timeout-ms", "0"],
+            &["a.pbf", "--color-format", "rgba16f"],
+            &["a.pbf", "--fill-msaa", "4"],
+            &["a.pbf", "--fill-polygons", "--fill-msaa", "3"],
+            &["a.pbf", "--fill-max-objects", "5000"],
+            &["a.pbf", "--no-such-flag"],
+        ] {
+            assert!(parse(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+}
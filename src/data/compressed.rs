@@ -0,0 +1,500 @@
+//! Blocked-compressed data files, decompressed per block on read
+//!
+//! Experimental: neither the loader nor the server reads this format yet,
+//! they keep mapping the uncompressed data file with `MappedData`. Only
+//! `examples/compress_data.rs` uses it, to measure the space savings
+//! against the read overhead.
+
+use super::serialization::{
+    read_map_object, BOUNDING_BOX_SIZE, KIND_SIZE, POINTS_LEN_SIZE, POINT_SIZE, STRING_LEN_SIZE, TAGS_LEN_SIZE,
+};
+use super::types::{MapObject, MapObjectOffset};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use memmap2::Mmap;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+// Blocked compression format for the map object data file:
+// - Header: 40 bytes
+//   - magic: 4 bytes ("OSMZ")
+//   - version: 4 bytes (u32)
+//   - block_size: 8 bytes (u64, uncompressed bytes per block)
+//   - uncompressed_len: 8 bytes (u64)
+//   - table_offset: 8 bytes (u64, position of the block table)
+//   - block_count: 8 bytes (u64)
+// - Compressed blocks (raw deflate), back to back
+// - Block table: block_count * 16 bytes
+//   - each entry: compressed offset (8 bytes u64) + compressed length (8 bytes u64)
+//
+// Map object offsets stay offsets into the *uncompressed* data, so an offset
+// resolves to the pair (offset / block_size, offset % block_size) and the
+// TileIndex does not change. Objects may straddle block boundaries.
+
+pub const MAGIC: &[u8; 4] = b"OSMZ";
+pub const VERSION: u32 = 1;
+pub const HEADER_SIZE: usize = 40;
+pub const BLOCK_ENTRY_SIZE: usize = 16;
+
+/// Default uncompressed block size (64 KiB)
+pub const DEFAULT_BLOCK_SIZE: u64 = 64 * 1024;
+
+/// Default number of decompressed blocks kept in memory
+pub const DEFAULT_CACHE_BLOCKS: usize = 64;
+
+/// Size statistics from compressing a data file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionStats {
+    pub uncompressed_bytes: u64,
+    pub compressed_bytes: u64,
+    pub blocks: u64,
+}
+
+impl CompressionStats {
+    /// Compressed size as a fraction of the uncompressed size
+    pub fn ratio(&self) -> f64 {
+        if self.uncompressed_bytes == 0 {
+            return 1.0;
+        }
+        self.compressed_bytes as f64 / self.uncompressed_bytes as f64
+    }
+}
+
+/// Compress an uncompressed data file into the blocked format
+pub fn compress_data<R: Read, W: Write + Seek>(
+    reader: &mut R,
+    writer: &mut W,
+    block_size: u64,
+) -> io::Result<CompressionStats> {
+    if block_size == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "block size must be non-zero"));
+    }
+
+    let start = writer.stream_position()?;
+
+    // Header placeholder, rewritten once the block table position is known
+    writer.write_all(&[0u8; HEADER_SIZE])?;
+
+    let mut table = Vec::new();
+    let mut uncompressed_len = 0u64;
+    let mut block = vec![0u8; block_size as usize];
+
+    loop {
+        let filled = read_full(reader, &mut block)?;
+        if filled == 0 {
+            break;
+        }
+        uncompressed_len += filled as u64;
+
+        let block_offset = writer.stream_position()? - start;
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&block[..filled])?;
+        let compressed = encoder.finish()?;
+        writer.write_all(&compressed)?;
+        table.push((block_offset, compressed.len() as u64));
+
+        if filled < block.len() {
+            break;
+        }
+    }
+
+    // Write block table
+    let table_offset = writer.stream_position()? - start;
+    for (offset, len) in &table {
+        writer.write_u64::<LittleEndian>(*offset)?;
+        writer.write_u64::<LittleEndian>(*len)?;
+    }
+    let end = writer.stream_position()?;
+
+    // Write header
+    writer.seek(SeekFrom::Start(start))?;
+    writer.write_all(MAGIC)?;
+    writer.write_u32::<LittleEndian>(VERSION)?;
+    writer.write_u64::<LittleEndian>(block_size)?;
+    writer.write_u64::<LittleEndian>(uncompressed_len)?;
+    writer.write_u64::<LittleEndian>(table_offset)?;
+    writer.write_u64::<LittleEndian>(table.len() as u64)?;
+    writer.seek(SeekFrom::Start(end))?;
+
+    Ok(CompressionStats {
+        uncompressed_bytes: uncompressed_len,
+        compressed_bytes: end - start,
+        blocks: table.len() as u64,
+    })
+}
+
+/// Compress the data file at `input` into `output`
+pub fn compress_data_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    input: P,
+    output: Q,
+    block_size: u64,
+) -> io::Result<CompressionStats> {
+    let mut reader = io::BufReader::new(File::open(input)?);
+    let mut writer = io::BufWriter::new(File::create(output)?);
+    let stats = compress_data(&mut reader, &mut writer, block_size)?;
+    writer.flush()?;
+    Ok(stats)
+}
+
+/// Fill `buf` from `reader`, returning fewer bytes only at end of input
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Memory-mapped blocked-compressed data file
+///
+/// Unlike `MappedData`, objects can't be viewed in place; each read decompresses
+/// the block(s) containing the object and returns an owned `MapObject`.
+/// Recently used blocks are kept in a small LRU cache.
+pub struct CompressedData {
+    _file: File, // Keep file open for the lifetime of the mmap
+    mmap: Mmap,
+    block_size: u64,
+    uncompressed_len: u64,
+    blocks: Vec<(u64, u64)>,
+    cache: Mutex<BlockCache>,
+}
+
+impl CompressedData {
+    /// Open a blocked-compressed data file
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::with_cache_blocks(path, DEFAULT_CACHE_BLOCKS)
+    }
+
+    /// Open a blocked-compressed data file with a custom block cache size
+    pub fn with_cache_blocks<P: AsRef<Path>>(path: P, cache_blocks: usize) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        if mmap.len() < HEADER_SIZE || &mmap[..4] != MAGIC {
+            return Err(invalid_data("not a compressed data file"));
+        }
+
+        let mut header = Cursor::new(&mmap[4..HEADER_SIZE]);
+        let version = header.read_u32::<LittleEndian>()?;
+        if version != VERSION {
+            return Err(invalid_data(format!("unsupported compressed data version {}", version)));
+        }
+        let block_size = header.read_u64::<LittleEndian>()?;
+        let uncompressed_len = header.read_u64::<LittleEndian>()?;
+        let table_offset = header.read_u64::<LittleEndian>()?;
+        let block_count = header.read_u64::<LittleEndian>()?;
+
+        if block_size == 0 {
+            return Err(invalid_data("block size must be non-zero"));
+        }
+        let table_end = block_count
+            .checked_mul(BLOCK_ENTRY_SIZE as u64)
+            .and_then(|len| len.checked_add(table_offset))
+            .ok_or_else(|| invalid_data("block table out of range"))?;
+        if table_end > mmap.len() as u64 {
+            return Err(invalid_data("block table out of range"));
+        }
+
+        let mut table = Cursor::new(&mmap[table_offset as usize..table_end as usize]);
+        let mut blocks = Vec::with_capacity(block_count as usize);
+        for _ in 0..block_count {
+            let offset = table.read_u64::<LittleEndian>()?;
+            let len = table.read_u64::<LittleEndian>()?;
+            if offset.checked_add(len).is_none_or(|end| end > table_offset) {
+                return Err(invalid_data("compressed block out of range"));
+            }
+            blocks.push((offset, len));
+        }
+
+        Ok(CompressedData {
+            _file: file,
+            mmap,
+            block_size,
+            uncompressed_len,
+            blocks,
+            cache: Mutex::new(BlockCache::new(cache_blocks.max(1))),
+        })
+    }
+
+    /// Read and decompress the map object at the given (uncompressed) offset
+    pub fn read_map_object(&self, offset: MapObjectOffset) -> io::Result<MapObject> {
+        // Read the object piece by piece, each length telling how much follows
+        let mut bytes = Vec::new();
+        let prefix = self.read_appended(offset, &mut bytes, BOUNDING_BOX_SIZE + POINTS_LEN_SIZE)?;
+        let points_len = (&prefix[BOUNDING_BOX_SIZE..]).read_i64::<LittleEndian>()?;
+        if points_len < 0 {
+            return Err(invalid_data("negative points length"));
+        }
+        let points_size = usize::try_from(points_len)
+            .ok()
+            .and_then(|len| len.checked_mul(POINT_SIZE))
+            .ok_or_else(|| invalid_data("points length out of range"))?;
+        self.read_appended(offset, &mut bytes, points_size + KIND_SIZE)?;
+        let tags_len = self.read_appended(offset, &mut bytes, TAGS_LEN_SIZE)?.read_u32::<LittleEndian>()?;
+        // A key and a value per tag
+        let strings = u64::from(tags_len) * 2;
+        for _ in 0..strings {
+            let string_len = self.read_appended(offset, &mut bytes, STRING_LEN_SIZE)?.read_u32::<LittleEndian>()?;
+            self.read_appended(offset, &mut bytes, string_len as usize)?;
+        }
+
+        read_map_object(&mut Cursor::new(&bytes), 0)
+    }
+
+    /// Append the `len` bytes following the bytes of the object at `offset` read so far
+    fn read_appended<'b>(&self, offset: MapObjectOffset, bytes: &'b mut Vec<u8>, len: usize) -> io::Result<&'b [u8]> {
+        let start = bytes.len();
+        let position = offset.checked_add(start as u64).ok_or_else(|| invalid_data("object offset out of range"))?;
+        let end = start.checked_add(len).ok_or_else(|| invalid_data("object length out of range"))?;
+        // Corrupt lengths fail here rather than allocating first
+        if position.checked_add(len as u64).is_none_or(|end| end > self.uncompressed_len) {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "read past end of data"));
+        }
+        bytes.resize(end, 0);
+        self.read_at(position, &mut bytes[start..])?;
+        Ok(&bytes[start..])
+    }
+
+    /// Copy uncompressed bytes starting at `offset` into `buf`, spanning blocks as needed
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        if offset.checked_add(buf.len() as u64).is_none_or(|end| end > self.uncompressed_len) {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "read past end of data"));
+        }
+
+        let mut copied = 0;
+        while copied < buf.len() {
+            let position = offset + copied as u64;
+            let block_index = position / self.block_size;
+            let within_block = (position % self.block_size) as usize;
+
+            let block = self.block(block_index)?;
+            let available = block.len().saturating_sub(within_block);
+            if available == 0 {
+                return Err(invalid_data("compressed block shorter than block size"));
+            }
+            let n = available.min(buf.len() - copied);
+            buf[copied..copied + n].copy_from_slice(&block[within_block..within_block + n]);
+            copied += n;
+        }
+
+        Ok(())
+    }
+
+    /// Get a decompressed block, from the cache if possible
+    fn block(&self, block_index: u64) -> io::Result<Arc<Vec<u8>>> {
+        if let Some(block) = self.cache.lock().unwrap().get(block_index) {
+            return Ok(block);
+        }
+
+        let &(offset, len) = self
+            .blocks
+            .get(block_index as usize)
+            .ok_or_else(|| invalid_data("block index out of range"))?;
+        let compressed = &self.mmap[offset as usize..(offset + len) as usize];
+
+        let mut block = Vec::with_capacity(self.block_size as usize);
+        DeflateDecoder::new(compressed).read_to_end(&mut block)?;
+        let block = Arc::new(block);
+
+        self.cache.lock().unwrap().insert(block_index, block.clone());
+        Ok(block)
+    }
+
+    /// Size of the uncompressed data in bytes
+    pub fn uncompressed_len(&self) -> u64 {
+        self.uncompressed_len
+    }
+
+    /// Size of the compressed file in bytes
+    pub fn len(&self) -> usize {
+        self.mmap.len()
+    }
+
+    /// Check if the compressed data holds no objects
+    pub fn is_empty(&self) -> bool {
+        self.uncompressed_len == 0
+    }
+}
+
+/// Small LRU cache of decompressed blocks (most recently used at the back)
+struct BlockCache {
+    capacity: usize,
+    entries: VecDeque<(u64, Arc<Vec<u8>>)>,
+}
+
+impl BlockCache {
+    fn new(capacity: usize) -> Self {
+        BlockCache {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    fn get(&mut self, block_index: u64) -> Option<Arc<Vec<u8>>> {
+        let position = self.entries.iter().position(|(index, _)| *index == block_index)?;
+        let entry = self.entries.remove(position)?;
+        let block = entry.1.clone();
+        self.entries.push_back(entry);
+        Some(block)
+    }
+
+    fn insert(&mut self, block_index: u64, block: Arc<Vec<u8>>) {
+        if self.entries.iter().any(|(index, _)| *index == block_index) {
+            return;
+        }
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((block_index, block));
+    }
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::serialization::write_map_object;
+    use crate::data::types::{BoundingBox, Point};
+    use tempfile::NamedTempFile;
+
+    fn make_object(i: usize) -> MapObject {
+        let base = i as f64 * 0.001;
+        let points: Vec<Point> = (0..(i % 7) + 2)
+            .map(|j| Point::new(10.0 + base + j as f64 * 0.0001, 53.0 + base))
+            .collect();
+        let mut object = MapObject::new(BoundingBox::from_points(&points).unwrap(), points);
+        if i % 3 == 0 {
+            object.tags = vec![("name".to_string(), format!("Way {}", i))];
+        }
+        object
+    }
+
+    #[test]
+    fn test_compressed_round_trip() -> io::Result<()> {
+        // Write uncompressed data file
+        let mut data_file = NamedTempFile::new()?;
+        let objects: Vec<MapObject> = (0..500).map(make_object).collect();
+        let mut offsets = Vec::new();
+        for obj in &objects {
+            offsets.push(write_map_object(data_file.as_file_mut(), obj)?);
+        }
+        data_file.as_file_mut().flush()?;
+
+        // Small blocks so that objects straddle block boundaries
+        let compressed_file = NamedTempFile::new()?;
+        let stats = compress_data_file(data_file.path(), compressed_file.path(), 1000)?;
+        assert_eq!(stats.uncompressed_bytes, std::fs::metadata(data_file.path())?.len());
+        assert!(stats.blocks > 1);
+        assert!(stats.ratio() < 1.0, "expected space savings, got ratio {}", stats.ratio());
+
+        let data = CompressedData::with_cache_blocks(compressed_file.path(), 4)?;
+        assert_eq!(data.uncompressed_len(), stats.uncompressed_bytes);
+
+        // Read in reverse order to exercise cache eviction
+        for (obj, &offset) in objects.iter().zip(&offsets).rev() {
+            let read = data.read_map_object(offset)?;
+            assert_eq!(read.bounding_box, obj.bounding_box);
+            assert_eq!(read.points, obj.points);
+            assert_eq!(read.tags, obj.tags);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed_empty_and_exact_blocks() -> io::Result<()> {
+        // Empty input
+        let mut output = Cursor::new(Vec::new());
+        let stats = compress_data(&mut io::empty(), &mut output, DEFAULT_BLOCK_SIZE)?;
+        assert_eq!(stats.blocks, 0);
+        assert_eq!(stats.uncompressed_bytes, 0);
+
+        // Input that is an exact multiple of the block size
+        let input = vec![7u8; 256];
+        let mut output = Cursor::new(Vec::new());
+        let stats = compress_data(&mut input.as_slice(), &mut output, 128)?;
+        assert_eq!(stats.blocks, 2);
+
+        let file = NamedTempFile::new()?;
+        std::fs::write(file.path(), output.into_inner())?;
+        let data = CompressedData::new(file.path())?;
+        let mut buf = [0u8; 10];
+        data.read_at(123, &mut buf)?;
+        assert_eq!(buf, [7u8; 10]);
+        assert!(data.read_at(250, &mut buf).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed_rejects_overflowing_lengths() -> io::Result<()> {
+        let compress = |data: &[u8]| -> io::Result<NamedTempFile> {
+            let mut output = Cursor::new(Vec::new());
+            compress_data(&mut &data[..], &mut output, 128)?;
+            let file = NamedTempFile::new()?;
+            std::fs::write(file.path(), output.into_inner())?;
+            Ok(file)
+        };
+        let object = make_object(1);
+        let mut data = Vec::new();
+        let offset = write_map_object(&mut Cursor::new(&mut data), &object)?;
+
+        // A block entry whose end overflows u64
+        let file = compress(&data)?;
+        let mut bytes = std::fs::read(file.path())?;
+        let table_offset = u64::from_le_bytes(bytes[24..32].try_into().unwrap()) as usize;
+        bytes[table_offset..table_offset + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        std::fs::write(file.path(), bytes)?;
+        let err = CompressedData::new(file.path()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // Points and tag counts too large to add up
+        let points_len_at = offset as usize + BOUNDING_BOX_SIZE;
+        let tags_len_at = points_len_at + POINTS_LEN_SIZE + object.points.len() * POINT_SIZE + KIND_SIZE;
+        for (at, value) in [(points_len_at, &i64::MAX.to_le_bytes()[..]), (tags_len_at, &u32::MAX.to_le_bytes()[..])] {
+            let mut corrupt = data.clone();
+            corrupt[at..at + value.len()].copy_from_slice(value);
+            let file = compress(&corrupt)?;
+            let err = CompressedData::new(file.path())?.read_map_object(offset).unwrap_err();
+            assert!(matches!(err.kind(), io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof), "{:?}", err);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed_rejects_uncompressed_file() -> io::Result<()> {
+        let mut data_file = NamedTempFile::new()?;
+        write_map_object(data_file.as_file_mut(), &make_object(1))?;
+        data_file.as_file_mut().flush()?;
+
+        let err = CompressedData::new(data_file.path()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_cache_lru() {
+        let mut cache = BlockCache::new(2);
+        cache.insert(1, Arc::new(vec![1]));
+        cache.insert(2, Arc::new(vec![2]));
+        assert!(cache.get(1).is_some()); // 1 is now most recently used
+        cache.insert(3, Arc::new(vec![3])); // Evicts 2
+        assert!(cache.get(2).is_none());
+        assert!(cache.get(1).is_some());
+        assert!(cache.get(3).is_some());
+    }
+}
@@ -0,0 +1,409 @@
+//! On-disk tile index
+//!
+//! Index file layout (little endian, all fields 8 bytes):
+//! - magic: `INDEX_MAGIC`, or `MORTON_INDEX_MAGIC` for Morton tile keys
+//! - source_fingerprint: u64, see `TileIndex::source_fingerprint`
+//! - max_points: u64
+//! - has_bounds: u64 (0 or 1), then min.lon, min.lat, max.lon, max.lat (f64)
+//! - way_ids_len: u64, then (offset u64, way_id i64) pairs sorted by offset
+//! - classes_len: u64, then (offset u64, class u64) pairs sorted by offset
+//! - tiles_len: u64, then per tile in ascending key order:
+//!   key u64, offsets_len u64, offsets_len * offset u64
+//!
+//! Spill runs (see `IndexSpiller`) use the tile section encoding without
+//! a count and end at EOF.
+
+use super::spatial::{TileIndex, TileKey, TileKeyScheme, TileMap, TileMapKind};
+use super::types::{BoundingBox, MapObjectOffset, Point};
+use crate::style::ClassId;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Magic bytes (and format version) at the start of an index file
+pub const INDEX_MAGIC: &[u8; 8] = b"OSMTIDX2";
+
+/// Magic bytes of an index file keyed by `TileKeyScheme::Morton`
+pub const MORTON_INDEX_MAGIC: &[u8; 8] = b"OSMTIDZ2";
+
+/// Write a complete tile index to `path`
+pub fn write_index<P: AsRef<Path>>(path: P, index: &TileIndex) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_index_to(&mut writer, index)?;
+    writer.flush()
+}
+
+/// Read a tile index written by `write_index` or `IndexSpiller::finish`
+pub fn read_index<P: AsRef<Path>>(path: P) -> io::Result<TileIndex> {
+    read_index_from(&mut BufReader::new(File::open(path)?))
+}
+
+/// Write a complete tile index to `writer`, see `TileIndex::save_to`
+pub fn write_index_to<W: Write>(writer: &mut W, index: &TileIndex) -> io::Result<()> {
+    write_header(writer, index)?;
+
+    let mut tiles: Vec<(&TileKey, &Vec<MapObjectOffset>)> = index.tiles.iter().collect();
+    tiles.sort_unstable_by_key(|&(&key, _)| key);
+    writer.write_u64::<LittleEndian>(tiles.len() as u64)?;
+    for (&key, offsets) in tiles {
+        write_tile(writer, key, offsets)?;
+    }
+    Ok(())
+}
+
+/// Read a tile index written by `write_index_to`, see `TileIndex::load_from`
+pub fn read_index_from<R: Read>(reader: &mut R) -> io::Result<TileIndex> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    let key_scheme = match &magic {
+        INDEX_MAGIC => TileKeyScheme::Quadtree,
+        MORTON_INDEX_MAGIC => TileKeyScheme::Morton,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "not a tile index file (or an older version)")),
+    };
+
+    let mut index = TileIndex::with_key_scheme(TileMapKind::default(), key_scheme);
+    index.source_fingerprint = reader.read_u64::<LittleEndian>()?;
+    index.max_points = reader.read_u64::<LittleEndian>()? as usize;
+    let has_bounds = reader.read_u64::<LittleEndian>()? != 0;
+    let bounds = BoundingBox {
+        min: Point::new(reader.read_f64::<LittleEndian>()?, reader.read_f64::<LittleEndian>()?),
+        max: Point::new(reader.read_f64::<LittleEndian>()?, reader.read_f64::<LittleEndian>()?),
+    };
+    index.bounds = has_bounds.then_some(bounds);
+
+    let way_ids_len = reader.read_u64::<LittleEndian>()? as usize;
+    index.way_ids.reserve_exact(way_ids_len);
+    for _ in 0..way_ids_len {
+        let offset = reader.read_u64::<LittleEndian>()?;
+        let way_id = reader.read_i64::<LittleEndian>()?;
+        index.way_ids.push((offset, way_id));
+    }
+
+    let classes_len = reader.read_u64::<LittleEndian>()? as usize;
+    index.classes.reserve_exact(classes_len);
+    for _ in 0..classes_len {
+        let offset = reader.read_u64::<LittleEndian>()?;
+        let class = reader.read_u64::<LittleEndian>()?;
+        let class = ClassId::try_from(class).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        index.classes.push((offset, class));
+    }
+
+    let tiles_len = reader.read_u64::<LittleEndian>()? as usize;
+    index.tiles = TileMap::with_capacity(index.tiles.kind(), tiles_len);
+    for _ in 0..tiles_len {
+        let (key, len) = read_tile_header(reader)?
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        let offsets = index.tiles.get_or_default(key);
+        offsets.reserve_exact(len);
+        read_offsets(reader, len, offsets)?;
+    }
+    Ok(index)
+}
+
+/// Builds the tile index in bounded memory by spilling it to sorted runs
+///
+/// Ways arrive in file order, not spatially, so a tile's object list is
+/// only complete once the whole PBF has been read. Instead of waiting for
+/// that, the loader hands its tile map to `add_entries`; once it holds
+/// `max_entries` offsets it is written to a run file sorted by tile key
+/// and cleared. `finish` k-way merges the runs into the index file and
+/// into the returned index. Runs are merged in order, and offsets grow as
+/// objects are written, so each tile keeps its offsets in ascending order
+/// just like an in-memory build.
+///
+/// Tradeoff: peak memory while loading is bounded by `max_entries` plus
+/// the final index, which is allocated at its exact size instead of with
+/// the growth slack of `Vec` and hash map doubling. In exchange every
+/// entry is written to disk twice (run and index file) and read once.
+/// Way ids and bounds are small per way and stay in memory.
+pub struct IndexSpiller {
+    index_path: PathBuf,
+    max_entries: usize,
+    entries: usize,
+    runs: Vec<PathBuf>,
+}
+
+impl IndexSpiller {
+    /// Spill to runs next to `index_path` whenever `max_entries` offsets are held
+    pub fn new<P: AsRef<Path>>(index_path: P, max_entries: usize) -> Self {
+        IndexSpiller {
+            index_path: index_path.as_ref().to_path_buf(),
+            max_entries: max_entries.max(1),
+            entries: 0,
+            runs: Vec::new(),
+        }
+    }
+
+    /// Account for `count` new offsets in `tiles`, spilling if the limit is reached
+    pub fn add_entries(&mut self, tiles: &mut TileMap, count: usize) -> io::Result<()> {
+        self.entries += count;
+        if self.entries >= self.max_entries {
+            self.spill(tiles)?;
+        }
+        Ok(())
+    }
+
+    /// Number of runs written so far
+    pub fn runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    fn spill(&mut self, tiles: &mut TileMap) -> io::Result<()> {
+        if tiles.is_empty() {
+            return Ok(());
+        }
+        let path = self.index_path.with_extension(format!("run{}", self.runs.len()));
+        let full = std::mem::replace(tiles, TileMap::new(tiles.kind()));
+        let sorted = full.into_sorted_vec();
+
+        let mut writer = BufWriter::new(File::create(&path)?);
+        for (key, offsets) in &sorted {
+            write_tile(&mut writer, *key, offsets)?;
+        }
+        writer.flush()?;
+
+        log::debug!("Spilled {} tiles ({} entries) to {}", sorted.len(), self.entries, path.display());
+        self.runs.push(path);
+        self.entries = 0;
+        Ok(())
+    }
+
+    /// Merge all runs and the remaining tiles of `index` into the index file
+    ///
+    /// Returns the complete index; run files are removed.
+    pub fn finish(mut self, mut index: TileIndex) -> io::Result<TileIndex> {
+        self.spill(&mut index.tiles)?;
+        let result = self.merge(&mut index);
+        for run in &self.runs {
+            fs::remove_file(run).ok();
+        }
+        result?;
+        Ok(index)
+    }
+
+    fn merge(&self, index: &mut TileIndex) -> io::Result<()> {
+        let mut runs: Vec<BufReader<File>> = self
+            .runs
+            .iter()
+            .map(|path| File::open(path).map(BufReader::new))
+            .collect::<io::Result<_>>()?;
+        let mut heads: Vec<Option<(TileKey, usize)>> = runs
+            .iter_mut()
+            .map(read_tile_header)
+            .collect::<io::Result<_>>()?;
+
+        // The tile count is only known after merging, so patch it in at the end
+        let mut writer = BufWriter::new(File::create(&self.index_path)?);
+        write_header(&mut writer, index)?;
+        let tiles_len_position = writer.stream_position()?;
+        writer.write_u64::<LittleEndian>(0)?;
+
+        let mut merged = Vec::new();
+        while let Some(key) = heads.iter().flatten().map(|&(key, _)| key).min() {
+            let len: usize = heads.iter().flatten().filter(|&&(k, _)| k == key).map(|&(_, len)| len).sum();
+            let mut offsets = Vec::with_capacity(len);
+            for (run, head) in runs.iter_mut().zip(heads.iter_mut()) {
+                if let Some((k, run_len)) = *head {
+                    if k == key {
+                        read_offsets(run, run_len, &mut offsets)?;
+                        *head = read_tile_header(run)?;
+                    }
+                }
+            }
+            // Like `TileIndex::finalize`, across runs
+            offsets.sort_unstable();
+            offsets.dedup();
+            write_tile(&mut writer, key, &offsets)?;
+            merged.push((key, offsets));
+        }
+        writer.seek(SeekFrom::Start(tiles_len_position))?;
+        writer.write_u64::<LittleEndian>(merged.len() as u64)?;
+        writer.flush()?;
+
+        index.tiles = TileMap::with_capacity(index.tiles.kind(), merged.len());
+        for (key, offsets) in merged {
+            *index.tiles.get_or_default(key) = offsets;
+        }
+        Ok(())
+    }
+}
+
+fn write_header<W: Write>(writer: &mut W, index: &TileIndex) -> io::Result<()> {
+    writer.write_all(match index.key_scheme() {
+        TileKeyScheme::Quadtree => INDEX_MAGIC,
+        TileKeyScheme::Morton => MORTON_INDEX_MAGIC,
+    })?;
+    writer.write_u64::<LittleEndian>(index.source_fingerprint)?;
+    writer.write_u64::<LittleEndian>(index.max_points as u64)?;
+    let bounds = index.bounds.unwrap_or(BoundingBox {
+        min: Point::new(0.0, 0.0),
+        max: Point::new(0.0, 0.0),
+    });
+    writer.write_u64::<LittleEndian>(index.bounds.is_some() as u64)?;
+    writer.write_f64::<LittleEndian>(bounds.min.lon)?;
+    writer.write_f64::<LittleEndian>(bounds.min.lat)?;
+    writer.write_f64::<LittleEndian>(bounds.max.lon)?;
+    writer.write_f64::<LittleEndian>(bounds.max.lat)?;
+
+    writer.write_u64::<LittleEndian>(index.way_ids.len() as u64)?;
+    for &(offset, way_id) in &index.way_ids {
+        writer.write_u64::<LittleEndian>(offset)?;
+        writer.write_i64::<LittleEndian>(way_id)?;
+    }
+
+    writer.write_u64::<LittleEndian>(index.classes.len() as u64)?;
+    for &(offset, class) in &index.classes {
+        writer.write_u64::<LittleEndian>(offset)?;
+        writer.write_u64::<LittleEndian>(class as u64)?;
+    }
+    Ok(())
+}
+
+fn write_tile<W: Write>(writer: &mut W, key: TileKey, offsets: &[MapObjectOffset]) -> io::Result<()> {
+    writer.write_u64::<LittleEndian>(key)?;
+    writer.write_u64::<LittleEndian>(offsets.len() as u64)?;
+    for &offset in offsets {
+        writer.write_u64::<LittleEndian>(offset)?;
+    }
+    Ok(())
+}
+
+/// Read the key and offset count of the next tile, `None` at EOF
+fn read_tile_header<R: Read>(reader: &mut R) -> io::Result<Option<(TileKey, usize)>> {
+    let key = match reader.read_u64::<LittleEndian>() {
+        Ok(key) => key,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let len = reader.read_u64::<LittleEndian>()? as usize;
+    Ok(Some((key, len)))
+}
+
+fn read_offsets<R: Read>(reader: &mut R, len: usize, offsets: &mut Vec<MapObjectOffset>) -> io::Result<()> {
+    for _ in 0..len {
+        offsets.push(reader.read_u64::<LittleEndian>()?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::types::Tile;
+    use tempfile::TempDir;
+
+    fn sample_index() -> TileIndex {
+        let mut index = TileIndex::new();
+        index.max_points = 42;
+        for (i, tile) in [Tile::new(0, 0, 0), Tile::new(1, 0, 1), Tile::new(0, 0, 0)].into_iter().enumerate() {
+            let offset = i as u64 * 64;
+            index.insert(tile, offset);
+            index.record_way_id(offset, 100 + i as i64);
+            index.record_class(offset, i as ClassId);
+        }
+        index.source_fingerprint = 0xfeed;
+        index.extend_bounds(&BoundingBox {
+            min: Point::new(9.9, 53.4),
+            max: Point::new(10.1, 53.6),
+        });
+        index
+    }
+
+    fn sorted_tiles(index: &TileIndex) -> Vec<(TileKey, Vec<MapObjectOffset>)> {
+        let mut tiles: Vec<_> = index.tiles.iter().map(|(&key, offsets)| (key, offsets.clone())).collect();
+        tiles.sort_unstable();
+        tiles
+    }
+
+    #[test]
+    fn test_index_round_trip() -> io::Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("index.bin");
+        let index = sample_index();
+
+        write_index(&path, &index)?;
+        let loaded = read_index(&path)?;
+        assert_eq!(loaded.max_points, 42);
+        assert_eq!(loaded.way_ids, index.way_ids);
+        assert_eq!(loaded.classes, index.classes);
+        assert_eq!(loaded.source_fingerprint, 0xfeed);
+        assert_eq!(loaded.bounds, index.bounds);
+        assert_eq!(sorted_tiles(&loaded), sorted_tiles(&index));
+
+        // Same bytes through any writer and reader
+        let mut buffer = Vec::new();
+        index.save_to(&mut buffer)?;
+        assert_eq!(buffer, std::fs::read(&path)?);
+        let loaded = TileIndex::load_from(&mut buffer.as_slice())?;
+        assert_eq!(sorted_tiles(&loaded), sorted_tiles(&index));
+        assert!(TileIndex::load_from(&mut &buffer[..buffer.len() - 1]).is_err(), "truncated index loaded");
+
+        // Empty index without bounds
+        write_index(&path, &TileIndex::new())?;
+        let loaded = read_index(&path)?;
+        assert!(loaded.is_empty() && loaded.bounds.is_none());
+
+        // Morton keys are read back as such
+        let mut morton = TileIndex::with_key_scheme(TileMapKind::BTree, TileKeyScheme::Morton);
+        morton.insert(Tile::new(1081, 660, 11), 5);
+        write_index(&path, &morton)?;
+        let loaded = read_index(&path)?;
+        assert_eq!(loaded.key_scheme(), TileKeyScheme::Morton);
+        assert_eq!(loaded.get(&Tile::new(1081, 660, 11)), Some(&vec![5]));
+
+        std::fs::write(&path, b"not an index")?;
+        assert!(read_index(&path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_spiller_matches_in_memory() -> io::Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("index.bin");
+        let expected = sample_index();
+
+        // Spill after every entry, so each run holds a single tile
+        let mut spiller = IndexSpiller::new(&path, 1);
+        let mut index = TileIndex::new();
+        index.max_points = expected.max_points;
+        index.way_ids = expected.way_ids.clone();
+        index.bounds = expected.bounds;
+        for (i, tile) in [Tile::new(0, 0, 0), Tile::new(1, 0, 1), Tile::new(0, 0, 0)].into_iter().enumerate() {
+            index.insert(tile, i as u64 * 64);
+            spiller.add_entries(&mut index.tiles, 1)?;
+            assert!(index.is_empty());
+        }
+        assert_eq!(spiller.runs(), 3);
+
+        let index = spiller.finish(index)?;
+        assert_eq!(sorted_tiles(&index), sorted_tiles(&expected));
+        assert_eq!(index.get(&Tile::new(0, 0, 0)).unwrap(), &vec![0, 128]);
+        assert_eq!(sorted_tiles(&read_index(&path)?), sorted_tiles(&expected));
+
+        // Only the index file is left behind
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_spiller_drops_duplicate_offsets() -> io::Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("index.bin");
+        let tile = Tile::new(0, 0, 0);
+
+        // The same offset twice in one run and again in another
+        let mut spiller = IndexSpiller::new(&path, 2);
+        let mut index = TileIndex::new();
+        for offset in [64, 64, 64, 128] {
+            index.insert(tile, offset);
+            spiller.add_entries(&mut index.tiles, 1)?;
+        }
+        assert_eq!(spiller.runs(), 2);
+
+        let index = spiller.finish(index)?;
+        assert_eq!(index.get(&tile).unwrap(), &vec![64, 128]);
+        assert_eq!(read_index(&path)?.get(&tile).unwrap(), &vec![64, 128]);
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+//! Format detection of OSM input files by their magic bytes
+//!
+//! Only PBF can be loaded, optionally gzip-compressed, which is decompressed
+//! while reading. Other formats are recognized to tell what to convert.
+
+use flate2::read::GzDecoder;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+/// Bytes read to detect the format
+const SNIFF_LEN: usize = 64;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const BZIP2_MAGIC: &[u8] = b"BZh";
+const UTF8_BOM: &[u8] = &[0xef, 0xbb, 0xbf];
+
+/// Format of an OSM input file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    /// Anything else, left to the PBF reader to reject
+    Pbf,
+    GzipPbf,
+    Xml,
+    GzipXml,
+    /// bzip2-compressed, usually OSM XML (`.osm.bz2`)
+    Bzip2,
+}
+
+impl InputFormat {
+    /// Detect the format of the file at `path`, looking into gzip streams
+    pub fn detect(path: &Path) -> io::Result<InputFormat> {
+        let prefix = read_prefix(File::open(path)?)?;
+        if prefix.starts_with(GZIP_MAGIC) {
+            let inner = read_prefix(GzDecoder::new(File::open(path)?))?;
+            return Ok(match InputFormat::of_uncompressed(&inner) {
+                InputFormat::Xml => InputFormat::GzipXml,
+                _ => InputFormat::GzipPbf,
+            });
+        }
+        if prefix.starts_with(BZIP2_MAGIC) {
+            return Ok(InputFormat::Bzip2);
+        }
+        Ok(InputFormat::of_uncompressed(&prefix))
+    }
+
+    /// Format of uncompressed data starting with `prefix`
+    fn of_uncompressed(prefix: &[u8]) -> InputFormat {
+        let text = prefix.strip_prefix(UTF8_BOM).unwrap_or(prefix);
+        match text.iter().find(|b| !b.is_ascii_whitespace()) {
+            Some(b'<') => InputFormat::Xml,
+            _ => InputFormat::Pbf,
+        }
+    }
+
+    /// Whether the PBF reader can read files of this format, see `open`
+    pub fn is_pbf(self) -> bool {
+        matches!(self, InputFormat::Pbf | InputFormat::GzipPbf)
+    }
+
+    /// Open a PBF file of this format, decompressing it if needed
+    pub fn open(self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        let file = BufReader::new(File::open(path)?);
+        Ok(match self {
+            InputFormat::GzipPbf => Box::new(BufReader::new(GzDecoder::new(file))),
+            _ => Box::new(file),
+        })
+    }
+}
+
+impl fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            InputFormat::Pbf => "OSM PBF",
+            InputFormat::GzipPbf => "gzip-compressed OSM PBF",
+            InputFormat::Xml => "OSM XML",
+            InputFormat::GzipXml => "gzip-compressed OSM XML",
+            InputFormat::Bzip2 => "bzip2-compressed",
+        })
+    }
+}
+
+/// Up to `SNIFF_LEN` bytes from the start of `reader`
+///
+/// Short or corrupt streams give what could be read, the PBF reader reports them.
+fn read_prefix(reader: impl Read) -> io::Result<Vec<u8>> {
+    let mut prefix = Vec::with_capacity(SNIFF_LEN);
+    match reader.take(SNIFF_LEN as u64).read_to_end(&mut prefix) {
+        Ok(_) => Ok(prefix),
+        Err(e) if matches!(e.kind(), io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof) => {
+            Ok(prefix)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn detect(contents: &[u8]) -> InputFormat {
+        let file = NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), contents).unwrap();
+        InputFormat::detect(file.path()).unwrap()
+    }
+
+    fn gzip(contents: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(contents).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_detect_input_format() {
+        // A PBF starts with the length of its first blob header
+        let pbf = b"\x00\x00\x00\x0d\x0a\x09OSMHeader";
+        assert_eq!(detect(pbf), InputFormat::Pbf);
+        assert_eq!(detect(&gzip(pbf)), InputFormat::GzipPbf);
+
+        let xml = b"<?xml version='1.0' encoding='UTF-8'?>\n<osm version=\"0.6\">";
+        assert_eq!(detect(xml), InputFormat::Xml);
+        assert_eq!(detect(b"\xef\xbb\xbf  \n<osm>"), InputFormat::Xml);
+        assert_eq!(detect(&gzip(xml)), InputFormat::GzipXml);
+        assert_eq!(detect(b"BZh91AY&SY"), InputFormat::Bzip2);
+
+        // Empty and truncated files are left to the PBF reader
+        assert_eq!(detect(b""), InputFormat::Pbf);
+        assert_eq!(detect(&gzip(pbf)[..4]), InputFormat::GzipPbf);
+
+        assert!(InputFormat::GzipPbf.is_pbf());
+        assert!(!InputFormat::GzipXml.is_pbf());
+    }
+
+    #[test]
+    fn test_open_gzip_pbf() {
+        let file = NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), gzip(b"blob bytes")).unwrap();
+        let mut contents = Vec::new();
+        InputFormat::GzipPbf.open(file.path()).unwrap().read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, b"blob bytes");
+    }
+}
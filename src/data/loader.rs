@@ -1,13 +1,28 @@
-use super::serialization::write_map_object;
-use super::spatial::TileIndex;
-use super::types::{BoundingBox, MapObject, Point};
-use crate::projection::get_tiles_for_bounding_box;
-use osmpbf::{Element, ElementReader};
-use std::fs::File;
-use std::io;
-use std::path::Path;
-
-/// Check if a way should be displayed at zoom levels < 11
+use super::index_file::{write_index, IndexSpiller};
+use super::input::InputFormat;
+use super::serialization::{align_up, write_data_header, write_map_object, DATA_HEADER_SIZE};
+use super::spatial::{TileIndex, TileKeyScheme, TileMapKind};
+use super::types::{AffineTransform, BoundingBox, MapObject, MapObjectOffset, ObjectKind, Point};
+use crate::filter::{RetainTags, TagFilter};
+use crate::geometry::{degrees_per_pixel, simplify};
+use crate::projection::{get_tiles_for_bounding_box, split_at_antimeridian};
+use crate::style::MapStyle;
+use osmpbf::{BlobDecode, BlobReader, Element, PrimitiveBlock};
+use rayon::prelude::*;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Number of ways with node references inspected to detect missing node locations
+const LOCATION_SAMPLE_WAYS: u64 = 1000;
+
+/// Lowest zoom level of ways other than major roads without a style
+/// (default of `LoadOptions::detail_zoom`)
+pub const DETAIL_ZOOM: u32 = 11;
+
+/// Check if a way should be displayed below the detail zoom
 /// Only major roads are shown at lower zoom levels
 fn is_important_way(tags: &[(String, String)]) -> bool {
     for (key, value) in tags {
@@ -23,6 +38,44 @@ fn is_important_way(tags: &[(String, String)]) -> bool {
     false
 }
 
+/// Lowest zoom level POI nodes are indexed at without a style
+const POI_MIN_ZOOM: u32 = 14;
+
+/// Check if a node is a POI worth drawing as a point
+/// Most tagged nodes are parts of ways (crossings, gates, signals)
+fn is_poi_node(tags: &[(String, String)]) -> bool {
+    tags.iter().any(|(key, value)| match key.as_str() {
+        "amenity" | "shop" | "tourism" => true,
+        "natural" => value == "peak",
+        _ => false,
+    })
+}
+
+/// Options for `load_osm_data_with_options`
+#[derive(Debug, Clone, Default)]
+pub struct LoadOptions {
+    /// Transform applied to every node location before indexing
+    pub transform: AffineTransform,
+    /// Spill the index to this file every `max_entries` tile entries,
+    /// see `load_osm_data_spilled`
+    pub spill_index: Option<(PathBuf, usize)>,
+    /// Classify ways and POI nodes by the style rules: unmatched ones are
+    /// skipped and each rule's `min_zoom` replaces the built-in zoom checks
+    pub style: Option<Arc<MapStyle>>,
+    /// Numbering of the tile keys in the built index
+    pub key_scheme: TileKeyScheme,
+    /// Only load ways and POI nodes matching this filter (`--filter`)
+    pub filter: Option<Arc<TagFilter>>,
+    /// Keep these tags of every way in `MapObject::tags` (`--retain-tags`)
+    pub retain_tags: Option<RetainTags>,
+    /// Index ways at each zoom simplified to this many pixels of tolerance,
+    /// as extra copies in the data file (`--simplify-px`)
+    pub simplify_px: Option<f64>,
+    /// Lowest zoom of ways other than major roads when there's no style,
+    /// `DETAIL_ZOOM` if `None` (`--detail-zoom`)
+    pub detail_zoom: Option<u32>,
+}
+
 /// Load OSM data from a PBF file and build spatial index
 ///
 /// # Arguments
@@ -36,89 +89,1010 @@ pub fn load_osm_data<P: AsRef<Path>>(
     osm_path: P,
     max_z: u32,
     temp_file: &mut File,
-) -> io::Result<TileIndex> {
-    let reader = ElementReader::from_path(osm_path)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+) -> Result<TileIndex, LoaderError> {
+    load_osm_data_with_options(osm_path, max_z, temp_file, &LoadOptions::default())
+}
+
+/// Load OSM data like `load_osm_data`, capping the memory used for the index
+///
+/// Whenever the tile map holds `max_entries` offsets it is spilled to a
+/// sorted run next to `index_path`; the runs are merged into the index
+/// file at `index_path` at the end. See `IndexSpiller` for the tradeoff.
+pub fn load_osm_data_spilled<P: AsRef<Path>, Q: AsRef<Path>>(
+    osm_path: P,
+    max_z: u32,
+    temp_file: &mut File,
+    index_path: Q,
+    max_entries: usize,
+) -> Result<TileIndex, LoaderError> {
+    let options = LoadOptions {
+        spill_index: Some((index_path.as_ref().to_path_buf(), max_entries)),
+        ..Default::default()
+    };
+    load_osm_data_with_options(osm_path, max_z, temp_file, &options)
+}
 
-    let mut tile_index = TileIndex::new();
-    let mut way_count = 0u64;
+/// Load OSM data like `load_osm_data` with the given `LoadOptions`
+pub fn load_osm_data_with_options<P: AsRef<Path>>(
+    osm_path: P,
+    max_z: u32,
+    temp_file: &mut File,
+    options: &LoadOptions,
+) -> Result<TileIndex, LoaderError> {
+    let mut spiller = options
+        .spill_index
+        .as_ref()
+        .map(|(index_path, max_entries)| IndexSpiller::new(index_path, *max_entries));
+    let tile_index = load_ways(osm_path.as_ref(), max_z, temp_file, options, spiller.as_mut())?;
+    let mut tile_index = match spiller {
+        Some(spiller) => {
+            log::info!("Merging {} index runs...", spiller.runs());
+            spiller.finish(tile_index).map_err(LoaderError::Index)?
+        }
+        None => tile_index,
+    };
+    tile_index.max_zoom = max_z;
+    Ok(tile_index)
+}
 
-    log::info!("Loading OSM data...");
+/// Load several OSM files into one data file and a merged tile index
+///
+/// Up to `max_concurrent_loads` files are loaded at once, each into its
+/// own part file next to `data_path`; the parts are then appended to the
+/// data file in the order of `osm_paths` with their offsets shifted, so
+/// the result doesn't depend on the concurrency. Peak memory grows with
+/// every concurrent load, hence `--max-concurrent-loads` (default 1).
+/// Ways in several files are loaded once per file.
+///
+/// With `LoadOptions::spill_index` each file spills to its own runs and
+/// the merged index is written to the index path at the end.
+pub fn load_osm_files<P: AsRef<Path> + Sync>(
+    osm_paths: &[P],
+    max_z: u32,
+    data_path: &Path,
+    options: &LoadOptions,
+    max_concurrent_loads: usize,
+) -> Result<TileIndex, LoaderError> {
+    if let [osm_path] = osm_paths {
+        let mut data_file = File::create(data_path)?;
+        let tile_index = load_osm_data_with_options(osm_path, max_z, &mut data_file, options)?;
+        data_file.flush()?;
+        return Ok(tile_index);
+    }
 
-    reader
-        .for_each(|element| {
-            if let Element::Way(way) = element {
-                // Use node_locations() to get coordinates from osmium-processed files
-                let points: Vec<Point> = way
-                    .node_locations()
-                    .map(|loc| Point::new(loc.lon(), loc.lat()))
-                    .collect();
-
-                if points.is_empty() {
-                    return;
+    let part_path = |i: usize| data_path.with_extension(format!("part{}", i));
+    let result = load_parts(osm_paths, max_z, options, max_concurrent_loads, &part_path)
+        .and_then(|parts| merge_parts(parts, data_path, options));
+    for i in 0..osm_paths.len() {
+        fs::remove_file(part_path(i)).ok();
+    }
+    result.map(|mut tile_index| {
+        tile_index.max_zoom = max_z;
+        tile_index
+    })
+}
+
+/// Load each file into its part file, at most `max_concurrent_loads` at a time
+fn load_parts<P: AsRef<Path> + Sync>(
+    osm_paths: &[P],
+    max_z: u32,
+    options: &LoadOptions,
+    max_concurrent_loads: usize,
+    part_path: &(dyn Fn(usize) -> PathBuf + Sync),
+) -> Result<Vec<(TileIndex, PathBuf)>, LoaderError> {
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<Result<TileIndex, LoaderError>>>> =
+        Mutex::new((0..osm_paths.len()).map(|_| None).collect());
+    let load_part = |i: usize| -> Result<TileIndex, LoaderError> {
+        let osm_path = osm_paths[i].as_ref();
+        let part_options = LoadOptions {
+            spill_index: options
+                .spill_index
+                .as_ref()
+                .map(|(index_path, max_entries)| (index_path.with_extension(format!("part{}.idx", i)), *max_entries)),
+            ..options.clone()
+        };
+        let mut part_file = File::create(part_path(i))?;
+        let tile_index = load_osm_data_with_options(osm_path, max_z, &mut part_file, &part_options)?;
+        part_file.flush()?;
+        if let Some((index_path, _)) = &part_options.spill_index {
+            fs::remove_file(index_path).ok();
+        }
+        log::info!(
+            "Loaded {} ({} of {}), resident memory {}",
+            osm_path.display(),
+            i + 1,
+            osm_paths.len(),
+            resident_memory().map_or("unknown".to_string(), |bytes| format!("{} MiB", bytes >> 20))
+        );
+        Ok(tile_index)
+    };
+
+    std::thread::scope(|scope| {
+        for _ in 0..max_concurrent_loads.clamp(1, osm_paths.len()) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                if i >= osm_paths.len() {
+                    break;
                 }
+                let result = load_part(i);
+                results.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
 
-                // Calculate bounding box
-                let bounding_box = match BoundingBox::from_points(&points) {
-                    Some(bbox) => bbox,
-                    None => return,
-                };
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .enumerate()
+        .map(|(i, result)| Ok((result.expect("every part is loaded")?, part_path(i))))
+        .collect()
+}
 
-                // Create map object
-                let map_object = MapObject::new(bounding_box, points);
+/// Append the part files to the data file at `data_path` and merge their indexes
+fn merge_parts(parts: Vec<(TileIndex, PathBuf)>, data_path: &Path, options: &LoadOptions) -> Result<TileIndex, LoaderError> {
+    let mut data_file = File::create(data_path)?;
+    write_data_header(&mut data_file)?;
+    let mut tile_index = TileIndex::with_key_scheme(TileMapKind::default(), options.key_scheme);
+    tile_index.retained_tags = options.retain_tags.clone();
+    for (part_index, part_path) in parts {
+        // Parts without objects don't even have a header
+        let mut part_file = File::open(&part_path)?;
+        if part_file.metadata()?.len() <= DATA_HEADER_SIZE as u64 {
+            continue;
+        }
+        // Keep objects aligned, see `serialization::ALIGNMENT`; each part's
+        // own header is dropped, which shifts its offsets back by its size
+        let end = data_file.seek(SeekFrom::End(0))?;
+        let start = align_up(end);
+        data_file.write_all(&vec![0u8; (start - end) as usize])?;
+        part_file.seek(SeekFrom::Start(DATA_HEADER_SIZE as u64))?;
+        io::copy(&mut part_file, &mut data_file)?;
+        tile_index.append(part_index, start - DATA_HEADER_SIZE as u64);
+    }
+    data_file.flush()?;
+    tile_index.finalize();
 
-                // Update max points
-                tile_index.update_max_points(map_object.points.len());
+    if let Some((index_path, _)) = &options.spill_index {
+        write_index(index_path, &tile_index).map_err(LoaderError::Index)?;
+    }
+    log::info!("Merged index: {} tiles, {} ways", tile_index.len(), tile_index.way_ids.len());
+    Ok(tile_index)
+}
 
-                // Write to temp file
-                let offset = match write_map_object(temp_file, &map_object) {
-                    Ok(offset) => offset,
-                    Err(e) => {
-                        log::error!("Failed to write map object: {}", e);
-                        return;
-                    }
-                };
+/// Resident set size of this process, if available (Linux only)
+fn resident_memory() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let kb = status.lines().find_map(|line| line.strip_prefix("VmRSS:"))?;
+    kb.trim().trim_end_matches("kB").trim().parse::<u64>().ok().map(|kb| kb * 1024)
+}
 
-                // Get tags for filtering
-                let tags: Vec<(String, String)> = way
-                    .tags()
-                    .map(|(k, v)| (k.to_string(), v.to_string()))
-                    .collect();
-                let is_important = is_important_way(&tags);
+/// Load the ways and POI nodes of `osm_path`, appending them to `temp_file`
+///
+/// Blocks are decoded and indexed in parallel, a batch of
+/// `BLOCKS_PER_THREAD` per thread at a time, each into a `BlockFragment`.
+/// The fragments are appended in file order, so the data file and index
+/// are the same as when loading block by block. With a spiller, the index
+/// may exceed its limit by the entries of one batch before spilling.
+fn load_ways(
+    osm_path: &Path,
+    max_z: u32,
+    temp_file: &mut File,
+    options: &LoadOptions,
+    mut spiller: Option<&mut IndexSpiller>,
+) -> Result<TileIndex, LoaderError> {
+    let open_error = |e: io::Error| match e.kind() {
+        io::ErrorKind::NotFound => LoaderError::FileNotFound(osm_path.to_path_buf()),
+        _ => LoaderError::Read(e.into()),
+    };
+    let format = InputFormat::detect(osm_path).map_err(open_error)?;
+    if !format.is_pbf() {
+        return Err(LoaderError::UnsupportedFormat { path: osm_path.to_path_buf(), format });
+    }
+    if format == InputFormat::GzipPbf {
+        log::info!("Decompressing {} while loading", osm_path.display());
+    }
+    let mut blobs = BlobReader::new(format.open(osm_path).map_err(open_error)?);
 
-                // Get all tiles that overlap with this way's bounding box
-                let tiles = get_tiles_for_bounding_box(&bounding_box, 0, max_z);
+    let mut tile_index = TileIndex::with_key_scheme(TileMapKind::default(), options.key_scheme);
+    tile_index.retained_tags = options.retain_tags.clone();
+    let mut counts = BlockCounts::default();
+    // Whether one of the first ways with node references had locations,
+    // to detect files without embedded node locations
+    let mut found_locations = false;
 
-                for tile in tiles {
-                    // Skip non-important ways at zoom < 11
-                    if !is_important && tile.z < 11 {
-                        continue;
-                    }
+    log::info!("Loading OSM data...");
 
-                    tile_index.insert(tile, offset);
-                }
+    let batch_size = rayon::current_num_threads() * BLOCKS_PER_THREAD;
+    loop {
+        let batch = blobs
+            .by_ref()
+            .take(batch_size)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| classify_read_error(osm_path, e))?;
+        if batch.is_empty() {
+            break;
+        }
+        let fragments: Vec<Result<BlockFragment, LoaderError>> = batch
+            .into_par_iter()
+            .map(|blob| match blob.decode().map_err(|e| classify_read_error(osm_path, e))? {
+                BlobDecode::OsmData(block) => load_block(&block, max_z, options),
+                BlobDecode::OsmHeader(_) | BlobDecode::Unknown(_) => Ok(BlockFragment::new(options)),
+            })
+            .collect();
 
-                way_count += 1;
-                if way_count % 100_000 == 0 {
-                    log::info!("Processed {} ways...", way_count);
-                }
+        for fragment in fragments {
+            let fragment = fragment?;
+            if let Some(first) = fragment.first_way_with_locations {
+                found_locations |= counts.ways_with_refs + first < LOCATION_SAMPLE_WAYS;
+            }
+            if (counts.ways + fragment.counts.ways) / 100_000 > counts.ways / 100_000 {
+                log::info!("Processed {} ways...", counts.ways + fragment.counts.ways);
+            }
+            counts.add(&fragment.counts);
+            // Blocks without objects don't even have a header
+            let data = fragment.data.into_inner();
+            if data.len() <= DATA_HEADER_SIZE {
+                continue;
             }
-        })
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
 
+            // Same layout as `merge_parts`: aligned, without the fragment's header
+            if temp_file.stream_position()? == 0 {
+                write_data_header(temp_file)?;
+            }
+            let end = temp_file.stream_position()?;
+            let start = align_up(end);
+            temp_file.write_all(&vec![0u8; (start - end) as usize])?;
+            temp_file.write_all(&data[DATA_HEADER_SIZE..])?;
+            tile_index.append(fragment.tile_index, start - DATA_HEADER_SIZE as u64);
+
+            if let Some(spiller) = spiller.as_deref_mut() {
+                spiller
+                    .add_entries(&mut tile_index.tiles, fragment.entries)
+                    .map_err(LoaderError::Index)?;
+            }
+        }
+    }
+
+    if counts.filtered > 0 {
+        log::info!("Skipped {} ways and POI nodes not matching the filter", counts.filtered);
+    }
+    if counts.unstyled > 0 {
+        log::info!("Skipped {} ways and POI nodes matching no style rule", counts.unstyled);
+    }
+
+    if counts.ways_with_refs > 0 && !found_locations {
+        return Err(LoaderError::MissingNodeLocations(osm_path.to_path_buf()));
+    }
+
+    let index_bytes = tile_index.heap_bytes();
+    tile_index.finalize();
     log::info!(
-        "Loaded {} ways, max points: {}, tiles: {}",
-        way_count,
+        "Loaded {} ways and {} POI nodes, max points: {}, tiles: {}, index {} MiB ({} MiB before shrinking)",
+        counts.ways,
+        counts.pois,
         tile_index.max_points,
-        tile_index.len()
+        tile_index.len(),
+        tile_index.heap_bytes() >> 20,
+        index_bytes >> 20
     );
 
     Ok(tile_index)
 }
 
+/// PBF blocks decoded at once per thread of the rayon pool
+const BLOCKS_PER_THREAD: usize = 2;
+
+/// Element counts of a block, see `BlockFragment`
+#[derive(Debug, Default)]
+struct BlockCounts {
+    ways: u64,
+    pois: u64,
+    unstyled: u64,
+    filtered: u64,
+    ways_with_refs: u64,
+}
+
+impl BlockCounts {
+    fn add(&mut self, other: &BlockCounts) {
+        self.ways += other.ways;
+        self.pois += other.pois;
+        self.unstyled += other.unstyled;
+        self.filtered += other.filtered;
+        self.ways_with_refs += other.ways_with_refs;
+    }
+}
+
+/// Map objects and index of one PBF block, loaded on its own
+///
+/// `data` starts with a data header unless empty, so offsets in `tile_index` are those
+/// of a data file holding only this block.
+struct BlockFragment {
+    data: Cursor<Vec<u8>>,
+    tile_index: TileIndex,
+    /// Tile entries inserted, for the `IndexSpiller`
+    entries: usize,
+    counts: BlockCounts,
+    /// Ways with node references before the first one with locations
+    first_way_with_locations: Option<u64>,
+}
+
+impl BlockFragment {
+    fn new(options: &LoadOptions) -> Self {
+        BlockFragment {
+            data: Cursor::new(Vec::new()),
+            tile_index: TileIndex::with_key_scheme(TileMapKind::default(), options.key_scheme),
+            entries: 0,
+            counts: BlockCounts::default(),
+            first_way_with_locations: None,
+        }
+    }
+}
+
+/// Load the ways and POI nodes of one block
+fn load_block(block: &PrimitiveBlock, max_z: u32, options: &LoadOptions) -> Result<BlockFragment, LoaderError> {
+    let mut fragment = BlockFragment::new(options);
+    for element in block.elements() {
+        load_element(&mut fragment, element, max_z, options)?;
+    }
+    Ok(fragment)
+}
+
+fn load_element(fragment: &mut BlockFragment, element: Element, max_z: u32, options: &LoadOptions) -> io::Result<()> {
+    let transform = options.transform;
+    let tile_index = &mut fragment.tile_index;
+    let temp_file = &mut fragment.data;
+
+    let owned_tags = |tags: &mut dyn Iterator<Item = (&str, &str)>| -> Vec<(String, String)> {
+        tags.map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    };
+    let (points, tags, way_id) = match element {
+        Element::Way(way) => {
+            // Use node_locations() to get coordinates from osmium-processed files
+            let points: Vec<Point> = way
+                .node_locations()
+                .map(|loc| transform.apply(Point::new(loc.lon(), loc.lat())))
+                .collect();
+
+            if !way.raw_refs().is_empty() {
+                if !points.is_empty() && fragment.first_way_with_locations.is_none() {
+                    fragment.first_way_with_locations = Some(fragment.counts.ways_with_refs);
+                }
+                fragment.counts.ways_with_refs += 1;
+            }
+
+            if points.is_empty() {
+                return Ok(());
+            }
+            (points, owned_tags(&mut way.tags()), Some(way.id()))
+        }
+        Element::Node(node) => {
+            let tags = owned_tags(&mut node.tags());
+            if !is_poi_node(&tags) {
+                return Ok(());
+            }
+            (vec![transform.apply(Point::new(node.lon(), node.lat()))], tags, None)
+        }
+        Element::DenseNode(node) => {
+            let tags = owned_tags(&mut node.tags());
+            if !is_poi_node(&tags) {
+                return Ok(());
+            }
+            (vec![transform.apply(Point::new(node.lon(), node.lat()))], tags, None)
+        }
+        Element::Relation(_) => return Ok(()),
+    };
+    let kind = match way_id {
+        Some(_) => ObjectKind::of_way(&points),
+        None => ObjectKind::Point,
+    };
+
+    if options.filter.as_ref().is_some_and(|filter| !filter.matches(&tags)) {
+        fragment.counts.filtered += 1;
+        return Ok(());
+    }
+    let (class, min_zoom) = match options.style.as_deref() {
+        Some(style) => match style.classify(&tags) {
+            Some(rule) => (Some(rule.class), rule.min_zoom),
+            None => {
+                fragment.counts.unstyled += 1;
+                return Ok(());
+            }
+        },
+        None if kind == ObjectKind::Point => (None, POI_MIN_ZOOM),
+        // Skip non-important ways below the detail zoom
+        None if is_important_way(&tags) => (None, 0),
+        None => (None, options.detail_zoom.unwrap_or(DETAIL_ZOOM)),
+    };
+
+    // Ways crossing the antimeridian are indexed as one object per side
+    let parts = split_at_antimeridian(&points).unwrap_or_else(|| vec![points]);
+    for points in parts {
+        // Calculate bounding box
+        let bounding_box = match BoundingBox::from_points(&points) {
+            Some(bbox) => bbox,
+            None => return Ok(()),
+        };
+
+        // Create map object
+        let mut map_object = MapObject::new(bounding_box, points);
+        map_object.kind = kind;
+        if let Some(retain) = &options.retain_tags {
+            map_object.tags = retain.select(&tags);
+        }
+
+        // Update max points and data bounds
+        tile_index.update_max_points(map_object.points.len());
+        tile_index.extend_bounds(&bounding_box);
+
+        // Write to temp file
+        let offset = write_map_object(temp_file, &map_object)?;
+
+        // Node ids are a separate id space, so diffs only cover ways
+        if let Some(way_id) = way_id {
+            tile_index.record_way_id(offset, way_id);
+        }
+        if let Some(class) = class {
+            tile_index.record_class(offset, class);
+        }
+
+        // Low zooms get a simplified copy, until simplifying no longer drops points
+        let mut full_from_zoom = min_zoom;
+        if let Some(tolerance_px) = options.simplify_px {
+            let lat = bounding_box.center().lat;
+            let mut copy: Option<(Vec<Point>, MapObjectOffset)> = None;
+            for z in min_zoom..=max_z {
+                let simplified = simplify(&map_object.points, tolerance_px * degrees_per_pixel(z, lat));
+                if simplified.len() == map_object.points.len() {
+                    break;
+                }
+                full_from_zoom = z + 1;
+                let copy_offset = match &copy {
+                    Some((points, offset)) if *points == simplified => *offset,
+                    _ => {
+                        let simplified_object = MapObject {
+                            points: simplified.clone(),
+                            ..map_object.clone()
+                        };
+                        let offset = write_map_object(temp_file, &simplified_object)?;
+                        if let Some(way_id) = way_id {
+                            tile_index.record_way_id(offset, way_id);
+                        }
+                        if let Some(class) = class {
+                            tile_index.record_class(offset, class);
+                        }
+                        copy = Some((simplified, offset));
+                        offset
+                    }
+                };
+                for tile in get_tiles_for_bounding_box(&bounding_box, z, z) {
+                    tile_index.insert(tile, copy_offset);
+                    fragment.entries += 1;
+                }
+            }
+        }
+
+        // Get all tiles that overlap with this object's bounding box
+        let tiles = get_tiles_for_bounding_box(&bounding_box, full_from_zoom, max_z);
+
+        for tile in tiles {
+            tile_index.insert(tile, offset);
+            fragment.entries += 1;
+        }
+    }
+
+    if way_id.is_none() {
+        fragment.counts.pois += 1;
+    } else {
+        fragment.counts.ways += 1;
+    }
+    Ok(())
+}
+
+/// Distinguish "this isn't a PBF at all" from I/O failures while reading
+fn classify_read_error(osm_path: &Path, error: osmpbf::Error) -> LoaderError {
+    match error.kind() {
+        osmpbf::ErrorKind::Io(io_err) if io_err.kind() != io::ErrorKind::UnexpectedEof => {
+            LoaderError::Read(error)
+        }
+        _ => LoaderError::NotPbf {
+            path: osm_path.to_path_buf(),
+            source: error,
+        },
+    }
+}
+
+/// OSM loading errors
+#[derive(Debug, thiserror::Error)]
+pub enum LoaderError {
+    #[error("OSM file not found: {}", .0.display())]
+    FileNotFound(PathBuf),
+
+    #[error("{} is not a valid OSM PBF file: {source}", path.display())]
+    NotPbf {
+        path: PathBuf,
+        source: osmpbf::Error,
+    },
+
+    #[error(
+        "{} is {format}, not OSM PBF; convert it with \
+         `osmium add-locations-to-ways {} -o prepared.osm.pbf`",
+        path.display(),
+        path.display()
+    )]
+    UnsupportedFormat {
+        path: PathBuf,
+        format: InputFormat,
+    },
+
+    #[error(
+        "{} has no node locations on its ways; prepare it with \
+         `osmium add-locations-to-ways input.osm.pbf -o prepared.osm.pbf`",
+        .0.display()
+    )]
+    MissingNodeLocations(PathBuf),
+
+    #[error("Failed to read OSM data: {0}")]
+    Read(osmpbf::Error),
+
+    #[error("Failed to write map object data: {0}")]
+    Write(#[from] io::Error),
+
+    #[error("Failed to write tile index: {0}")]
+    Index(io::Error),
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::data::mmap::MappedData;
+    use crate::data::test_pbf::PbfBuilder;
+    use crate::data::types::Tile;
+    use crate::style::ClassId;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_load_osm_data() -> Result<(), LoaderError> {
+        let pbf = NamedTempFile::new()?;
+        PbfBuilder::new()
+            .add_way(1, &[(10.0, 53.0), (10.01, 53.01)], &[("highway", "motorway")])
+            .add_way(2, &[(10.0, 53.0), (10.01, 53.0)], &[("highway", "residential")])
+            .write_to(pbf.path())?;
+
+        let mut data_file = NamedTempFile::new()?;
+        let tile_index = load_osm_data(pbf.path(), 12, data_file.as_file_mut())?;
+        assert_eq!(tile_index.max_points, 2);
+        let way_ids: Vec<i64> = tile_index.way_ids.iter().map(|&(_, id)| id).collect();
+        assert_eq!(way_ids, vec![1, 2]);
+
+        // Only the motorway is indexed below zoom 11
+        assert_eq!(tile_index.get(&Tile::new(0, 0, 0)).unwrap().len(), 1);
+        let (x, y) = crate::projection::deg2num(53.0, 10.0, 12);
+        assert_eq!(tile_index.get(&Tile::new(x, y, 12)).unwrap().len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_with_detail_zoom() -> Result<(), LoaderError> {
+        let pbf = NamedTempFile::new()?;
+        PbfBuilder::new()
+            .add_way(1, &[(10.0, 53.0), (10.01, 53.01)], &[("highway", "motorway")])
+            .add_way(2, &[(10.0, 53.0), (10.01, 53.0)], &[("highway", "residential")])
+            .write_to(pbf.path())?;
+
+        let options = LoadOptions {
+            detail_zoom: Some(8),
+            ..Default::default()
+        };
+        let mut data_file = NamedTempFile::new()?;
+        let tile_index = load_osm_data_with_options(pbf.path(), 12, data_file.as_file_mut(), &options)?;
+        let count = |z: u32| {
+            let (x, y) = crate::projection::deg2num(53.0, 10.0, z);
+            tile_index.get(&Tile::new(x, y, z)).map_or(0, |offsets| offsets.len())
+        };
+        assert_eq!((count(7), count(8), count(12)), (1, 2, 2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_blocks_in_parallel() -> Result<(), LoaderError> {
+        let mut builder = PbfBuilder::new();
+        for id in 0..40 {
+            let lon = 10.0 + id as f64 * 0.03;
+            let highway = if id % 4 == 0 { "primary" } else { "residential" };
+            builder.add_way(id, &[(lon, 53.0), (lon + 0.02, 53.02), (lon, 53.04)], &[("highway", highway)]);
+        }
+        builder.add_node(100, 10.5, 53.01, &[("amenity", "cafe")]);
+        let single = NamedTempFile::new()?;
+        builder.write_to(single.path())?;
+        let blocks = NamedTempFile::new()?;
+        builder.block_size(3).write_to(blocks.path())?;
+
+        let mut expected_file = NamedTempFile::new()?;
+        let expected = load_osm_data(single.path(), 14, expected_file.as_file_mut())?;
+        let mut data_file = NamedTempFile::new()?;
+        let tile_index = load_osm_data(blocks.path(), 14, data_file.as_file_mut())?;
+
+        // Blocks are appended in file order, so nothing depends on the threads
+        let sorted_tiles = |index: &TileIndex| {
+            let mut tiles: Vec<_> = index.tiles.iter().map(|(&key, offsets)| (key, offsets.clone())).collect();
+            tiles.sort_unstable();
+            tiles
+        };
+        assert_eq!(sorted_tiles(&tile_index), sorted_tiles(&expected));
+        assert_eq!(tile_index.way_ids, expected.way_ids);
+        assert_eq!(tile_index.max_points, expected.max_points);
+        assert_eq!(tile_index.bounds, expected.bounds);
+        assert_eq!(fs::read(data_file.path())?, fs::read(expected_file.path())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_osm_data_spilled() -> Result<(), LoaderError> {
+        let pbf = NamedTempFile::new()?;
+        let mut builder = PbfBuilder::new();
+        for id in 0..20 {
+            let lon = 10.0 + id as f64 * 0.05;
+            let highway = if id % 3 == 0 { "primary" } else { "residential" };
+            builder.add_way(id, &[(lon, 53.0), (lon + 0.02, 53.02)], &[("highway", highway)]);
+        }
+        builder.write_to(pbf.path())?;
+
+        let mut data_file = NamedTempFile::new()?;
+        let expected = load_osm_data(pbf.path(), 12, data_file.as_file_mut())?;
+
+        // Spill every few entries, so tiles are split across many runs
+        let dir = tempfile::TempDir::new()?;
+        let index_path = dir.path().join("index.bin");
+        let mut data_file = NamedTempFile::new()?;
+        let spilled = load_osm_data_spilled(pbf.path(), 12, data_file.as_file_mut(), &index_path, 5)?;
+
+        let sorted_tiles = |index: &TileIndex| {
+            let mut tiles: Vec<_> = index.tiles.iter().map(|(&key, offsets)| (key, offsets.clone())).collect();
+            tiles.sort_unstable();
+            tiles
+        };
+        assert_eq!(sorted_tiles(&spilled), sorted_tiles(&expected));
+        assert_eq!(spilled.max_points, expected.max_points);
+        assert_eq!(spilled.way_ids, expected.way_ids);
+        assert_eq!(spilled.bounds, expected.bounds);
+
+        // The persisted index matches too
+        let persisted = crate::data::index_file::read_index(&index_path)?;
+        assert_eq!(sorted_tiles(&persisted), sorted_tiles(&expected));
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_with_transform() -> Result<(), LoaderError> {
+        let pbf = NamedTempFile::new()?;
+        // Local survey coordinates in km around an origin
+        PbfBuilder::new()
+            .add_way(1, &[(0.0, 0.0), (1.0, 1.0)], &[("highway", "motorway")])
+            .write_to(pbf.path())?;
+
+        // Shift onto Hamburg (10E, 53.5N) at ~0.01 degrees per km
+        let options = LoadOptions {
+            transform: AffineTransform {
+                scale_x: 0.015,
+                scale_y: 0.009,
+                offset_x: 10.0,
+                offset_y: 53.5,
+            },
+            ..Default::default()
+        };
+        let mut data_file = NamedTempFile::new()?;
+        let tile_index = load_osm_data_with_options(pbf.path(), 12, data_file.as_file_mut(), &options)?;
+
+        let bounds = tile_index.bounds.unwrap();
+        assert!((bounds.min.lon - 10.0).abs() < 1e-6 && (bounds.min.lat - 53.5).abs() < 1e-6);
+        assert!((bounds.max.lon - 10.015).abs() < 1e-6 && (bounds.max.lat - 53.509).abs() < 1e-6);
+
+        // Indexed at the transformed location, not at null island
+        let (x, y) = crate::projection::deg2num(53.505, 10.007, 12);
+        assert!(tile_index.get(&Tile::new(x, y, 12)).is_some());
+        let (x, y) = crate::projection::deg2num(0.5, 0.5, 12);
+        assert!(tile_index.get(&Tile::new(x, y, 12)).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_with_style() -> Result<(), LoaderError> {
+        let pbf = NamedTempFile::new()?;
+        PbfBuilder::new()
+            .add_way(1, &[(10.0, 53.0), (10.01, 53.01)], &[("highway", "motorway")])
+            .add_way(2, &[(10.0, 53.0), (10.01, 53.0)], &[("waterway", "river")])
+            .add_way(3, &[(10.0, 53.0), (10.0, 53.01)], &[("building", "yes")])
+            .write_to(pbf.path())?;
+
+        let style: MapStyle = r##"
+            [[rule]]
+            class = "road"
+            match = "highway"
+            min_zoom = 2
+            [[rule]]
+            class = "water"
+            match = ["waterway=river", "natural=water"]
+            min_zoom = 9
+            [style.road]
+            color = "#000"
+            [style.water]
+            color = "#00f"
+        "##
+        .parse()
+        .unwrap();
+        let (road, water) = (style.class_id("road").unwrap(), style.class_id("water").unwrap());
+        let options = LoadOptions {
+            style: Some(Arc::new(style)),
+            ..Default::default()
+        };
+        let mut data_file = NamedTempFile::new()?;
+        let tile_index = load_osm_data_with_options(pbf.path(), 12, data_file.as_file_mut(), &options)?;
+
+        // The building matches no rule and isn't loaded
+        let way_ids: Vec<i64> = tile_index.way_ids.iter().map(|&(_, id)| id).collect();
+        assert_eq!(way_ids, vec![1, 2]);
+        let classes: Vec<ClassId> = tile_index.classes.iter().map(|&(_, class)| class).collect();
+        assert_eq!(classes, vec![road, water]);
+
+        // Each class starts at its rule's min_zoom
+        let count = |z: u32| {
+            let (x, y) = crate::projection::deg2num(53.0, 10.0, z);
+            tile_index.get(&Tile::new(x, y, z)).map_or(0, |offsets| offsets.len())
+        };
+        assert_eq!((count(1), count(2), count(8), count(9), count(12)), (0, 1, 1, 2, 2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_several_files_with_bounded_concurrency() -> Result<(), LoaderError> {
+        let dir = tempfile::TempDir::new()?;
+        let mut pbfs = Vec::new();
+        for file in 0..4i64 {
+            let path = dir.path().join(format!("extract{}.osm.pbf", file));
+            let mut builder = PbfBuilder::new();
+            // Odd point counts, so parts end off the alignment
+            for way in 0..=file {
+                let lon = 10.0 + file as f64 * 0.1 + way as f64 * 0.01;
+                builder.add_way(file * 10 + way, &[(lon, 53.0), (lon, 53.01), (lon + 0.005, 53.02)], &[("highway", "primary")]);
+            }
+            builder.write_to(&path)?;
+            pbfs.push(path);
+        }
+
+        let load = |max_concurrent_loads: usize| -> Result<(TileIndex, MappedData), LoaderError> {
+            let data_path = dir.path().join(format!("data{}.bin", max_concurrent_loads));
+            let index = load_osm_files(&pbfs, 12, &data_path, &LoadOptions::default(), max_concurrent_loads)?;
+            Ok((index, MappedData::new(&data_path)?))
+        };
+        let sorted_tiles = |index: &TileIndex| {
+            let mut tiles: Vec<_> = index.tiles.iter().map(|(&key, offsets)| (key, offsets.clone())).collect();
+            tiles.sort_unstable();
+            tiles
+        };
+
+        let (sequential, sequential_data) = load(1)?;
+        let way_ids: Vec<i64> = sequential.way_ids.iter().map(|&(_, id)| id).collect();
+        assert_eq!(way_ids, vec![0, 10, 11, 20, 21, 22, 30, 31, 32, 33]);
+        for &(offset, id) in &sequential.way_ids {
+            let lon = 10.0 + (id / 10) as f64 * 0.1 + (id % 10) as f64 * 0.01;
+            let first = sequential_data.read_map_object(offset).points()[0];
+            assert!((first.lon - lon).abs() < 1e-6 && (first.lat - 53.0).abs() < 1e-6, "way {} at {:?}", id, first);
+        }
+        assert_eq!(sequential.max_points, 3);
+
+        let (parallel, parallel_data) = load(3)?;
+        assert_eq!(sorted_tiles(&parallel), sorted_tiles(&sequential));
+        assert_eq!(parallel.way_ids, sequential.way_ids);
+        assert_eq!(parallel.bounds, sequential.bounds);
+        assert_eq!(parallel_data.len(), sequential_data.len());
+
+        // Part files are cleaned up
+        let leftovers = std::fs::read_dir(dir.path())?
+            .filter(|entry| entry.as_ref().unwrap().path().to_string_lossy().contains(".part"))
+            .count();
+        assert_eq!(leftovers, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_with_simplification() -> Result<(), LoaderError> {
+        let pbf = NamedTempFile::new()?;
+        // A coastline zigzagging by ~10m, invisible below zoom ~13
+        let coast: Vec<(f64, f64)> = (0..100)
+            .map(|i| (10.0 + i as f64 * 0.001, 53.0 + if i % 2 == 0 { 0.0 } else { 0.0001 }))
+            .collect();
+        PbfBuilder::new().add_way(1, &coast, &[("highway", "primary")]).write_to(pbf.path())?;
+
+        let mut data_file = NamedTempFile::new()?;
+        let options = LoadOptions {
+            simplify_px: Some(1.0),
+            ..Default::default()
+        };
+        let tile_index = load_osm_data_with_options(pbf.path(), 16, data_file.as_file_mut(), &options)?;
+        let mmap = MappedData::new(data_file.path())?;
+        let points_at = |z: u32| {
+            let (x, y) = crate::projection::deg2num(53.0, 10.0, z);
+            let offsets = tile_index.get(&Tile::new(x, y, z)).unwrap();
+            assert_eq!(offsets.len(), 1);
+            mmap.read_map_object(offsets[0]).points.len()
+        };
+
+        // Low zooms share one simplified copy, high zooms read the full way
+        assert_eq!(points_at(0), 2);
+        assert_eq!(points_at(10), 2);
+        assert_eq!(points_at(16), 100);
+        assert!(tile_index.way_ids.iter().all(|&(_, id)| id == 1));
+        assert!(tile_index.way_ids.len() >= 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_way_across_antimeridian() -> Result<(), LoaderError> {
+        let pbf = NamedTempFile::new()?;
+        PbfBuilder::new()
+            .add_way(1, &[(179.0, -17.0), (-179.0, -17.0)], &[("highway", "motorway")])
+            .write_to(pbf.path())?;
+
+        let mut data_file = NamedTempFile::new()?;
+        let tile_index = load_osm_data(pbf.path(), 8, data_file.as_file_mut())?;
+        let mmap = MappedData::new(data_file.path())?;
+
+        // One object per side, both for the way
+        assert_eq!(tile_index.way_ids.iter().map(|&(_, id)| id).collect::<Vec<_>>(), vec![1, 1]);
+        for &(offset, _) in &tile_index.way_ids {
+            assert!(!mmap.read_map_object(offset).bbox.crosses_antimeridian());
+        }
+
+        // Only the tiles at both edges of the map, not the whole row in between
+        let (_, y) = crate::projection::deg2num(-17.0, 179.0, 8);
+        assert_eq!(tile_index.get(&Tile::new(255, y, 8)).unwrap().len(), 1);
+        assert_eq!(tile_index.get(&Tile::new(0, y, 8)).unwrap().len(), 1);
+        assert!(tile_index.get(&Tile::new(128, y, 8)).is_none());
+        assert_eq!(tile_index.get(&Tile::new(0, 0, 0)).unwrap().len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_poi_nodes() -> Result<(), LoaderError> {
+        let pbf = NamedTempFile::new()?;
+        let square = [(10.0, 53.0), (10.01, 53.0), (10.01, 53.01), (10.0, 53.0)];
+        PbfBuilder::new()
+            .add_node(1, 10.005, 53.005, &[("shop", "bakery"), ("name", "Kruse")])
+            .add_node(2, 10.006, 53.006, &[("natural", "peak")])
+            .add_node(3, 10.007, 53.007, &[("highway", "crossing")])
+            .add_node(4, 10.008, 53.008, &[])
+            .add_way(5, &square, &[("highway", "primary")])
+            .add_way(6, &[(10.0, 53.0), (10.01, 53.01)], &[("highway", "primary")])
+            .write_to(pbf.path())?;
+
+        let mut data_file = NamedTempFile::new()?;
+        let tile_index = load_osm_data(pbf.path(), 15, data_file.as_file_mut())?;
+        let mmap = MappedData::new(data_file.path())?;
+
+        // Only the ways have way ids
+        let kinds: Vec<ObjectKind> = tile_index.way_ids.iter().map(|&(offset, _)| mmap.read_map_object(offset).kind).collect();
+        assert_eq!(kinds, vec![ObjectKind::Polygon, ObjectKind::Line]);
+
+        // The shop and the peak, from zoom 14
+        let (x, y) = crate::projection::deg2num(53.005, 10.005, 13);
+        assert_eq!(tile_index.get(&Tile::new(x, y, 13)).unwrap().len(), 2);
+        let (x, y) = crate::projection::deg2num(53.005, 10.005, 14);
+        let offsets = tile_index.get(&Tile::new(x, y, 14)).unwrap();
+        let points: Vec<_> = offsets
+            .iter()
+            .map(|&offset| mmap.read_map_object(offset))
+            .filter(|view| view.kind == ObjectKind::Point)
+            .map(|view| view.points[0])
+            .collect();
+        assert_eq!(points.len(), 2);
+        assert!((points[0].lon - 10.005).abs() < 1e-6 && (points[0].lat - 53.005).abs() < 1e-6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_with_filter_and_retained_tags() -> Result<(), LoaderError> {
+        let pbf = NamedTempFile::new()?;
+        PbfBuilder::new()
+            .add_way(1, &[(10.0, 53.0), (10.01, 53.01)], &[("highway", "primary"), ("surface", "asphalt")])
+            .add_way(2, &[(10.0, 53.0), (10.01, 53.0)], &[("waterway", "river"), ("name", "Elbe")])
+            .add_way(3, &[(10.0, 53.0), (10.0, 53.01)], &[("waterway", "ditch")])
+            .write_to(pbf.path())?;
+
+        let options = LoadOptions {
+            filter: Some(Arc::new("highway=primary OR (waterway AND name)".parse().unwrap())),
+            retain_tags: Some("highway,name".parse().unwrap()),
+            ..Default::default()
+        };
+        let mut data_file = NamedTempFile::new()?;
+        let tile_index = load_osm_data_with_options(pbf.path(), 12, data_file.as_file_mut(), &options)?;
+
+        let way_ids: Vec<i64> = tile_index.way_ids.iter().map(|&(_, id)| id).collect();
+        assert_eq!(way_ids, vec![1, 2]);
+        let mmap = MappedData::new(data_file.path())?;
+        let tags = |i: usize| mmap.read_map_object(tile_index.way_ids[i].0).tags().collect::<Vec<_>>();
+        assert_eq!(tags(0), vec![("highway", "primary")]);
+        assert_eq!(tags(1), vec![("name", "Elbe")]);
+        assert_eq!(mmap.read_map_object(tile_index.way_ids[1].0).tag("name"), Some("Elbe"));
+        assert_eq!(tile_index.retained_tags, options.retain_tags);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_missing_file() {
+        let mut data_file = NamedTempFile::new().unwrap();
+        let result = load_osm_data("/nonexistent/input.osm.pbf", 15, data_file.as_file_mut()).err();
+        assert!(matches!(result, Some(LoaderError::FileNotFound(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn test_load_not_pbf() {
+        let input = NamedTempFile::new().unwrap();
+        std::fs::write(input.path(), "not a pbf file").unwrap();
+
+        let mut data_file = NamedTempFile::new().unwrap();
+        let result = load_osm_data(input.path(), 15, data_file.as_file_mut()).err();
+        assert!(matches!(result, Some(LoaderError::NotPbf { .. })), "{:?}", result);
+
+        // Recognized formats tell how to convert them
+        std::fs::write(input.path(), "<?xml version='1.0'?><osm></osm>").unwrap();
+        let result = load_osm_data(input.path(), 15, data_file.as_file_mut()).err();
+        assert!(
+            matches!(result, Some(LoaderError::UnsupportedFormat { format: InputFormat::Xml, .. })),
+            "{:?}",
+            result
+        );
+        assert!(result.unwrap().to_string().contains("is OSM XML, not OSM PBF; convert it with `osmium"));
+        std::fs::write(input.path(), "BZh91AY&SY").unwrap();
+        let result = load_osm_data(input.path(), 15, data_file.as_file_mut()).err();
+        assert!(matches!(result, Some(LoaderError::UnsupportedFormat { format: InputFormat::Bzip2, .. })));
+    }
+
+    #[test]
+    fn test_load_gzip_pbf() -> Result<(), LoaderError> {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+
+        let pbf = NamedTempFile::new()?;
+        PbfBuilder::new()
+            .add_way(1, &[(10.0, 53.0), (10.01, 53.01)], &[("highway", "motorway")])
+            .add_way(2, &[(10.02, 53.02), (10.03, 53.03), (10.04, 53.02)], &[("building", "yes")])
+            .write_to(pbf.path())?;
+        let gzipped = NamedTempFile::new()?;
+        let mut encoder = GzEncoder::new(File::create(gzipped.path())?, Compression::default());
+        encoder.write_all(&fs::read(pbf.path())?)?;
+        encoder.finish()?;
+
+        let mut plain_data = NamedTempFile::new()?;
+        let plain = load_osm_data(pbf.path(), 15, plain_data.as_file_mut())?;
+        let mut gzip_data = NamedTempFile::new()?;
+        let gzip = load_osm_data(gzipped.path(), 15, gzip_data.as_file_mut())?;
+
+        let sorted_tiles = |index: &TileIndex| {
+            let mut tiles: Vec<_> = index.tiles.iter().map(|(&key, offsets)| (key, offsets.clone())).collect();
+            tiles.sort_unstable();
+            tiles
+        };
+        assert_eq!(sorted_tiles(&gzip), sorted_tiles(&plain));
+        assert_eq!(gzip.bounds, plain.bounds);
+        assert_eq!(fs::read(gzip_data.path())?, fs::read(plain_data.path())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_missing_node_locations() {
+        let pbf = NamedTempFile::new().unwrap();
+        PbfBuilder::new()
+            .add_way_without_locations(1, &[10, 11, 12], &[("highway", "primary")])
+            .write_to(pbf.path())
+            .unwrap();
+
+        let mut data_file = NamedTempFile::new().unwrap();
+        let result = load_osm_data(pbf.path(), 15, data_file.as_file_mut()).err();
+        assert!(matches!(result, Some(LoaderError::MissingNodeLocations(_))), "{:?}", result);
+        assert!(result.unwrap().to_string().contains("osmium add-locations-to-ways"));
+    }
 
     #[test]
     fn test_is_important_way() {
@@ -134,4 +1108,16 @@ mod tests {
         let footway = vec![("highway".to_string(), "footway".to_string())];
         assert!(!is_important_way(&footway));
     }
+
+    #[test]
+    fn test_is_poi_node() {
+        let tags = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            pairs.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect()
+        };
+        assert!(is_poi_node(&tags(&[("amenity", "cafe")])));
+        assert!(is_poi_node(&tags(&[("name", "Brocken"), ("natural", "peak")])));
+        assert!(!is_poi_node(&tags(&[("natural", "tree")])));
+        assert!(!is_poi_node(&tags(&[("highway", "traffic_signals")])));
+        assert!(!is_poi_node(&[]));
+    }
 }
@@ -1,5 +1,8 @@
-use super::serialization::{BOUNDING_BOX_SIZE, POINT_SIZE, POINTS_LEN_SIZE};
-use super::types::{BoundingBox, MapObjectOffset, Point};
+use super::serialization::{
+    check_data_header, ALIGNMENT, BOUNDING_BOX_SIZE, KIND_SIZE, POINT_SIZE, POINTS_LEN_SIZE, STRING_LEN_SIZE,
+    TAGS_LEN_SIZE,
+};
+use super::types::{BoundingBox, MapObjectOffset, ObjectKind, Point};
 use memmap2::Mmap;
 use std::fs::File;
 use std::io;
@@ -13,15 +16,48 @@ pub struct MappedData {
 
 impl MappedData {
     /// Create a new memory-mapped file
+    ///
+    /// Files of other format versions are rejected, see `check_data_header`;
+    /// an empty file holds no objects.
     pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
         let file = File::open(path)?;
         let mmap = unsafe { Mmap::map(&file)? };
+        if !mmap.is_empty() {
+            check_data_header(&mmap)?;
+        }
         Ok(MappedData { _file: file, mmap })
     }
 
     /// Get a zero-copy view of a map object at the given offset
     pub fn read_map_object(&self, offset: MapObjectOffset) -> MapObjectView {
-        unsafe { MapObjectView::from_ptr(self.mmap.as_ptr().add(offset as usize)) }
+        debug_assert_eq!(offset % ALIGNMENT as u64, 0, "unaligned map object offset {}", offset);
+        unsafe { MapObjectView::from_ptr(self.mmap.as_ptr().add(offset as usize), self.mmap.as_ptr_range().end) }
+    }
+
+    /// Size of the map object at `offset` if it lies entirely within the file
+    ///
+    /// Unlike `read_map_object` this checks alignment and bounds, for
+    /// validating offsets of an index that may not belong to this file.
+    pub fn checked_object_size(&self, offset: MapObjectOffset) -> Option<usize> {
+        if !offset.is_multiple_of(ALIGNMENT as u64) {
+            return None;
+        }
+        let start = usize::try_from(offset).ok()?;
+        let len_start = start.checked_add(BOUNDING_BOX_SIZE)?;
+        let len_bytes = self.mmap.get(len_start..len_start.checked_add(POINTS_LEN_SIZE)?)?;
+        let points_len = usize::try_from(i64::from_le_bytes(len_bytes.try_into().unwrap())).ok()?;
+        let kind_start = points_len
+            .checked_mul(POINT_SIZE)?
+            .checked_add(BOUNDING_BOX_SIZE + POINTS_LEN_SIZE)?
+            .checked_add(start)?;
+        ObjectKind::from_u8(*self.mmap.get(kind_start)?)?;
+        let tags_start = kind_start + KIND_SIZE;
+        let mut tags = Tags::new(self.mmap.get(tags_start..)?)?;
+        tags.by_ref().for_each(drop);
+        if !tags.is_complete() {
+            return None;
+        }
+        Some(self.mmap.len() - tags.bytes.len() - start)
     }
 
     /// Get the size of the memory-mapped region
@@ -45,6 +81,9 @@ impl MappedData {
 pub struct MapObjectView<'a> {
     pub bbox: &'a BoundingBox,
     pub points: &'a [Point],
+    pub kind: ObjectKind,
+    // From the tag block to the end of the file
+    tag_bytes: &'a [u8],
 }
 
 impl<'a> MapObjectView<'a> {
@@ -55,9 +94,15 @@ impl<'a> MapObjectView<'a> {
     /// - 32 bytes: BoundingBox
     /// - 8 bytes: i64 length
     /// - length * 16 bytes: Point array
+    /// - 1 byte: object kind
+    /// - the tag block, ending before `end`
+    ///
+    /// The pointer must be aligned to `ALIGNMENT` (8) bytes.
     ///
     /// The memory must remain valid and unchanged for the lifetime 'a.
-    unsafe fn from_ptr(ptr: *const u8) -> Self {
+    unsafe fn from_ptr(ptr: *const u8, end: *const u8) -> Self {
+        debug_assert!(ptr.cast::<BoundingBox>().is_aligned(), "unaligned map object at {:p}", ptr);
+
         // Read bounding box (first 32 bytes)
         let bbox = &*(ptr as *const BoundingBox);
 
@@ -65,12 +110,19 @@ impl<'a> MapObjectView<'a> {
         let points_len = *(ptr.add(BOUNDING_BOX_SIZE) as *const i64);
 
         // Read points array (starting at byte 40)
-        let points = std::slice::from_raw_parts(
-            ptr.add(BOUNDING_BOX_SIZE + POINTS_LEN_SIZE) as *const Point,
-            points_len as usize,
-        );
+        let points_ptr = ptr.add(BOUNDING_BOX_SIZE + POINTS_LEN_SIZE) as *const Point;
+        debug_assert!(points_ptr.is_aligned(), "unaligned points at {:p}", points_ptr);
+        let points = std::slice::from_raw_parts(points_ptr, points_len as usize);
 
-        MapObjectView { bbox, points }
+        // Unknown kinds are only rejected by `checked_object_size`; draw them as lines
+        let kind_ptr = points_ptr.add(points.len()) as *const u8;
+        let kind = ObjectKind::from_u8(*kind_ptr).unwrap_or_default();
+
+        // Tags are parsed on demand, see `tags`
+        let tags_ptr = kind_ptr.add(KIND_SIZE);
+        let tag_bytes = std::slice::from_raw_parts(tags_ptr, end.offset_from(tags_ptr).max(0) as usize);
+
+        MapObjectView { bbox, points, kind, tag_bytes }
     }
 
     /// Get the bounding box
@@ -87,13 +139,72 @@ impl<'a> MapObjectView<'a> {
     pub fn num_points(&self) -> usize {
         self.points.len()
     }
+
+    /// Retained tags, parsed from the data file as they are iterated
+    pub fn tags(&self) -> Tags<'a> {
+        Tags::new(self.tag_bytes).unwrap_or_default()
+    }
+
+    /// Value of the retained tag `key`
+    pub fn tag(&self, key: &str) -> Option<&'a str> {
+        self.tags().find(|&(k, _)| k == key).map(|(_, value)| value)
+    }
+}
+
+/// Key/value pairs of a map object's tag block, see `MapObjectView::tags`
+///
+/// Iteration stops early at a truncated or non-UTF-8 tag.
+#[derive(Debug, Clone, Default)]
+pub struct Tags<'a> {
+    bytes: &'a [u8],
+    remaining: u32,
+}
+
+impl<'a> Tags<'a> {
+    /// Tags of the block at the start of `bytes`, `None` if it is truncated
+    fn new(bytes: &'a [u8]) -> Option<Self> {
+        let (len, bytes) = bytes.split_first_chunk::<TAGS_LEN_SIZE>()?;
+        Some(Tags {
+            bytes,
+            remaining: u32::from_le_bytes(*len),
+        })
+    }
+
+    /// Whether all tags were read successfully
+    fn is_complete(&self) -> bool {
+        self.remaining == 0
+    }
+
+    fn take_string(&mut self) -> Option<&'a str> {
+        let (len, rest) = self.bytes.split_first_chunk::<STRING_LEN_SIZE>()?;
+        let len = u32::from_le_bytes(*len) as usize;
+        if rest.len() < len {
+            return None;
+        }
+        let (string, rest) = rest.split_at(len);
+        self.bytes = rest;
+        std::str::from_utf8(string).ok()
+    }
+}
+
+impl<'a> Iterator for Tags<'a> {
+    type Item = (&'a str, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let tag = self.take_string().zip(self.take_string())?;
+        self.remaining -= 1;
+        Some(tag)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::data::serialization::write_map_object;
-    use crate::data::types::MapObject;
+    use crate::data::serialization::{map_object_size, write_data_header, write_map_object};
+    use crate::data::types::{MapObject, ObjectKind};
     use tempfile::NamedTempFile;
 
     #[test]
@@ -110,6 +221,8 @@ mod tests {
                 Point::new(15.0, 25.0),
                 Point::new(20.0, 30.0),
             ],
+            kind: ObjectKind::Line,
+            tags: Vec::new(),
         };
 
         let obj2 = MapObject {
@@ -122,6 +235,11 @@ mod tests {
                 Point::new(60.0, 70.0),
                 Point::new(65.0, 75.0),
             ],
+            kind: ObjectKind::Polygon,
+            tags: vec![
+                ("highway".to_string(), "primary".to_string()),
+                ("name".to_string(), "Große Straße".to_string()),
+            ],
         };
 
         // Write objects
@@ -152,6 +270,69 @@ mod tests {
         assert_eq!(view2.points[1].lon, 60.0);
         assert_eq!(view2.points[1].lat, 70.0);
 
+        // Retained tags
+        assert_eq!(view1.tags().count(), 0);
+        assert_eq!(view2.tags().collect::<Vec<_>>(), vec![("highway", "primary"), ("name", "Große Straße")]);
+        assert_eq!(view2.tag("name"), Some("Große Straße"));
+        assert_eq!(view2.tag("surface"), None);
+        assert_eq!(mmap_data.checked_object_size(offset2), Some(map_object_size(3, &obj2.tags)));
+
+        assert_eq!((view1.kind, view2.kind), (ObjectKind::Line, ObjectKind::Polygon));
+        // Unknown kinds fail validation
+        let mut bytes = std::fs::read(temp_file.path())?;
+        bytes[offset1 as usize + BOUNDING_BOX_SIZE + POINTS_LEN_SIZE + 2 * POINT_SIZE] = 9;
+        std::fs::write(temp_file.path(), bytes)?;
+        let mmap_data = MappedData::new(temp_file.path())?;
+        assert_eq!(mmap_data.checked_object_size(offset1), None);
+        assert!(mmap_data.checked_object_size(offset2).is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mmap_rejects_headerless_file() -> io::Result<()> {
+        // Data files from before the header: a bare map object
+        let temp_file = NamedTempFile::new()?;
+        std::fs::write(temp_file.path(), [0u8; 64])?;
+        let err = MappedData::new(temp_file.path()).err().expect("headerless file accepted");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        std::fs::write(temp_file.path(), [])?;
+        assert!(MappedData::new(temp_file.path())?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mmap_alignment() -> io::Result<()> {
+        use std::io::Write;
+
+        // Objects of varying sizes after an unaligned stray byte
+        let mut temp_file = NamedTempFile::new()?;
+        write_data_header(temp_file.as_file_mut())?;
+        temp_file.as_file_mut().write_all(&[1])?;
+        let mut offsets = Vec::new();
+        for num_points in [1, 2, 3, 5] {
+            let points: Vec<Point> = (0..num_points).map(|i| Point::new(i as f64, -(i as f64))).collect();
+            let obj = MapObject {
+                bounding_box: BoundingBox::from_points(&points).unwrap(),
+                points,
+                kind: ObjectKind::Line,
+                tags: vec![("ref".to_string(), "x".repeat(num_points))],
+            };
+            offsets.push(write_map_object(temp_file.as_file_mut(), &obj)?);
+        }
+        temp_file.as_file_mut().sync_all()?;
+
+        let mmap_data = MappedData::new(temp_file.path())?;
+        for (&offset, num_points) in offsets.iter().zip([1, 2, 3, 5]) {
+            assert_eq!(offset % ALIGNMENT as u64, 0);
+            let view = mmap_data.read_map_object(offset);
+            assert!((view.bbox as *const BoundingBox).is_aligned());
+            assert!(view.points.as_ptr().is_aligned());
+            assert_eq!(view.num_points(), num_points);
+            assert_eq!(view.points[num_points - 1].lat, -((num_points - 1) as f64));
+        }
+
         Ok(())
     }
 }
@@ -1,5 +1,12 @@
 pub mod types;
 pub mod serialization;
+pub mod input;
 pub mod loader;
 pub mod mmap;
+pub mod compressed;
 pub mod spatial;
+pub mod index_file;
+pub mod source;
+pub mod mvt;
+#[cfg(test)]
+pub(crate) mod test_pbf;
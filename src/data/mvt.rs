@@ -0,0 +1,433 @@
+//! Upstream Mapbox Vector Tile server as a `TileSource`
+//!
+//! Fetches `{z}/{x}/{y}` tiles over plain HTTP and decodes the line and
+//! polygon geometry of all layers. Point features are ignored, since the
+//! renderer only draws lines. The protobuf is decoded by hand; only the
+//! fields needed for geometry are read.
+
+use super::source::{TileSource, TileSourceError};
+use super::types::{BoundingBox, MapObject, ObjectKind, Point, Tile};
+use crate::projection::num2deg;
+use flate2::read::GzDecoder;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// Extent of a layer that doesn't specify one
+const DEFAULT_EXTENT: u32 = 4096;
+
+/// Timeout for connecting to and reading from the upstream server
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Vector tiles from an upstream server, e.g. `http://localhost:3000/{z}/{x}/{y}.pbf`
+pub struct MvtTileSource {
+    url_template: String,
+    /// Highest zoom the server provides; deeper tiles read their ancestor
+    max_zoom: u32,
+}
+
+impl MvtTileSource {
+    pub fn new(url_template: &str, max_zoom: u32) -> Self {
+        MvtTileSource {
+            url_template: url_template.to_string(),
+            max_zoom,
+        }
+    }
+
+    fn url(&self, tile: &Tile) -> String {
+        self.url_template
+            .replace("{z}", &tile.z.to_string())
+            .replace("{x}", &tile.x.to_string())
+            .replace("{y}", &tile.y.to_string())
+    }
+}
+
+impl TileSource for MvtTileSource {
+    fn objects_for_tile(&self, tile: &Tile) -> Result<Vec<MapObject>, TileSourceError> {
+        let source_tile = tile.get_ancestor(tile.z.min(self.max_zoom)).unwrap_or(*tile);
+        let url = self.url(&source_tile);
+        log::info!("Fetching vector tile {}", url);
+
+        let body = http_get(&url).map_err(|message| TileSourceError::Fetch {
+            url: url.clone(),
+            message,
+        })?;
+        decode_mvt(&body, &source_tile)
+    }
+}
+
+/// Fetch `url` with a minimal HTTP/1.0 GET, returning the (gunzipped) body
+///
+/// Only `http://` is supported. 204 and 404 are treated as empty tiles.
+fn http_get(url: &str) -> Result<Vec<u8>, String> {
+    let rest = url.strip_prefix("http://").ok_or("only http:// URLs are supported")?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let address = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+
+    let mut stream = TcpStream::connect(&address).map_err(|e| e.to_string())?;
+    stream.set_read_timeout(Some(FETCH_TIMEOUT)).map_err(|e| e.to_string())?;
+    stream.set_write_timeout(Some(FETCH_TIMEOUT)).map_err(|e| e.to_string())?;
+    // HTTP/1.0 keeps the response unchunked and closes the connection after it
+    // One write, so servers that respond after the first read see the whole request
+    let request = format!("GET {} HTTP/1.0\r\nHost: {}\r\nAccept-Encoding: gzip\r\n\r\n", path, host);
+    stream.write_all(request.as_bytes()).map_err(|e| e.to_string())?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).map_err(|e| e.to_string())?;
+
+    let header_end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or("malformed HTTP response")?;
+    let status_line = String::from_utf8_lossy(&response[..header_end]).lines().next().unwrap_or_default().to_string();
+    let status = status_line.split_whitespace().nth(1).and_then(|s| s.parse::<u16>().ok());
+    let body = &response[header_end + 4..];
+    match status {
+        Some(200) => {}
+        Some(204) | Some(404) => return Ok(Vec::new()),
+        _ => return Err(format!("unexpected response {:?}", status_line)),
+    }
+
+    // Tile servers commonly gzip tiles regardless of Accept-Encoding
+    if body.starts_with(&[0x1f, 0x8b]) {
+        let mut decoded = Vec::new();
+        GzDecoder::new(body).read_to_end(&mut decoded).map_err(|e| e.to_string())?;
+        Ok(decoded)
+    } else {
+        Ok(body.to_vec())
+    }
+}
+
+/// Decode the line and polygon features of a vector tile for `tile`
+pub fn decode_mvt(data: &[u8], tile: &Tile) -> Result<Vec<MapObject>, TileSourceError> {
+    let mut objects = Vec::new();
+    let mut tile_message = ProtoReader::new(data);
+    while let Some((field, value)) = tile_message.next_field()? {
+        // Tile.layers = 3
+        if let (3, Value::Bytes(layer)) = (field, value) {
+            decode_layer(layer, tile, &mut objects)?;
+        }
+    }
+    Ok(objects)
+}
+
+fn decode_layer(data: &[u8], tile: &Tile, objects: &mut Vec<MapObject>) -> Result<(), TileSourceError> {
+    // The extent may follow the features, so collect those first
+    let mut features = Vec::new();
+    let mut extent = DEFAULT_EXTENT;
+    let mut layer = ProtoReader::new(data);
+    while let Some((field, value)) = layer.next_field()? {
+        match (field, value) {
+            // Layer.features = 2, Layer.extent = 5
+            (2, Value::Bytes(feature)) => features.push(feature),
+            (5, Value::Varint(value)) => extent = value as u32,
+            _ => {}
+        }
+    }
+    if extent == 0 {
+        return Err(TileSourceError::Decode("layer extent is 0".to_string()));
+    }
+
+    for feature in features {
+        let mut geom_type = 0;
+        let mut geometry = None;
+        let mut reader = ProtoReader::new(feature);
+        while let Some((field, value)) = reader.next_field()? {
+            match (field, value) {
+                // Feature.type = 3 (1 point, 2 linestring, 3 polygon), Feature.geometry = 4
+                (3, Value::Varint(value)) => geom_type = value,
+                (4, Value::Bytes(packed)) => geometry = Some(packed),
+                _ => {}
+            }
+        }
+        if let (2 | 3, Some(geometry)) = (geom_type, geometry) {
+            for part in decode_geometry(geometry)? {
+                let points: Vec<Point> = part
+                    .iter()
+                    .map(|&(x, y)| {
+                        num2deg(
+                            tile.x as f64 + x as f64 / extent as f64,
+                            tile.y as f64 + y as f64 / extent as f64,
+                            tile.z,
+                        )
+                    })
+                    .collect();
+                if let Some(bbox) = BoundingBox::from_points(&points) {
+                    let mut object = MapObject::new(bbox, points);
+                    if geom_type == 3 {
+                        object.kind = ObjectKind::Polygon;
+                    }
+                    objects.push(object);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Decode MVT geometry commands into parts of tile-local coordinates
+///
+/// Every MoveTo starts a new part, ClosePath repeats its first point.
+/// Parts with fewer than two points are dropped.
+fn decode_geometry(data: &[u8]) -> Result<Vec<Vec<(i64, i64)>>, TileSourceError> {
+    let mut integers = Vec::new();
+    let mut reader = ProtoReader::new(data);
+    while !reader.is_empty() {
+        integers.push(reader.varint()? as u32);
+    }
+
+    let mut parts: Vec<Vec<(i64, i64)>> = Vec::new();
+    let (mut x, mut y) = (0i64, 0i64);
+    let mut i = 0;
+    while i < integers.len() {
+        let (command, count) = (integers[i] & 0x7, (integers[i] >> 3) as usize);
+        i += 1;
+        match command {
+            // MoveTo, LineTo
+            1 | 2 => {
+                for n in 0..count {
+                    let (dx, dy) = match (integers.get(i), integers.get(i + 1)) {
+                        (Some(&dx), Some(&dy)) => (zigzag(dx), zigzag(dy)),
+                        _ => return Err(TileSourceError::Decode("truncated geometry".to_string())),
+                    };
+                    i += 2;
+                    x += dx;
+                    y += dy;
+                    if command == 1 && n == 0 {
+                        parts.push(Vec::new());
+                    }
+                    match parts.last_mut() {
+                        Some(part) => part.push((x, y)),
+                        None => return Err(TileSourceError::Decode("LineTo before MoveTo".to_string())),
+                    }
+                }
+            }
+            // ClosePath
+            7 => {
+                if let Some(part) = parts.last_mut() {
+                    if let Some(&first) = part.first() {
+                        part.push(first);
+                    }
+                }
+            }
+            _ => return Err(TileSourceError::Decode(format!("unknown geometry command {}", command))),
+        }
+    }
+
+    parts.retain(|part| part.len() >= 2);
+    Ok(parts)
+}
+
+fn zigzag(value: u32) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+/// A protobuf field value
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Minimal protobuf wire format reader
+struct ProtoReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ProtoReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        ProtoReader { data, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn varint(&mut self) -> Result<u64, TileSourceError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or_else(|| TileSourceError::Decode("truncated varint".to_string()))?;
+            self.pos += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(TileSourceError::Decode("varint too long".to_string()))
+    }
+
+    fn skip(&mut self, len: usize) -> Result<&'a [u8], TileSourceError> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.data.len());
+        let end = end.ok_or_else(|| TileSourceError::Decode("truncated field".to_string()))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// Read the next field number and value, `None` at the end of the message
+    fn next_field(&mut self) -> Result<Option<(u64, Value<'a>)>, TileSourceError> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let value = match key & 0x7 {
+            0 => Value::Varint(self.varint()?),
+            1 => {
+                self.skip(8)?;
+                Value::Fixed
+            }
+            2 => {
+                let len = self.varint()? as usize;
+                Value::Bytes(self.skip(len)?)
+            }
+            5 => {
+                self.skip(4)?;
+                Value::Fixed
+            }
+            wire_type => return Err(TileSourceError::Decode(format!("unsupported wire type {}", wire_type))),
+        };
+        Ok(Some((key >> 3, value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::projection::get_bounding_box;
+    use flate2::write::GzEncoder;
+    use std::net::TcpListener;
+
+    fn varint(mut value: u64, out: &mut Vec<u8>) {
+        while value >= 0x80 {
+            out.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    fn bytes_field(field: u64, bytes: &[u8], out: &mut Vec<u8>) {
+        varint(field << 3 | 2, out);
+        varint(bytes.len() as u64, out);
+        out.extend_from_slice(bytes);
+    }
+
+    fn command(id: u32, count: u32) -> u32 {
+        id | (count << 3)
+    }
+
+    fn zz(value: i32) -> u32 {
+        ((value << 1) ^ (value >> 31)) as u32
+    }
+
+    fn feature(geom_type: u64, geometry: &[u32]) -> Vec<u8> {
+        let mut out = Vec::new();
+        varint(3 << 3, &mut out);
+        varint(geom_type, &mut out);
+        let mut packed = Vec::new();
+        for &value in geometry {
+            varint(value as u64, &mut packed);
+        }
+        bytes_field(4, &packed, &mut out);
+        out
+    }
+
+    /// Tile with one layer (extent 4096): a diagonal line, a square ring and a point
+    fn encode_test_tile() -> Vec<u8> {
+        let line = feature(2, &[command(1, 1), zz(0), zz(0), command(2, 1), zz(4096), zz(4096)]);
+        let ring = feature(
+            3,
+            &[command(1, 1), zz(1024), zz(1024), command(2, 3), zz(2048), zz(0), zz(0), zz(2048), zz(-2048), zz(0), command(7, 1)],
+        );
+        let point = feature(1, &[command(1, 1), zz(10), zz(10)]);
+
+        let mut layer = Vec::new();
+        bytes_field(1, b"roads", &mut layer);
+        for feature in [line, ring, point] {
+            bytes_field(2, &feature, &mut layer);
+        }
+        varint(5 << 3, &mut layer);
+        varint(4096, &mut layer);
+
+        let mut tile = Vec::new();
+        bytes_field(3, &layer, &mut tile);
+        tile
+    }
+
+    #[test]
+    fn test_decode_mvt() {
+        let tile = Tile::new(1081, 660, 11);
+        let bbox = get_bounding_box(&tile);
+        let objects = decode_mvt(&encode_test_tile(), &tile).unwrap();
+
+        // The point is skipped
+        assert_eq!(objects.len(), 2);
+
+        // Line from the top-left to the bottom-right corner
+        let line = &objects[0];
+        assert_eq!(line.points.len(), 2);
+        assert!((line.points[0].lon - bbox.min.lon).abs() < 1e-9 && (line.points[0].lat - bbox.max.lat).abs() < 1e-9);
+        assert!((line.points[1].lon - bbox.max.lon).abs() < 1e-9 && (line.points[1].lat - bbox.min.lat).abs() < 1e-9);
+
+        // Closed ring in the middle of the tile
+        let ring = &objects[1];
+        assert_eq!(ring.points.len(), 5);
+        assert_eq!(ring.points.first(), ring.points.last());
+        assert_eq!((line.kind, ring.kind), (ObjectKind::Line, ObjectKind::Polygon));
+        assert!(bbox.contains(&ring.bounding_box.min) && bbox.contains(&ring.bounding_box.max));
+
+        assert!(decode_mvt(&[0x1a, 0x05, 0x12], &tile).is_err());
+        assert!(decode_mvt(&[], &tile).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_mvt_tile_source_over_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        // Serve a gzipped tile for 11/1081/660 and 404 for everything else
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let len = stream.read(&mut buf).unwrap();
+                    assert!(len > 0, "connection closed mid-request");
+                    request.extend_from_slice(&buf[..len]);
+                }
+                let request = String::from_utf8_lossy(&request).to_string();
+                if request.starts_with("GET /tiles/11/1081/660.pbf ") {
+                    let mut encoder = GzEncoder::new(Vec::new(), Default::default());
+                    encoder.write_all(&encode_test_tile()).unwrap();
+                    let body = encoder.finish().unwrap();
+                    write!(stream, "HTTP/1.0 200 OK\r\nContent-Length: {}\r\n\r\n", body.len()).unwrap();
+                    stream.write_all(&body).unwrap();
+                } else {
+                    stream.write_all(b"HTTP/1.0 404 Not Found\r\n\r\n").unwrap();
+                }
+                requests.push(request);
+            }
+            requests
+        });
+
+        let source = MvtTileSource::new(&format!("http://{}/tiles/{{z}}/{{x}}/{{y}}.pbf", address), 11);
+        // Overzoomed tiles fetch their ancestor at the max zoom
+        let objects = source.objects_for_tile(&Tile::new(1081 * 2, 660 * 2, 12)).unwrap();
+        assert_eq!(objects.len(), 2);
+        assert!(source.objects_for_tile(&Tile::new(0, 0, 0)).unwrap().is_empty());
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].contains("Accept-Encoding: gzip"));
+    }
+}
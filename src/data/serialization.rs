@@ -1,25 +1,95 @@
-use super::types::{BoundingBox, MapObject, MapObjectOffset, Point};
+use super::types::{BoundingBox, MapObject, MapObjectOffset, ObjectKind, Point};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use std::fs::File;
 use std::io::{self, Seek, SeekFrom};
 
-/// Binary format (must match Go version):
-/// - BoundingBox: 32 bytes
-///   - min.lon: 8 bytes (f64)
-///   - min.lat: 8 bytes (f64)
-///   - max.lon: 8 bytes (f64)
-///   - max.lat: 8 bytes (f64)
-/// - points_len: 8 bytes (i64)
-/// - points: points_len * 16 bytes
-///   - each point: lon (8 bytes f64) + lat (8 bytes f64)
+/// Binary format:
+/// - Header at the start of the file: `DATA_MAGIC` and `DATA_VERSION` (8 bytes)
+/// - Map objects, each:
+///   - BoundingBox: 32 bytes
+///     - min.lon: 8 bytes (f64)
+///     - min.lat: 8 bytes (f64)
+///     - max.lon: 8 bytes (f64)
+///     - max.lat: 8 bytes (f64)
+///   - points_len: 8 bytes (i64)
+///   - points: points_len * 16 bytes
+///     - each point: lon (8 bytes f64) + lat (8 bytes f64)
+///   - kind: 1 byte (`ObjectKind`: line, polygon or point)
+///   - tags_len: 4 bytes (u32)
+///   - tags: tags_len key/value pairs, each string a u32 byte length and UTF-8
+///
+/// Objects start on `ALIGNMENT` boundaries and the fields up to the points
+/// are multiples of `ALIGNMENT` bytes, so the memory-mapped bounding box and
+/// points can be reinterpreted in place. New fields must keep this (pad them
+/// to 8 bytes) or go after the points, which are followed by fields read
+/// unaligned.
+///
+/// Version 1 (no header, no tags) was the format of the Go version, version
+/// 2 had no kind.
+
+pub const DATA_MAGIC: &[u8; 7] = b"OSMDATA";
+pub const DATA_VERSION: u8 = 3;
+pub const DATA_HEADER_SIZE: usize = 8;
 
 pub const BOUNDING_BOX_SIZE: usize = 32;
 pub const POINTS_LEN_SIZE: usize = 8;
 pub const POINT_SIZE: usize = 16;
+pub const KIND_SIZE: usize = 1;
+pub const TAGS_LEN_SIZE: usize = 4;
+pub const STRING_LEN_SIZE: usize = 4;
+
+/// Alignment of every map object and field (f64)
+pub const ALIGNMENT: usize = 8;
+
+const _: () = assert!(DATA_HEADER_SIZE.is_multiple_of(ALIGNMENT));
+const _: () = assert!(BOUNDING_BOX_SIZE.is_multiple_of(ALIGNMENT));
+const _: () = assert!(POINTS_LEN_SIZE.is_multiple_of(ALIGNMENT));
+const _: () = assert!(POINT_SIZE.is_multiple_of(ALIGNMENT));
+
+/// Round `n` up to the next multiple of `ALIGNMENT`
+pub fn align_up(n: u64) -> u64 {
+    n.div_ceil(ALIGNMENT as u64) * ALIGNMENT as u64
+}
+
+/// Write the file header, see `check_data_header`
+pub fn write_data_header<W: WriteBytesExt>(writer: &mut W) -> io::Result<()> {
+    writer.write_all(DATA_MAGIC)?;
+    writer.write_u8(DATA_VERSION)
+}
+
+/// Check that `bytes` start with the header of this format version
+///
+/// Data files of other versions have to be loaded again from the OSM file.
+pub fn check_data_header(bytes: &[u8]) -> io::Result<()> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    match bytes.get(..DATA_HEADER_SIZE) {
+        Some([magic @ .., version]) if magic == DATA_MAGIC => match *version {
+            DATA_VERSION => Ok(()),
+            version => Err(invalid(format!(
+                "Map data file version {}, expected {}; load it again from the OSM file",
+                version, DATA_VERSION
+            ))),
+        },
+        _ => Err(invalid(format!(
+            "Not a map data file of version {} (no {} header); load it again from the OSM file",
+            DATA_VERSION,
+            String::from_utf8_lossy(DATA_MAGIC)
+        ))),
+    }
+}
 
 /// Write a map object to a writer and return its offset
+///
+/// The file header is written first at the start of the writer, zero
+/// padding if the writer isn't at an aligned position.
 pub fn write_map_object<W: WriteBytesExt + Seek>(writer: &mut W, obj: &MapObject) -> io::Result<MapObjectOffset> {
-    let offset = writer.stream_position()?;
+    if writer.stream_position()? == 0 {
+        write_data_header(writer)?;
+    }
+    let position = writer.stream_position()?;
+    let offset = align_up(position);
+    for _ in position..offset {
+        writer.write_u8(0)?;
+    }
 
     // Write bounding box (32 bytes)
     writer.write_f64::<LittleEndian>(obj.bounding_box.min.lon)?;
@@ -36,11 +106,22 @@ pub fn write_map_object<W: WriteBytesExt + Seek>(writer: &mut W, obj: &MapObject
         writer.write_f64::<LittleEndian>(point.lat)?;
     }
 
+    writer.write_u8(obj.kind as u8)?;
+
+    // Write tags
+    writer.write_u32::<LittleEndian>(obj.tags.len() as u32)?;
+    for (key, value) in &obj.tags {
+        for string in [key, value] {
+            writer.write_u32::<LittleEndian>(string.len() as u32)?;
+            writer.write_all(string.as_bytes())?;
+        }
+    }
+
     Ok(offset)
 }
 
-/// Read a map object from a file at a given offset
-pub fn read_map_object(file: &mut File, offset: MapObjectOffset) -> io::Result<MapObject> {
+/// Read a map object from a file (or any seekable reader) at a given offset
+pub fn read_map_object<R: ReadBytesExt + Seek>(file: &mut R, offset: MapObjectOffset) -> io::Result<MapObject> {
     file.seek(SeekFrom::Start(offset))?;
 
     // Read bounding box (32 bytes)
@@ -65,21 +146,42 @@ pub fn read_map_object(file: &mut File, offset: MapObjectOffset) -> io::Result<M
         points.push(Point::new(lon, lat));
     }
 
+    let kind = file.read_u8()?;
+    let kind = ObjectKind::from_u8(kind)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("invalid object kind {}", kind)))?;
+
+    // Read tags
+    let tags_len = file.read_u32::<LittleEndian>()?;
+    let mut read_string = || -> io::Result<String> {
+        let mut bytes = vec![0; file.read_u32::<LittleEndian>()? as usize];
+        file.read_exact(&mut bytes)?;
+        String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    };
+    let mut tags = Vec::with_capacity(tags_len as usize);
+    for _ in 0..tags_len {
+        tags.push((read_string()?, read_string()?));
+    }
+
     Ok(MapObject {
         bounding_box,
         points,
+        kind,
+        tags,
     })
 }
 
 /// Calculate the size of a map object in bytes
-pub fn map_object_size(num_points: usize) -> usize {
-    BOUNDING_BOX_SIZE + POINTS_LEN_SIZE + (num_points * POINT_SIZE)
+///
+/// The next object starts at the following `ALIGNMENT` boundary.
+pub fn map_object_size(num_points: usize, tags: &[(String, String)]) -> usize {
+    let tags_size: usize = tags.iter().map(|(key, value)| 2 * STRING_LEN_SIZE + key.len() + value.len()).sum();
+    BOUNDING_BOX_SIZE + POINTS_LEN_SIZE + (num_points * POINT_SIZE) + KIND_SIZE + TAGS_LEN_SIZE + tags_size
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Cursor;
+    use std::io::{Cursor, Write};
     use tempfile::NamedTempFile;
 
     #[test]
@@ -96,11 +198,16 @@ mod tests {
                 Point::new(20.0, 30.0),
                 Point::new(25.0, 35.0),
             ],
+            kind: ObjectKind::Polygon,
+            tags: vec![
+                ("highway".to_string(), "primary".to_string()),
+                ("name".to_string(), "Mönckebergstraße".to_string()),
+            ],
         };
 
         // Write
         let offset = write_map_object(temp_file.as_file_mut(), &original)?;
-        assert_eq!(offset, 0);
+        assert_eq!(offset, DATA_HEADER_SIZE as u64);
 
         // Read
         let read_obj = read_map_object(temp_file.as_file_mut(), offset)?;
@@ -127,20 +234,55 @@ mod tests {
             assert_eq!(point.lon, original.points[i].lon);
             assert_eq!(point.lat, original.points[i].lat);
         }
+        assert_eq!(read_obj.kind, original.kind);
+        assert_eq!(read_obj.tags, original.tags);
 
         Ok(())
     }
 
     #[test]
     fn test_map_object_size() {
-        assert_eq!(map_object_size(0), 40); // 32 + 8 + 0
-        assert_eq!(map_object_size(1), 56); // 32 + 8 + 16
-        assert_eq!(map_object_size(10), 200); // 32 + 8 + 160
+        assert_eq!(map_object_size(0, &[]), 45); // 32 + 8 + 0 + 1 + 4
+        assert_eq!(map_object_size(1, &[]), 61); // 32 + 8 + 16 + 1 + 4
+        assert_eq!(map_object_size(10, &[]), 205); // 32 + 8 + 160 + 1 + 4
+        let tags = [("highway".to_string(), "primary".to_string())];
+        assert_eq!(map_object_size(1, &tags), 83); // 61 + 4 + 7 + 4 + 7
+    }
+
+    #[test]
+    fn test_alignment() -> io::Result<()> {
+        assert_eq!(align_up(0), 0);
+        assert_eq!(align_up(1), 8);
+        assert_eq!(align_up(40), 40);
+        assert_eq!(align_up(41), 48);
+
+        // Writing after unaligned data pads to the next boundary
+        let mut cursor = Cursor::new(Vec::new());
+        cursor.write_all(&[0xff; 3])?;
+        let obj = MapObject {
+            bounding_box: BoundingBox {
+                min: Point::new(1.0, 2.0),
+                max: Point::new(3.0, 4.0),
+            },
+            points: vec![Point::new(1.0, 2.0), Point::new(3.0, 4.0)],
+            kind: ObjectKind::Line,
+            tags: vec![("ref".to_string(), "A7".to_string())],
+        };
+        let first = write_map_object(&mut cursor, &obj)?;
+        let second = write_map_object(&mut cursor, &obj)?;
+        assert_eq!(first, 8);
+        assert_eq!(second, first + align_up(map_object_size(2, &obj.tags) as u64));
+        assert_eq!(&cursor.get_ref()[3..8], &[0; 5]);
+
+        let read_obj = read_map_object(&mut cursor, second)?;
+        assert_eq!(read_obj.points.len(), 2);
+        assert_eq!(read_obj.points[1].lat, 4.0);
+        assert_eq!(read_obj.tags, obj.tags);
+        Ok(())
     }
 
     #[test]
     fn test_binary_layout() -> io::Result<()> {
-        // Test that the binary layout matches Go's expectations
         let mut buffer = Vec::new();
         let mut cursor = Cursor::new(&mut buffer);
 
@@ -150,15 +292,19 @@ mod tests {
                 max: Point::new(3.0, 4.0),
             },
             points: vec![Point::new(5.0, 6.0)],
+            kind: ObjectKind::Point,
+            tags: vec![("k".to_string(), "vv".to_string())],
         };
 
         write_map_object(&mut cursor, &obj)?;
 
         // Check total size
-        assert_eq!(buffer.len(), 56); // 32 + 8 + 16
+        assert_eq!(buffer.len(), 8 + 56 + 1 + 4 + 4 + 1 + 4 + 2); // header, 32 + 8 + 16, kind, tags
+        assert_eq!(&buffer[..8], b"OSMDATA\x03");
+        check_data_header(&buffer)?;
 
         // Check that we can read back the bounding box
-        let mut cursor = Cursor::new(&buffer);
+        let mut cursor = Cursor::new(&buffer[DATA_HEADER_SIZE..]);
         let min_lon = cursor.read_f64::<LittleEndian>()?;
         let min_lat = cursor.read_f64::<LittleEndian>()?;
         let max_lon = cursor.read_f64::<LittleEndian>()?;
@@ -174,7 +320,28 @@ mod tests {
         assert_eq!(points_len, 1);
         assert_eq!(point_lon, 5.0);
         assert_eq!(point_lat, 6.0);
+        assert_eq!(cursor.read_u8()?, ObjectKind::Point as u8);
+        assert_eq!(cursor.read_u32::<LittleEndian>()?, 1);
+        assert_eq!(cursor.read_u32::<LittleEndian>()?, 1);
+        assert_eq!(cursor.read_u8()?, b'k');
 
         Ok(())
     }
+
+    #[test]
+    fn test_check_data_header() {
+        let mut header = Vec::new();
+        write_data_header(&mut header).unwrap();
+        assert!(check_data_header(&header).is_ok());
+
+        // Version 1 files start right with a bounding box
+        let error = check_data_header(&10.0f64.to_le_bytes()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("OSMDATA header"), "{}", error);
+        assert!(check_data_header(b"OSM").is_err());
+
+        header[7] = 2;
+        let error = check_data_header(&header).unwrap_err();
+        assert!(error.to_string().contains("version 2, expected 3"), "{}", error);
+    }
 }
@@ -0,0 +1,108 @@
+//! Tile sources: where the renderer gets a tile's map objects from
+//!
+//! The memory-mapped data file is the built-in source; `mvt` adds an
+//! upstream vector tile server.
+
+use super::mmap::MappedData;
+use super::spatial::TileIndex;
+use super::types::{MapObject, Tile};
+use crate::projection::get_bounding_box;
+
+/// Provider of the map objects to draw for a tile
+///
+/// Objects may extend past the tile; the renderer clips them. Sources are
+/// shared across requests, so they must be usable from any thread.
+pub trait TileSource: Send + Sync {
+    fn objects_for_tile(&self, tile: &Tile) -> Result<Vec<MapObject>, TileSourceError>;
+}
+
+/// Tile source errors
+#[derive(Debug, thiserror::Error)]
+pub enum TileSourceError {
+    #[error("Failed to fetch {url}: {message}")]
+    Fetch { url: String, message: String },
+
+    #[error("Invalid vector tile: {0}")]
+    Decode(String),
+}
+
+/// The tile index and memory-mapped data file as a `TileSource`
+pub struct MmapTileSource<'a> {
+    pub index: &'a TileIndex,
+    pub data: &'a MappedData,
+}
+
+impl TileSource for MmapTileSource<'_> {
+    fn objects_for_tile(&self, tile: &Tile) -> Result<Vec<MapObject>, TileSourceError> {
+        // Zoom levels above the index read their ancestor's objects
+        let lookup_tile = tile.get_ancestor(tile.z.min(self.index.max_zoom)).unwrap_or(*tile);
+        let bbox = get_bounding_box(tile);
+
+        let mut offsets = self.index.get(&lookup_tile).cloned().unwrap_or_default();
+        offsets.sort_unstable();
+        offsets.dedup();
+        Ok(offsets
+            .into_iter()
+            .map(|offset| self.data.read_map_object(offset))
+            .filter(|view| view.bbox.overlaps(&bbox))
+            .map(|view| MapObject {
+                bounding_box: *view.bbox,
+                points: view.points.to_vec(),
+                kind: view.kind,
+                tags: view.tags().map(|(key, value)| (key.to_string(), value.to_string())).collect(),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::serialization::write_map_object;
+    use crate::data::types::{BoundingBox, Point};
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn line(from: Point, to: Point) -> MapObject {
+        MapObject::new(BoundingBox::from_points(&[from, to]).unwrap(), vec![from, to])
+    }
+
+    #[test]
+    fn test_mmap_tile_source() {
+        let hamburg = line(Point::new(10.0, 53.5), Point::new(10.01, 53.51));
+        let berlin = line(Point::new(13.4, 52.5), Point::new(13.41, 52.51));
+
+        let mut file = NamedTempFile::new().unwrap();
+        let hamburg_offset = write_map_object(file.as_file_mut(), &hamburg).unwrap();
+        let berlin_offset = write_map_object(file.as_file_mut(), &berlin).unwrap();
+        file.flush().unwrap();
+        let data = MappedData::new(file.path()).unwrap();
+
+        let tile_z0 = Tile::new(0, 0, 0);
+        let (x, y) = crate::projection::deg2num(53.5, 10.0, 15);
+        let tile_z15 = Tile::new(x, y, 15);
+        let mut index = TileIndex::new();
+        for offset in [hamburg_offset, berlin_offset, hamburg_offset] {
+            index.insert(tile_z0, offset);
+        }
+        index.insert(tile_z15, hamburg_offset);
+        let source = MmapTileSource { index: &index, data: &data };
+
+        // Duplicates are dropped
+        let objects = source.objects_for_tile(&tile_z0).unwrap();
+        assert_eq!(objects.len(), 2);
+        assert_eq!(objects[0].points, hamburg.points);
+        assert_eq!(objects[1].points, berlin.points);
+
+        // Above the index, the z15 ancestor's objects that overlap the tile
+        let (x, y) = crate::projection::deg2num(53.5, 10.0, 17);
+        assert_eq!(source.objects_for_tile(&Tile::new(x, y, 17)).unwrap().len(), 1);
+        assert!(source.objects_for_tile(&Tile::new(0, 0, 1)).unwrap().is_empty());
+
+        // An index built only up to zoom 0 serves every zoom from there
+        index.max_zoom = 0;
+        let source = MmapTileSource { index: &index, data: &data };
+        assert_eq!(source.objects_for_tile(&Tile::new(x, y, 17)).unwrap().len(), 1);
+        assert_eq!(source.objects_for_tile(&Tile::new(0, 0, 1)).unwrap().len(), 0);
+    }
+}
@@ -1,41 +1,398 @@
-use std::collections::HashMap;
-use super::types::{Tile, MapObjectOffset};
+use rustc_hash::FxHashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use super::index_file;
+use super::mmap::MappedData;
+use super::types::{BoundingBox, Tile, MapObjectOffset};
+use crate::filter::RetainTags;
+use crate::style::ClassId;
+use crate::renderer::renderer::MAX_INDEXED_ZOOM;
 
 /// Tile key is the unique index for a tile
 pub type TileKey = u64;
 
+/// Storage strategy for the tile map
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TileMapKind {
+    /// Hash map with the Fx hasher (fast for integer keys)
+    #[default]
+    Fx,
+    /// Hash map with the standard SipHash hasher
+    SipHash,
+    /// Ordered map; better locality and iteration in key (zoom) order
+    BTree,
+}
+
+/// How tiles are numbered as `TileKey`s
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TileKeyScheme {
+    /// Per-zoom cumulative offset, `Tile::index`; matches the Go version
+    #[default]
+    Quadtree,
+    /// Interleaved Z-order code, `Tile::morton_index`; spatially close
+    /// tiles get close keys and descendants form a key range
+    Morton,
+}
+
+impl TileKeyScheme {
+    pub fn key(&self, tile: &Tile) -> TileKey {
+        match self {
+            TileKeyScheme::Quadtree => tile.index(),
+            TileKeyScheme::Morton => tile.morton_index(),
+        }
+    }
+
+    pub fn tile(&self, key: TileKey) -> Tile {
+        match self {
+            TileKeyScheme::Quadtree => Tile::from_index(key),
+            TileKeyScheme::Morton => Tile::from_morton_index(key),
+        }
+    }
+}
+
+impl FromStr for TileKeyScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "quadtree" => Ok(TileKeyScheme::Quadtree),
+            "morton" => Ok(TileKeyScheme::Morton),
+            _ => Err(format!("unknown tile key scheme {:?} (expected quadtree or morton)", s)),
+        }
+    }
+}
+
+/// Map from tile key to map object offsets, backed by one of `TileMapKind`
+pub enum TileMap {
+    Fx(FxHashMap<TileKey, Vec<MapObjectOffset>>),
+    SipHash(HashMap<TileKey, Vec<MapObjectOffset>>),
+    BTree(BTreeMap<TileKey, Vec<MapObjectOffset>>),
+}
+
+impl TileMap {
+    pub fn new(kind: TileMapKind) -> Self {
+        Self::with_capacity(kind, 0)
+    }
+
+    /// Create a map with room for `capacity` tiles (ignored by `BTree`)
+    pub fn with_capacity(kind: TileMapKind, capacity: usize) -> Self {
+        match kind {
+            TileMapKind::Fx => {
+                TileMap::Fx(FxHashMap::with_capacity_and_hasher(capacity, Default::default()))
+            }
+            TileMapKind::SipHash => TileMap::SipHash(HashMap::with_capacity(capacity)),
+            TileMapKind::BTree => TileMap::BTree(BTreeMap::new()),
+        }
+    }
+
+    pub fn kind(&self) -> TileMapKind {
+        match self {
+            TileMap::Fx(_) => TileMapKind::Fx,
+            TileMap::SipHash(_) => TileMapKind::SipHash,
+            TileMap::BTree(_) => TileMapKind::BTree,
+        }
+    }
+
+    pub fn get(&self, key: &TileKey) -> Option<&Vec<MapObjectOffset>> {
+        match self {
+            TileMap::Fx(map) => map.get(key),
+            TileMap::SipHash(map) => map.get(key),
+            TileMap::BTree(map) => map.get(key),
+        }
+    }
+
+    /// Get the offsets of `key`, inserting an empty list if missing
+    pub fn get_or_default(&mut self, key: TileKey) -> &mut Vec<MapObjectOffset> {
+        match self {
+            TileMap::Fx(map) => map.entry(key).or_default(),
+            TileMap::SipHash(map) => map.entry(key).or_default(),
+            TileMap::BTree(map) => map.entry(key).or_default(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            TileMap::Fx(map) => map.len(),
+            TileMap::SipHash(map) => map.len(),
+            TileMap::BTree(map) => map.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterate over the tiles with keys in `keys`, if the map is ordered (`BTree`)
+    pub fn range(
+        &self,
+        keys: std::ops::Range<TileKey>,
+    ) -> Option<impl Iterator<Item = (&TileKey, &Vec<MapObjectOffset>)> + '_> {
+        match self {
+            TileMap::BTree(map) => Some(map.range(keys)),
+            TileMap::Fx(_) | TileMap::SipHash(_) => None,
+        }
+    }
+
+    /// Iterate over all tiles; in key order only for `BTree`
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (&TileKey, &Vec<MapObjectOffset>)> + '_> {
+        match self {
+            TileMap::Fx(map) => Box::new(map.iter()),
+            TileMap::SipHash(map) => Box::new(map.iter()),
+            TileMap::BTree(map) => Box::new(map.iter()),
+        }
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Vec<MapObjectOffset>> + '_ {
+        self.iter().map(|(_, offsets)| offsets)
+    }
+
+    pub fn values_mut(&mut self) -> Box<dyn Iterator<Item = &mut Vec<MapObjectOffset>> + '_> {
+        match self {
+            TileMap::Fx(map) => Box::new(map.values_mut()),
+            TileMap::SipHash(map) => Box::new(map.values_mut()),
+            TileMap::BTree(map) => Box::new(map.values_mut()),
+        }
+    }
+
+    /// Drop spare capacity of the map itself (not of its offset lists)
+    pub fn shrink_to_fit(&mut self) {
+        match self {
+            TileMap::Fx(map) => map.shrink_to_fit(),
+            TileMap::SipHash(map) => map.shrink_to_fit(),
+            TileMap::BTree(_) => {}
+        }
+    }
+
+    /// Consume the map into its tiles in ascending key order
+    pub fn into_sorted_vec(self) -> Vec<(TileKey, Vec<MapObjectOffset>)> {
+        let mut tiles: Vec<_> = match self {
+            TileMap::Fx(map) => map.into_iter().collect(),
+            TileMap::SipHash(map) => map.into_iter().collect(),
+            TileMap::BTree(map) => return map.into_iter().collect(),
+        };
+        tiles.sort_unstable_by_key(|&(key, _)| key);
+        tiles
+    }
+}
+
+impl<'a> IntoIterator for &'a TileMap {
+    type Item = (&'a TileKey, &'a Vec<MapObjectOffset>);
+    type IntoIter = Box<dyn Iterator<Item = Self::Item> + 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
 /// Spatial index mapping tiles to map objects
 pub struct TileIndex {
     /// Map from tile key to list of map object offsets
-    pub tiles: HashMap<TileKey, Vec<MapObjectOffset>>,
+    pub tiles: TileMap,
+    // Fixed at creation, since existing keys would change meaning
+    key_scheme: TileKeyScheme,
     /// Maximum number of points in any single map object
     pub max_points: usize,
+    /// OSM way id of each map object, sorted by offset
+    pub way_ids: Vec<(MapObjectOffset, i64)>,
+    /// Style class of each map object, sorted by offset (only with `--style`)
+    pub classes: Vec<(MapObjectOffset, ClassId)>,
+    /// Which tag keys the map objects hold (`MapObject::tags`), `None` if
+    /// tags weren't retained
+    pub retained_tags: Option<RetainTags>,
+    /// Bounding box of all indexed objects, `None` while empty
+    pub bounds: Option<BoundingBox>,
+    /// Identifies the inputs and options the index was built from, so a
+    /// saved index is only reused for the same ones (0 if unknown)
+    pub source_fingerprint: u64,
+    /// Highest indexed zoom; tiles above it are drawn from their ancestor
+    /// at this zoom (`--max-zoom`, not saved with the index)
+    pub max_zoom: u32,
 }
 
 impl TileIndex {
     pub fn new() -> Self {
+        Self::with_kind(TileMapKind::default())
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
         TileIndex {
-            tiles: HashMap::new(),
+            tiles: TileMap::with_capacity(TileMapKind::default(), capacity),
+            key_scheme: TileKeyScheme::default(),
             max_points: 0,
+            way_ids: Vec::new(),
+            classes: Vec::new(),
+            retained_tags: None,
+            bounds: None,
+            source_fingerprint: 0,
+            max_zoom: MAX_INDEXED_ZOOM,
         }
     }
 
-    pub fn with_capacity(capacity: usize) -> Self {
+    /// Create an empty index using the given tile map storage
+    pub fn with_kind(kind: TileMapKind) -> Self {
+        Self::with_key_scheme(kind, TileKeyScheme::default())
+    }
+
+    /// Create an empty index using the given tile map storage and key scheme
+    pub fn with_key_scheme(kind: TileMapKind, key_scheme: TileKeyScheme) -> Self {
         TileIndex {
-            tiles: HashMap::with_capacity(capacity),
+            tiles: TileMap::new(kind),
+            key_scheme,
             max_points: 0,
+            way_ids: Vec::new(),
+            classes: Vec::new(),
+            retained_tags: None,
+            bounds: None,
+            source_fingerprint: 0,
+            max_zoom: MAX_INDEXED_ZOOM,
         }
     }
 
+    /// Numbering of the keys in `tiles`
+    pub fn key_scheme(&self) -> TileKeyScheme {
+        self.key_scheme
+    }
+
     /// Insert a map object offset into a tile
     pub fn insert(&mut self, tile: Tile, offset: MapObjectOffset) {
-        let key = tile.index();
-        self.tiles.entry(key).or_insert_with(Vec::new).push(offset);
+        let key = self.key_scheme.key(&tile);
+        self.tiles.get_or_default(key).push(offset);
+    }
+
+    /// Sort and deduplicate each tile's offsets and drop the spare capacity
+    /// left by `insert`
+    ///
+    /// Sorted offsets read the data file front to back, which helps mmap
+    /// read locality; an offset inserted twice into a tile would be drawn
+    /// twice. Call once loading is done.
+    pub fn finalize(&mut self) {
+        for offsets in self.tiles.values_mut() {
+            offsets.sort_unstable();
+            offsets.dedup();
+            offsets.shrink_to_fit();
+        }
+        self.tiles.shrink_to_fit();
+        self.way_ids.shrink_to_fit();
+        self.classes.shrink_to_fit();
+    }
+
+    /// Heap memory held by the offset lists, way ids and classes, in bytes
+    ///
+    /// Counts allocated capacity, not just the entries in use; the tile
+    /// map's own table isn't included.
+    pub fn heap_bytes(&self) -> usize {
+        let offsets: usize = self.tiles.values().map(|offsets| offsets.capacity()).sum();
+        offsets * std::mem::size_of::<MapObjectOffset>()
+            + self.way_ids.capacity() * std::mem::size_of::<(MapObjectOffset, i64)>()
+            + self.classes.capacity() * std::mem::size_of::<(MapObjectOffset, ClassId)>()
     }
 
     /// Get map object offsets for a tile
     pub fn get(&self, tile: &Tile) -> Option<&Vec<MapObjectOffset>> {
-        self.tiles.get(&tile.index())
+        self.tiles.get(&self.key_scheme.key(tile))
+    }
+
+    /// Get map object offsets from all descendants of a tile at `target_z`
+    ///
+    /// Offsets are sorted and deduplicated, since a way usually overlaps
+    /// several sibling tiles. With Morton keys in a `BTree` map this is a
+    /// single range scan instead of a lookup per descendant.
+    pub fn get_descendants(&self, tile: &Tile, target_z: u32) -> Vec<MapObjectOffset> {
+        let range = match self.key_scheme {
+            TileKeyScheme::Morton => self.tiles.range(tile.morton_descendant_range(target_z)),
+            TileKeyScheme::Quadtree => None,
+        };
+        let mut offsets: Vec<MapObjectOffset> = match range {
+            Some(tiles) => tiles.flat_map(|(_, offsets)| offsets).copied().collect(),
+            None => tile
+                .descendants(target_z)
+                .iter()
+                .filter_map(|descendant| self.get(descendant))
+                .flatten()
+                .copied()
+                .collect(),
+        };
+        offsets.sort_unstable();
+        offsets.dedup();
+        offsets
+    }
+
+    /// Lowest and highest zoom with indexed tiles, `None` while empty
+    pub fn zoom_range(&self) -> Option<(u32, u32)> {
+        let mut zooms = self.tiles.iter().map(|(&key, _)| self.key_scheme.tile(key).z);
+        let first = zooms.next()?;
+        Some(zooms.fold((first, first), |(min, max), z| (min.min(z), max.max(z))))
+    }
+
+    /// Grow the data bounds to include `bbox`
+    pub fn extend_bounds(&mut self, bbox: &BoundingBox) {
+        self.bounds = Some(match &self.bounds {
+            Some(bounds) => bounds.union(bbox),
+            None => *bbox,
+        });
+    }
+
+    /// Record the OSM way id of the map object at `offset`
+    ///
+    /// Objects are written sequentially, so ids are normally recorded in
+    /// offset order; out-of-order inserts are kept sorted.
+    pub fn record_way_id(&mut self, offset: MapObjectOffset, way_id: i64) {
+        record_sorted(&mut self.way_ids, offset, way_id);
+    }
+
+    /// Get the OSM way id of the map object at `offset`, if recorded
+    pub fn way_id(&self, offset: MapObjectOffset) -> Option<i64> {
+        lookup_sorted(&self.way_ids, offset)
+    }
+
+    /// Record the style class of the map object at `offset`, like `record_way_id`
+    pub fn record_class(&mut self, offset: MapObjectOffset, class: ClassId) {
+        record_sorted(&mut self.classes, offset, class);
+    }
+
+    /// Get the style class of the map object at `offset`, if recorded
+    pub fn class(&self, offset: MapObjectOffset) -> Option<ClassId> {
+        lookup_sorted(&self.classes, offset)
+    }
+
+    /// Add the tiles and objects of `other`, whose offsets are shifted by `shift`
+    ///
+    /// For data files appended to this index's data file at `shift`, which
+    /// must be past all offsets indexed so far; both indexes must use the
+    /// same key scheme and style.
+    pub fn append(&mut self, other: TileIndex, shift: u64) {
+        debug_assert_eq!(self.key_scheme, other.key_scheme);
+        for (key, offsets) in other.tiles.into_sorted_vec() {
+            self.tiles.get_or_default(key).extend(offsets.into_iter().map(|offset| offset + shift));
+        }
+        fn shifted<T>(entries: Vec<(MapObjectOffset, T)>, shift: u64) -> impl Iterator<Item = (MapObjectOffset, T)> {
+            entries.into_iter().map(move |(offset, value)| (offset + shift, value))
+        }
+        self.way_ids.extend(shifted(other.way_ids, shift));
+        self.classes.extend(shifted(other.classes, shift));
+        self.update_max_points(other.max_points);
+        if let Some(bounds) = &other.bounds {
+            self.extend_bounds(bounds);
+        }
+    }
+
+    /// Serialize the index, see `index_file` for the format
+    pub fn save_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        index_file::write_index_to(writer, self)
+    }
+
+    /// Deserialize an index written by `save_to`
+    ///
+    /// `retained_tags` isn't stored and is left `None`.
+    pub fn load_from<R: Read>(reader: &mut R) -> io::Result<TileIndex> {
+        index_file::read_index_from(reader)
+    }
+
+    /// Check whether way ids were recorded for the indexed objects
+    pub fn has_way_ids(&self) -> bool {
+        self.is_empty() || !self.way_ids.is_empty()
     }
 
     /// Get the number of tiles in the index
@@ -54,6 +411,176 @@ impl TileIndex {
             self.max_points = num_points;
         }
     }
+
+    /// Check the index against the data file it is used with (`--verify-index`)
+    ///
+    /// Every key must decode to a tile within its zoom's grid and back, no
+    /// tile may be above `max_z`, and every offset must point to a whole
+    /// object in `data`. This catches indexes of a crashed load or of a
+    /// different data file. Anomalies are returned in key order.
+    pub fn verify(&self, max_z: u32, data: &MappedData) -> Vec<IndexAnomaly> {
+        let mut tiles: Vec<_> = self.tiles.iter().collect();
+        tiles.sort_unstable_by_key(|&(&key, _)| key);
+
+        let mut anomalies = Vec::new();
+        for (&key, offsets) in tiles {
+            let Some(tile) = self.decode_key(key) else {
+                anomalies.push(IndexAnomaly::InvalidKey { key, scheme: self.key_scheme });
+                continue;
+            };
+            if tile.z > max_z {
+                anomalies.push(IndexAnomaly::ZoomAboveMax { tile, max_z });
+            }
+            for &offset in offsets {
+                if data.checked_object_size(offset).is_none() {
+                    anomalies.push(IndexAnomaly::OffsetOutOfBounds { tile, offset, data_len: data.len() });
+                }
+            }
+        }
+        anomalies
+    }
+
+    /// Check that every offset lies within a data file of `data_len` bytes
+    ///
+    /// A cheap sanity check for a reloaded index; `verify` also checks the
+    /// objects the offsets point to.
+    pub fn check_offsets(&self, data_len: usize) -> io::Result<()> {
+        let offsets = self.tiles.iter().flat_map(|(_, offsets)| offsets);
+        match offsets.max() {
+            Some(&offset) if offset >= data_len as u64 => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("offset {} is past the end of the data file ({} bytes)", offset, data_len),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Decode `key` if it is exactly the key of a valid tile
+    fn decode_key(&self, key: TileKey) -> Option<Tile> {
+        // Keys of zoom 32 and above (or 0 for Morton) would overflow decoding
+        let decodable = match self.key_scheme {
+            TileKeyScheme::Quadtree => key < Tile::new(0, 0, 31).index(),
+            TileKeyScheme::Morton => key != 0,
+        };
+        if !decodable {
+            return None;
+        }
+        let tile = self.key_scheme.tile(key);
+        let in_grid = (tile.x as u64) < 1u64 << tile.z && (tile.y as u64) < 1u64 << tile.z;
+        (in_grid && self.key_scheme.key(&tile) == key).then_some(tile)
+    }
+
+    /// Build per-zoom statistics of the index
+    pub fn report(&self) -> IndexReport {
+        let mut zooms: BTreeMap<u32, ZoomStats> = BTreeMap::new();
+        let mut unique = HashSet::new();
+
+        for (&key, offsets) in &self.tiles {
+            let z = self.key_scheme.tile(key).z;
+            let count = offsets.len();
+            let stats = zooms.entry(z).or_insert(ZoomStats {
+                z,
+                tiles: 0,
+                min_objects: usize::MAX,
+                max_objects: 0,
+                mean_objects: 0.0,
+                total_entries: 0,
+            });
+            stats.tiles += 1;
+            stats.min_objects = stats.min_objects.min(count);
+            stats.max_objects = stats.max_objects.max(count);
+            stats.total_entries += count;
+            unique.extend(offsets.iter().copied());
+        }
+
+        let zooms: Vec<ZoomStats> = zooms
+            .into_values()
+            .map(|mut stats| {
+                stats.mean_objects = stats.total_entries as f64 / stats.tiles as f64;
+                stats
+            })
+            .collect();
+        let total_entries = zooms.iter().map(|stats| stats.total_entries).sum();
+
+        IndexReport {
+            zooms,
+            total_entries,
+            unique_objects: unique.len(),
+        }
+    }
+}
+
+/// Inconsistency found by `TileIndex::verify`
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum IndexAnomaly {
+    #[error("Tile key {key} doesn't decode to a tile ({scheme:?} keys)")]
+    InvalidKey { key: TileKey, scheme: TileKeyScheme },
+
+    #[error("Tile {tile} is above the maximum zoom {max_z}")]
+    ZoomAboveMax { tile: Tile, max_z: u32 },
+
+    #[error("Tile {tile} references offset {offset}, not a whole aligned object within the {data_len} byte data file")]
+    OffsetOutOfBounds {
+        tile: Tile,
+        offset: MapObjectOffset,
+        data_len: usize,
+    },
+}
+
+/// Per-zoom statistics of a tile index
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZoomStats {
+    pub z: u32,
+    /// Number of tiles with at least one object
+    pub tiles: usize,
+    pub min_objects: usize,
+    pub max_objects: usize,
+    pub mean_objects: f64,
+    /// Sum of all offset entries at this zoom
+    pub total_entries: usize,
+}
+
+/// Human-readable summary of a tile index, see `TileIndex::report`
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexReport {
+    /// Statistics per zoom level, in ascending zoom order
+    pub zooms: Vec<ZoomStats>,
+    /// Offset entries across all tiles
+    pub total_entries: usize,
+    /// Distinct map objects referenced by the index
+    pub unique_objects: usize,
+}
+
+impl fmt::Display for IndexReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>4} {:>10} {:>8} {:>8} {:>10} {:>12}",
+            "zoom", "tiles", "min", "max", "mean", "entries"
+        )?;
+        for stats in &self.zooms {
+            writeln!(
+                f,
+                "{:>4} {:>10} {:>8} {:>8} {:>10.1} {:>12}",
+                stats.z,
+                stats.tiles,
+                stats.min_objects,
+                stats.max_objects,
+                stats.mean_objects,
+                stats.total_entries
+            )?;
+        }
+        let duplication = if self.unique_objects > 0 {
+            self.total_entries as f64 / self.unique_objects as f64
+        } else {
+            0.0
+        };
+        writeln!(
+            f,
+            "total entries: {}, unique objects: {}, entries per object: {:.2}",
+            self.total_entries, self.unique_objects, duplication
+        )
+    }
 }
 
 impl Default for TileIndex {
@@ -62,6 +589,24 @@ impl Default for TileIndex {
     }
 }
 
+/// Insert or overwrite `value` at `offset`, keeping `entries` sorted by offset
+fn record_sorted<T>(entries: &mut Vec<(MapObjectOffset, T)>, offset: MapObjectOffset, value: T) {
+    match entries.last() {
+        Some(&(last, _)) if last >= offset => match entries.binary_search_by_key(&offset, |&(o, _)| o) {
+            Ok(i) => entries[i].1 = value,
+            Err(i) => entries.insert(i, (offset, value)),
+        },
+        _ => entries.push((offset, value)),
+    }
+}
+
+fn lookup_sorted<T: Copy>(entries: &[(MapObjectOffset, T)], offset: MapObjectOffset) -> Option<T> {
+    entries
+        .binary_search_by_key(&offset, |&(o, _)| o)
+        .ok()
+        .map(|i| entries[i].1)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -80,6 +625,263 @@ mod tests {
         assert_eq!(offsets[1], 200);
     }
 
+    #[test]
+    fn test_tile_map_kinds() {
+        for kind in [TileMapKind::Fx, TileMapKind::SipHash, TileMapKind::BTree] {
+            let mut index = TileIndex::with_kind(kind);
+            assert_eq!(index.tiles.kind(), kind);
+            assert!(index.is_empty());
+
+            index.insert(Tile::new(1, 1, 1), 300);
+            index.insert(Tile::new(0, 0, 0), 100);
+            index.insert(Tile::new(0, 0, 0), 200);
+
+            assert_eq!(index.len(), 2);
+            assert_eq!(index.get(&Tile::new(0, 0, 0)).unwrap(), &vec![100, 200]);
+            assert!(index.get(&Tile::new(0, 0, 1)).is_none());
+
+            let mut keys: Vec<TileKey> = index.tiles.iter().map(|(&key, _)| key).collect();
+            if kind == TileMapKind::BTree {
+                assert_eq!(keys, vec![0, 4]);
+            }
+            keys.sort_unstable();
+            assert_eq!(keys, vec![0, 4]);
+            assert_eq!(index.tiles.values().flatten().count(), 3);
+        }
+    }
+
+    #[test]
+    fn test_finalize_sorts_and_shrinks() {
+        for kind in [TileMapKind::Fx, TileMapKind::BTree] {
+            let mut index = TileIndex::with_kind(kind);
+            for offset in [300, 100, 200, 400, 500] {
+                index.insert(Tile::new(0, 0, 0), offset);
+            }
+            index.insert(Tile::new(1, 1, 1), 600);
+            index.record_way_id(100, 7);
+            let before = index.heap_bytes();
+
+            index.finalize();
+            assert_eq!(index.get(&Tile::new(0, 0, 0)).unwrap(), &vec![100, 200, 300, 400, 500]);
+            assert!(index.tiles.values().all(|offsets| offsets.capacity() == offsets.len()));
+            assert_eq!(index.heap_bytes(), 6 * 8 + 16);
+            assert!(index.heap_bytes() < before);
+        }
+    }
+
+    #[test]
+    fn test_finalize_drops_duplicate_offsets() {
+        let mut index = TileIndex::new();
+        let tile = Tile::new(0, 0, 0);
+        for offset in [200, 100, 200, 100, 300, 200] {
+            index.insert(tile, offset);
+        }
+        index.insert(Tile::new(1, 1, 1), 100);
+
+        index.finalize();
+        assert_eq!(index.get(&tile).unwrap(), &vec![100, 200, 300]);
+        assert_eq!(index.get(&Tile::new(1, 1, 1)).unwrap(), &vec![100]);
+    }
+
+    #[test]
+    fn test_morton_keys() {
+        let parent = Tile::new(1081, 660, 11);
+        let far = Tile::new(5, 5, 11);
+        let build = |kind, scheme| {
+            let mut index = TileIndex::with_key_scheme(kind, scheme);
+            for (i, tile) in parent.descendants(13).into_iter().enumerate() {
+                index.insert(tile, (i % 5) as u64);
+            }
+            index.insert(far.descendants(13)[0], 99);
+            index.insert(parent, 7);
+            index
+        };
+
+        let quadtree = build(TileMapKind::Fx, TileKeyScheme::Quadtree);
+        for kind in [TileMapKind::Fx, TileMapKind::BTree] {
+            let morton = build(kind, TileKeyScheme::Morton);
+            assert_eq!(morton.key_scheme(), TileKeyScheme::Morton);
+            assert_eq!(morton.get(&parent), Some(&vec![7]));
+            assert_eq!(morton.get_descendants(&parent, 13), vec![0, 1, 2, 3, 4]);
+            assert_eq!(morton.get_descendants(&parent, 13), quadtree.get_descendants(&parent, 13));
+            assert_eq!(morton.get_descendants(&far, 13), vec![99]);
+            assert!(morton.get_descendants(&parent, 10).is_empty());
+            assert_eq!(morton.report().zooms, quadtree.report().zooms);
+        }
+
+        // Descendants of a tile are adjacent in a Morton ordered map
+        let morton = build(TileMapKind::BTree, TileKeyScheme::Morton);
+        let keys: Vec<TileKey> = morton.tiles.iter().map(|(&key, _)| key).collect();
+        let first = keys.iter().position(|&key| key >> 4 == parent.morton_index()).unwrap();
+        assert!(keys[first..first + 16].iter().all(|&key| key >> 4 == parent.morton_index()));
+
+        assert_eq!("morton".parse(), Ok(TileKeyScheme::Morton));
+        assert!("zorder".parse::<TileKeyScheme>().is_err());
+    }
+
+    #[test]
+    fn test_verify_flags_corrupted_entries() {
+        use crate::data::serialization::write_map_object;
+        use crate::data::types::{MapObject, Point};
+
+        let mut data_file = tempfile::NamedTempFile::new().unwrap();
+        let object = MapObject::new(
+            BoundingBox::new(Point::new(9.9, 53.5), Point::new(10.0, 53.6)),
+            vec![Point::new(9.9, 53.5), Point::new(10.0, 53.6)],
+        );
+        let first = write_map_object(data_file.as_file_mut(), &object).unwrap();
+        let second = write_map_object(data_file.as_file_mut(), &object).unwrap();
+        let data = MappedData::new(data_file.path()).unwrap();
+
+        for scheme in [TileKeyScheme::Quadtree, TileKeyScheme::Morton] {
+            let mut index = TileIndex::with_key_scheme(TileMapKind::BTree, scheme);
+            index.insert(Tile::new(540, 330, 10), first);
+            index.insert(Tile::new(1081, 660, 11), second);
+            assert_eq!(index.verify(15, &data), vec![], "{:?}", scheme);
+            assert!(index.check_offsets(data.len()).is_ok());
+
+            // Past the end, misaligned, and above the zoom the index was built for
+            index.insert(Tile::new(540, 330, 10), data.len() as u64);
+            index.insert(Tile::new(540, 330, 10), second + 4);
+            index.insert(Tile::new(0, 0, 16), first);
+            assert!(index.check_offsets(data.len()).is_err());
+            let tile = Tile::new(540, 330, 10);
+            assert_eq!(
+                index.verify(15, &data),
+                vec![
+                    IndexAnomaly::OffsetOutOfBounds { tile, offset: data.len() as u64, data_len: data.len() },
+                    IndexAnomaly::OffsetOutOfBounds { tile, offset: second + 4, data_len: data.len() },
+                    IndexAnomaly::ZoomAboveMax { tile: Tile::new(0, 0, 16), max_z: 15 },
+                ],
+                "{:?}",
+                scheme
+            );
+        }
+
+        // Keys that are no tile's
+        let mut index = TileIndex::with_key_scheme(TileMapKind::BTree, TileKeyScheme::Morton);
+        index.tiles.get_or_default(0).push(first);
+        index.tiles.get_or_default(0b11).push(first); // y = 1 at zoom 0
+        let mut quadtree = TileIndex::new();
+        quadtree.tiles.get_or_default(u64::MAX).push(first);
+        assert_eq!(
+            index.verify(15, &data),
+            vec![
+                IndexAnomaly::InvalidKey { key: 0, scheme: TileKeyScheme::Morton },
+                IndexAnomaly::InvalidKey { key: 0b11, scheme: TileKeyScheme::Morton },
+            ]
+        );
+        assert_eq!(
+            quadtree.verify(15, &data),
+            vec![IndexAnomaly::InvalidKey { key: u64::MAX, scheme: TileKeyScheme::Quadtree }]
+        );
+
+        // A truncated data file cuts the last object short
+        data_file.as_file().set_len(second + 16).unwrap();
+        let truncated = MappedData::new(data_file.path()).unwrap();
+        assert_eq!(truncated.checked_object_size(first), Some(77));
+        assert_eq!(truncated.checked_object_size(second), None);
+    }
+
+    #[test]
+    fn test_tile_index_get_descendants() {
+        let mut index = TileIndex::new();
+        let tile = Tile::new(0, 0, 10);
+
+        // An important way indexed at z10 and z11, a minor way only at z11
+        index.insert(tile, 100);
+        index.insert(Tile::new(0, 0, 11), 100);
+        index.insert(Tile::new(1, 0, 11), 100);
+        index.insert(Tile::new(1, 1, 11), 200);
+        // Tile outside the z10 tile must not be included
+        index.insert(Tile::new(2, 0, 11), 300);
+
+        assert_eq!(index.get(&tile).unwrap(), &vec![100]);
+        assert_eq!(index.get_descendants(&tile, 10), vec![100]);
+        assert_eq!(index.get_descendants(&tile, 11), vec![100, 200]);
+        assert!(index.get_descendants(&tile, 12).is_empty());
+    }
+
+    #[test]
+    fn test_tile_index_report() {
+        let mut index = TileIndex::new();
+        index.insert(Tile::new(0, 0, 0), 100);
+        index.insert(Tile::new(0, 0, 0), 200);
+        index.insert(Tile::new(0, 0, 1), 100);
+        index.insert(Tile::new(1, 0, 1), 100);
+        index.insert(Tile::new(1, 0, 1), 200);
+        index.insert(Tile::new(1, 0, 1), 300);
+
+        let report = index.report();
+        assert_eq!(report.zooms.len(), 2);
+
+        let z0 = &report.zooms[0];
+        assert_eq!((z0.z, z0.tiles, z0.min_objects, z0.max_objects, z0.total_entries), (0, 1, 2, 2, 2));
+
+        let z1 = &report.zooms[1];
+        assert_eq!((z1.z, z1.tiles, z1.min_objects, z1.max_objects, z1.total_entries), (1, 2, 1, 3, 4));
+        assert_eq!(z1.mean_objects, 2.0);
+
+        assert_eq!(report.total_entries, 6);
+        assert_eq!(report.unique_objects, 3);
+
+        let text = report.to_string();
+        assert!(text.contains("entries per object: 2.00"), "{}", text);
+    }
+
+    #[test]
+    fn test_tile_index_way_ids() {
+        let mut index = TileIndex::new();
+        assert!(index.has_way_ids());
+
+        index.insert(Tile::new(0, 0, 0), 100);
+        assert!(!index.has_way_ids());
+
+        index.record_way_id(100, 7);
+        index.record_way_id(200, 9);
+        // Out of order and overwritten entries stay sorted
+        index.record_way_id(50, 3);
+        index.record_way_id(200, 11);
+
+        assert!(index.has_way_ids());
+        assert_eq!(index.way_ids, vec![(50, 3), (100, 7), (200, 11)]);
+        assert_eq!(index.way_id(100), Some(7));
+        assert_eq!(index.way_id(200), Some(11));
+        assert_eq!(index.way_id(150), None);
+
+        index.record_class(200, 1);
+        index.record_class(100, 0);
+        assert_eq!(index.classes, vec![(100, 0), (200, 1)]);
+        assert_eq!(index.class(200), Some(1));
+        assert_eq!(index.class(50), None);
+    }
+
+    #[test]
+    fn test_tile_index_bounds() {
+        use crate::data::types::Point;
+
+        let mut index = TileIndex::new();
+        assert!(index.bounds.is_none());
+
+        index.extend_bounds(&BoundingBox::new(Point::new(10.0, 50.0), Point::new(11.0, 51.0)));
+        index.extend_bounds(&BoundingBox::new(Point::new(9.5, 50.5), Point::new(10.5, 52.0)));
+        assert_eq!(
+            index.bounds,
+            Some(BoundingBox::new(Point::new(9.5, 50.0), Point::new(11.0, 52.0)))
+        );
+    }
+
+    #[test]
+    fn test_tile_index_zoom_range() {
+        let mut index = TileIndex::new();
+        assert_eq!(index.zoom_range(), None);
+
+        index.insert(Tile::new(5, 3, 4), 0);
+        index.insert(Tile::new(0, 0, 0), 0);
+        index.insert(Tile::new(100, 200, 9), 0);
+        assert_eq!(index.zoom_range(), Some((0, 9)));
+    }
+
     #[test]
     fn test_tile_index_max_points() {
         let mut index = TileIndex::new();
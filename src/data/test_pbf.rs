@@ -0,0 +1,190 @@
+//! Minimal OSM PBF writer for tests
+//!
+//! Encodes uncompressed (raw) blobs by hand so loader tests don't need
+//! osmium or fixture files. Only the fields the loader reads are written.
+
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Builder for an OSM PBF file, with a single block unless `block_size` is set
+#[derive(Default)]
+pub struct PbfBuilder {
+    strings: Vec<String>,
+    block_size: Option<usize>,
+    nodes: Vec<(i64, f64, f64, Vec<(u32, u32)>)>,
+    ways: Vec<(i64, Vec<i64>, Option<Vec<(f64, f64)>>, Vec<(u32, u32)>)>,
+}
+
+impl PbfBuilder {
+    pub fn new() -> Self {
+        PbfBuilder {
+            // Index 0 of the string table is reserved
+            strings: vec![String::new()],
+            ..Default::default()
+        }
+    }
+
+    /// Add a node at (lon, lat) with tags
+    pub fn add_node(&mut self, id: i64, lon: f64, lat: f64, tags: &[(&str, &str)]) -> &mut Self {
+        let tags = self.intern_tags(tags);
+        self.nodes.push((id, lat, lon, tags));
+        self
+    }
+
+    /// Add a way with embedded node locations (as produced by osmium add-locations-to-ways)
+    pub fn add_way(&mut self, id: i64, points: &[(f64, f64)], tags: &[(&str, &str)]) -> &mut Self {
+        let refs = (0..points.len() as i64).map(|i| id * 1000 + i).collect();
+        let tags = self.intern_tags(tags);
+        self.ways.push((id, refs, Some(points.to_vec()), tags));
+        self
+    }
+
+    /// Add a way with node references only (no embedded locations)
+    pub fn add_way_without_locations(&mut self, id: i64, node_ids: &[i64], tags: &[(&str, &str)]) -> &mut Self {
+        let tags = self.intern_tags(tags);
+        self.ways.push((id, node_ids.to_vec(), None, tags));
+        self
+    }
+
+    /// Split the elements into blocks of at most `elements` nodes and ways
+    pub fn block_size(&mut self, elements: usize) -> &mut Self {
+        self.block_size = Some(elements.max(1));
+        self
+    }
+
+    fn intern(&mut self, s: &str) -> u32 {
+        match self.strings.iter().position(|existing| existing == s) {
+            Some(index) => index as u32,
+            None => {
+                self.strings.push(s.to_string());
+                (self.strings.len() - 1) as u32
+            }
+        }
+    }
+
+    fn intern_tags(&mut self, tags: &[(&str, &str)]) -> Vec<(u32, u32)> {
+        tags.iter().map(|(k, v)| (self.intern(k), self.intern(v))).collect()
+    }
+
+    /// Encode the complete PBF file
+    pub fn build(&self) -> Vec<u8> {
+        let mut header_block = Vec::new();
+        write_bytes_field(&mut header_block, 4, b"OsmSchema-V0.6");
+
+        let mut string_table = Vec::new();
+        for s in &self.strings {
+            write_bytes_field(&mut string_table, 1, s.as_bytes());
+        }
+
+        // Encoded elements as (group field, message), nodes first
+        let mut elements = Vec::new();
+        for (id, lat, lon, tags) in &self.nodes {
+            let mut node = Vec::new();
+            write_varint_field(&mut node, 1, zigzag(*id));
+            write_packed(&mut node, 2, tags.iter().map(|(k, _)| *k as u64));
+            write_packed(&mut node, 3, tags.iter().map(|(_, v)| *v as u64));
+            write_varint_field(&mut node, 8, zigzag(to_nano(*lat)));
+            write_varint_field(&mut node, 9, zigzag(to_nano(*lon)));
+            elements.push((1, node));
+        }
+        for (id, refs, locations, tags) in &self.ways {
+            let mut way = Vec::new();
+            write_varint_field(&mut way, 1, *id as u64);
+            write_packed(&mut way, 2, tags.iter().map(|(k, _)| *k as u64));
+            write_packed(&mut way, 3, tags.iter().map(|(_, v)| *v as u64));
+            write_packed(&mut way, 8, delta(refs.iter().copied()));
+            if let Some(points) = locations {
+                write_packed(&mut way, 9, delta(points.iter().map(|(_, lat)| to_nano(*lat))));
+                write_packed(&mut way, 10, delta(points.iter().map(|(lon, _)| to_nano(*lon))));
+            }
+            elements.push((3, way));
+        }
+
+        let mut file = Vec::new();
+        write_blob(&mut file, "OSMHeader", &header_block);
+        // Every block repeats the whole string table
+        let block_size = self.block_size.unwrap_or(elements.len()).max(1);
+        for chunk in elements.chunks(block_size) {
+            let mut group = Vec::new();
+            for (field, element) in chunk {
+                write_bytes_field(&mut group, *field, element);
+            }
+            let mut primitive_block = Vec::new();
+            write_bytes_field(&mut primitive_block, 1, &string_table);
+            write_bytes_field(&mut primitive_block, 2, &group);
+            write_blob(&mut file, "OSMData", &primitive_block);
+        }
+        file
+    }
+
+    /// Write the PBF file to `path`
+    pub fn write_to<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = std::fs::File::create(path)?;
+        file.write_all(&self.build())?;
+        file.flush()
+    }
+}
+
+/// Degrees to the default 100-nanodegree granularity
+fn to_nano(deg: f64) -> i64 {
+    (deg * 1e7).round() as i64
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn delta<I: Iterator<Item = i64>>(values: I) -> impl Iterator<Item = u64> {
+    let mut previous = 0i64;
+    values.map(move |value| {
+        let d = value - previous;
+        previous = value;
+        zigzag(d)
+    })
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn write_varint_field(buf: &mut Vec<u8>, field: u32, value: u64) {
+    write_varint(buf, (field as u64) << 3);
+    write_varint(buf, value);
+}
+
+fn write_bytes_field(buf: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    write_varint(buf, ((field as u64) << 3) | 2);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn write_packed<I: Iterator<Item = u64>>(buf: &mut Vec<u8>, field: u32, values: I) {
+    let mut packed = Vec::new();
+    for value in values {
+        write_varint(&mut packed, value);
+    }
+    if !packed.is_empty() {
+        write_bytes_field(buf, field, &packed);
+    }
+}
+
+fn write_blob(file: &mut Vec<u8>, blob_type: &str, data: &[u8]) {
+    let mut blob = Vec::new();
+    write_bytes_field(&mut blob, 1, data);
+
+    let mut header = Vec::new();
+    write_bytes_field(&mut header, 1, blob_type.as_bytes());
+    write_varint_field(&mut header, 3, blob.len() as u64);
+
+    file.extend_from_slice(&(header.len() as u32).to_be_bytes());
+    file.extend_from_slice(&header);
+    file.extend_from_slice(&blob);
+}
@@ -27,6 +27,61 @@ impl Tile {
         total + level_pos as u64
     }
 
+    /// Decode a quadtree index back into a tile (inverse of `index`)
+    pub fn from_index(index: u64) -> Tile {
+        let mut z = 0u32;
+        let mut total = 0u64;
+        loop {
+            let level_tiles = 4u64.pow(z);
+            if index < total + level_tiles {
+                break;
+            }
+            total += level_tiles;
+            z += 1;
+        }
+
+        let level_pos = index - total;
+        let n = 2u64.pow(z);
+        Tile {
+            x: (level_pos % n) as u32,
+            y: (level_pos / n) as u32,
+            z,
+        }
+    }
+
+    /// Calculate the Morton (Z-order) key of the tile
+    ///
+    /// The bits of x and y are interleaved (x in the even bits) below a
+    /// marker bit at 2 * z, so tiles of all zooms get distinct keys, nearby
+    /// tiles get nearby keys, and the keys of a tile's descendants share
+    /// its key as prefix (see `morton_descendant_range`). Zooms up to 31 fit.
+    pub fn morton_index(&self) -> u64 {
+        (1u64 << (2 * self.z)) | spread_bits(self.x) | (spread_bits(self.y) << 1)
+    }
+
+    /// Decode a Morton key back into a tile (inverse of `morton_index`)
+    pub fn from_morton_index(index: u64) -> Tile {
+        let z = (63 - index.leading_zeros()) / 2;
+        let code = index & !(1u64 << (2 * z));
+        Tile {
+            x: compact_bits(code),
+            y: compact_bits(code >> 1),
+            z,
+        }
+    }
+
+    /// Morton keys of all descendants at `target_z`, a contiguous range
+    ///
+    /// Empty if target_z < self.z.
+    pub fn morton_descendant_range(&self, target_z: u32) -> std::ops::Range<u64> {
+        if target_z < self.z {
+            return 0..0;
+        }
+        let levels_down = 2 * (target_z - self.z);
+        let first = self.morton_index() << levels_down;
+        first..first + (1u64 << levels_down)
+    }
+
     /// Get the parent tile (one zoom level up)
     pub fn get_parent(&self) -> Option<Tile> {
         if self.z == 0 {
@@ -58,6 +113,67 @@ impl Tile {
             z: target_z,
         })
     }
+
+    /// Get all descendant tiles at a specific zoom level
+    /// Returns an empty list if target_z < self.z
+    pub fn descendants(&self, target_z: u32) -> Vec<Tile> {
+        if target_z < self.z {
+            return Vec::new();
+        }
+
+        let levels_down = target_z - self.z;
+        let n = 1u32 << levels_down;
+        let mut tiles = Vec::with_capacity((n * n) as usize);
+        for dy in 0..n {
+            for dx in 0..n {
+                tiles.push(Tile {
+                    x: (self.x << levels_down) + dx,
+                    y: (self.y << levels_down) + dy,
+                    z: target_z,
+                });
+            }
+        }
+        tiles
+    }
+
+    /// Get this tile and its up to 8 neighbours at the same zoom level
+    /// Tiles outside the world are left out
+    pub fn neighborhood(&self) -> Vec<Tile> {
+        let n = 1i64 << self.z;
+        let mut tiles = Vec::with_capacity(9);
+        for dy in -1..=1i64 {
+            for dx in -1..=1i64 {
+                let x = self.x as i64 + dx;
+                let y = self.y as i64 + dy;
+                if (0..n).contains(&x) && (0..n).contains(&y) {
+                    tiles.push(Tile { x: x as u32, y: y as u32, z: self.z });
+                }
+            }
+        }
+        tiles
+    }
+
+    /// Tiles of the opposite edge column that border this one across the antimeridian
+    ///
+    /// Each comes with the longitude shift (±360°) that moves its objects
+    /// next to this tile. Empty unless this tile is in the first or last
+    /// column. With `rows` 1 the rows above and below are included too
+    /// (see `neighborhood`), with 0 only this row.
+    pub fn wrapped_neighbors(&self, rows: u32) -> Vec<(Tile, f64)> {
+        let last = (1u32 << self.z) - 1;
+        let min_y = self.y.saturating_sub(rows);
+        let max_y = self.y.saturating_add(rows).min(last);
+
+        let mut tiles = Vec::new();
+        // West of x = 0 lies the last column, shifted below -180°
+        if self.x == 0 {
+            tiles.extend((min_y..=max_y).map(|y| (Tile { x: last, y, z: self.z }, -360.0)));
+        }
+        if self.x == last {
+            tiles.extend((min_y..=max_y).map(|y| (Tile { x: 0, y, z: self.z }, 360.0)));
+        }
+        tiles
+    }
 }
 
 impl fmt::Display for Tile {
@@ -79,6 +195,70 @@ impl Point {
     }
 }
 
+/// Affine pre-transform applied to source coordinates before projection
+///
+/// Maps `lon` to `lon * scale_x + offset_x` and `lat` to
+/// `lat * scale_y + offset_y`, for data in a shifted local system that
+/// should land on the WGS84 lon/lat grid. Defaults to the identity.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AffineTransform {
+    pub scale_x: f64,
+    pub scale_y: f64,
+    pub offset_x: f64,
+    pub offset_y: f64,
+}
+
+impl AffineTransform {
+    pub const IDENTITY: AffineTransform = AffineTransform {
+        scale_x: 1.0,
+        scale_y: 1.0,
+        offset_x: 0.0,
+        offset_y: 0.0,
+    };
+
+    pub fn is_identity(&self) -> bool {
+        *self == Self::IDENTITY
+    }
+
+    pub fn apply(&self, point: Point) -> Point {
+        Point::new(
+            point.lon * self.scale_x + self.offset_x,
+            point.lat * self.scale_y + self.offset_y,
+        )
+    }
+}
+
+impl Default for AffineTransform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl std::str::FromStr for AffineTransform {
+    type Err = String;
+
+    /// Parse `scale_x,scale_y,offset_x,offset_y`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values: Vec<f64> = s
+            .split(',')
+            .map(|value| value.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("invalid transform {:?}: {}", s, e))?;
+        match values[..] {
+            [scale_x, scale_y, offset_x, offset_y] => Ok(AffineTransform {
+                scale_x,
+                scale_y,
+                offset_x,
+                offset_y,
+            }),
+            _ => Err(format!(
+                "invalid transform {:?}: expected scale_x,scale_y,offset_x,offset_y",
+                s
+            )),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 #[repr(C)]
 pub struct BoundingBox {
@@ -91,20 +271,80 @@ impl BoundingBox {
         BoundingBox { min, max }
     }
 
+    /// Whether the box wraps around the ±180° meridian, i.e. spans from
+    /// `min.lon` east to 180° and on from -180° to `max.lon`
+    pub fn crosses_antimeridian(&self) -> bool {
+        self.min.lon > self.max.lon
+    }
+
+    /// Longitude ranges covered, two for boxes crossing the antimeridian
+    fn lon_ranges(&self) -> [(f64, f64); 2] {
+        if self.crosses_antimeridian() {
+            [(self.min.lon, 180.0), (-180.0, self.max.lon)]
+        } else {
+            [(self.min.lon, self.max.lon); 2]
+        }
+    }
+
     /// Check if a point is inside this bounding box
     pub fn contains(&self, point: &Point) -> bool {
         point.lat >= self.min.lat
             && point.lat <= self.max.lat
-            && point.lon >= self.min.lon
-            && point.lon <= self.max.lon
+            && self.lon_ranges().iter().any(|&(min, max)| point.lon >= min && point.lon <= max)
     }
 
     /// Check if this bounding box overlaps with another
     pub fn overlaps(&self, other: &BoundingBox) -> bool {
         self.min.lat <= other.max.lat
             && self.max.lat >= other.min.lat
-            && self.min.lon <= other.max.lon
-            && self.max.lon >= other.min.lon
+            && self.lon_ranges().iter().any(|&(min, max)| {
+                other.lon_ranges().iter().any(|&(other_min, other_max)| min <= other_max && max >= other_min)
+            })
+    }
+
+    /// Check if the segment from `a` to `b` passes through this bounding box
+    ///
+    /// Finer than comparing the segment's bounding box: a diagonal segment
+    /// passing a corner of the box doesn't count. The segment is straight in
+    /// degrees, which differs from the projected line by far less than a
+    /// pixel for the short segments of OSM ways.
+    pub fn intersects_segment(&self, a: &Point, b: &Point) -> bool {
+        self.lon_ranges().iter().any(|&(min_lon, max_lon)| {
+            if a.lon.max(b.lon) < min_lon
+                || a.lon.min(b.lon) > max_lon
+                || a.lat.max(b.lat) < self.min.lat
+                || a.lat.min(b.lat) > self.max.lat
+            {
+                return false;
+            }
+            // The segment misses the box if all corners lie on the same side of its line
+            let side = |lon: f64, lat: f64| (b.lon - a.lon) * (lat - a.lat) - (b.lat - a.lat) * (lon - a.lon);
+            let sides = [
+                side(min_lon, self.min.lat),
+                side(max_lon, self.min.lat),
+                side(min_lon, self.max.lat),
+                side(max_lon, self.max.lat),
+            ];
+            !(sides.iter().all(|&s| s > 0.0) || sides.iter().all(|&s| s < 0.0))
+        })
+    }
+
+    /// Check if any segment of the polyline `points` passes through this
+    /// bounding box, see `intersects_segment`; a single point must be inside
+    pub fn intersects_polyline(&self, points: &[Point]) -> bool {
+        match points {
+            [] => false,
+            [point] => self.contains(point),
+            _ => points.windows(2).any(|pair| self.intersects_segment(&pair[0], &pair[1])),
+        }
+    }
+
+    /// Smallest bounding box containing both this one and `other`
+    pub fn union(&self, other: &BoundingBox) -> BoundingBox {
+        BoundingBox {
+            min: Point::new(self.min.lon.min(other.min.lon), self.min.lat.min(other.min.lat)),
+            max: Point::new(self.max.lon.max(other.max.lon), self.max.lat.max(other.max.lat)),
+        }
     }
 
     /// Get the center point of this bounding box
@@ -116,6 +356,10 @@ impl BoundingBox {
     }
 
     /// Create bounding box from a list of points
+    ///
+    /// Consecutive points more than 180° of longitude apart are taken to be
+    /// joined across the antimeridian, which gives a box crossing it (see
+    /// `crosses_antimeridian`) instead of one spanning the globe.
     pub fn from_points(points: &[Point]) -> Option<Self> {
         if points.is_empty() {
             return None;
@@ -125,12 +369,37 @@ impl BoundingBox {
         let mut min_lat = f64::MAX;
         let mut max_lon = f64::MIN;
         let mut max_lat = f64::MIN;
+        // Longitudes continued past ±180° where the points cross the antimeridian
+        let mut unwrapped_lon = points[0].lon;
+        let (mut min_unwrapped, mut max_unwrapped) = (unwrapped_lon, unwrapped_lon);
+        let mut crosses = false;
 
-        for point in points {
+        for (i, point) in points.iter().enumerate() {
             min_lon = min_lon.min(point.lon);
             min_lat = min_lat.min(point.lat);
             max_lon = max_lon.max(point.lon);
             max_lat = max_lat.max(point.lat);
+
+            if i > 0 {
+                let mut step = point.lon - points[i - 1].lon;
+                if step.abs() > 180.0 {
+                    crosses = true;
+                    step -= 360.0_f64.copysign(step);
+                }
+                unwrapped_lon += step;
+                min_unwrapped = min_unwrapped.min(unwrapped_lon);
+                max_unwrapped = max_unwrapped.max(unwrapped_lon);
+            }
+        }
+
+        if crosses && max_unwrapped - min_unwrapped < 360.0 {
+            let normalize = |lon: f64| (lon + 180.0).rem_euclid(360.0) - 180.0;
+            min_lon = normalize(min_unwrapped);
+            max_lon = normalize(max_unwrapped);
+        } else if crosses {
+            // Around the globe
+            min_lon = -180.0;
+            max_lon = 180.0;
         }
 
         Some(BoundingBox {
@@ -149,11 +418,47 @@ pub struct Pixel {
 /// Offset into the memory-mapped file
 pub type MapObjectOffset = u64;
 
-/// Map object representing a way from OSM
+/// How a map object is drawn, stored as a byte in the data file
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ObjectKind {
+    /// Open way
+    #[default]
+    Line = 0,
+    /// Closed way, drawn as its outline or filled (`RendererOptions::fill_polygons`)
+    Polygon = 1,
+    /// Single node (POI)
+    Point = 2,
+}
+
+impl ObjectKind {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(ObjectKind::Line),
+            1 => Some(ObjectKind::Polygon),
+            2 => Some(ObjectKind::Point),
+            _ => None,
+        }
+    }
+
+    /// Kind of a way with `points`: closed ways are polygons
+    pub fn of_way(points: &[Point]) -> Self {
+        if points.len() > 2 && points.first() == points.last() {
+            ObjectKind::Polygon
+        } else {
+            ObjectKind::Line
+        }
+    }
+}
+
+/// Map object representing a way or node from OSM
 #[derive(Debug, Clone)]
 pub struct MapObject {
     pub bounding_box: BoundingBox,
     pub points: Vec<Point>,
+    pub kind: ObjectKind,
+    /// Retained OSM tags (`--retain-tags`), empty if none
+    pub tags: Vec<(String, String)>,
 }
 
 impl MapObject {
@@ -161,10 +466,42 @@ impl MapObject {
         MapObject {
             bounding_box,
             points,
+            kind: ObjectKind::Line,
+            tags: Vec::new(),
+        }
+    }
+
+    /// Single-point object at `point`
+    pub fn point(point: Point) -> Self {
+        MapObject {
+            bounding_box: BoundingBox::new(point, point),
+            points: vec![point],
+            kind: ObjectKind::Point,
+            tags: Vec::new(),
         }
     }
 }
 
+/// Spread the bits of `v` to the even bits of the result
+fn spread_bits(v: u32) -> u64 {
+    let mut v = v as u64;
+    v = (v | (v << 16)) & 0x0000_ffff_0000_ffff;
+    v = (v | (v << 8)) & 0x00ff_00ff_00ff_00ff;
+    v = (v | (v << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+    v = (v | (v << 2)) & 0x3333_3333_3333_3333;
+    (v | (v << 1)) & 0x5555_5555_5555_5555
+}
+
+/// Collect the even bits of `v` (inverse of `spread_bits`)
+fn compact_bits(v: u64) -> u32 {
+    let mut v = v & 0x5555_5555_5555_5555;
+    v = (v | (v >> 1)) & 0x3333_3333_3333_3333;
+    v = (v | (v >> 2)) & 0x0f0f_0f0f_0f0f_0f0f;
+    v = (v | (v >> 4)) & 0x00ff_00ff_00ff_00ff;
+    v = (v | (v >> 8)) & 0x0000_ffff_0000_ffff;
+    (v | (v >> 16)) as u32
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -182,6 +519,68 @@ mod tests {
         assert_eq!(Tile::new(0, 0, 2).index(), 5);
     }
 
+    #[test]
+    fn test_tile_from_index() {
+        assert_eq!(Tile::from_index(0), Tile::new(0, 0, 0));
+        assert_eq!(Tile::from_index(4), Tile::new(1, 1, 1));
+        assert_eq!(Tile::from_index(5), Tile::new(0, 0, 2));
+
+        for tile in [
+            Tile::new(3, 5, 3),
+            Tile::new(1081, 660, 11),
+            Tile::new(32767, 0, 15),
+            Tile::new(0, 32767, 15),
+        ] {
+            assert_eq!(Tile::from_index(tile.index()), tile);
+        }
+    }
+
+    #[test]
+    fn test_tile_morton_index() {
+        assert_eq!(Tile::new(0, 0, 0).morton_index(), 1);
+        assert_eq!(Tile::new(0, 0, 1).morton_index(), 0b100);
+        assert_eq!(Tile::new(1, 0, 1).morton_index(), 0b101);
+        assert_eq!(Tile::new(0, 1, 1).morton_index(), 0b110);
+        assert_eq!(Tile::new(3, 5, 3).morton_index(), 0b1_100_111);
+
+        for tile in [
+            Tile::new(0, 0, 0),
+            Tile::new(3, 5, 3),
+            Tile::new(1081, 660, 11),
+            Tile::new(32767, 0, 15),
+            Tile::new(0, 32767, 15),
+            Tile::new((1 << 31) - 1, 12345, 31),
+        ] {
+            assert_eq!(Tile::from_morton_index(tile.morton_index()), tile);
+        }
+
+        // Descendants are the keys with the tile's key as prefix
+        let tile = Tile::new(1081, 660, 11);
+        let range = tile.morton_descendant_range(13);
+        assert_eq!(range.end - range.start, 16);
+        let mut keys: Vec<u64> = tile.descendants(13).iter().map(Tile::morton_index).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, range.collect::<Vec<_>>());
+        assert!(keys.iter().all(|key| key >> 4 == tile.morton_index()));
+        assert_eq!(tile.morton_descendant_range(11), tile.morton_index()..tile.morton_index() + 1);
+        assert!(tile.morton_descendant_range(10).is_empty());
+    }
+
+    #[test]
+    fn test_tile_neighborhood() {
+        assert_eq!(Tile::new(0, 0, 0).neighborhood(), vec![Tile::new(0, 0, 0)]);
+
+        let corner = Tile::new(0, 0, 2).neighborhood();
+        assert_eq!(corner, vec![
+            Tile::new(0, 0, 2), Tile::new(1, 0, 2),
+            Tile::new(0, 1, 2), Tile::new(1, 1, 2),
+        ]);
+
+        let inner = Tile::new(2, 2, 3).neighborhood();
+        assert_eq!(inner.len(), 9);
+        assert!(inner.contains(&Tile::new(1, 1, 3)) && inner.contains(&Tile::new(3, 3, 3)));
+    }
+
     #[test]
     fn test_tile_parent() {
         let tile = Tile::new(4, 6, 3);
@@ -216,6 +615,32 @@ mod tests {
         assert_eq!(ancestor, Tile::new(8, 16, 15));
     }
 
+    #[test]
+    fn test_tile_descendants() {
+        let tile = Tile::new(1, 2, 3);
+        assert_eq!(tile.descendants(3), vec![tile]);
+
+        let children = tile.descendants(4);
+        assert_eq!(
+            children,
+            vec![
+                Tile::new(2, 4, 4),
+                Tile::new(3, 4, 4),
+                Tile::new(2, 5, 4),
+                Tile::new(3, 5, 4),
+            ]
+        );
+        for child in &children {
+            assert_eq!(child.get_parent(), Some(tile));
+        }
+
+        let grandchildren = tile.descendants(5);
+        assert_eq!(grandchildren.len(), 16);
+        assert!(grandchildren.iter().all(|t| t.get_ancestor(3) == Some(tile)));
+
+        assert!(tile.descendants(2).is_empty());
+    }
+
     #[test]
     fn test_bounding_box_contains() {
         let bbox = BoundingBox::new(
@@ -246,6 +671,58 @@ mod tests {
         assert!(bbox1.overlaps(&bbox2));
         assert!(bbox2.overlaps(&bbox1));
         assert!(!bbox1.overlaps(&bbox3));
+
+        assert_eq!(
+            bbox1.union(&bbox3),
+            BoundingBox::new(Point::new(10.0, 20.0), Point::new(70.0, 80.0))
+        );
+    }
+
+    #[test]
+    fn test_bounding_box_union() {
+        let bbox = BoundingBox::new(Point::new(10.0, 20.0), Point::new(30.0, 40.0));
+
+        // Disjoint boxes: spans the gap between them, in either order
+        let disjoint = BoundingBox::new(Point::new(-50.0, 45.0), Point::new(-40.0, 60.0));
+        let expected = BoundingBox::new(Point::new(-50.0, 20.0), Point::new(30.0, 60.0));
+        assert_eq!(bbox.union(&disjoint), expected);
+        assert_eq!(disjoint.union(&bbox), expected);
+
+        // Nested boxes: the outer one
+        let nested = BoundingBox::new(Point::new(15.0, 25.0), Point::new(20.0, 30.0));
+        assert_eq!(bbox.union(&nested), bbox);
+        assert_eq!(nested.union(&bbox), bbox);
+        assert_eq!(bbox.union(&bbox), bbox);
+    }
+
+    #[test]
+    fn test_bounding_box_intersects_segment() {
+        let bbox = BoundingBox::new(Point::new(10.0, 20.0), Point::new(30.0, 40.0));
+
+        // Inside, crossing and touching
+        assert!(bbox.intersects_segment(&Point::new(15.0, 25.0), &Point::new(20.0, 30.0)));
+        assert!(bbox.intersects_segment(&Point::new(0.0, 30.0), &Point::new(50.0, 30.0)));
+        assert!(bbox.intersects_segment(&Point::new(0.0, 50.0), &Point::new(20.0, 30.0)));
+        assert!(bbox.intersects_segment(&Point::new(0.0, 10.0), &Point::new(10.0, 20.0)));
+        // Segment bounding boxes overlap, but the diagonal passes the corner
+        assert!(!bbox.intersects_segment(&Point::new(0.0, 35.0), &Point::new(15.0, 50.0)));
+        assert!(bbox.overlaps(&BoundingBox::from_points(&[Point::new(0.0, 35.0), Point::new(15.0, 50.0)]).unwrap()));
+        // Entirely beside the box
+        assert!(!bbox.intersects_segment(&Point::new(40.0, 0.0), &Point::new(40.0, 60.0)));
+
+        // An L-shaped way around the corner misses it, though its bounding box overlaps
+        let l_shape = [Point::new(0.0, 35.0), Point::new(0.0, 45.0), Point::new(15.0, 45.0)];
+        assert!(bbox.overlaps(&BoundingBox::from_points(&l_shape).unwrap()));
+        assert!(!bbox.intersects_polyline(&l_shape));
+        let through = [Point::new(0.0, 45.0), Point::new(20.0, 30.0)];
+        assert!(bbox.intersects_polyline(&through));
+        assert!(bbox.intersects_polyline(&[Point::new(20.0, 30.0)]));
+        assert!(!bbox.intersects_polyline(&[]));
+
+        // Boxes across the antimeridian are tested on both sides
+        let wrapping = BoundingBox::new(Point::new(170.0, -10.0), Point::new(-170.0, 10.0));
+        assert!(wrapping.intersects_segment(&Point::new(-175.0, -20.0), &Point::new(-175.0, 20.0)));
+        assert!(!wrapping.intersects_segment(&Point::new(0.0, -20.0), &Point::new(0.0, 20.0)));
     }
 
     #[test]
@@ -261,5 +738,56 @@ mod tests {
         assert_eq!(bbox.min.lat, 20.0);
         assert_eq!(bbox.max.lon, 30.0);
         assert_eq!(bbox.max.lat, 40.0);
+        assert!(!bbox.crosses_antimeridian());
+    }
+
+    #[test]
+    fn test_bounding_box_across_antimeridian() {
+        // Fiji: a way from lon 179 to -179 spans 2°, not the globe
+        let bbox = BoundingBox::from_points(&[Point::new(179.0, -17.0), Point::new(-179.0, -16.0)]).unwrap();
+        assert!(bbox.crosses_antimeridian());
+        assert_eq!(bbox, BoundingBox::new(Point::new(179.0, -17.0), Point::new(-179.0, -16.0)));
+        assert!(bbox.contains(&Point::new(179.5, -16.5)));
+        assert!(bbox.contains(&Point::new(-179.5, -16.5)));
+        assert!(!bbox.contains(&Point::new(0.0, -16.5)));
+
+        let east = BoundingBox::new(Point::new(178.0, -18.0), Point::new(179.5, -16.0));
+        let west = BoundingBox::new(Point::new(-180.0, -18.0), Point::new(-179.5, -16.0));
+        let greenwich = BoundingBox::new(Point::new(-1.0, -18.0), Point::new(1.0, -16.0));
+        assert!(bbox.overlaps(&east) && east.overlaps(&bbox));
+        assert!(bbox.overlaps(&west) && west.overlaps(&bbox));
+        assert!(!bbox.overlaps(&greenwich) && !greenwich.overlaps(&bbox));
+    }
+
+    #[test]
+    fn test_affine_transform() {
+        let point = Point::new(2.0, -3.0);
+        assert_eq!(AffineTransform::default().apply(point), point);
+        assert!(AffineTransform::default().is_identity());
+
+        let transform: AffineTransform = "2, 0.5, 10, 50".parse().unwrap();
+        assert_eq!(transform.apply(point), Point::new(14.0, 48.5));
+        assert!(!transform.is_identity());
+
+        assert!("1,1,0".parse::<AffineTransform>().is_err());
+        assert!("1,1,0,x".parse::<AffineTransform>().is_err());
+    }
+
+    #[test]
+    fn test_wrapped_neighbors() {
+        // Interior columns don't wrap
+        assert!(Tile::new(1, 1, 2).wrapped_neighbors(1).is_empty());
+
+        assert_eq!(Tile::new(0, 1, 2).wrapped_neighbors(0), vec![(Tile::new(3, 1, 2), -360.0)]);
+        assert_eq!(
+            Tile::new(3, 0, 2).wrapped_neighbors(1),
+            vec![(Tile::new(0, 0, 2), 360.0), (Tile::new(0, 1, 2), 360.0)]
+        );
+
+        // The only z0 tile borders itself on both sides
+        assert_eq!(
+            Tile::new(0, 0, 0).wrapped_neighbors(1),
+            vec![(Tile::new(0, 0, 0), -360.0), (Tile::new(0, 0, 0), 360.0)]
+        );
     }
 }
@@ -0,0 +1,206 @@
+use image::codecs::avif::AvifEncoder;
+use image::{ExtendedColorType, ImageEncoder, RgbaImage};
+use std::fmt;
+use std::str::FromStr;
+
+/// AVIF encoder speed, 1 (slowest, smallest) to 10
+pub const AVIF_SPEED: u8 = 8;
+
+/// AVIF encoder quality, 1 to 100
+pub const AVIF_QUALITY: u8 = 80;
+
+/// Encoded tile image format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TileFormat {
+    /// Lossy AV1 still image
+    Avif,
+    /// Lossless WebP
+    WebP,
+    Png,
+}
+
+impl TileFormat {
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            TileFormat::Avif => "image/avif",
+            TileFormat::WebP => "image/webp",
+            TileFormat::Png => "image/png",
+        }
+    }
+
+    /// Encode in this format; indexed PNG goes through `encode_png_indexed`
+    pub fn encode(&self, image: &RgbaImage) -> Result<Vec<u8>, image::ImageError> {
+        let mut buffer = Vec::new();
+        let (width, height) = image.dimensions();
+        match self {
+            TileFormat::Avif => AvifEncoder::new_with_speed_quality(&mut buffer, AVIF_SPEED, AVIF_QUALITY)
+                .write_image(image.as_raw(), width, height, ExtendedColorType::Rgba8)?,
+            TileFormat::WebP => return super::webp::encode_webp(image),
+            TileFormat::Png => return super::png::encode_png(image),
+        }
+        Ok(buffer)
+    }
+}
+
+impl fmt::Display for TileFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TileFormat::Avif => "avif",
+            TileFormat::WebP => "webp",
+            TileFormat::Png => "png",
+        })
+    }
+}
+
+impl FromStr for TileFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "avif" => Ok(TileFormat::Avif),
+            "webp" => Ok(TileFormat::WebP),
+            "png" => Ok(TileFormat::Png),
+            other => Err(format!("unknown tile format {:?} (expected avif, webp or png)", other)),
+        }
+    }
+}
+
+/// Enabled tile formats, most preferred first (`--format-preference`)
+///
+/// Each request gets the first format its `Accept` header allows, or the
+/// last one in the chain if it allows none or names no image type, so the
+/// chain should end in a format every client takes (PNG). The default is
+/// PNG only.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatPreference(Vec<TileFormat>);
+
+impl Default for FormatPreference {
+    fn default() -> Self {
+        FormatPreference(vec![TileFormat::Png])
+    }
+}
+
+impl FormatPreference {
+    pub fn formats(&self) -> &[TileFormat] {
+        &self.0
+    }
+
+    /// Whether responses depend on the `Accept` header
+    pub fn is_negotiated(&self) -> bool {
+        self.0.len() > 1
+    }
+
+    /// Pick the format for a request with this `Accept` header
+    ///
+    /// Formats must be named by the header, or allowed by `image/*`. A
+    /// missing header or a bare `*/*` says nothing about image support, so
+    /// it gets the end of the chain, like unknown types. Quality values only
+    /// matter in that `q=0` excludes a type; the order is the server's
+    /// preference.
+    pub fn negotiate(&self, accept: Option<&str>) -> TileFormat {
+        let last = *self.0.last().expect("preference has at least one format");
+        let Some(accept) = accept else {
+            return last;
+        };
+        self.0
+            .iter()
+            .copied()
+            .find(|format| accepts(accept, format.mime_type()))
+            .unwrap_or(last)
+    }
+}
+
+impl FromStr for FormatPreference {
+    type Err = String;
+
+    /// Parse a comma separated chain such as `avif,webp,png`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut formats: Vec<TileFormat> = Vec::new();
+        for format in s.split(',') {
+            let format = format.parse()?;
+            if formats.contains(&format) {
+                return Err(format!("tile format {} is listed twice", format));
+            }
+            formats.push(format);
+        }
+        Ok(FormatPreference(formats))
+    }
+}
+
+/// Whether an `Accept` header allows `mime_type`, directly or by `type/*`
+///
+/// The most specific matching media range decides, as in RFC 9110, but
+/// `*/*` alone doesn't allow a type.
+fn accepts(accept: &str, mime_type: &str) -> bool {
+    let (kind, _) = mime_type.split_once('/').unwrap_or((mime_type, ""));
+    let mut best: Option<(u8, bool)> = None;
+    for range in accept.split(',') {
+        let mut params = range.split(';');
+        let media_range = params.next().unwrap_or("").trim().to_ascii_lowercase();
+        let specificity = if media_range == mime_type {
+            2
+        } else if media_range.strip_suffix("/*") == Some(kind) {
+            1
+        } else if media_range == "*/*" {
+            0
+        } else {
+            continue;
+        };
+        let allowed = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .next()
+            .and_then(|q| q.trim().parse::<f32>().ok())
+            .is_none_or(|q| q > 0.0);
+        if best.is_none_or(|(best_specificity, _)| specificity > best_specificity) {
+            best = Some((specificity, allowed));
+        }
+    }
+    best.is_some_and(|(specificity, allowed)| allowed && specificity > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate_in_preference_order() {
+        let preference: FormatPreference = "avif,webp,png".parse().unwrap();
+        assert_eq!(preference.negotiate(Some("image/webp,image/png")), TileFormat::WebP);
+        assert_eq!(preference.negotiate(Some("image/png, image/webp;q=0.5")), TileFormat::WebP);
+        assert_eq!(preference.negotiate(Some("image/avif,image/webp,*/*;q=0.8")), TileFormat::Avif);
+        assert_eq!(preference.negotiate(Some("image/png")), TileFormat::Png);
+        // Nothing acceptable, or nothing known: the end of the chain
+        assert_eq!(preference.negotiate(Some("text/html")), TileFormat::Png);
+        assert_eq!(preference.negotiate(None), TileFormat::Png);
+        assert_eq!(preference.negotiate(Some("*/*")), TileFormat::Png);
+
+        // Client exclusions and wildcards
+        assert_eq!(preference.negotiate(Some("image/*, image/avif;q=0")), TileFormat::WebP);
+        assert_eq!(preference.negotiate(Some("image/*;q=0, */*")), TileFormat::Png);
+
+        let webp_last: FormatPreference = "png,webp".parse().unwrap();
+        assert_eq!(webp_last.negotiate(Some("image/webp,image/png")), TileFormat::Png);
+        assert!(webp_last.is_negotiated());
+        assert!(!FormatPreference::default().is_negotiated());
+
+        assert!("png,gif".parse::<FormatPreference>().is_err());
+        assert!("png,png".parse::<FormatPreference>().is_err());
+        assert!("".parse::<FormatPreference>().is_err());
+    }
+
+    #[test]
+    fn test_encode_formats() {
+        let image = RgbaImage::from_fn(16, 16, |x, y| image::Rgba([x as u8 * 16, y as u8 * 16, 0, 255]));
+
+        let webp = TileFormat::WebP.encode(&image).unwrap();
+        assert_eq!((&webp[0..4], &webp[8..12]), (&b"RIFF"[..], &b"WEBP"[..]));
+        let decoded = image::load_from_memory_with_format(&webp, image::ImageFormat::WebP).unwrap();
+        assert_eq!(decoded.to_rgba8(), image, "lossless");
+
+        let avif = TileFormat::Avif.encode(&image).unwrap();
+        assert_eq!(&avif[4..12], b"ftypavif");
+
+        let png = TileFormat::Png.encode(&image).unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+    }
+}
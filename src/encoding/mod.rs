@@ -1 +1,6 @@
+pub mod datauri;
+pub mod format;
+pub mod mvt;
 pub mod png;
+pub mod vector;
+pub mod webp;
@@ -0,0 +1,303 @@
+//! Mapbox Vector Tile encoding (vector tile spec 2.1)
+//!
+//! Tiles have a single layer with the objects' retained tags as string
+//! properties. Geometry is quantized with `quantize_mvt` and not clipped,
+//! so features may reach outside the extent.
+
+use super::vector::quantize_mvt;
+use crate::data::types::{BoundingBox, ObjectKind, Point};
+use crate::projection::tile_to_pixel;
+use rustc_hash::FxHashMap;
+
+/// MIME type of encoded vector tiles
+pub const MVT_MIME_TYPE: &str = "application/vnd.mapbox-vector-tile";
+
+/// Name of the layer holding all objects
+pub const MVT_LAYER_NAME: &str = "osm";
+
+/// Version of the vector tile spec the layers follow
+const MVT_VERSION: u32 = 2;
+
+// Geometry types of the spec's `GeomType` enum
+const GEOM_POINT: u32 = 1;
+const GEOM_LINESTRING: u32 = 2;
+const GEOM_POLYGON: u32 = 3;
+
+// Geometry commands
+const MOVE_TO: u32 = 1;
+const LINE_TO: u32 = 2;
+const CLOSE_PATH: u32 = 7;
+
+// Protobuf wire types
+const VARINT: u32 = 0;
+const LENGTH_DELIMITED: u32 = 2;
+
+/// One layer of a vector tile, built feature by feature
+///
+/// Keys and values are shared between features, as the spec intends.
+pub struct MvtLayer {
+    name: String,
+    extent: u32,
+    keys: Vec<String>,
+    key_indices: FxHashMap<String, u32>,
+    values: Vec<String>,
+    value_indices: FxHashMap<String, u32>,
+    // Encoded features, each as a length-delimited layer field
+    features: Vec<u8>,
+    feature_count: usize,
+}
+
+impl MvtLayer {
+    pub fn new(name: &str, extent: u32) -> Self {
+        MvtLayer {
+            name: name.to_string(),
+            extent,
+            keys: Vec::new(),
+            key_indices: FxHashMap::default(),
+            values: Vec::new(),
+            value_indices: FxHashMap::default(),
+            features: Vec::new(),
+            feature_count: 0,
+        }
+    }
+
+    /// Number of features added so far
+    pub fn len(&self) -> usize {
+        self.feature_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.feature_count == 0
+    }
+
+    /// Add a map object drawn in the tile `bbox`
+    ///
+    /// Polygons that aren't closed are added as lines. Returns false if the
+    /// geometry collapses on the grid and nothing was added.
+    pub fn add_object<'a>(
+        &mut self,
+        kind: ObjectKind,
+        points: &[Point],
+        bbox: &BoundingBox,
+        tags: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> bool {
+        let Some((geom_type, geometry)) = encode_geometry(kind, points, bbox, self.extent) else {
+            return false;
+        };
+
+        let mut tag_indices = Vec::new();
+        for (key, value) in tags {
+            tag_indices.push(intern(&mut self.keys, &mut self.key_indices, key));
+            tag_indices.push(intern(&mut self.values, &mut self.value_indices, value));
+        }
+
+        let mut feature = Vec::new();
+        if !tag_indices.is_empty() {
+            write_packed(&mut feature, 2, &tag_indices);
+        }
+        write_field_varint(&mut feature, 3, geom_type);
+        write_packed(&mut feature, 4, &geometry);
+        write_field_bytes(&mut self.features, 2, &feature);
+        self.feature_count += 1;
+        true
+    }
+
+    /// Encode a tile holding this layer, or no layer if it has no features
+    pub fn encode_tile(&self) -> Vec<u8> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+        let mut layer = Vec::new();
+        write_field_bytes(&mut layer, 1, self.name.as_bytes());
+        layer.extend_from_slice(&self.features);
+        for key in &self.keys {
+            write_field_bytes(&mut layer, 3, key.as_bytes());
+        }
+        for value in &self.values {
+            // Value message with only `string_value`
+            let mut encoded = Vec::new();
+            write_field_bytes(&mut encoded, 1, value.as_bytes());
+            write_field_bytes(&mut layer, 4, &encoded);
+        }
+        write_field_varint(&mut layer, 5, self.extent);
+        write_field_varint(&mut layer, 15, MVT_VERSION);
+
+        let mut tile = Vec::with_capacity(layer.len() + 8);
+        write_field_bytes(&mut tile, 3, &layer);
+        tile
+    }
+}
+
+/// Index of `s` in `table`, adding it if new
+fn intern(table: &mut Vec<String>, indices: &mut FxHashMap<String, u32>, s: &str) -> u32 {
+    if let Some(&index) = indices.get(s) {
+        return index;
+    }
+    let index = table.len() as u32;
+    table.push(s.to_string());
+    indices.insert(s.to_string(), index);
+    index
+}
+
+/// Geometry type and command integers of an object, `None` if it collapses
+fn encode_geometry(kind: ObjectKind, points: &[Point], bbox: &BoundingBox, extent: u32) -> Option<(u32, Vec<u32>)> {
+    if kind == ObjectKind::Point {
+        let pixel = tile_to_pixel(points.first()?, bbox, extent);
+        let (x, y) = (pixel.x.round() as i32, pixel.y.round() as i32);
+        return Some((GEOM_POINT, vec![command(MOVE_TO, 1), zigzag(x), zigzag(y)]));
+    }
+
+    let mut positions = quantize_mvt(points, bbox, extent)?;
+    let closed = positions.first() == positions.last();
+    if kind == ObjectKind::Line || !closed {
+        return Some((GEOM_LINESTRING, path_commands(&positions)));
+    }
+
+    // Exterior rings wind clockwise in tile coordinates (positive area with y down)
+    positions.pop();
+    if ring_area(&positions) < 0 {
+        positions.reverse();
+    }
+    let mut geometry = path_commands(&positions);
+    geometry.push(command(CLOSE_PATH, 1));
+    Some((GEOM_POLYGON, geometry))
+}
+
+/// MoveTo the first position, then LineTo the others, as zigzag deltas
+fn path_commands(positions: &[(i32, i32)]) -> Vec<u32> {
+    let mut geometry = Vec::with_capacity(2 * positions.len() + 2);
+    let mut cursor = (0, 0);
+    for (i, &(x, y)) in positions.iter().enumerate() {
+        match i {
+            0 => geometry.push(command(MOVE_TO, 1)),
+            1 => geometry.push(command(LINE_TO, positions.len() as u32 - 1)),
+            _ => {}
+        }
+        geometry.push(zigzag(x - cursor.0));
+        geometry.push(zigzag(y - cursor.1));
+        cursor = (x, y);
+    }
+    geometry
+}
+
+/// Twice the signed area of an unclosed ring (shoelace formula)
+fn ring_area(ring: &[(i32, i32)]) -> i64 {
+    let n = ring.len();
+    (0..n)
+        .map(|i| {
+            let ((x0, y0), (x1, y1)) = (ring[i], ring[(i + 1) % n]);
+            x0 as i64 * y1 as i64 - x1 as i64 * y0 as i64
+        })
+        .sum()
+}
+
+fn command(id: u32, count: u32) -> u32 {
+    (id & 0x7) | (count << 3)
+}
+
+fn zigzag(n: i32) -> u32 {
+    ((n << 1) ^ (n >> 31)) as u32
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn write_key(out: &mut Vec<u8>, field: u32, wire_type: u32) {
+    write_varint(out, u64::from(field << 3 | wire_type));
+}
+
+fn write_field_varint(out: &mut Vec<u8>, field: u32, value: u32) {
+    write_key(out, field, VARINT);
+    write_varint(out, u64::from(value));
+}
+
+fn write_field_bytes(out: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    write_key(out, field, LENGTH_DELIMITED);
+    write_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn write_packed(out: &mut Vec<u8>, field: u32, values: &[u32]) {
+    let mut packed = Vec::with_capacity(values.len() * 2);
+    for &value in values {
+        write_varint(&mut packed, u64::from(value));
+    }
+    write_field_bytes(out, field, &packed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::types::Pixel;
+    use crate::projection::pixel_to_tile;
+
+    fn bbox() -> BoundingBox {
+        BoundingBox {
+            min: Point::new(9.975, 53.540),
+            max: Point::new(10.0, 53.555),
+        }
+    }
+
+    /// Points at the given tile coordinates of `bbox()`
+    fn points(positions: &[(i32, i32)]) -> Vec<Point> {
+        positions
+            .iter()
+            .map(|&(x, y)| pixel_to_tile(&Pixel { x: x as f64, y: y as f64 }, &bbox(), 4096))
+            .collect()
+    }
+
+    #[test]
+    fn test_geometry_commands() {
+        assert_eq!(zigzag(0), 0);
+        assert_eq!(zigzag(-1), 1);
+        assert_eq!(zigzag(2), 4);
+
+        let line = points(&[(1, 1), (3, 4), (3, 6)]);
+        assert_eq!(
+            encode_geometry(ObjectKind::Line, &line, &bbox(), 4096),
+            Some((GEOM_LINESTRING, vec![9, 2, 2, 18, 4, 6, 0, 4]))
+        );
+        let point = points(&[(25, 17)]);
+        assert_eq!(encode_geometry(ObjectKind::Point, &point, &bbox(), 4096), Some((GEOM_POINT, vec![9, 50, 34])));
+
+        // Counter-clockwise on screen: reversed to clockwise, closing point dropped
+        let ring = points(&[(0, 0), (0, 10), (10, 10), (10, 0), (0, 0)]);
+        let (geom_type, geometry) = encode_geometry(ObjectKind::Polygon, &ring, &bbox(), 4096).unwrap();
+        assert_eq!(geom_type, GEOM_POLYGON);
+        assert_eq!(geometry, vec![9, 20, 0, 26, 0, 20, 19, 0, 0, 19, 15]);
+        assert!(ring_area(&[(0, 0), (10, 0), (10, 10), (0, 10)]) > 0);
+
+        // Open "polygons" are lines; collapsed geometry is skipped
+        let open = points(&[(0, 0), (0, 10), (10, 10)]);
+        assert_eq!(encode_geometry(ObjectKind::Polygon, &open, &bbox(), 4096).unwrap().0, GEOM_LINESTRING);
+        assert_eq!(encode_geometry(ObjectKind::Line, &points(&[(5, 5), (5, 5)]), &bbox(), 4096), None);
+    }
+
+    #[test]
+    fn test_layer_encoding() {
+        let mut layer = MvtLayer::new(MVT_LAYER_NAME, 4096);
+        assert!(layer.encode_tile().is_empty(), "tile without layers");
+
+        let line = points(&[(1, 1), (3, 4)]);
+        assert!(layer.add_object(ObjectKind::Line, &line, &bbox(), [("highway", "primary"), ("name", "A")]));
+        assert!(layer.add_object(ObjectKind::Line, &line, &bbox(), [("highway", "primary")]));
+        assert!(!layer.add_object(ObjectKind::Line, &line[..1], &bbox(), []));
+        assert_eq!(layer.len(), 2);
+        // Keys and values are shared
+        assert_eq!(layer.keys, vec!["highway", "name"]);
+        assert_eq!(layer.values, vec!["primary", "A"]);
+
+        let tile = layer.encode_tile();
+        // Tile.layers (field 3, length-delimited), then Layer.name
+        assert_eq!(tile[0], 0x1a);
+        let layer_start = if tile[1] & 0x80 == 0 { 2 } else { 3 };
+        assert_eq!(&tile[layer_start..layer_start + 5], b"\x0a\x03osm");
+        // Extent 4096 and version 2 close the layer
+        assert!(tile.ends_with(&[0x28, 0x80, 0x20, 0x78, 0x02]));
+    }
+}
@@ -1,8 +1,17 @@
-use image::{ImageFormat, RgbaImage};
+use image::codecs::png::PngEncoder;
+use image::error::{EncodingError, ImageFormatHint};
+use image::{ExtendedColorType, ImageBuffer, ImageEncoder, ImageFormat, PixelWithColorType, RgbaImage};
+use std::collections::HashMap;
 use std::io::Cursor;
 
-/// Encode an RgbaImage to PNG bytes
-pub fn encode_png(image: &RgbaImage) -> Result<Vec<u8>, image::ImageError> {
+/// Most colors an indexed PNG can hold
+pub const MAX_PALETTE_COLORS: usize = 256;
+
+/// Encode an RgbaImage (or another 8-bit image such as a GrayImage) to PNG bytes
+pub fn encode_png<P>(image: &ImageBuffer<P, Vec<u8>>) -> Result<Vec<u8>, image::ImageError>
+where
+    P: PixelWithColorType<Subpixel = u8>,
+{
     let mut buffer = Vec::new();
     let mut cursor = Cursor::new(&mut buffer);
 
@@ -10,3 +19,204 @@ pub fn encode_png(image: &RgbaImage) -> Result<Vec<u8>, image::ImageError> {
 
     Ok(buffer)
 }
+
+/// Encode an RgbaImage to a truecolor PNG marked as sRGB
+///
+/// The `sRGB` chunk tells viewers the colors are sRGB encoded, as images
+/// rendered with `ColorFormat::Srgb` are, instead of leaving them to guess.
+pub fn encode_png_srgb(image: &RgbaImage) -> Result<Vec<u8>, image::ImageError> {
+    encode_png_rgba(image.as_raw(), image.width(), image.height(), true)
+}
+
+/// Encode 8-bit RGBA pixels, row by row, to a truecolor PNG, marked as sRGB with `srgb`
+///
+/// Gives the bytes of `encode_png` or `encode_png_srgb` of an image of
+/// these pixels, for callers that have the pixels but no `RgbaImage`.
+pub fn encode_png_rgba(data: &[u8], width: u32, height: u32, srgb: bool) -> Result<Vec<u8>, image::ImageError> {
+    let mut buffer = Vec::new();
+    if !srgb {
+        PngEncoder::new(&mut buffer).write_image(data, width, height, ExtendedColorType::Rgba8)?;
+        return Ok(buffer);
+    }
+    let mut encoder = png::Encoder::new(&mut buffer, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    writer.write_image_data(data).map_err(png_error)?;
+    writer.finish().map_err(png_error)?;
+
+    Ok(buffer)
+}
+
+/// Encode an RgbaImage to an indexed (palette) PNG
+///
+/// The palette holds the image's exact colors, so the output is lossless;
+/// tiles drawn in a few style colors shrink considerably. Images with more
+/// than `MAX_PALETTE_COLORS` colors (e.g. anti-aliased edges) fall back to
+/// truecolor `encode_png`.
+pub fn encode_png_indexed(image: &RgbaImage) -> Result<Vec<u8>, image::ImageError> {
+    encode_indexed(image, false)
+}
+
+/// `encode_png_indexed` marked as sRGB, falling back to `encode_png_srgb`
+pub fn encode_png_indexed_srgb(image: &RgbaImage) -> Result<Vec<u8>, image::ImageError> {
+    encode_indexed(image, true)
+}
+
+fn encode_indexed(image: &RgbaImage, srgb: bool) -> Result<Vec<u8>, image::ImageError> {
+    let mut palette: Vec<[u8; 4]> = Vec::new();
+    let mut indices: HashMap<[u8; 4], u8> = HashMap::new();
+    let mut pixels = Vec::with_capacity(image.width() as usize * image.height() as usize);
+    for pixel in image.pixels() {
+        let index = match indices.get(&pixel.0) {
+            Some(&index) => index,
+            None if palette.len() < MAX_PALETTE_COLORS => {
+                let index = palette.len() as u8;
+                palette.push(pixel.0);
+                indices.insert(pixel.0, index);
+                index
+            }
+            None => {
+                log::debug!("More than {} colors, encoding truecolor PNG", MAX_PALETTE_COLORS);
+                return if srgb { encode_png_srgb(image) } else { encode_png(image) };
+            }
+        };
+        pixels.push(index);
+    }
+
+    // Smallest bit depth that fits the palette
+    let (depth, bits) = match palette.len() {
+        0..=2 => (png::BitDepth::One, 1),
+        3..=4 => (png::BitDepth::Two, 2),
+        5..=16 => (png::BitDepth::Four, 4),
+        _ => (png::BitDepth::Eight, 8),
+    };
+    let data = pack_rows(&pixels, image.width() as usize, bits);
+
+    let mut buffer = Vec::new();
+    let mut encoder = png::Encoder::new(&mut buffer, image.width(), image.height());
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(depth);
+    encoder.set_palette(palette.iter().flat_map(|c| [c[0], c[1], c[2]]).collect::<Vec<u8>>());
+    // Alpha per palette entry; trailing opaque entries may be omitted
+    let mut trns: Vec<u8> = palette.iter().map(|c| c[3]).collect();
+    while trns.last() == Some(&255) {
+        trns.pop();
+    }
+    if !trns.is_empty() {
+        encoder.set_trns(trns);
+    }
+    if srgb {
+        encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+    }
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    writer.write_image_data(&data).map_err(png_error)?;
+    writer.finish().map_err(png_error)?;
+
+    Ok(buffer)
+}
+
+/// Pack palette indices of `bits` each into bytes, MSB first, rows padded to whole bytes
+fn pack_rows(pixels: &[u8], width: usize, bits: usize) -> Vec<u8> {
+    if bits == 8 {
+        return pixels.to_vec();
+    }
+    let per_byte = 8 / bits;
+    let mut data = Vec::with_capacity(pixels.len() / per_byte + width);
+    for row in pixels.chunks(width) {
+        for chunk in row.chunks(per_byte) {
+            let mut byte = 0u8;
+            for (i, &index) in chunk.iter().enumerate() {
+                byte |= index << (8 - bits * (i + 1));
+            }
+            data.push(byte);
+        }
+    }
+    data
+}
+
+fn png_error(e: png::EncodingError) -> image::ImageError {
+    image::ImageError::Encoding(EncodingError::new(ImageFormatHint::Exact(ImageFormat::Png), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_truecolor_keeps_alpha() {
+        // Overlay tile: transparent background, an opaque and an antialiased pixel
+        let mut image = RgbaImage::from_pixel(16, 16, Rgba([0, 0, 0, 0]));
+        image.put_pixel(3, 4, Rgba([0, 0, 0, 255]));
+        image.put_pixel(4, 4, Rgba([0, 0, 0, 96]));
+        let decoded = image::load_from_memory(&encode_png(&image).unwrap()).unwrap();
+        assert_eq!(decoded.color(), image::ColorType::Rgba8);
+        assert_eq!(decoded.to_rgba8(), image);
+    }
+
+    #[test]
+    fn test_indexed_two_color_tile() {
+        // White tile with a black cross
+        let mut image = RgbaImage::from_pixel(256, 256, Rgba([255, 255, 255, 255]));
+        for i in 0..256 {
+            image.put_pixel(i, 128, Rgba([0, 0, 0, 255]));
+            image.put_pixel(128, i, Rgba([0, 0, 0, 255]));
+        }
+
+        let truecolor = encode_png(&image).unwrap();
+        let indexed = encode_png_indexed(&image).unwrap();
+        assert!(indexed.len() < truecolor.len(), "indexed {} >= truecolor {}", indexed.len(), truecolor.len());
+
+        let decoded = image::load_from_memory(&indexed).unwrap().to_rgba8();
+        assert_eq!(decoded, image);
+    }
+
+    #[test]
+    fn test_indexed_palette_depths_and_alpha() {
+        // 3 colors (2 bit), one translucent, with an odd width to exercise row padding
+        let mut image = RgbaImage::from_pixel(5, 3, Rgba([0, 0, 0, 0]));
+        image.put_pixel(1, 1, Rgba([220, 0, 0, 128]));
+        image.put_pixel(4, 2, Rgba([0, 170, 0, 255]));
+        let decoded = image::load_from_memory(&encode_png_indexed(&image).unwrap()).unwrap().to_rgba8();
+        assert_eq!(decoded, image);
+
+        // 20 colors (8 bit)
+        let image = RgbaImage::from_fn(20, 2, |x, _| Rgba([x as u8 * 10, 0, 0, 255]));
+        let decoded = image::load_from_memory(&encode_png_indexed(&image).unwrap()).unwrap().to_rgba8();
+        assert_eq!(decoded, image);
+    }
+
+    #[test]
+    fn test_indexed_falls_back_to_truecolor() {
+        let image = RgbaImage::from_fn(300, 1, |x, _| Rgba([(x % 256) as u8, (x / 256) as u8, 0, 255]));
+        let encoded = encode_png_indexed(&image).unwrap();
+        assert_eq!(encoded, encode_png(&image).unwrap());
+        assert_eq!(image::load_from_memory(&encoded).unwrap().to_rgba8(), image);
+    }
+
+    /// Whether the encoded PNG has an `sRGB` chunk
+    fn has_srgb_chunk(encoded: &[u8]) -> bool {
+        encoded.windows(4).any(|w| w == b"sRGB")
+    }
+
+    #[test]
+    fn test_srgb_chunk() {
+        let mut image = RgbaImage::from_pixel(8, 8, Rgba([255, 255, 255, 255]));
+        image.put_pixel(2, 2, Rgba([40, 80, 120, 96]));
+        assert!(!has_srgb_chunk(&encode_png(&image).unwrap()));
+        assert!(!has_srgb_chunk(&encode_png_indexed(&image).unwrap()));
+
+        for encoded in [encode_png_srgb(&image).unwrap(), encode_png_indexed_srgb(&image).unwrap()] {
+            assert!(has_srgb_chunk(&encoded));
+            assert_eq!(image::load_from_memory(&encoded).unwrap().to_rgba8(), image);
+        }
+
+        assert_eq!(encode_png_rgba(image.as_raw(), 8, 8, false).unwrap(), encode_png(&image).unwrap());
+
+        // The truecolor fallback is marked too
+        let image = RgbaImage::from_fn(300, 1, |x, _| Rgba([(x % 256) as u8, (x / 256) as u8, 0, 255]));
+        assert_eq!(encode_png_indexed_srgb(&image).unwrap(), encode_png_srgb(&image).unwrap());
+    }
+}
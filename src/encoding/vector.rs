@@ -0,0 +1,127 @@
+//! Coordinate precision shared by the vector outputs (GeoJSON, MVT)
+//!
+//! GeoJSON positions are rounded to `--vector-precision` decimals and MVT
+//! geometry is quantized to the layer extent. Both can collapse nearby
+//! points, so consecutive duplicates are dropped afterwards and parts that
+//! become degenerate are dropped entirely.
+
+use crate::data::types::{BoundingBox, Point};
+use crate::projection::tile_to_pixel;
+use serde_json::Value;
+
+/// Extent of generated MVT layers
+pub const MVT_EXTENT: u32 = 4096;
+
+/// Most decimals accepted for GeoJSON output (about 0.1 mm)
+pub const MAX_DECIMALS: u32 = 9;
+
+/// Round `value` to `decimals` decimal places
+pub fn round_coordinate(value: f64, decimals: u32) -> f64 {
+    let scale = 10f64.powi(decimals.min(MAX_DECIMALS) as i32);
+    (value * scale).round() / scale
+}
+
+/// GeoJSON coordinates of a line or ring, rounded to `decimals` if given
+///
+/// Returns `None` if fewer than two distinct positions remain, or fewer
+/// than four for a closed ring.
+pub fn geojson_coordinates(points: &[Point], decimals: Option<u32>) -> Option<Value> {
+    let round = |value: f64| decimals.map_or(value, |decimals| round_coordinate(value, decimals));
+    let positions = dedup_consecutive(points.iter().map(|p| (round(p.lon), round(p.lat))));
+    if is_degenerate(&positions) {
+        return None;
+    }
+    Some(Value::Array(
+        positions.into_iter().map(|(lon, lat)| serde_json::json!([lon, lat])).collect(),
+    ))
+}
+
+/// Integer MVT coordinates of a line or ring within `extent` of the tile `bbox`
+///
+/// Points are projected like the renderer does and rounded to the grid;
+/// zero-length segments are dropped. Returns `None` for geometry that
+/// collapses, like `geojson_coordinates`.
+pub fn quantize_mvt(points: &[Point], bbox: &BoundingBox, extent: u32) -> Option<Vec<(i32, i32)>> {
+    let positions = dedup_consecutive(points.iter().map(|point| {
+        let pixel = tile_to_pixel(point, bbox, extent);
+        (pixel.x.round() as i32, pixel.y.round() as i32)
+    }));
+    if is_degenerate(&positions) {
+        return None;
+    }
+    Some(positions)
+}
+
+fn dedup_consecutive<T: PartialEq>(positions: impl Iterator<Item = T>) -> Vec<T> {
+    let mut out: Vec<T> = Vec::new();
+    for position in positions {
+        if out.last() != Some(&position) {
+            out.push(position);
+        }
+    }
+    out
+}
+
+fn is_degenerate<T: PartialEq>(positions: &[T]) -> bool {
+    let closed = positions.len() > 1 && positions.first() == positions.last();
+    positions.len() < if closed { 4 } else { 2 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::types::Tile;
+    use crate::projection::get_bounding_box;
+
+    // A wiggly line through Hamburg with points ~1m apart
+    fn line() -> Vec<Point> {
+        (0..50)
+            .map(|i| Point::new(9.993_682 + i as f64 * 0.000_013_7, 53.551_086 + (i % 3) as f64 * 0.000_009_1))
+            .collect()
+    }
+
+    #[test]
+    fn test_geojson_precision() {
+        assert_eq!(round_coordinate(9.993_682_4, 3), 9.994);
+        assert_eq!(round_coordinate(-0.000_04, 4), -0.0);
+
+        let full = geojson_coordinates(&line(), None).unwrap().to_string();
+        let rounded = geojson_coordinates(&line(), Some(4)).unwrap();
+        assert!(rounded.to_string().len() * 2 < full.len(), "{} vs {} bytes", rounded.to_string().len(), full.len());
+
+        // Rounding merges neighbours but never leaves repeated positions
+        let positions = rounded.as_array().unwrap();
+        assert!(positions.len() >= 2 && positions.len() < 50);
+        assert!(positions.windows(2).all(|pair| pair[0] != pair[1]));
+
+        // Collapses to a single position
+        assert_eq!(geojson_coordinates(&line(), Some(2)), None);
+    }
+
+    #[test]
+    fn test_mvt_quantization() {
+        let (x, y) = crate::projection::deg2num(53.551, 9.994, 14);
+        let bbox = get_bounding_box(&Tile::new(x, y, 14));
+        let line = line();
+
+        // About 2.5 units between points at z14
+        assert_eq!(quantize_mvt(&line, &bbox, MVT_EXTENT).unwrap().len(), line.len());
+        let coarse = quantize_mvt(&line, &bbox, 512).unwrap();
+        assert!(coarse.len() < line.len(), "nearby points merge");
+        assert!(coarse.windows(2).all(|pair| pair[0] != pair[1]), "zero-length segment");
+
+        // Points a fraction of a grid unit apart become a single one
+        let center = Point::new((bbox.min.lon + bbox.max.lon) / 2.0, (bbox.min.lat + bbox.max.lat) / 2.0);
+        let near = |dlon: f64, dlat: f64| Point::new(center.lon + dlon, center.lat + dlat);
+        let short = [center, near(1e-6, 0.0)];
+        assert_eq!(quantize_mvt(&short, &bbox, MVT_EXTENT), None);
+        assert!(quantize_mvt(&short, &bbox, 1 << 20).is_some());
+
+        // A ring too small for the grid
+        let ring = [center, near(1e-6, 0.0), near(1e-6, 1e-6), center];
+        assert_eq!(quantize_mvt(&ring, &bbox, MVT_EXTENT), None);
+        assert!(quantize_mvt(&ring, &bbox, 1 << 22).is_some());
+        let big_ring = [bbox.min, Point::new(bbox.max.lon, bbox.min.lat), bbox.max, bbox.min];
+        assert_eq!(quantize_mvt(&big_ring, &bbox, MVT_EXTENT).unwrap().len(), 4);
+    }
+}
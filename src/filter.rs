@@ -0,0 +1,502 @@
+//! Tag filter expressions (`--filter`, `?filter=`)
+//!
+//! ```text
+//! highway=primary OR (waterway AND name)
+//! NOT (highway=service OR access=private)
+//! ```
+//!
+//! `key=value` matches a tag, a bare `key` matches any value of it.
+//! `NOT` binds tighter than `AND`, which binds tighter than `OR`; the
+//! operators are case-insensitive. Keys and values are runs of anything
+//! but whitespace, parentheses and `=`, or double quoted (`"a b"`, with
+//! `\"` and `\\` escapes). The value after `=` is never an operator, so
+//! `name=not` needs no quotes.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+/// Parsed tag filter expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TagFilter {
+    /// `key=value`
+    Equals { key: String, value: String },
+    /// `key`, with any value
+    Has(String),
+    And(Box<TagFilter>, Box<TagFilter>),
+    Or(Box<TagFilter>, Box<TagFilter>),
+    Not(Box<TagFilter>),
+}
+
+/// Filter syntax error at a character position of the expression
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("invalid filter at position {position}: {message}")]
+pub struct FilterError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl TagFilter {
+    /// Evaluate against the tags of a way
+    pub fn matches<K: AsRef<str>, V: AsRef<str>>(&self, tags: &[(K, V)]) -> bool {
+        match self {
+            TagFilter::Equals { key, value } => tags.iter().any(|(k, v)| k.as_ref() == key && v.as_ref() == value),
+            TagFilter::Has(key) => tags.iter().any(|(k, _)| k.as_ref() == key),
+            TagFilter::And(a, b) => a.matches(tags) && b.matches(tags),
+            TagFilter::Or(a, b) => a.matches(tags) || b.matches(tags),
+            TagFilter::Not(a) => !a.matches(tags),
+        }
+    }
+
+    /// Tag keys the filter looks at
+    pub fn keys(&self) -> BTreeSet<&str> {
+        let mut keys = BTreeSet::new();
+        self.collect_keys(&mut keys);
+        keys
+    }
+
+    fn collect_keys<'a>(&'a self, keys: &mut BTreeSet<&'a str>) {
+        match self {
+            TagFilter::Equals { key, .. } | TagFilter::Has(key) => {
+                keys.insert(key);
+            }
+            TagFilter::And(a, b) | TagFilter::Or(a, b) => {
+                a.collect_keys(keys);
+                b.collect_keys(keys);
+            }
+            TagFilter::Not(a) => a.collect_keys(keys),
+        }
+    }
+}
+
+impl fmt::Display for TagFilter {
+    /// Fully parenthesized, parses back to the same filter
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TagFilter::Equals { key, value } => write!(f, "{}={}", quote(key), quote(value)),
+            TagFilter::Has(key) => write!(f, "{}", quote(key)),
+            TagFilter::And(a, b) => write!(f, "({} AND {})", a, b),
+            TagFilter::Or(a, b) => write!(f, "({} OR {})", a, b),
+            TagFilter::Not(a) => write!(f, "NOT {}", a),
+        }
+    }
+}
+
+fn quote(word: &str) -> String {
+    let needs_quotes = word.is_empty()
+        || word.chars().any(|c| c.is_whitespace() || "()=\"\\".contains(c))
+        || Keyword::of(word).is_some();
+    if needs_quotes {
+        format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        word.to_string()
+    }
+}
+
+impl FromStr for TagFilter {
+    type Err = FilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { tokens: tokenize(s)?, pos: 0, end: s.chars().count() };
+        let filter = parser.or()?;
+        match parser.peek() {
+            None => Ok(filter),
+            Some((position, _)) => Err(FilterError {
+                position: *position,
+                message: "expected AND, OR or the end of the filter".to_string(),
+            }),
+        }
+    }
+}
+
+/// Comma separated `key=value` tags, or bare keys for any value
+/// (`--include-tags`, `--exclude-tags`)
+///
+/// A shorthand for a filter ORing the tags, see `any`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagList(Vec<TagFilter>);
+
+impl TagList {
+    /// Filter matching ways with any of the tags
+    pub fn any(&self) -> TagFilter {
+        self.0
+            .iter()
+            .cloned()
+            .reduce(|a, b| TagFilter::Or(Box::new(a), Box::new(b)))
+            .expect("tag lists are never empty")
+    }
+}
+
+impl FromStr for TagList {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|tag| {
+                let (key, value) = match tag.split_once('=') {
+                    Some((key, value)) => (key.trim(), Some(value.trim())),
+                    None => (tag.trim(), None),
+                };
+                if key.is_empty() {
+                    return Err(format!("empty tag key in {:?}", s));
+                }
+                Ok(match value {
+                    Some(value) => TagFilter::Equals { key: key.to_string(), value: value.to_string() },
+                    None => TagFilter::Has(key.to_string()),
+                })
+            })
+            .collect::<Result<_, _>>()
+            .map(TagList)
+    }
+}
+
+/// Tag keys kept per map object for `?filter=` (`--retain-tags`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RetainTags {
+    /// Comma separated keys
+    Keys(BTreeSet<String>),
+    /// `*`: every tag
+    All,
+}
+
+impl RetainTags {
+    pub fn retains(&self, key: &str) -> bool {
+        match self {
+            RetainTags::Keys(keys) => keys.contains(key),
+            RetainTags::All => true,
+        }
+    }
+
+    /// Also retain `key`
+    pub fn with_key(self, key: &str) -> Self {
+        match self {
+            RetainTags::Keys(mut keys) => {
+                keys.insert(key.to_string());
+                RetainTags::Keys(keys)
+            }
+            RetainTags::All => RetainTags::All,
+        }
+    }
+
+    /// Keep the retained tags of a way
+    pub fn select(&self, tags: &[(String, String)]) -> Vec<(String, String)> {
+        tags.iter().filter(|(key, _)| self.retains(key)).cloned().collect()
+    }
+}
+
+impl FromStr for RetainTags {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim() == "*" {
+            return Ok(RetainTags::All);
+        }
+        let keys: BTreeSet<String> = s.split(',').map(|key| key.trim().to_string()).collect();
+        if keys.contains("") {
+            return Err(format!("empty tag key in {:?}", s));
+        }
+        Ok(RetainTags::Keys(keys))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Equals,
+    Word { text: String, quoted: bool },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Keyword {
+    And,
+    Or,
+    Not,
+}
+
+impl Keyword {
+    fn of(word: &str) -> Option<Keyword> {
+        match word.to_ascii_uppercase().as_str() {
+            "AND" => Some(Keyword::And),
+            "OR" => Some(Keyword::Or),
+            "NOT" => Some(Keyword::Not),
+            _ => None,
+        }
+    }
+}
+
+/// Split into tokens, each with its character position
+fn tokenize(s: &str) -> Result<Vec<(usize, Token)>, FilterError> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().enumerate().peekable();
+    while let Some(&(position, c)) = chars.peek() {
+        let token = match c {
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '=' => Token::Equals,
+            '"' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c @ ('"' | '\\'))) => text.push(c),
+                            _ => {
+                                return Err(FilterError {
+                                    position,
+                                    message: "invalid escape in quoted string, expected \\\" or \\\\".to_string(),
+                                })
+                            }
+                        },
+                        Some((_, c)) => text.push(c),
+                        None => {
+                            return Err(FilterError { position, message: "unterminated quoted string".to_string() })
+                        }
+                    }
+                }
+                tokens.push((position, Token::Word { text, quoted: true }));
+                continue;
+            }
+            _ => {
+                let mut text = String::new();
+                while let Some(&(_, c)) = chars.peek() {
+                    if c.is_whitespace() || "()=\"".contains(c) {
+                        break;
+                    }
+                    text.push(c);
+                    chars.next();
+                }
+                tokens.push((position, Token::Word { text, quoted: false }));
+                continue;
+            }
+        };
+        chars.next();
+        tokens.push((position, token));
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over the grammar
+///
+/// ```text
+/// or      = and { OR and }
+/// and     = not { AND not }
+/// not     = NOT not | primary
+/// primary = "(" or ")" | word [ "=" word ]
+/// ```
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    // Position reported for errors at the end of the input
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&(usize, Token)> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_keyword(&self) -> Option<Keyword> {
+        match self.peek() {
+            Some((_, Token::Word { text, quoted: false })) => Keyword::of(text),
+            _ => None,
+        }
+    }
+
+    fn error(&self, message: &str) -> FilterError {
+        FilterError {
+            position: self.peek().map_or(self.end, |(position, _)| *position),
+            message: message.to_string(),
+        }
+    }
+
+    fn or(&mut self) -> Result<TagFilter, FilterError> {
+        let mut filter = self.and()?;
+        while self.peek_keyword() == Some(Keyword::Or) {
+            self.pos += 1;
+            filter = TagFilter::Or(Box::new(filter), Box::new(self.and()?));
+        }
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> Result<TagFilter, FilterError> {
+        let mut filter = self.not()?;
+        while self.peek_keyword() == Some(Keyword::And) {
+            self.pos += 1;
+            filter = TagFilter::And(Box::new(filter), Box::new(self.not()?));
+        }
+        Ok(filter)
+    }
+
+    fn not(&mut self) -> Result<TagFilter, FilterError> {
+        if self.peek_keyword() == Some(Keyword::Not) {
+            self.pos += 1;
+            return Ok(TagFilter::Not(Box::new(self.not()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<TagFilter, FilterError> {
+        match self.peek().cloned() {
+            Some((_, Token::Open)) => {
+                self.pos += 1;
+                let filter = self.or()?;
+                match self.peek() {
+                    Some((_, Token::Close)) => {
+                        self.pos += 1;
+                        Ok(filter)
+                    }
+                    _ => Err(self.error("expected )")),
+                }
+            }
+            Some((_, Token::Word { text, quoted })) if quoted || Keyword::of(&text).is_none() => {
+                self.pos += 1;
+                if !matches!(self.peek(), Some((_, Token::Equals))) {
+                    return Ok(TagFilter::Has(text));
+                }
+                self.pos += 1;
+                match self.peek().cloned() {
+                    Some((_, Token::Word { text: value, .. })) => {
+                        self.pos += 1;
+                        Ok(TagFilter::Equals { key: text, value })
+                    }
+                    _ => Err(self.error("expected a value after =")),
+                }
+            }
+            _ => Err(self.error("expected a tag key, NOT or (")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn has(key: &str) -> TagFilter {
+        TagFilter::Has(key.to_string())
+    }
+
+    fn equals(key: &str, value: &str) -> TagFilter {
+        TagFilter::Equals { key: key.to_string(), value: value.to_string() }
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        let filter: TagFilter = "highway=primary OR waterway AND name".parse().unwrap();
+        assert_eq!(
+            filter,
+            TagFilter::Or(
+                Box::new(equals("highway", "primary")),
+                Box::new(TagFilter::And(Box::new(has("waterway")), Box::new(has("name")))),
+            )
+        );
+
+        let filter: TagFilter = "(highway=primary or waterway) and not name".parse().unwrap();
+        assert_eq!(
+            filter,
+            TagFilter::And(
+                Box::new(TagFilter::Or(Box::new(equals("highway", "primary")), Box::new(has("waterway")))),
+                Box::new(TagFilter::Not(Box::new(has("name")))),
+            )
+        );
+
+        // Left associative
+        let filter: TagFilter = "a OR b OR c".parse().unwrap();
+        assert_eq!(
+            filter,
+            TagFilter::Or(Box::new(TagFilter::Or(Box::new(has("a")), Box::new(has("b")))), Box::new(has("c")))
+        );
+        let filter: TagFilter = "NOT NOT a".parse().unwrap();
+        assert_eq!(filter, TagFilter::Not(Box::new(TagFilter::Not(Box::new(has("a"))))));
+    }
+
+    #[test]
+    fn test_parse_words() {
+        assert_eq!("addr:street".parse(), Ok(has("addr:street")));
+        assert_eq!(" name = \"Elbe Tunnel\" ".parse(), Ok(equals("name", "Elbe Tunnel")));
+        assert_eq!(r#""say \"hi\"\\"=x"#.parse(), Ok(equals("say \"hi\"\\", "x")));
+        assert_eq!("\"not\"".parse(), Ok(has("not")));
+        assert_eq!("name=not".parse(), Ok(equals("name", "not")));
+        assert_eq!("name=\"\"".parse(), Ok(equals("name", "")));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |s: &str| s.parse::<TagFilter>().unwrap_err();
+        assert_eq!(error("").position, 0);
+        assert_eq!(error("a AND").position, 5);
+        assert_eq!(error("(a OR b").message, "expected )");
+        assert_eq!(error("a b").position, 2);
+        assert_eq!(error("a = ").message, "expected a value after =");
+        assert_eq!(error("a=(b)").position, 2);
+        assert_eq!(error("AND a").position, 0);
+        assert_eq!(error("a)").position, 1);
+        assert_eq!(error("name=\"x").message, "unterminated quoted string");
+        assert_eq!(error("\"\\n\"").position, 0);
+        assert_eq!(error("=x").position, 0);
+    }
+
+    #[test]
+    fn test_matches() {
+        let filter: TagFilter = "highway=primary OR (waterway AND name)".parse().unwrap();
+        assert!(filter.matches(&tags(&[("highway", "primary")])));
+        assert!(!filter.matches(&tags(&[("highway", "secondary")])));
+        assert!(filter.matches(&tags(&[("waterway", "river"), ("name", "Elbe")])));
+        assert!(!filter.matches(&tags(&[("waterway", "river")])));
+        assert!(!filter.matches(&tags(&[])));
+
+        let filter: TagFilter = "highway AND NOT (highway=service OR access=private)".parse().unwrap();
+        assert!(filter.matches(&tags(&[("highway", "residential")])));
+        assert!(!filter.matches(&tags(&[("highway", "service")])));
+        assert!(!filter.matches(&tags(&[("highway", "residential"), ("access", "private")])));
+        assert!(!filter.matches(&tags(&[("access", "yes")])));
+
+        assert_eq!(filter.keys().into_iter().collect::<Vec<_>>(), vec!["access", "highway"]);
+    }
+
+    #[test]
+    fn test_retain_tags() {
+        let way = tags(&[("highway", "primary"), ("name", "B4"), ("surface", "asphalt")]);
+        let retain: RetainTags = "highway, name".parse().unwrap();
+        assert_eq!(retain.select(&way), tags(&[("highway", "primary"), ("name", "B4")]));
+        assert!(!retain.retains("surface"));
+        assert_eq!("*".parse::<RetainTags>().unwrap().select(&way), way);
+        assert!("highway,,name".parse::<RetainTags>().is_err());
+    }
+
+    #[test]
+    fn test_tag_list() {
+        let list: TagList = "waterway, natural=water".parse().unwrap();
+        assert_eq!(list.any(), "waterway OR natural=water".parse().unwrap());
+        assert!(list.any().matches(&tags(&[("waterway", "river")])));
+        assert!(!list.any().matches(&tags(&[("natural", "wood")])));
+        assert_eq!("building".parse::<TagList>().unwrap().any(), TagFilter::Has("building".to_string()));
+        assert!("highway,,name".parse::<TagList>().is_err());
+        assert!("=water".parse::<TagList>().is_err());
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        for source in [
+            "highway=primary OR (waterway AND name)",
+            "NOT (a OR b) AND c",
+            "\"two words\"=\"a=b\" OR \"and\"",
+            "NOT NOT x=\"\"",
+        ] {
+            let filter: TagFilter = source.parse().unwrap();
+            let printed = filter.to_string();
+            assert_eq!(printed.parse::<TagFilter>(), Ok(filter), "{} printed as {}", source, printed);
+        }
+        let filter: TagFilter = "a OR b AND c".parse().unwrap();
+        assert_eq!(filter.to_string(), "(a OR (b AND c))");
+    }
+}
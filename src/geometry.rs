@@ -0,0 +1,110 @@
+//! Way geometry simplification (Ramer–Douglas–Peucker)
+
+use crate::data::types::Point;
+
+/// Tile size the ground resolution of `degrees_per_pixel` refers to
+const RESOLUTION_TILE_SIZE: f64 = 256.0;
+
+/// Simplify a polyline, keeping points further than `epsilon` from the
+/// simplified line
+///
+/// Distances are measured in the coordinates of the points, degrees for
+/// map data. The first and last point are always kept, so closed rings stay
+/// closed. With a smaller `epsilon` the result keeps a superset of the
+/// points kept with a larger one.
+pub fn simplify(points: &[Point], epsilon: f64) -> Vec<Point> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+    // Spans still to split, iteratively since coastlines have many points
+    let mut spans = vec![(0, points.len() - 1)];
+    while let Some((start, end)) = spans.pop() {
+        let farthest = (start + 1..end)
+            .map(|i| (i, distance_to_segment(&points[i], &points[start], &points[end])))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((i, distance)) = farthest {
+            if distance > epsilon {
+                keep[i] = true;
+                spans.push((start, i));
+                spans.push((i, end));
+            }
+        }
+    }
+
+    points.iter().zip(keep).filter(|(_, keep)| *keep).map(|(point, _)| *point).collect()
+}
+
+/// Distance of `p` to the line through `a` and `b`, or to `a` if they coincide
+fn distance_to_segment(p: &Point, a: &Point, b: &Point) -> f64 {
+    let (dx, dy) = (b.lon - a.lon, b.lat - a.lat);
+    let length = dx.hypot(dy);
+    if length == 0.0 {
+        return (p.lon - a.lon).hypot(p.lat - a.lat);
+    }
+    (dx * (a.lat - p.lat) - dy * (a.lon - p.lon)).abs() / length
+}
+
+/// Degrees covered by one pixel of a 256px tile at `zoom` near latitude `lat`
+///
+/// That's the latitude span, which is smaller than the longitude span by
+/// the Mercator scale factor, so a tolerance based on it is safe on both axes.
+pub fn degrees_per_pixel(zoom: u32, lat: f64) -> f64 {
+    360.0 / (RESOLUTION_TILE_SIZE * 2.0_f64.powi(zoom as i32)) * lat.to_radians().cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(coords: &[(f64, f64)]) -> Vec<Point> {
+        coords.iter().map(|&(lon, lat)| Point::new(lon, lat)).collect()
+    }
+
+    #[test]
+    fn test_simplify_known_polyline() {
+        let line = points(&[
+            (0.0, 0.0),
+            (1.0, 0.1),
+            (2.0, -0.1),
+            (3.0, 5.0),
+            (4.0, 6.0),
+            (5.0, 7.0),
+            (6.0, 8.1),
+            (7.0, 9.0),
+            (8.0, 9.0),
+            (9.0, 9.0),
+        ]);
+        assert_eq!(
+            simplify(&line, 1.0),
+            points(&[(0.0, 0.0), (2.0, -0.1), (3.0, 5.0), (7.0, 9.0), (9.0, 9.0)])
+        );
+        // Less tolerance keeps more points, including all kept before
+        let finer = simplify(&line, 0.05);
+        assert_eq!(
+            finer,
+            points(&[(0.0, 0.0), (1.0, 0.1), (2.0, -0.1), (3.0, 5.0), (6.0, 8.1), (7.0, 9.0), (9.0, 9.0)])
+        );
+        assert!(simplify(&line, 1.0).iter().all(|point| finer.contains(point)));
+        assert_eq!(simplify(&line, 100.0), points(&[(0.0, 0.0), (9.0, 9.0)]));
+        assert_eq!(simplify(&line[..2], 100.0), line[..2].to_vec());
+    }
+
+    #[test]
+    fn test_simplify_ring_stays_closed() {
+        let ring = points(&[(0.0, 0.0), (1.0, 0.0), (1.0, 0.01), (1.0, 1.0), (0.0, 1.0), (0.0, 0.0)]);
+        let simplified = simplify(&ring, 0.1);
+        assert_eq!(simplified, points(&[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0), (0.0, 0.0)]));
+    }
+
+    #[test]
+    fn test_degrees_per_pixel() {
+        // 360° over 256px at zoom 0 on the equator, halving per zoom level
+        assert_eq!(degrees_per_pixel(0, 0.0), 360.0 / 256.0);
+        assert_eq!(degrees_per_pixel(3, 0.0), 360.0 / 2048.0);
+        assert!((degrees_per_pixel(0, 60.0) - 180.0 / 256.0).abs() < 1e-12);
+    }
+}
@@ -1,5 +1,10 @@
+pub mod cli;
 pub mod data;
+pub mod filter;
+pub mod geometry;
 pub mod projection;
 pub mod renderer;
+pub mod selftest;
 pub mod server;
+pub mod style;
 pub mod encoding;
@@ -1,81 +1,482 @@
-use rust_osm_renderer::data::loader::load_osm_data;
+use clap::Parser;
+use rust_osm_renderer::cli::Args;
+use rust_osm_renderer::data::loader::{load_osm_files, LoadOptions};
 use rust_osm_renderer::data::mmap::MappedData;
-use rust_osm_renderer::renderer::ShaderType;
-use rust_osm_renderer::server::{create_app, AppState};
-use std::env;
-use std::path::Path;
+use rust_osm_renderer::filter::RetainTags;
+use rust_osm_renderer::renderer::clip::ClipRegion;
+use rust_osm_renderer::renderer::pool::Pool;
+use rust_osm_renderer::renderer::renderer::LINE_COLOR;
+use rust_osm_renderer::data::index_file::{read_index, write_index};
+use rust_osm_renderer::data::spatial::TileIndex;
+use rust_osm_renderer::selftest;
+use rust_osm_renderer::server::budget::RenderBudget;
+use rust_osm_renderer::server::cache::TileCache;
+use rust_osm_renderer::server::encode::{EncodePool, QUEUE_SLOTS_PER_THREAD};
+use rust_osm_renderer::server::{create_app, AppState, DiffBase};
+use rust_osm_renderer::style::line_colors::{LineColors, HIGHWAY_KEY};
+use rust_osm_renderer::style::MapStyle;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
-    // Parse command line arguments
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Usage: {} <osm-file.pbf> [--simple-shader|--debug-shader]", args[0]);
-        eprintln!("  --simple-shader: Use simplified linear projection (better for debugging)");
-        eprintln!("  --debug-shader: Output all vertices at center (pipeline test)");
-        std::process::exit(1);
+    // Exits with usage on invalid arguments, see `cli::Args`
+    let args = Args::parse();
+    let shader_type = args.shader.shader_type();
+    let context = args.vulkan.context();
+    if args.selftest {
+        let report = selftest::run(shader_type, context);
+        print!("{}", report);
+        std::process::exit(if report.passed() { 0 } else { 1 });
     }
 
-    let osm_path = &args[1];
-    let shader_type = if args.iter().any(|s| s == "--simple-shader") {
-        ShaderType::Simple
-    } else if args.iter().any(|s| s == "--debug-shader") {
-        ShaderType::Debug
-    } else {
-        ShaderType::Mercator
+    let line_colors = match (args.highway_colors, args.default_line_color) {
+        (true, default) => Some(LineColors::highway(default.unwrap_or(LINE_COLOR))),
+        (false, Some(default)) => Some(LineColors::new(default)),
+        (false, None) => None,
+    };
+    // Coloring and widths by highway need the tag in the data file
+    let retain_tags = match args.retain_tags.clone() {
+        retain_tags if !args.highway_colors && !args.line_widths => retain_tags,
+        Some(retain) => Some(retain.with_key(HIGHWAY_KEY)),
+        None => Some(RetainTags::Keys([HIGHWAY_KEY.to_string()].into())),
+    };
+    let lod = args.lod();
+    let vertex_budgets = args.vertex_budget.clone().unwrap_or_default();
+    let pool_size = args.renderer_pool_size.unwrap_or_else(num_cpus::get);
+    let encode_threads = args.encode_threads.unwrap_or_else(num_cpus::get);
+    let clip_region = match &args.clip_region {
+        Some(path) => match ClipRegion::from_geojson_file(path) {
+            Ok(clip_region) => {
+                log::info!("Clipping tiles to {:?}", clip_region.bounds());
+                Some(Arc::new(clip_region))
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
     };
-    if !Path::new(osm_path).exists() {
-        eprintln!("Error: OSM file not found: {}", osm_path);
+    let tile_cache_entries = args.tile_cache_entries();
+    if args.supersample > 1 {
+        log::info!("Supersampling tiles {}x, downscaled with the {:?} filter", args.supersample, args.downscale_filter);
+    }
+    if args.stale_while_revalidate && tile_cache_entries.is_none() {
+        eprintln!("Error: --stale-while-revalidate requires the tile cache, disabled by --tile-cache 0");
         std::process::exit(1);
     }
+    let style = match &args.style {
+        Some(path) => match MapStyle::from_file(path) {
+            Ok(style) => {
+                log::info!("Loaded style with {} rules and {} classes", style.rules.len(), style.classes.len());
+                Some(Arc::new(style))
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    for path in args.osm_files.iter().chain(&args.diff_against) {
+        if !path.exists() {
+            eprintln!("Error: OSM file not found: {}", path.display());
+            std::process::exit(1);
+        }
+    }
 
     log::info!("Starting OSM tile renderer...");
-    log::info!("Loading OSM data from: {}", osm_path);
-
-    // Create temporary file for map objects
-    let temp_file_path = "/tmp/rust-osm-renderer-data.bin";
-    let mut temp_file = std::fs::File::create(temp_file_path)?;
+    if lod.is_enabled() {
+        log::info!(
+            "Object LOD: skip below {}px, simplify below {}px",
+            lod.skip_below_px,
+            lod.simplify_below_px
+        );
+    }
+    if vertex_budgets.is_enabled() {
+        log::info!("Vertex budgets: {:?}", vertex_budgets);
+    }
+    let osm_names: Vec<String> = args.osm_files.iter().map(|path| path.display().to_string()).collect();
+    log::info!("Loading OSM data from: {}", osm_names.join(", "));
 
     // Load OSM data and build spatial index
-    // We index up to zoom 15, but can render higher zoom levels by using parent tiles
-    let max_z = 15;
-    log::info!("Loading OSM data (max zoom: {})...", max_z);
-    let tile_index = load_osm_data(osm_path, max_z, &mut temp_file)?;
-
-    // Ensure data is flushed
-    use std::io::Write;
-    temp_file.flush()?;
-    drop(temp_file);
-
+    // We index up to --max-zoom, but can render higher zoom levels by using parent tiles
+    let max_z = args.max_zoom;
     log::info!(
-        "OSM data loaded: {} tiles, max {} points per way",
-        tile_index.len(),
-        tile_index.max_points
+        "Indexing zooms up to {} (minor ways from {}); each extra zoom roughly quadruples the index \
+         entries of long ways, higher zooms render from their ancestor at zoom {}",
+        max_z,
+        args.detail_zoom,
+        max_z
     );
+    let temp_file_path = process_data_path(&args.tmp_dir, DATA_FILE_NAME);
+    let load_options = LoadOptions {
+        transform: args.transform.unwrap_or_default(),
+        spill_index: None,
+        style: style.clone(),
+        key_scheme: args.tile_keys,
+        filter: args.load_filter().map(Arc::new),
+        retain_tags,
+        simplify_px: args.simplify_px,
+        detail_zoom: Some(args.detail_zoom),
+    };
+    let reuse_index = !args.rebuild_index;
+    let spill_entries = args.spill_index;
+    let tile_index = load_data_file(
+        &args.osm_files,
+        &temp_file_path,
+        max_z,
+        spill_entries,
+        &load_options,
+        args.max_concurrent_loads,
+        reuse_index,
+    )?;
+    match &tile_index.bounds {
+        Some(bounds) => log::info!(
+            "Data bounds: {:.5},{:.5} to {:.5},{:.5} (lon,lat)",
+            bounds.min.lon,
+            bounds.min.lat,
+            bounds.max.lon,
+            bounds.max.lat
+        ),
+        None => log::warn!("No map objects loaded, the data has no bounds"),
+    }
+
+    if args.index_report {
+        print!("{}", tile_index.report());
+        return Ok(());
+    }
 
     // Memory-map the temp file
     log::info!("Memory-mapping data file...");
-    let mmap_data = MappedData::new(temp_file_path)?;
+    let mmap_data = MappedData::new(&temp_file_path)?;
     log::info!("Data file size: {} bytes", mmap_data.len());
+    if args.verify_index {
+        verify_data(&tile_index, max_z, &mmap_data, &temp_file_path);
+    }
+
+    // Load the base data set for diff tiles
+    let diff_base = match &args.diff_against {
+        Some(base_path) => {
+            log::info!("Loading diff base from: {}", base_path.display());
+            let base_file_path = process_data_path(&args.tmp_dir, BASE_FILE_NAME);
+            let base_index = load_data_file(
+                std::slice::from_ref(base_path),
+                &base_file_path,
+                max_z,
+                spill_entries,
+                &load_options,
+                1,
+                reuse_index,
+            )?;
+            if !tile_index.has_way_ids() || !base_index.has_way_ids() {
+                eprintln!("Error: --diff-against requires way ids in both data sets");
+                std::process::exit(1);
+            }
+            let base_mmap = MappedData::new(&base_file_path)?;
+            if args.verify_index {
+                verify_data(&base_index, max_z, &base_mmap, &base_file_path);
+            }
+            Some(DiffBase {
+                data: Arc::new(base_index),
+                mmap: Arc::new(base_mmap),
+            })
+        }
+        None => None,
+    };
 
     // Create app state
     let app_state = AppState {
         data: Arc::new(tile_index),
         mmap: Arc::new(mmap_data),
         shader_type,
+        vulkan: context,
+        buffer_fraction: args.buffer_fraction,
+        diff_base,
+        out_of_coverage: args.out_of_coverage,
+        lod,
+        vertex_budgets,
+        max_vertex_buffer: args.max_vertex_buffer,
+        render_timeout: Duration::from_millis(args.render_timeout_ms),
+        wrap_antimeridian: args.wrap_antimeridian,
+        crisp_lines: args.crisp_lines,
+        line_widths: args.line_widths,
+        casing: args.casing,
+        color_format: args.color_format,
+        point_decimation_px: args.point_decimation_px,
+        precise_overlap: args.precise_overlap,
+        png_indexed: args.png_indexed,
+        overlay: args.overlay,
+        format_preference: args.format_preference.clone().unwrap_or_default(),
+        admin_token: args.admin_token.clone(),
+        msaa_samples: args.msaa,
+        fill_polygons: args.fill_polygons,
+        fill_msaa_samples: args.fill_msaa,
+        fill_max_objects: args.fill_max_objects,
+        supersample: args.supersample,
+        downscale_filter: args.downscale_filter,
+        tile_origin: args.tile_origin,
+        tile_scheme: args.scheme,
+        renderers: Arc::new(Pool::new(pool_size, args.renderer_timeout_ms.map(Duration::from_millis))),
+        encoders: Arc::new(EncodePool::new(encode_threads, encode_threads * QUEUE_SLOTS_PER_THREAD)),
+        debug_error_tiles: args.debug_error_tiles,
+        max_age_secs: args.max_age,
+        max_request_zoom: args.max_request_zoom,
+        render_budget: Arc::new(RenderBudget::new(args.render_budget_ms.map(Duration::from_millis))),
+        tile_metrics: Default::default(),
+        nodata_tiles: Default::default(),
+        style,
+        line_colors: line_colors.map(Arc::new),
+        clip_region,
+        vector_precision: args.vector_precision,
+        tile_cache: tile_cache_entries.map(|entries| {
+            let stale_while_revalidate = args.stale_while_revalidate;
+            log::info!("Tile cache: {} entries{}", entries, if stale_while_revalidate { ", stale-while-revalidate" } else { "" });
+            Arc::new(TileCache::new(entries, stale_while_revalidate, Some(temp_file_path.clone())))
+        }),
     };
+    log::info!("Renderer pool size: {}, encode threads: {}", pool_size, encode_threads);
 
-    // Create HTTP server
+    // Create HTTP server, keeping the renderers to release on shutdown
+    let renderers = app_state.renderers.clone();
     let app = create_app(app_state);
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await?;
-    log::info!("Server listening on http://0.0.0.0:8080");
-    log::info!("Try: http://0.0.0.0:8080/tile/0/0/0.png");
+    let bind_addr = args.bind_addr();
+    let listener = match tokio::net::TcpListener::bind(bind_addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Error: failed to listen on {}: {}", bind_addr, e);
+            std::process::exit(1);
+        }
+    };
+    // With port 0 the system picks one
+    let local_addr = listener.local_addr()?;
+    log::info!("Server listening on http://{}", local_addr);
+    log::info!("Try: http://{}/tile/0/0/0.png", local_addr);
 
-    axum::serve(listener, app).await?;
+    // Stop accepting connections on SIGTERM or Ctrl-C and finish the requests in flight
+    axum::serve(listener, app).with_graceful_shutdown(shutdown_signal()).await?;
+
+    // Renders started by requests (or background refreshes) still hold renderers;
+    // dropping them waits for their devices to go idle
+    let released = renderers.drain().await;
+    log::info!("Shut down, released {} renderers", released);
+    // The saved copies stay for later starts
+    remove_data_file(&temp_file_path);
+    if args.diff_against.is_some() {
+        remove_data_file(&process_data_path(&args.tmp_dir, BASE_FILE_NAME));
+    }
 
     Ok(())
 }
+
+/// Resolves on SIGTERM (service managers) or Ctrl-C
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            log::error!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                log::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => log::info!("Ctrl-C received, shutting down"),
+        _ = terminate => log::info!("SIGTERM received, shutting down"),
+    }
+}
+
+/// Name of the data file built from the OSM files, without extension
+const DATA_FILE_NAME: &str = "rust-osm-renderer-data";
+
+/// Name of the data file of the `--diff-against` base, without extension
+const BASE_FILE_NAME: &str = "rust-osm-renderer-base";
+
+/// This process's data file `name` in `dir`
+///
+/// The process id keeps instances sharing the directory from writing to
+/// each other's memory-mapped files.
+fn process_data_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.{}.bin", name, std::process::id()))
+}
+
+/// Load OSM files into the data file at `data_path` and build their tile index
+///
+/// Several files are merged, loading up to `max_concurrent_loads` at once.
+/// With `spill_entries` the index is built in bounded memory.
+///
+/// The data file and its index (`.idx`) are saved without the process id
+/// (see `process_data_path`) for later starts, as hard links atomically replacing the previous ones, so files
+/// other processes have mapped are never changed. With `reuse_index`, a
+/// saved index that is newer than the OSM files and was built with the
+/// same options is loaded instead, with the saved data file linked to
+/// `data_path`.
+///
+/// Exits the process with a message if the OSM file can't be loaded.
+fn load_data_file(
+    osm_paths: &[PathBuf],
+    data_path: &Path,
+    max_z: u32,
+    spill_entries: Option<usize>,
+    options: &LoadOptions,
+    max_concurrent_loads: usize,
+    reuse_index: bool,
+) -> anyhow::Result<TileIndex> {
+    let index_path = data_path.with_extension("idx");
+    // name.pid.bin -> name.bin
+    let saved_path = data_path.with_extension("").with_extension("bin");
+    let saved_index_path = saved_path.with_extension("idx");
+    let fingerprint = source_fingerprint(osm_paths, max_z, options);
+    // Left behind by an earlier process with the same id
+    remove_data_file(data_path);
+    if reuse_index {
+        let saved = std::fs::hard_link(&saved_path, data_path)
+            .map_err(|e| format!("{}: {}", saved_path.display(), e))
+            .and_then(|()| load_saved_index(osm_paths, data_path, &saved_index_path, fingerprint));
+        match saved {
+            Ok(mut tile_index) => {
+                log::info!("Reusing saved index {}: {} tiles", saved_index_path.display(), tile_index.len());
+                tile_index.retained_tags = options.retain_tags.clone();
+                tile_index.max_zoom = max_z;
+                return Ok(tile_index);
+            }
+            Err(reason) => {
+                log::info!("Not reusing saved index {}: {}", saved_index_path.display(), reason);
+                // Unlink before building, the saved file may be mapped by others
+                remove_data_file(data_path);
+            }
+        }
+    }
+
+    log::info!("Loading OSM data (max zoom: {})...", max_z);
+    if !options.transform.is_identity() {
+        log::info!("Applying coordinate transform {:?}", options.transform);
+    }
+    if let Some(filter) = &options.filter {
+        log::info!("Loading ways matching {}", filter);
+    }
+    let options = LoadOptions {
+        spill_index: spill_entries.map(|max_entries| (index_path.clone(), max_entries)),
+        ..options.clone()
+    };
+    let mut tile_index = match load_osm_files(osm_paths, max_z, data_path, &options, max_concurrent_loads) {
+        Ok(tile_index) => tile_index,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    log::info!(
+        "OSM data loaded: {} tiles, max {} points per way",
+        tile_index.len(),
+        tile_index.max_points
+    );
+
+    // Rewritten even after spilling, to record the fingerprint
+    tile_index.source_fingerprint = fingerprint;
+    let saved = write_index(&index_path, &tile_index)
+        .and_then(|()| save_file(data_path, &saved_path))
+        .and_then(|()| save_file(&index_path, &saved_index_path));
+    if let Err(e) = saved {
+        log::warn!("Failed to save index {}: {}", saved_index_path.display(), e);
+    }
+
+    Ok(tile_index)
+}
+
+/// Hard link `path` at `saved`, atomically replacing what was there
+fn save_file(path: &Path, saved: &Path) -> std::io::Result<()> {
+    let mut staging = saved.as_os_str().to_owned();
+    staging.push(format!(".{}.tmp", std::process::id()));
+    let staging = PathBuf::from(staging);
+    let _ = std::fs::remove_file(&staging);
+    std::fs::hard_link(path, &staging)?;
+    std::fs::rename(&staging, saved)
+}
+
+/// Remove this process's data file and index, if any
+fn remove_data_file(data_path: &Path) {
+    for path in [data_path.to_path_buf(), data_path.with_extension("idx")] {
+        match std::fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => log::warn!("Failed to remove {}: {}", path.display(), e),
+        }
+    }
+}
+
+/// Fingerprint of what `load_data_file` builds an index from
+fn source_fingerprint(osm_paths: &[PathBuf], max_z: u32, options: &LoadOptions) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    osm_paths.hash(&mut hasher);
+    max_z.hash(&mut hasher);
+    let options = LoadOptions { spill_index: None, ..options.clone() };
+    format!("{:?}", options).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Load the index saved by an earlier `load_data_file`, if it is still valid
+///
+/// It must be newer than the OSM files and the data file, match
+/// `fingerprint`, and only point into the data file.
+fn load_saved_index(osm_paths: &[PathBuf], data_path: &Path, index_path: &Path, fingerprint: u64) -> Result<TileIndex, String> {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|metadata| metadata.modified());
+    let saved = modified(index_path).map_err(|e| e.to_string())?;
+    for path in osm_paths.iter().map(PathBuf::as_path).chain([data_path]) {
+        match modified(path) {
+            Ok(input) if input <= saved => {}
+            Ok(_) => return Err(format!("{} is newer", path.display())),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    let tile_index = read_index(index_path).map_err(|e| e.to_string())?;
+    if tile_index.source_fingerprint != fingerprint {
+        return Err("built from other files or options".to_string());
+    }
+    let data_len = std::fs::metadata(data_path).map_err(|e| e.to_string())?.len();
+    tile_index.check_offsets(data_len as usize).map_err(|e| e.to_string())?;
+    Ok(tile_index)
+}
+
+/// Most anomalies printed by `--verify-index`
+const MAX_REPORTED_ANOMALIES: usize = 20;
+
+/// Verify `tile_index` against its data file, see `TileIndex::verify`
+///
+/// Exits the process with the anomalies found, if any.
+fn verify_data(tile_index: &TileIndex, max_z: u32, mmap_data: &MappedData, data_path: &Path) {
+    let anomalies = tile_index.verify(max_z, mmap_data);
+    if anomalies.is_empty() {
+        log::info!("Verified index of {}: {} tiles, no anomalies", data_path.display(), tile_index.len());
+        return;
+    }
+    eprintln!("Error: index of {} is inconsistent, {} anomalies:", data_path.display(), anomalies.len());
+    for anomaly in anomalies.iter().take(MAX_REPORTED_ANOMALIES) {
+        eprintln!("  {}", anomaly);
+    }
+    if anomalies.len() > MAX_REPORTED_ANOMALIES {
+        eprintln!("  ... and {} more", anomalies.len() - MAX_REPORTED_ANOMALIES);
+    }
+    std::process::exit(1);
+}
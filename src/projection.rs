@@ -1,7 +1,10 @@
-use crate::data::types::{BoundingBox, Point, Tile};
+use crate::data::types::{BoundingBox, Pixel, Point, Tile};
+use rustc_hash::FxHashSet;
 use std::f64::consts::PI;
+use std::str::FromStr;
 
-const MAX_LAT: f64 = 85.0511287798;
+/// Latitude limit of the Web Mercator tile grid
+pub const MAX_LAT: f64 = 85.0511287798;
 
 /// Convert latitude to Mercator Y coordinate
 pub fn lat_to_mercator(lat: f64) -> f64 {
@@ -10,13 +13,114 @@ pub fn lat_to_mercator(lat: f64) -> f64 {
     (PI / 4.0 + lat_rad / 2.0).tan().ln()
 }
 
+/// Convert a Mercator Y coordinate back to latitude (inverse of `lat_to_mercator`)
+pub fn mercator_to_lat(y: f64) -> f64 {
+    (2.0 * y.exp().atan() - PI / 2.0) * 180.0 / PI
+}
+
+/// Convert a point to pixel coordinates within a tile's bounding box
+///
+/// Matches the Mercator vertex shader: x grows east, y grows south,
+/// and (0, 0) is the top-left corner of the tile.
+pub fn tile_to_pixel(point: &Point, bbox: &BoundingBox, tile_size: u32) -> Pixel {
+    let size = tile_size as f64;
+    let x = (point.lon - bbox.min.lon) / (bbox.max.lon - bbox.min.lon);
+
+    let min_y = lat_to_mercator(bbox.min.lat);
+    let max_y = lat_to_mercator(bbox.max.lat);
+    let y = (lat_to_mercator(point.lat) - min_y) / (max_y - min_y);
+
+    Pixel {
+        x: x * size,
+        y: (1.0 - y) * size,
+    }
+}
+
+/// Convert pixel coordinates within a tile's bounding box to a point (inverse of `tile_to_pixel`)
+pub fn pixel_to_tile(pixel: &Pixel, bbox: &BoundingBox, tile_size: u32) -> Point {
+    let size = tile_size as f64;
+    let lon = bbox.min.lon + pixel.x / size * (bbox.max.lon - bbox.min.lon);
+
+    let min_y = lat_to_mercator(bbox.min.lat);
+    let max_y = lat_to_mercator(bbox.max.lat);
+    let y = min_y + (1.0 - pixel.y / size) * (max_y - min_y);
+
+    Point::new(lon, mercator_to_lat(y))
+}
+
+/// Move points to the center of the pixel they fall in (`--crisp-lines`)
+///
+/// A 1px line through pixel centers covers exactly one pixel per step
+/// instead of straddling two, so horizontal and vertical lines stay sharp.
+pub fn snap_to_pixel_centers(points: &[Point], bbox: &BoundingBox, tile_size: u32) -> Vec<Point> {
+    points
+        .iter()
+        .map(|point| {
+            let pixel = tile_to_pixel(point, bbox, tile_size);
+            let center = Pixel {
+                x: pixel.x.floor() + 0.5,
+                y: pixel.y.floor() + 0.5,
+            };
+            pixel_to_tile(&center, bbox, tile_size)
+        })
+        .collect()
+}
+
+/// Split a way where it crosses the antimeridian
+///
+/// Consecutive points more than 180° of longitude apart are joined the short
+/// way, across ±180°. Each crossing ends a part at lon 180 (or -180) and starts
+/// the next one at the other side, at the latitude where the segment crosses in
+/// Mercator space, as it is drawn. Parts of a closed ring are closed again along
+/// the antimeridian, with the first and last part joined. Returns `None` if the
+/// way doesn't cross.
+pub fn split_at_antimeridian(points: &[Point]) -> Option<Vec<Vec<Point>>> {
+    let mut parts = Vec::new();
+    let mut part = vec![*points.first()?];
+    for pair in points.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        if (b.lon - a.lon).abs() > 180.0 {
+            let edge = 180.0_f64.copysign(a.lon);
+            let b_lon = b.lon + 2.0 * edge;
+            let t = (edge - a.lon) / (b_lon - a.lon);
+            let (y_a, y_b) = (lat_to_mercator(a.lat), lat_to_mercator(b.lat));
+            let lat = mercator_to_lat(y_a + t * (y_b - y_a));
+            part.push(Point::new(edge, lat));
+            parts.push(std::mem::replace(&mut part, vec![Point::new(-edge, lat)]));
+        }
+        part.push(b);
+    }
+    if parts.is_empty() {
+        return None;
+    }
+    parts.push(part);
+
+    if points.len() > 2 && points.first() == points.last() {
+        // The ring's start is inside the last part, which goes on in the first
+        let last = parts.pop().unwrap();
+        parts[0].splice(0..1, last);
+        for part in &mut parts {
+            part.push(part[0]);
+        }
+    }
+    Some(parts)
+}
+
 /// Get bounding box for a tile
 pub fn get_bounding_box(tile: &Tile) -> BoundingBox {
+    get_buffered_bounding_box(tile, 0.0)
+}
+
+/// Get bounding box for a tile extended by `buffer` tile widths on each side
+///
+/// The extension is uniform in Mercator pixel space, so a buffered tile still
+/// renders into a square image. Near the poles the box extends past `MAX_LAT`.
+pub fn get_buffered_bounding_box(tile: &Tile, buffer: f64) -> BoundingBox {
     let n = 2.0_f64.powi(tile.z as i32);
-    let lon_min = (tile.x as f64) / n * 360.0 - 180.0;
-    let lat_min = ((PI * (1.0 - 2.0 * (tile.y as f64) / n)).sinh()).atan() * 180.0 / PI;
-    let lon_max = ((tile.x + 1) as f64) / n * 360.0 - 180.0;
-    let lat_max = ((PI * (1.0 - 2.0 * ((tile.y + 1) as f64) / n)).sinh()).atan() * 180.0 / PI;
+    let lon_min = (tile.x as f64 - buffer) / n * 360.0 - 180.0;
+    let lat_min = ((PI * (1.0 - 2.0 * (tile.y as f64 - buffer) / n)).sinh()).atan() * 180.0 / PI;
+    let lon_max = ((tile.x + 1) as f64 + buffer) / n * 360.0 - 180.0;
+    let lat_max = ((PI * (1.0 - 2.0 * ((tile.y + 1) as f64 + buffer) / n)).sinh()).atan() * 180.0 / PI;
 
     BoundingBox {
         min: Point {
@@ -39,13 +143,107 @@ pub fn deg2num(lat_deg: f64, lon_deg: f64, zoom: u32) -> (u32, u32) {
     (x, y)
 }
 
+/// Convert fractional tile coordinates at a given zoom level to lon/lat
+///
+/// The inverse of `deg2num` before flooring: (x, y) = (1.5, 0.5) at zoom 1
+/// is the center of the top-right tile.
+pub fn num2deg(x: f64, y: f64, zoom: u32) -> Point {
+    let n = 2.0_f64.powi(zoom as i32);
+    let lon = x / n * 360.0 - 180.0;
+    let lat = (PI * (1.0 - 2.0 * y / n)).sinh().atan() * 180.0 / PI;
+    Point::new(lon, lat)
+}
+
+/// Where tile row 0 and pixel row 0 are (`--tile-origin`)
+///
+/// `TopLeft` is the XYZ scheme: y grows southward in the tile grid and the
+/// image. With `BottomLeft`, tile row 0 is the southernmost row and images
+/// are drawn with north at the bottom, i.e. pixel row 0 is the south edge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TileOrigin {
+    #[default]
+    TopLeft,
+    BottomLeft,
+}
+
+impl TileOrigin {
+    /// The XYZ tile covering the same area as `tile` in this origin's grid
+    pub fn to_xyz(self, tile: &Tile) -> Tile {
+        match self {
+            TileOrigin::TopLeft => *tile,
+            TileOrigin::BottomLeft => Tile::new(tile.x, (1u32 << tile.z) - 1 - tile.y, tile.z),
+        }
+    }
+}
+
+impl FromStr for TileOrigin {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "top-left" => Ok(TileOrigin::TopLeft),
+            "bottom-left" => Ok(TileOrigin::BottomLeft),
+            _ => Err(format!("unknown tile origin {:?} (expected top-left or bottom-left)", s)),
+        }
+    }
+}
+
+/// Row numbering of requested tiles (`--scheme`)
+///
+/// `Tms` numbers rows from the south edge like `TileOrigin::BottomLeft`,
+/// but only the numbering differs: images are drawn north up as with `Xyz`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TileScheme {
+    #[default]
+    Xyz,
+    Tms,
+}
+
+impl TileScheme {
+    /// The XYZ tile numbered `tile` in this scheme; its own inverse
+    pub fn to_xyz(self, tile: &Tile) -> Tile {
+        match self {
+            TileScheme::Xyz => *tile,
+            TileScheme::Tms => Tile::new(tile.x, (1u32 << tile.z) - 1 - tile.y, tile.z),
+        }
+    }
+}
+
+impl FromStr for TileScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "xyz" => Ok(TileScheme::Xyz),
+            "tms" => Ok(TileScheme::Tms),
+            _ => Err(format!("unknown tile scheme {:?} (expected xyz or tms)", s)),
+        }
+    }
+}
+
 /// Get all tiles that overlap with a bounding box for a range of zoom levels
+///
+/// Boxes crossing the antimeridian cover the tiles of both of their halves.
 pub fn get_tiles_for_bounding_box(bbox: &BoundingBox, min_z: u32, max_z: u32) -> Vec<Tile> {
+    if bbox.crosses_antimeridian() {
+        let east = BoundingBox::new(bbox.min, Point::new(180.0, bbox.max.lat));
+        let west = BoundingBox::new(Point::new(-180.0, bbox.min.lat), bbox.max);
+        let mut tiles = get_tiles_for_bounding_box(&east, min_z, max_z);
+        // The halves share the tiles at low zoom, where one tile spans both
+        let east_tiles: FxHashSet<Tile> = tiles.iter().copied().collect();
+        tiles.extend(get_tiles_for_bounding_box(&west, min_z, max_z).into_iter().filter(|tile| !east_tiles.contains(tile)));
+        return tiles;
+    }
+
     let mut tiles = Vec::new();
 
     for z in min_z..=max_z {
+        // Lon 180 and the southern edge of the map are on the far edge of the last tile
+        let last = (1u32 << z) - 1;
         let (min_x, min_y) = deg2num(bbox.max.lat, bbox.min.lon, z);
         let (max_x, max_y) = deg2num(bbox.min.lat, bbox.max.lon, z);
+        let (min_x, min_y) = (min_x.min(last), min_y.min(last));
+        let (max_x, max_y) = (max_x.min(last), max_y.min(last));
 
         for x in min_x..=max_x {
             for y in min_y..=max_y {
@@ -61,6 +259,16 @@ pub fn get_tiles_for_bounding_box(bbox: &BoundingBox, min_z: u32, max_z: u32) ->
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_num2deg() {
+        let bbox = get_bounding_box(&Tile::new(1081, 660, 11));
+        assert_eq!(num2deg(1081.0, 661.0, 11), bbox.min);
+        assert_eq!(num2deg(1082.0, 660.0, 11), bbox.max);
+
+        let center = num2deg(1081.5, 660.5, 11);
+        assert_eq!(deg2num(center.lat, center.lon, 11), (1081, 660));
+    }
+
     #[test]
     fn test_lat_to_mercator() {
         // Test some known values
@@ -68,6 +276,95 @@ mod tests {
         assert!((y - 0.0).abs() < 1e-10);
     }
 
+    #[test]
+    fn test_tile_to_pixel() {
+        let tile = Tile::new(1081, 660, 11);
+        let bbox = get_bounding_box(&tile);
+
+        let top_left = tile_to_pixel(&Point::new(bbox.min.lon, bbox.max.lat), &bbox, 256);
+        assert!(top_left.x.abs() < 1e-6 && top_left.y.abs() < 1e-6);
+
+        let bottom_right = tile_to_pixel(&Point::new(bbox.max.lon, bbox.min.lat), &bbox, 256);
+        assert!((bottom_right.x - 256.0).abs() < 1e-6 && (bottom_right.y - 256.0).abs() < 1e-6);
+
+        // Mercator: the geographic center is below the pixel center in the northern hemisphere
+        let center = tile_to_pixel(&bbox.center(), &bbox, 256);
+        assert!((center.x - 128.0).abs() < 1e-6);
+        assert!(center.y > 128.0);
+
+        // Round trip
+        let point = Point::new(bbox.min.lon + 0.01, bbox.min.lat + 0.02);
+        let back = pixel_to_tile(&tile_to_pixel(&point, &bbox, 256), &bbox, 256);
+        assert!((back.lon - point.lon).abs() < 1e-9 && (back.lat - point.lat).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_snap_horizontal_line_to_pixel_row() {
+        let bbox = get_bounding_box(&Tile::new(1081, 660, 11));
+        // A horizontal line at fractional pixel row 100.3
+        let lat = pixel_to_tile(&Pixel { x: 0.0, y: 100.3 }, &bbox, 256).lat;
+        let line = [Point::new(bbox.min.lon + 1e-5, lat), Point::new(bbox.max.lon - 1e-5, lat)];
+
+        let snapped = snap_to_pixel_centers(&line, &bbox, 256);
+        let pixels: Vec<Pixel> = snapped.iter().map(|p| tile_to_pixel(p, &bbox, 256)).collect();
+        for pixel in &pixels {
+            assert!((pixel.y - 100.5).abs() < 1e-6, "{:?}", pixel);
+            assert!((pixel.x - pixel.x.floor() - 0.5).abs() < 1e-6, "{:?}", pixel);
+        }
+        assert!((pixels[0].x - 0.5).abs() < 1e-6 && (pixels[1].x - 255.5).abs() < 1e-6);
+        assert_eq!(snapped[0].lat, snapped[1].lat);
+    }
+
+    #[test]
+    fn test_buffered_bounding_box() {
+        let tile = Tile::new(1081, 660, 11);
+        let bbox = get_bounding_box(&tile);
+        let buffered = get_buffered_bounding_box(&tile, 0.25);
+
+        // A quarter tile on each side: 1.5x the longitude span, centered
+        let span = bbox.max.lon - bbox.min.lon;
+        assert!(((buffered.max.lon - buffered.min.lon) - 1.5 * span).abs() < 1e-9);
+        assert!((bbox.min.lon - buffered.min.lon - 0.25 * span).abs() < 1e-9);
+
+        // The nominal tile edges land a quarter of the way in on the buffered image
+        let top_left = tile_to_pixel(&Point::new(bbox.min.lon, bbox.max.lat), &buffered, 384);
+        assert!((top_left.x - 64.0).abs() < 1e-6 && (top_left.y - 64.0).abs() < 1e-6);
+        let bottom_right = tile_to_pixel(&Point::new(bbox.max.lon, bbox.min.lat), &buffered, 384);
+        assert!((bottom_right.x - 320.0).abs() < 1e-6 && (bottom_right.y - 320.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_tile_origin() {
+        assert_eq!(TileOrigin::TopLeft.to_xyz(&Tile::new(3, 1, 2)), Tile::new(3, 1, 2));
+        assert_eq!(TileOrigin::BottomLeft.to_xyz(&Tile::new(3, 1, 2)), Tile::new(3, 2, 2));
+        assert_eq!(TileOrigin::BottomLeft.to_xyz(&Tile::new(0, 0, 0)), Tile::new(0, 0, 0));
+
+        // Row 0 is the southernmost row
+        let bbox = get_bounding_box(&TileOrigin::BottomLeft.to_xyz(&Tile::new(0, 0, 1)));
+        assert!(bbox.max.lat <= 0.0);
+
+        assert_eq!("bottom-left".parse::<TileOrigin>(), Ok(TileOrigin::BottomLeft));
+        assert!("south".parse::<TileOrigin>().is_err());
+    }
+
+    #[test]
+    fn test_tms_scheme_round_trip() {
+        // The southernmost row in TMS is the last one in XYZ
+        assert_eq!(TileScheme::Tms.to_xyz(&Tile::new(0, 0, 0)), Tile::new(0, 0, 0));
+        assert_eq!(TileScheme::Tms.to_xyz(&Tile::new(1, 0, 1)), Tile::new(1, 1, 1));
+        assert_eq!(TileScheme::Tms.to_xyz(&Tile::new(1081, 1387, 11)), Tile::new(1081, 660, 11));
+        for z in [0, 1, 5, 11, 18, 30] {
+            let n = 1u32 << z;
+            for tile in [Tile::new(0, 0, z), Tile::new(n / 2, n / 3, z), Tile::new(n - 1, n - 1, z)] {
+                assert_eq!(TileScheme::Tms.to_xyz(&TileScheme::Tms.to_xyz(&tile)), tile);
+                assert_eq!(TileScheme::Xyz.to_xyz(&tile), tile);
+            }
+        }
+
+        assert_eq!("tms".parse::<TileScheme>(), Ok(TileScheme::Tms));
+        assert!("wmts".parse::<TileScheme>().is_err());
+    }
+
     #[test]
     fn test_deg2num() {
         // Test tile 0,0,0 contains the whole world
@@ -75,4 +372,65 @@ mod tests {
         assert_eq!(x, 0);
         assert_eq!(y, 0);
     }
+
+    #[test]
+    fn test_split_at_antimeridian() {
+        // Fiji: a way from lon 179 to -179 ends and restarts at the crossing
+        let parts = split_at_antimeridian(&[Point::new(179.0, -17.0), Point::new(-179.0, -17.0)]).unwrap();
+        assert_eq!(
+            parts,
+            vec![
+                vec![Point::new(179.0, -17.0), Point::new(180.0, -17.0)],
+                vec![Point::new(-180.0, -17.0), Point::new(-179.0, -17.0)],
+            ]
+        );
+        // Westward
+        let parts = split_at_antimeridian(&[Point::new(-179.0, 66.0), Point::new(177.0, 66.0 + 4.0)]).unwrap();
+        assert_eq!(parts[0][1].lon, -180.0);
+        assert_eq!(parts[1][0].lon, 180.0);
+        assert_eq!(parts[0][1].lat, parts[1][0].lat);
+        // A quarter of the way in Mercator, a bit north of a quarter in degrees
+        assert!(parts[0][1].lat > 67.0 && parts[0][1].lat < 67.1);
+
+        assert_eq!(split_at_antimeridian(&[Point::new(170.0, 0.0), Point::new(-10.0, 0.0)]), None);
+
+        // A ring around the antimeridian becomes a closed ring on each side
+        let ring = [
+            Point::new(179.0, -17.0),
+            Point::new(-179.0, -17.0),
+            Point::new(-179.0, -16.0),
+            Point::new(179.0, -16.0),
+            Point::new(179.0, -17.0),
+        ];
+        let parts = split_at_antimeridian(&ring).unwrap();
+        assert_eq!(parts.len(), 2);
+        for part in &parts {
+            assert_eq!(part.first(), part.last());
+            assert!(!BoundingBox::from_points(part).unwrap().crosses_antimeridian());
+        }
+        assert!(parts[0].iter().all(|p| p.lon >= 179.0));
+        assert!(parts[1].iter().all(|p| p.lon <= -179.0));
+    }
+
+    #[test]
+    fn test_tiles_for_bounding_box_across_antimeridian() {
+        let bbox = BoundingBox::new(Point::new(179.0, -17.0), Point::new(-179.0, -16.0));
+        let mut tiles = get_tiles_for_bounding_box(&bbox, 0, 2);
+        tiles.sort_by_key(|tile| (tile.z, tile.x, tile.y));
+        // Only the tiles at the left and right edge of the map, not the whole row
+        assert_eq!(
+            tiles,
+            vec![
+                Tile::new(0, 0, 0),
+                Tile::new(0, 1, 1),
+                Tile::new(1, 1, 1),
+                Tile::new(0, 2, 2),
+                Tile::new(3, 2, 2),
+            ]
+        );
+
+        // Lon 180 is in the last column, not past it
+        let east = BoundingBox::new(Point::new(179.0, -17.0), Point::new(180.0, -16.0));
+        assert_eq!(get_tiles_for_bounding_box(&east, 2, 2), vec![Tile::new(3, 2, 2)]);
+    }
 }
@@ -0,0 +1,256 @@
+use crate::data::types::{BoundingBox, Pixel, Point};
+use crate::projection::{tile_to_pixel, TileOrigin};
+use crate::renderer::renderer::NODATA_COLOR;
+use image::{Rgba, RgbaImage};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Area rendering is masked to (`--clip-region`)
+///
+/// Pixels whose center is outside the region are set to `NODATA_COLOR`
+/// after rendering. Rings of all polygons are combined by the even-odd
+/// rule, so holes and disjoint parts work as expected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClipRegion {
+    rings: Vec<Vec<Point>>,
+    bounds: BoundingBox,
+}
+
+/// Clip region loading errors
+#[derive(Debug, thiserror::Error)]
+pub enum ClipError {
+    #[error("Failed to read clip region {}: {source}", path.display())]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Invalid clip region JSON: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Invalid clip region: {0}")]
+    Invalid(String),
+}
+
+impl ClipRegion {
+    /// Region of polygon rings given as `Point`s, closed or not
+    pub fn new(rings: Vec<Vec<Point>>) -> Result<Self, ClipError> {
+        if let Some(ring) = rings.iter().find(|ring| ring.len() < 3) {
+            return Err(ClipError::Invalid(format!("ring with {} positions, expected at least 3", ring.len())));
+        }
+        let points: Vec<Point> = rings.iter().flatten().copied().collect();
+        let bounds = BoundingBox::from_points(&points)
+            .ok_or_else(|| ClipError::Invalid("no polygons".to_string()))?;
+        Ok(ClipRegion { rings, bounds })
+    }
+
+    /// Load the Polygon and MultiPolygon geometries of a GeoJSON file
+    pub fn from_geojson_file<P: AsRef<Path>>(path: P) -> Result<Self, ClipError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|source| ClipError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        text.parse()
+    }
+
+    pub fn bounds(&self) -> &BoundingBox {
+        &self.bounds
+    }
+
+    /// Mask a rendered image of `bbox` to the region
+    ///
+    /// `origin` is the image orientation the renderer drew with.
+    pub fn apply(&self, image: &mut RgbaImage, bbox: &BoundingBox, origin: TileOrigin) {
+        let (width, height) = image.dimensions();
+        if !self.bounds.overlaps(bbox) {
+            image.pixels_mut().for_each(|pixel| *pixel = Rgba(NODATA_COLOR));
+            return;
+        }
+
+        let rings: Vec<Vec<Pixel>> = self
+            .rings
+            .iter()
+            .map(|ring| ring.iter().map(|point| tile_to_pixel(point, bbox, width)).collect())
+            .collect();
+
+        let mut crossings = Vec::new();
+        for row in 0..height {
+            // Edge crossings of the scanline through the pixel centers
+            let y = row as f64 + 0.5;
+            crossings.clear();
+            for ring in &rings {
+                for (i, a) in ring.iter().enumerate() {
+                    let b = &ring[(i + 1) % ring.len()];
+                    if (a.y <= y) != (b.y <= y) {
+                        crossings.push(a.x + (y - a.y) / (b.y - a.y) * (b.x - a.x));
+                    }
+                }
+            }
+            crossings.sort_by(f64::total_cmp);
+
+            let image_row = match origin {
+                TileOrigin::TopLeft => row,
+                TileOrigin::BottomLeft => height - 1 - row,
+            };
+            let mut inside = false;
+            let mut next = 0;
+            for col in 0..width {
+                let x = col as f64 + 0.5;
+                while next < crossings.len() && crossings[next] <= x {
+                    inside = !inside;
+                    next += 1;
+                }
+                if !inside {
+                    image.put_pixel(col, image_row, Rgba(NODATA_COLOR));
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for ClipRegion {
+    type Err = ClipError;
+
+    /// Parse GeoJSON: a Polygon or MultiPolygon geometry, or a Feature,
+    /// FeatureCollection or GeometryCollection of them
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let json: Value = serde_json::from_str(s)?;
+        let mut rings = Vec::new();
+        collect_rings(&json, &mut rings)?;
+        ClipRegion::new(rings)
+    }
+}
+
+fn collect_rings(json: &Value, rings: &mut Vec<Vec<Point>>) -> Result<(), ClipError> {
+    let invalid = |message: &str| ClipError::Invalid(message.to_string());
+    let members = |key: &str| {
+        json.get(key)
+            .and_then(Value::as_array)
+            .ok_or_else(|| ClipError::Invalid(format!("missing {:?} array", key)))
+    };
+
+    match json.get("type").and_then(Value::as_str) {
+        Some("FeatureCollection") => {
+            for feature in members("features")? {
+                collect_rings(feature, rings)?;
+            }
+        }
+        Some("GeometryCollection") => {
+            for geometry in members("geometries")? {
+                collect_rings(geometry, rings)?;
+            }
+        }
+        Some("Feature") => {
+            let geometry = json.get("geometry").ok_or_else(|| invalid("feature without geometry"))?;
+            collect_rings(geometry, rings)?;
+        }
+        Some("Polygon") => {
+            for ring in members("coordinates")? {
+                rings.push(parse_ring(ring)?);
+            }
+        }
+        Some("MultiPolygon") => {
+            for polygon in members("coordinates")? {
+                let polygon = polygon.as_array().ok_or_else(|| invalid("polygon is not an array of rings"))?;
+                for ring in polygon {
+                    rings.push(parse_ring(ring)?);
+                }
+            }
+        }
+        Some(other) => {
+            return Err(ClipError::Invalid(format!(
+                "unsupported geometry type {:?}, expected Polygon or MultiPolygon",
+                other
+            )))
+        }
+        None => return Err(invalid("object without a \"type\"")),
+    }
+    Ok(())
+}
+
+fn parse_ring(ring: &Value) -> Result<Vec<Point>, ClipError> {
+    let invalid = || ClipError::Invalid(format!("invalid ring {}, expected [[lon, lat], ...]", ring));
+    ring.as_array()
+        .ok_or_else(invalid)?
+        .iter()
+        .map(|position| match position.as_array().map(Vec::as_slice) {
+            Some([lon, lat, ..]) => match (lon.as_f64(), lat.as_f64()) {
+                (Some(lon), Some(lat)) => Ok(Point::new(lon, lat)),
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::types::Tile;
+    use crate::projection::get_bounding_box;
+    use crate::renderer::renderer::BACKGROUND_COLOR;
+
+    #[test]
+    fn test_parse_geojson() {
+        let polygon = r#"{"type": "Polygon", "coordinates": [[[0, 0], [10, 0], [10, 10], [0, 0]]]}"#;
+        let region: ClipRegion = polygon.parse().unwrap();
+        assert_eq!(region.rings.len(), 1);
+        assert_eq!(region.bounds(), &BoundingBox::new(Point::new(0.0, 0.0), Point::new(10.0, 10.0)));
+
+        let collection = r#"{"type": "FeatureCollection", "features": [
+            {"type": "Feature", "properties": {}, "geometry": {"type": "MultiPolygon", "coordinates": [
+                [[[0, 0], [1, 0], [1, 1], [0, 0]]],
+                [[[5, 5], [6, 5], [6, 6], [5, 5]], [[5.2, 5.2], [5.8, 5.2], [5.8, 5.8], [5.2, 5.2]]]
+            ]}}
+        ]}"#;
+        let region: ClipRegion = collection.parse().unwrap();
+        assert_eq!(region.rings.len(), 3);
+
+        for bad in [
+            "not json",
+            r#"{"type": "LineString", "coordinates": [[0, 0], [1, 1]]}"#,
+            r#"{"type": "Polygon", "coordinates": [[[0, 0], [1, 1]]]}"#,
+            r#"{"type": "Polygon", "coordinates": [[[0, "a"], [1, 0], [1, 1]]]}"#,
+            r#"{"type": "FeatureCollection", "features": []}"#,
+            r#"{"coordinates": []}"#,
+        ] {
+            assert!(bad.parse::<ClipRegion>().is_err(), "{} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn test_apply_masks_outside_pixels() {
+        // Western hemisphere with a hole around 0,0 (lon -90..-45)
+        let region: ClipRegion = r#"{"type": "Polygon", "coordinates": [
+            [[-180, -80], [0, -80], [0, 80], [-180, 80], [-180, -80]],
+            [[-90, -20], [-45, -20], [-45, 20], [-90, 20], [-90, -20]]
+        ]}"#
+        .parse()
+        .unwrap();
+        let bbox = get_bounding_box(&Tile::new(0, 0, 0));
+        let drawn = Rgba([0, 0, 0, 255]);
+
+        for origin in [TileOrigin::TopLeft, TileOrigin::BottomLeft] {
+            let mut image = RgbaImage::from_pixel(256, 256, drawn);
+            region.apply(&mut image, &bbox, origin);
+            let row = |y: u32| match origin {
+                TileOrigin::TopLeft => y,
+                TileOrigin::BottomLeft => 255 - y,
+            };
+            // lon -135 at x 32, lon 45 at x 160, lon -67.5 (in the hole) at x 80
+            assert_eq!(*image.get_pixel(32, row(128)), drawn);
+            assert_eq!(image.get_pixel(160, row(128)).0, NODATA_COLOR);
+            assert_eq!(image.get_pixel(80, row(128)).0, NODATA_COLOR);
+            // North of the region (lat 80 is around y 32)
+            assert_eq!(image.get_pixel(32, row(4)).0, NODATA_COLOR);
+            assert_eq!(*image.get_pixel(32, row(40)), drawn);
+        }
+
+        // Tiles not overlapping the region are fully masked
+        let mut image = RgbaImage::from_pixel(256, 256, Rgba(BACKGROUND_COLOR));
+        region.apply(&mut image, &get_bounding_box(&Tile::new(3, 1, 2)), TileOrigin::TopLeft);
+        assert!(image.pixels().all(|p| p.0 == NODATA_COLOR));
+    }
+}
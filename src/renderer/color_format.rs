@@ -0,0 +1,119 @@
+//! Format of the rendered color image, and sRGB conversions
+//!
+//! With `ColorFormat::Srgb` the GPU blends and resolves MSAA in linear
+//! light and encodes the result to sRGB when storing it. Vertex colors stay
+//! UNORM and the fragment shader (`tile_srgb.frag`) decodes them to linear,
+//! so opaque colors come out unchanged and only blended pixels (antialiased
+//! edges, overlapping translucent lines) differ.
+
+use ash::vk;
+use std::str::FromStr;
+
+/// Color attachment format (`--color-format`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorFormat {
+    /// `R8G8B8A8_UNORM`: colors are blended as stored
+    #[default]
+    Unorm,
+    /// `R8G8B8A8_SRGB`: colors are blended in linear light
+    Srgb,
+}
+
+impl ColorFormat {
+    /// Format of the color attachment and the images read back
+    pub fn vk_format(self) -> vk::Format {
+        match self {
+            ColorFormat::Unorm => vk::Format::R8G8B8A8_UNORM,
+            ColorFormat::Srgb => vk::Format::R8G8B8A8_SRGB,
+        }
+    }
+
+    /// Clear value of `color`, which the attachment stores as given
+    pub fn clear_color(self, color: [u8; 4]) -> [f32; 4] {
+        let [r, g, b, a] = color;
+        let channel = |c: u8| match self {
+            ColorFormat::Unorm => c as f32 / 255.0,
+            ColorFormat::Srgb => srgb_to_linear(c),
+        };
+        [channel(r), channel(g), channel(b), a as f32 / 255.0]
+    }
+
+    /// Whether the device can render to, blend into and copy from images of
+    /// this format
+    pub fn is_supported(self, instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> bool {
+        let attachment = vk::FormatFeatureFlags::COLOR_ATTACHMENT
+            | vk::FormatFeatureFlags::COLOR_ATTACHMENT_BLEND
+            | vk::FormatFeatureFlags::TRANSFER_SRC;
+        let properties = unsafe { instance.get_physical_device_format_properties(physical_device, self.vk_format()) };
+        properties.optimal_tiling_features.contains(attachment)
+    }
+
+    pub fn is_srgb(self) -> bool {
+        self == ColorFormat::Srgb
+    }
+}
+
+impl FromStr for ColorFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unorm" => Ok(ColorFormat::Unorm),
+            "srgb" => Ok(ColorFormat::Srgb),
+            _ => Err(format!("unknown color format {:?} (expected unorm or srgb)", s)),
+        }
+    }
+}
+
+/// Linear intensity of the sRGB encoded channel value `c`
+pub fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// sRGB encoded channel value of the linear intensity `c`, clamped to 0..=1
+pub fn linear_to_srgb(c: f32) -> u8 {
+    let c = c.clamp(0.0, 1.0);
+    let encoded = if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+    (encoded * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_srgb_round_trip() {
+        assert_eq!(srgb_to_linear(0), 0.0);
+        assert_eq!(srgb_to_linear(255), 1.0);
+        // Mid gray is about a fifth of the light
+        assert!((srgb_to_linear(128) - 0.2158).abs() < 1e-3);
+        assert_eq!(linear_to_srgb(0.5), 188);
+        assert!((0..=255).all(|c| linear_to_srgb(srgb_to_linear(c)) == c));
+        assert_eq!(linear_to_srgb(2.0), 255);
+    }
+
+    #[test]
+    fn test_clear_color() {
+        assert_eq!(ColorFormat::Unorm.clear_color([255, 0, 51, 128]), [1.0, 0.0, 0.2, 128.0 / 255.0]);
+        let [r, g, b, a] = ColorFormat::Srgb.clear_color([255, 0, 128, 128]);
+        assert_eq!((r, g, a), (1.0, 0.0, 128.0 / 255.0));
+        assert!((b - 0.2158).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_color_format_from_str() {
+        assert_eq!("srgb".parse(), Ok(ColorFormat::Srgb));
+        assert_eq!("unorm".parse(), Ok(ColorFormat::Unorm));
+        assert!("rgba16f".parse::<ColorFormat>().is_err());
+        assert_eq!(ColorFormat::Srgb.vk_format(), vk::Format::R8G8B8A8_SRGB);
+    }
+}
@@ -0,0 +1,75 @@
+use crate::data::types::Pixel;
+use std::collections::HashMap;
+
+/// Decimate point features to at most one per `cell_px` × `cell_px` pixel cell
+///
+/// `points` holds each feature's projected pixel position and importance.
+/// In a contested cell the most important feature survives; ties keep the
+/// one that comes first. Returns the indices of the kept points in their
+/// original order. A `cell_px` of zero or less disables decimation.
+pub fn decimate_points(points: &[(Pixel, u32)], cell_px: f64) -> Vec<usize> {
+    if cell_px <= 0.0 {
+        return (0..points.len()).collect();
+    }
+
+    let mut best: HashMap<(i64, i64), usize> = HashMap::new();
+    for (i, (pixel, importance)) in points.iter().enumerate() {
+        let cell = (
+            (pixel.x / cell_px).floor() as i64,
+            (pixel.y / cell_px).floor() as i64,
+        );
+        best.entry(cell)
+            .and_modify(|kept| {
+                if *importance > points[*kept].1 {
+                    *kept = i;
+                }
+            })
+            .or_insert(i);
+    }
+
+    let mut kept: Vec<usize> = best.into_values().collect();
+    kept.sort_unstable();
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(x: f64, y: f64) -> Pixel {
+        Pixel { x, y }
+    }
+
+    #[test]
+    fn test_decimate_cluster() {
+        let points = vec![
+            // Cluster inside the first 16px cell
+            (pixel(1.0, 1.0), 0),
+            (pixel(3.0, 2.0), 0),
+            (pixel(15.0, 15.0), 0),
+            // Neighbouring cell
+            (pixel(17.0, 1.0), 0),
+            // Far away
+            (pixel(200.0, 100.0), 0),
+        ];
+
+        assert_eq!(decimate_points(&points, 16.0), vec![0, 3, 4]);
+        // Smaller cells keep more of the cluster
+        assert_eq!(decimate_points(&points, 4.0), vec![0, 2, 3, 4]);
+        // Disabled
+        assert_eq!(decimate_points(&points, 0.0), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_decimate_keeps_most_important() {
+        let points = vec![
+            (pixel(1.0, 1.0), 1),
+            (pixel(2.0, 2.0), 5),
+            (pixel(3.0, 3.0), 5),
+            (pixel(4.0, 4.0), 2),
+        ];
+
+        // Highest importance wins, first one on ties
+        assert_eq!(decimate_points(&points, 8.0), vec![1]);
+    }
+}
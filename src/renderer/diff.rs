@@ -0,0 +1,117 @@
+use crate::data::spatial::TileIndex;
+use crate::data::types::MapObjectOffset;
+use std::collections::HashSet;
+
+/// Diff status of a way between a base and a current data set, matched by way id
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffStatus {
+    Added,
+    Removed,
+    Unchanged,
+}
+
+impl DiffStatus {
+    /// RGBA line color used for this status in diff tiles
+    pub fn color(self) -> [u8; 4] {
+        match self {
+            DiffStatus::Added => [0, 170, 0, 255],
+            DiffStatus::Removed => [220, 0, 0, 255],
+            DiffStatus::Unchanged => [160, 160, 160, 255],
+        }
+    }
+}
+
+/// The objects of one tile split by diff status
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TileDiff {
+    /// Offsets into the current data file
+    pub added: Vec<MapObjectOffset>,
+    /// Offsets into the base data file
+    pub removed: Vec<MapObjectOffset>,
+    /// Offsets into the current data file
+    pub unchanged: Vec<MapObjectOffset>,
+}
+
+impl TileDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.unchanged.is_empty()
+    }
+}
+
+/// Classify a tile's objects by comparing way ids of the current and base data
+///
+/// Objects without a recorded way id can't be matched and are skipped.
+pub fn diff_tile(
+    current_offsets: &[MapObjectOffset],
+    current: &TileIndex,
+    base_offsets: &[MapObjectOffset],
+    base: &TileIndex,
+) -> TileDiff {
+    let current_ids: HashSet<i64> = current_offsets
+        .iter()
+        .filter_map(|&offset| current.way_id(offset))
+        .collect();
+    let base_ids: HashSet<i64> = base_offsets
+        .iter()
+        .filter_map(|&offset| base.way_id(offset))
+        .collect();
+
+    let mut diff = TileDiff::default();
+    for &offset in current_offsets {
+        match current.way_id(offset) {
+            Some(id) if base_ids.contains(&id) => diff.unchanged.push(offset),
+            Some(_) => diff.added.push(offset),
+            None => log::debug!("Object at offset {} has no way id, skipped in diff", offset),
+        }
+    }
+    for &offset in base_offsets {
+        match base.way_id(offset) {
+            Some(id) if !current_ids.contains(&id) => diff.removed.push(offset),
+            Some(_) => {}
+            None => log::debug!("Base object at offset {} has no way id, skipped in diff", offset),
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(objects: &[(MapObjectOffset, i64)]) -> TileIndex {
+        let mut index = TileIndex::new();
+        for &(offset, way_id) in objects {
+            index.record_way_id(offset, way_id);
+        }
+        index
+    }
+
+    #[test]
+    fn test_diff_tile() {
+        // Way 1 kept (at a different offset), way 2 removed, way 3 added
+        let base = index(&[(0, 1), (100, 2)]);
+        let current = index(&[(0, 3), (80, 1), (200, 4)]);
+
+        let diff = diff_tile(&[0, 80], &current, &[0, 100], &base);
+        assert_eq!(diff.unchanged, vec![80]);
+        assert_eq!(diff.added, vec![0]);
+        assert_eq!(diff.removed, vec![100]);
+
+        // Objects without ids are skipped
+        let diff = diff_tile(&[0, 500], &current, &[], &base);
+        assert_eq!(diff.added, vec![0]);
+        assert!(diff.unchanged.is_empty() && diff.removed.is_empty());
+
+        assert!(diff_tile(&[], &current, &[], &base).is_empty());
+    }
+
+    #[test]
+    fn test_diff_colors() {
+        let [r, g, b, _] = DiffStatus::Added.color();
+        assert!(g > r && g > b);
+        let [r, g, b, _] = DiffStatus::Removed.color();
+        assert!(r > g && r > b);
+        let [r, g, b, _] = DiffStatus::Unchanged.color();
+        assert!(r == g && g == b);
+    }
+}
@@ -0,0 +1,140 @@
+use image::imageops::{self, FilterType};
+use image::{ImageBuffer, Rgba, RgbaImage};
+use std::str::FromStr;
+
+/// Largest accepted supersampling factor (`--supersample`)
+pub const MAX_SUPERSAMPLE: u32 = 4;
+
+/// Filter that reduces supersampled renders to the tile size (`--downscale-filter`)
+///
+/// All filters work on premultiplied alpha, so the color of transparent
+/// pixels (the nodata background) doesn't bleed into feature edges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DownscaleFilter {
+    /// Average of each block of source pixels; fastest
+    #[default]
+    Box,
+    /// Linear weights over twice the block size
+    Triangle,
+    /// Windowed sinc over six blocks; sharpest, may ring at hard edges
+    Lanczos3,
+}
+
+impl FromStr for DownscaleFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(DownscaleFilter::Box),
+            "triangle" => Ok(DownscaleFilter::Triangle),
+            "lanczos3" => Ok(DownscaleFilter::Lanczos3),
+            _ => Err(format!("unknown downscale filter {:?} (expected box, triangle or lanczos3)", s)),
+        }
+    }
+}
+
+/// Downscale a square image to `size` pixels with `filter`
+///
+/// The box filter needs the image size to be a multiple of `size`, as
+/// supersampled renders are.
+pub fn downscale(image: &RgbaImage, size: u32, filter: DownscaleFilter) -> RgbaImage {
+    match filter {
+        DownscaleFilter::Box => downscale_box(image, size),
+        DownscaleFilter::Triangle => downscale_with(image, size, FilterType::Triangle),
+        DownscaleFilter::Lanczos3 => downscale_with(image, size, FilterType::Lanczos3),
+    }
+}
+
+fn downscale_box(image: &RgbaImage, size: u32) -> RgbaImage {
+    let factor = image.width() / size;
+    assert_eq!(image.width(), size * factor, "box downscale by a non-integer factor");
+    let samples = factor * factor;
+
+    RgbaImage::from_fn(size, size, |x, y| {
+        let mut sums = [0u32; 4];
+        for sy in y * factor..(y + 1) * factor {
+            for sx in x * factor..(x + 1) * factor {
+                let [r, g, b, a] = image.get_pixel(sx, sy).0;
+                let a32 = a as u32;
+                sums[0] += r as u32 * a32;
+                sums[1] += g as u32 * a32;
+                sums[2] += b as u32 * a32;
+                sums[3] += a32;
+            }
+        }
+        if sums[3] == 0 {
+            return Rgba([0, 0, 0, 0]);
+        }
+        // Alpha-weighted mean color, i.e. the premultiplied mean unpremultiplied
+        let color = |sum: u32| ((sum + sums[3] / 2) / sums[3]) as u8;
+        let alpha = ((sums[3] + samples / 2) / samples) as u8;
+        Rgba([color(sums[0]), color(sums[1]), color(sums[2]), alpha])
+    })
+}
+
+fn downscale_with(image: &RgbaImage, size: u32, filter: FilterType) -> RgbaImage {
+    let premultiplied: ImageBuffer<Rgba<f32>, Vec<f32>> = ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+        let [r, g, b, a] = image.get_pixel(x, y).0.map(|c| c as f32 / 255.0);
+        Rgba([r * a, g * a, b * a, a])
+    });
+    let resized = imageops::resize(&premultiplied, size, size, filter);
+
+    RgbaImage::from_fn(size, size, |x, y| {
+        // Lanczos overshoots; keep alpha in range and colors within it
+        let [r, g, b, a] = resized.get_pixel(x, y).0;
+        let a = a.clamp(0.0, 1.0);
+        if a <= 0.0 {
+            return Rgba([0, 0, 0, 0]);
+        }
+        let channel = |c: f32| ((c.clamp(0.0, a) / a) * 255.0).round() as u8;
+        Rgba([channel(r), channel(g), channel(b), (a * 255.0).round() as u8])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+    #[test]
+    fn test_box_vs_lanczos_edge() {
+        // Vertical edge on a block boundary, downscaled by 4
+        let edge = RgbaImage::from_fn(32, 32, |x, _| if x < 16 { BLACK } else { WHITE });
+        let boxed = downscale(&edge, 8, DownscaleFilter::Box);
+        let lanczos = downscale(&edge, 8, DownscaleFilter::Lanczos3);
+        let row = |image: &RgbaImage| (0..8).map(|x| image.get_pixel(x, 4).0[0]).collect::<Vec<_>>();
+
+        // Box keeps the edge within one pixel, Lanczos rings next to it
+        assert_eq!(row(&boxed), vec![0, 0, 0, 0, 255, 255, 255, 255]);
+        let lanczos_row = row(&lanczos);
+        assert!(lanczos_row[3] > 0 && lanczos_row[4] < 255, "{:?}", lanczos_row);
+        assert_eq!((lanczos_row[0], lanczos_row[7]), (0, 255), "{:?}", lanczos_row);
+
+        // Fine stripes average to gray either way
+        let stripes = RgbaImage::from_fn(32, 32, |x, _| if x % 2 == 0 { BLACK } else { WHITE });
+        for filter in [DownscaleFilter::Box, DownscaleFilter::Triangle, DownscaleFilter::Lanczos3] {
+            let gray = downscale(&stripes, 8, filter).get_pixel(4, 4).0;
+            assert!(gray[0].abs_diff(128) <= 2 && gray[3] == 255, "{:?}: {:?}", filter, gray);
+        }
+    }
+
+    #[test]
+    fn test_transparent_pixels_dont_bleed() {
+        // Opaque blue feature on transparent red (the red must never show)
+        let image = RgbaImage::from_fn(16, 16, |x, _| if x < 6 { Rgba([0, 0, 255, 255]) } else { Rgba([255, 0, 0, 0]) });
+        for filter in [DownscaleFilter::Box, DownscaleFilter::Triangle, DownscaleFilter::Lanczos3] {
+            let small = downscale(&image, 4, filter);
+            // 6 of 4x4 blocks: pixel 1 is half covered
+            let edge = small.get_pixel(1, 2).0;
+            assert_eq!(&edge[0..3], &[0, 0, 255], "{:?}: {:?}", filter, edge);
+            assert!(edge[3] > 0 && edge[3] < 255, "{:?}: {:?}", filter, edge);
+            assert!(small.pixels().all(|p| p.0[0] == 0), "{:?} bled red", filter);
+        }
+        assert_eq!(downscale(&image, 4, DownscaleFilter::Box).get_pixel(1, 2).0[3], 128);
+
+        assert_eq!("lanczos3".parse(), Ok(DownscaleFilter::Lanczos3));
+        assert!("bicubic".parse::<DownscaleFilter>().is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use crate::data::types::Pixel;
+
+/// Triangles covering `line` drawn `width` pixels wide
+///
+/// Each segment becomes a quad (two triangles) centered on it. At each
+/// inner vertex a bevel triangle fills the wedge between the two quads on
+/// the outside of the turn. Zero-length segments are skipped. Returns a
+/// vertex list for `TRIANGLE_LIST` topology: 6 vertices per segment plus 3
+/// per join.
+pub fn extrude_line(line: &[Pixel], width: f64) -> Vec<Pixel> {
+    let half = width / 2.0;
+    // Unit normals of the non-degenerate segments, with their endpoints
+    let segments: Vec<(Pixel, Pixel, Pixel)> = line
+        .windows(2)
+        .filter_map(|pair| {
+            let (dx, dy) = (pair[1].x - pair[0].x, pair[1].y - pair[0].y);
+            let len = dx.hypot(dy);
+            (len > 0.0).then(|| (pair[0], pair[1], Pixel { x: -dy / len, y: dx / len }))
+        })
+        .collect();
+
+    let offset = |p: &Pixel, n: &Pixel, side: f64| Pixel {
+        x: p.x + n.x * half * side,
+        y: p.y + n.y * half * side,
+    };
+    let mut triangles = Vec::with_capacity(segments.len() * 9);
+    for (i, &(a, b, n)) in segments.iter().enumerate() {
+        let (a_left, a_right) = (offset(&a, &n, 1.0), offset(&a, &n, -1.0));
+        let (b_left, b_right) = (offset(&b, &n, 1.0), offset(&b, &n, -1.0));
+        triangles.extend([a_left, a_right, b_left, b_left, a_right, b_right]);
+
+        if let Some(&(_, _, next)) = segments.get(i + 1) {
+            // The next segment turns towards `n` if its normal is rotated the same way
+            let cross = n.x * next.y - n.y * next.x;
+            if cross == 0.0 {
+                continue;
+            }
+            let outside = if cross > 0.0 { -1.0 } else { 1.0 };
+            triangles.extend([b, offset(&b, &n, outside), offset(&b, &next, outside)]);
+        }
+    }
+    triangles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether `p` lies in any of the triangles
+    fn covered(triangles: &[Pixel], p: Pixel) -> bool {
+        let side = |a: &Pixel, b: &Pixel| (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x);
+        triangles.chunks(3).any(|t| {
+            let (d1, d2, d3) = (side(&t[0], &t[1]), side(&t[1], &t[2]), side(&t[2], &t[0]));
+            let has_neg = d1 < 0.0 || d2 < 0.0 || d3 < 0.0;
+            let has_pos = d1 > 0.0 || d2 > 0.0 || d3 > 0.0;
+            !(has_neg && has_pos)
+        })
+    }
+
+    #[test]
+    fn test_extrude_right_angle_without_gap() {
+        // East, then south (y down): the outside of the turn is north-east
+        let p = |x, y| Pixel { x, y };
+        let line = [p(0.0, 10.0), p(10.0, 10.0), p(10.0, 20.0), p(10.0, 20.0)];
+        let triangles = extrude_line(&line, 4.0);
+        // Two quads and one join; the repeated point adds nothing
+        assert_eq!(triangles.len(), 2 * 6 + 3);
+
+        assert!(covered(&triangles, p(5.0, 8.5)), "first segment, 1.5px off center");
+        assert!(covered(&triangles, p(11.5, 15.0)), "second segment, 1.5px off center");
+        assert!(!covered(&triangles, p(5.0, 12.5)), "wider than 4px");
+        // Outer corner: only the bevel covers it
+        assert!(covered(&triangles, p(10.5, 9.0)));
+        let quads: Vec<Pixel> = triangles[..6].iter().chain(&triangles[9..]).copied().collect();
+        assert!(!covered(&quads, p(10.5, 9.0)), "gap without the bevel");
+
+        // Straight lines need no joins
+        assert_eq!(extrude_line(&[p(0.0, 0.0), p(1.0, 0.0), p(2.0, 0.0)], 2.0).len(), 12);
+        assert!(extrude_line(&[p(0.0, 0.0)], 2.0).is_empty());
+    }
+}
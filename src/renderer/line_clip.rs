@@ -0,0 +1,146 @@
+//! Clipping of line geometry to the tile (Liang–Barsky)
+//!
+//! Clipping happens in pixels rather than degrees: the vertex shader draws
+//! straight lines between projected points, and Mercator latitude isn't
+//! linear, so a cut computed in degrees would move off the drawn line.
+
+use crate::data::types::Pixel;
+
+/// Pixels lines may reach outside the tile before being cut, on top of half
+/// their width, so antialiased edges and line caps aren't clipped visibly
+pub const CLIP_MARGIN_PX: f64 = 1.0;
+
+/// Axis-aligned rectangle in pixels that lines are clipped to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClipRect {
+    pub min: Pixel,
+    pub max: Pixel,
+}
+
+impl ClipRect {
+    /// The `tile_size` square of a tile, grown by `margin` pixels on each side
+    pub fn around_tile(tile_size: u32, margin: f64) -> Self {
+        let size = tile_size as f64;
+        ClipRect {
+            min: Pixel { x: -margin, y: -margin },
+            max: Pixel { x: size + margin, y: size + margin },
+        }
+    }
+}
+
+/// The part of segment `a`–`b` inside `rect`, `None` if it's entirely outside
+///
+/// Endpoints inside the rectangle are returned unchanged.
+pub fn clip_segment(a: Pixel, b: Pixel, rect: &ClipRect) -> Option<(Pixel, Pixel)> {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    // Parameters along the segment where it enters and leaves the rectangle
+    let (mut t_enter, mut t_leave) = (0.0_f64, 1.0_f64);
+    let edges = [
+        (-dx, a.x - rect.min.x),
+        (dx, rect.max.x - a.x),
+        (-dy, a.y - rect.min.y),
+        (dy, rect.max.y - a.y),
+    ];
+    for (p, q) in edges {
+        if p == 0.0 {
+            // Parallel to this edge: either fully on the inside or fully outside
+            if q < 0.0 {
+                return None;
+            }
+            continue;
+        }
+        let t = q / p;
+        if p < 0.0 {
+            t_enter = t_enter.max(t);
+        } else {
+            t_leave = t_leave.min(t);
+        }
+        if t_enter > t_leave {
+            return None;
+        }
+    }
+
+    let at = |t: f64| Pixel { x: a.x + t * dx, y: a.y + t * dy };
+    let start = if t_enter > 0.0 { at(t_enter) } else { a };
+    let end = if t_leave < 1.0 { at(t_leave) } else { b };
+    Some((start, end))
+}
+
+/// The parts of `line` inside `rect`, as separate polylines
+///
+/// A line leaving and re-entering the rectangle is split in two, so
+/// consecutive points of each part are still joined as in `line`.
+pub fn clip_polyline(line: &[Pixel], rect: &ClipRect) -> Vec<Vec<Pixel>> {
+    let mut parts = Vec::new();
+    let mut part: Vec<Pixel> = Vec::new();
+    for pair in line.windows(2) {
+        let Some((start, end)) = clip_segment(pair[0], pair[1], rect) else {
+            continue;
+        };
+        if part.last() != Some(&start) {
+            if !part.is_empty() {
+                parts.push(std::mem::take(&mut part));
+            }
+            part.push(start);
+        }
+        part.push(end);
+    }
+    if !part.is_empty() {
+        parts.push(part);
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn px(x: f64, y: f64) -> Pixel {
+        Pixel { x, y }
+    }
+
+    #[test]
+    fn test_clip_segment() {
+        let rect = ClipRect::around_tile(256, 0.0);
+
+        // Fully inside: unchanged
+        assert_eq!(clip_segment(px(10.0, 10.0), px(100.0, 200.0), &rect), Some((px(10.0, 10.0), px(100.0, 200.0))));
+        // Fully outside, also when the bounding boxes overlap
+        assert_eq!(clip_segment(px(-10.0, 10.0), px(-5.0, 200.0), &rect), None);
+        assert_eq!(clip_segment(px(300.0, 0.0), px(300.0, 256.0), &rect), None);
+        assert_eq!(clip_segment(px(-100.0, 200.0), px(200.0, 500.0), &rect), None);
+
+        // Straddling one edge: the outside end is cut
+        assert_eq!(clip_segment(px(128.0, 128.0), px(384.0, 128.0), &rect), Some((px(128.0, 128.0), px(256.0, 128.0))));
+        // Crossing the whole tile: both ends are cut
+        assert_eq!(clip_segment(px(-256.0, 0.0), px(512.0, 384.0), &rect), Some((px(0.0, 128.0), px(256.0, 256.0))));
+        // Reversed segments keep their direction
+        assert_eq!(clip_segment(px(384.0, 128.0), px(128.0, 128.0), &rect), Some((px(256.0, 128.0), px(128.0, 128.0))));
+
+        // The margin lets lines reach outside the tile
+        let rect = ClipRect::around_tile(256, 2.0);
+        assert_eq!(clip_segment(px(-1.0, 10.0), px(-1.0, 200.0), &rect), Some((px(-1.0, 10.0), px(-1.0, 200.0))));
+        assert_eq!(clip_segment(px(10.0, -10.0), px(10.0, 10.0), &rect), Some((px(10.0, -2.0), px(10.0, 10.0))));
+    }
+
+    #[test]
+    fn test_clip_polyline_splits_at_exits() {
+        let rect = ClipRect::around_tile(256, 0.0);
+
+        let inside = vec![px(10.0, 10.0), px(20.0, 10.0), px(20.0, 20.0)];
+        assert_eq!(clip_polyline(&inside, &rect), vec![inside.clone()]);
+
+        // Out through the right edge and back in
+        let line = vec![px(200.0, 100.0), px(300.0, 100.0), px(300.0, 150.0), px(200.0, 150.0), px(200.0, 200.0)];
+        assert_eq!(
+            clip_polyline(&line, &rect),
+            vec![
+                vec![px(200.0, 100.0), px(256.0, 100.0)],
+                vec![px(256.0, 150.0), px(200.0, 150.0), px(200.0, 200.0)],
+            ]
+        );
+
+        let outside = vec![px(-10.0, -10.0), px(-20.0, 300.0)];
+        assert!(clip_polyline(&outside, &rect).is_empty());
+    }
+}
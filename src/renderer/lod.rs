@@ -0,0 +1,108 @@
+use crate::data::types::{BoundingBox, Point};
+use crate::projection::tile_to_pixel;
+
+/// Level of detail at which a single object is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lod {
+    /// Too small to be visible
+    Skip,
+    /// Drawn as one segment, see `simplify`
+    Simplified,
+    /// All points drawn
+    Full,
+}
+
+/// Object-level LOD thresholds in pixels of projected extent
+///
+/// Objects whose bounding box spans less than `skip_below_px` in the
+/// rendered tile are skipped, those below `simplify_below_px` are drawn as
+/// a single segment. Both default to 0, which draws everything in full.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LodThresholds {
+    pub skip_below_px: f64,
+    pub simplify_below_px: f64,
+}
+
+impl LodThresholds {
+    pub fn is_enabled(&self) -> bool {
+        self.skip_below_px > 0.0 || self.simplify_below_px > 0.0
+    }
+
+    /// Pick the LOD for an object spanning `extent_px` pixels
+    pub fn select(&self, extent_px: f64) -> Lod {
+        if extent_px < self.skip_below_px {
+            Lod::Skip
+        } else if extent_px < self.simplify_below_px {
+            Lod::Simplified
+        } else {
+            Lod::Full
+        }
+    }
+}
+
+/// Larger side in pixels of `object_bbox` projected into a `tile_size` tile
+pub fn pixel_extent(object_bbox: &BoundingBox, tile_bbox: &BoundingBox, tile_size: u32) -> f64 {
+    let min = tile_to_pixel(&object_bbox.min, tile_bbox, tile_size);
+    let max = tile_to_pixel(&object_bbox.max, tile_bbox, tile_size);
+    (max.x - min.x).abs().max((max.y - min.y).abs())
+}
+
+/// Reduce an object to a single segment
+///
+/// Uses the first and last point, or the first and middle point for
+/// closed ways, whose ends coincide.
+pub fn simplify(points: &[Point]) -> [Point; 2] {
+    let first = points[0];
+    let last = points[points.len() - 1];
+    if first == last {
+        [first, points[points.len() / 2]]
+    } else {
+        [first, last]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::types::Tile;
+    use crate::projection::get_bounding_box;
+
+    #[test]
+    fn test_lod_by_projected_size() {
+        let thresholds = LodThresholds {
+            skip_below_px: 2.0,
+            simplify_below_px: 16.0,
+        };
+        // z0 tile: 360 degrees over 256 px, ~1.4 degrees per pixel at the equator
+        let tile_bbox = get_bounding_box(&Tile::new(0, 0, 0));
+        let feature = |size: f64| BoundingBox::new(Point::new(0.0, 0.0), Point::new(size, size));
+
+        let small = pixel_extent(&feature(1.0), &tile_bbox, 256);
+        let medium = pixel_extent(&feature(10.0), &tile_bbox, 256);
+        let large = pixel_extent(&feature(90.0), &tile_bbox, 256);
+        assert!(small < 1.0 && (7.0..8.0).contains(&medium) && large > 64.0);
+
+        assert_eq!(thresholds.select(small), Lod::Skip);
+        assert_eq!(thresholds.select(medium), Lod::Simplified);
+        assert_eq!(thresholds.select(large), Lod::Full);
+
+        // Disabled by default
+        assert!(!LodThresholds::default().is_enabled());
+        assert_eq!(LodThresholds::default().select(0.0), Lod::Full);
+    }
+
+    #[test]
+    fn test_simplify() {
+        let line = [Point::new(0.0, 0.0), Point::new(1.0, 2.0), Point::new(3.0, 1.0)];
+        assert_eq!(simplify(&line), [line[0], line[2]]);
+
+        let ring = [
+            Point::new(0.0, 0.0),
+            Point::new(1.0, 0.0),
+            Point::new(1.0, 1.0),
+            Point::new(0.0, 1.0),
+            Point::new(0.0, 0.0),
+        ];
+        assert_eq!(simplify(&ring), [ring[0], ring[2]]);
+    }
+}
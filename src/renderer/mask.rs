@@ -0,0 +1,46 @@
+use crate::renderer::renderer::{BACKGROUND_COLOR, NODATA_COLOR};
+use image::{GrayImage, Luma, RgbaImage};
+
+/// Mask value of pixels touched by any feature
+pub const COVERED: u8 = 255;
+
+/// Coverage mask of a rendered tile
+///
+/// Every pixel that differs from the background is `COVERED`, all others
+/// are 0, regardless of feature type or line color. Clipped (nodata)
+/// pixels are not covered.
+pub fn coverage_mask(image: &RgbaImage) -> GrayImage {
+    GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        let pixel = image.get_pixel(x, y).0;
+        if pixel == BACKGROUND_COLOR || pixel == NODATA_COLOR {
+            Luma([0])
+        } else {
+            Luma([COVERED])
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_coverage_mask() {
+        let mut image = RgbaImage::from_pixel(8, 8, Rgba(BACKGROUND_COLOR));
+        // A diagonal line, one pixel in another color
+        for i in 0..8 {
+            image.put_pixel(i, i, Rgba([0, 0, 0, 255]));
+        }
+        image.put_pixel(3, 3, Rgba([220, 0, 0, 255]));
+        // Clipped away
+        image.put_pixel(7, 7, Rgba(NODATA_COLOR));
+
+        let mask = coverage_mask(&image);
+        assert_eq!(mask.dimensions(), (8, 8));
+        for (x, y, pixel) in mask.enumerate_pixels() {
+            let expected = if x == y && x != 7 { COVERED } else { 0 };
+            assert_eq!(pixel.0, [expected], "pixel {},{}", x, y);
+        }
+    }
+}
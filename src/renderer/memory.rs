@@ -1,4 +1,4 @@
-use super::vulkan::VulkanContext;
+use super::vulkan::{VulkanContext, VulkanError};
 use ash::vk;
 use gpu_allocator::vulkan::{Allocator, AllocatorCreateDesc};
 use gpu_allocator::MemoryLocation;
@@ -68,12 +68,43 @@ pub fn create_buffer(
     Ok((buffer, allocation))
 }
 
+/// Host pointer to a mapped allocation
+///
+/// gpu-allocator maps every host-visible allocation; `CpuToGpu` memory may
+/// still be device-local only on some configurations.
+pub fn mapped_ptr(allocation: &gpu_allocator::vulkan::Allocation, name: &'static str) -> Result<*mut u8, VulkanError> {
+    allocation
+        .mapped_ptr()
+        .map(|ptr| ptr.as_ptr() as *mut u8)
+        .ok_or(VulkanError::NotMappable(name))
+}
+
+/// How vertex data reaches the vertex buffer, decided at renderer creation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VertexUpload {
+    /// Vertices are written into the mapped vertex buffer
+    Direct,
+    /// Vertices are written into a mapped staging buffer and copied into
+    /// the device-local vertex buffer before drawing
+    Staged,
+}
+
+impl VertexUpload {
+    pub fn for_vertex_buffer(mapped: bool) -> Self {
+        if mapped {
+            VertexUpload::Direct
+        } else {
+            VertexUpload::Staged
+        }
+    }
+}
+
 /// Helper function to create an image with gpu-allocator
 pub fn create_image(
     device: &ash::Device,
     allocator: &mut Allocator,
-    width: u32,
-    height: u32,
+    extent: vk::Extent2D,
+    samples: vk::SampleCountFlags,
     format: vk::Format,
     usage: vk::ImageUsageFlags,
     location: MemoryLocation,
@@ -82,8 +113,8 @@ pub fn create_image(
     let image_info = vk::ImageCreateInfo::default()
         .image_type(vk::ImageType::TYPE_2D)
         .extent(vk::Extent3D {
-            width,
-            height,
+            width: extent.width,
+            height: extent.height,
             depth: 1,
         })
         .mip_levels(1)
@@ -93,7 +124,7 @@ pub fn create_image(
         .initial_layout(vk::ImageLayout::UNDEFINED)
         .usage(usage)
         .sharing_mode(vk::SharingMode::EXCLUSIVE)
-        .samples(vk::SampleCountFlags::TYPE_1);
+        .samples(samples);
 
     let image = unsafe { device.create_image(&image_info, None) }
         .map_err(|e| gpu_allocator::AllocationError::Internal(e.to_string()))?;
@@ -137,3 +168,15 @@ pub fn create_image_view(
 
     unsafe { device.create_image_view(&view_info, None) }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vertex_upload_selection() {
+        assert_eq!(VertexUpload::for_vertex_buffer(true), VertexUpload::Direct);
+        // Device-local only CpuToGpu memory falls back to a staging copy
+        assert_eq!(VertexUpload::for_vertex_buffer(false), VertexUpload::Staged);
+    }
+}
@@ -1,8 +1,21 @@
 pub mod vulkan;
 pub mod pipeline;
+pub mod clip;
+pub mod color_format;
 pub mod command;
 pub mod memory;
+pub mod decimate;
+pub mod diff;
+pub mod downscale;
+pub mod extrude;
+pub mod line_clip;
+pub mod lod;
+pub mod mask;
+pub mod pool;
 pub mod renderer;
+pub mod text;
+pub mod triangulate;
+pub mod vertex_budget;
 
-pub use renderer::VulkanRenderer;
+pub use renderer::{RendererConfig, RendererOptions, VulkanRenderer};
 pub use pipeline::ShaderType;
@@ -1,24 +1,59 @@
+use super::color_format::ColorFormat;
 use ash::vk;
 use std::fs::File;
 use std::io::Read;
+use std::str::FromStr;
 
 pub const TILE_SIZE: u32 = 256;
 pub const TILE_SIZE_2X: u32 = 512;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Line vertex as laid out in the vertex buffer
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vertex {
+    /// lon, lat
+    pub position: [f32; 2],
+    /// RGBA line color
+    pub color: [u8; 4],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum ShaderType {
+    #[default]
     Mercator,
     Simple,
     Debug,
 }
 
+impl FromStr for ShaderType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mercator" => Ok(ShaderType::Mercator),
+            "simple" => Ok(ShaderType::Simple),
+            "debug" => Ok(ShaderType::Debug),
+            _ => Err(format!("unknown shader {:?} (expected mercator, simple or debug)", s)),
+        }
+    }
+}
+
 /// Create a graphics pipeline for rendering lines
+///
+/// `topology` is `LINE_LIST` for 1px lines, `TRIANGLE_LIST` for lines
+/// extruded into quads, see `extrude::extrude_line`, and polygon fills, or
+/// `POINT_LIST` for point objects. Viewport and scissor are dynamic state, set for each
+/// render's image size, so one pipeline serves every tile size.
+/// Vertex colors are sRGB encoded UNORM; with an sRGB `color_format` the
+/// fragment shader decodes them to the linear values the attachment expects.
 pub fn create_graphics_pipeline(
     device: &ash::Device,
     render_pass: vk::RenderPass,
     descriptor_set_layout: vk::DescriptorSetLayout,
     shader_type: ShaderType,
-    tile_size: u32,
+    samples: vk::SampleCountFlags,
+    topology: vk::PrimitiveTopology,
+    color_format: ColorFormat,
 ) -> Result<(vk::Pipeline, vk::PipelineLayout), vk::Result> {
     // Load shader modules
     let vert_path = match shader_type {
@@ -27,7 +62,12 @@ pub fn create_graphics_pipeline(
         ShaderType::Debug => "shaders/tile_debug.vert.spv",
     };
     let vert_shader_module = create_shader_module(device, vert_path)?;
-    let frag_shader_module = create_shader_module(device, "shaders/tile.frag.spv")?;
+    let frag_path = if color_format.is_srgb() {
+        "shaders/tile_srgb.frag.spv"
+    } else {
+        "shaders/tile.frag.spv"
+    };
+    let frag_shader_module = create_shader_module(device, frag_path)?;
 
     let entry_point = std::ffi::CString::new("main").unwrap();
 
@@ -43,48 +83,40 @@ pub fn create_graphics_pipeline(
 
     let shader_stages = [vert_stage_info, frag_stage_info];
 
-    // Vertex input: 2 floats (lon, lat)
+    // Vertex input: 2 floats (lon, lat) + RGBA8 color
     let vertex_binding_descriptions = [vk::VertexInputBindingDescription::default()
         .binding(0)
-        .stride(8) // 2 * f32
+        .stride(std::mem::size_of::<Vertex>() as u32)
         .input_rate(vk::VertexInputRate::VERTEX)];
 
-    let vertex_attribute_descriptions = [vk::VertexInputAttributeDescription::default()
-        .binding(0)
-        .location(0)
-        .format(vk::Format::R32G32_SFLOAT)
-        .offset(0)];
+    let vertex_attribute_descriptions = [
+        vk::VertexInputAttributeDescription::default()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32_SFLOAT)
+            .offset(std::mem::offset_of!(Vertex, position) as u32),
+        vk::VertexInputAttributeDescription::default()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R8G8B8A8_UNORM)
+            .offset(std::mem::offset_of!(Vertex, color) as u32),
+    ];
 
     let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::default()
         .vertex_binding_descriptions(&vertex_binding_descriptions)
         .vertex_attribute_descriptions(&vertex_attribute_descriptions);
 
-    // Input assembly: line list
+    // Input assembly: line or triangle list
     let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::default()
-        .topology(vk::PrimitiveTopology::LINE_LIST)
+        .topology(topology)
         .primitive_restart_enable(false);
 
-    // Viewport and scissor (use provided tile_size)
-    let viewports = [vk::Viewport {
-        x: 0.0,
-        y: 0.0,
-        width: tile_size as f32,
-        height: tile_size as f32,
-        min_depth: 0.0,
-        max_depth: 1.0,
-    }];
-
-    let scissors = [vk::Rect2D {
-        offset: vk::Offset2D { x: 0, y: 0 },
-        extent: vk::Extent2D {
-            width: tile_size,
-            height: tile_size,
-        },
-    }];
-
+    // One viewport and scissor, set when recording the commands
     let viewport_state = vk::PipelineViewportStateCreateInfo::default()
-        .viewports(&viewports)
-        .scissors(&scissors);
+        .viewport_count(1)
+        .scissor_count(1);
+    let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state = vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
 
     // Rasterization
     let rasterizer = vk::PipelineRasterizationStateCreateInfo::default()
@@ -96,10 +128,10 @@ pub fn create_graphics_pipeline(
         .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
         .depth_bias_enable(false);
 
-    // Multisampling (disabled)
+    // Multisampling (TYPE_1 disables it)
     let multisampling = vk::PipelineMultisampleStateCreateInfo::default()
         .sample_shading_enable(false)
-        .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+        .rasterization_samples(samples);
 
     // Color blending
     let color_blend_attachment = vk::PipelineColorBlendAttachmentState::default()
@@ -108,8 +140,9 @@ pub fn create_graphics_pipeline(
         .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
         .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
         .color_blend_op(vk::BlendOp::ADD)
+        // Coverage accumulates, so overlay tiles (transparent background) stay transparent only where nothing is drawn
         .src_alpha_blend_factor(vk::BlendFactor::ONE)
-        .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
+        .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
         .alpha_blend_op(vk::BlendOp::ADD);
 
     let color_blend_attachments = [color_blend_attachment];
@@ -134,6 +167,7 @@ pub fn create_graphics_pipeline(
         .rasterization_state(&rasterizer)
         .multisample_state(&multisampling)
         .color_blend_state(&color_blending)
+        .dynamic_state(&dynamic_state)
         .layout(pipeline_layout)
         .render_pass(render_pass)
         .subpass(0);
@@ -155,50 +189,142 @@ pub fn create_graphics_pipeline(
 }
 
 /// Create render pass
+///
+/// With more than one sample, attachment 0 is the multisample color image and
+/// the subpass resolves it into attachment 1, the single-sample image used for
+/// readback. Either way the readback image ends in TRANSFER_SRC_OPTIMAL.
+///
+/// With `after_fills` the pass draws over the fills resolved by the pass of
+/// `create_fill_render_pass` instead of clearing. A multisample attachment
+/// can't load the resolved image, so this needs a single sample.
 pub fn create_render_pass(
     device: &ash::Device,
     format: vk::Format,
+    samples: vk::SampleCountFlags,
+    after_fills: bool,
+) -> Result<vk::RenderPass, vk::Result> {
+    build_render_pass(device, format, samples, after_fills, vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+}
+
+/// Create the render pass polygon fills are drawn in when their sample
+/// count differs from the lines', see `RendererOptions::fill_msaa_samples`
+///
+/// Like `create_render_pass` it clears and, multisampled, resolves into the
+/// readback image, which it leaves in COLOR_ATTACHMENT_OPTIMAL for the line
+/// pass to load.
+pub fn create_fill_render_pass(
+    device: &ash::Device,
+    format: vk::Format,
+    samples: vk::SampleCountFlags,
+) -> Result<vk::RenderPass, vk::Result> {
+    build_render_pass(device, format, samples, false, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+}
+
+/// Render pass with one subpass, leaving the readback image in `final_layout`
+fn build_render_pass(
+    device: &ash::Device,
+    format: vk::Format,
+    samples: vk::SampleCountFlags,
+    load: bool,
+    final_layout: vk::ImageLayout,
 ) -> Result<vk::RenderPass, vk::Result> {
+    let multisampled = samples != vk::SampleCountFlags::TYPE_1;
+    debug_assert!(!(load && multisampled), "a multisample attachment can't load the readback image");
+
     let color_attachment = vk::AttachmentDescription::default()
+        .format(format)
+        .samples(samples)
+        .load_op(if load { vk::AttachmentLoadOp::LOAD } else { vk::AttachmentLoadOp::CLEAR })
+        .store_op(if multisampled {
+            // Only the resolved image is read back
+            vk::AttachmentStoreOp::DONT_CARE
+        } else {
+            vk::AttachmentStoreOp::STORE
+        })
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(if load {
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        } else {
+            vk::ImageLayout::UNDEFINED
+        })
+        .final_layout(if multisampled {
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        } else {
+            final_layout
+        });
+
+    let resolve_attachment = vk::AttachmentDescription::default()
         .format(format)
         .samples(vk::SampleCountFlags::TYPE_1)
-        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .load_op(vk::AttachmentLoadOp::DONT_CARE)
         .store_op(vk::AttachmentStoreOp::STORE)
         .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
         .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
         .initial_layout(vk::ImageLayout::UNDEFINED)
-        .final_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+        .final_layout(final_layout);
 
     let color_attachment_ref = vk::AttachmentReference::default()
         .attachment(0)
         .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
 
+    let resolve_attachment_ref = vk::AttachmentReference::default()
+        .attachment(1)
+        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
     let color_attachments = [color_attachment_ref];
+    let resolve_attachments = [resolve_attachment_ref];
 
-    let subpass = vk::SubpassDescription::default()
+    let mut subpass = vk::SubpassDescription::default()
         .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
         .color_attachments(&color_attachments);
+    if multisampled {
+        subpass = subpass.resolve_attachments(&resolve_attachments);
+    }
 
+    // A loading pass waits for the fill pass's resolve writes
     let dependency = vk::SubpassDependency::default()
         .src_subpass(vk::SUBPASS_EXTERNAL)
         .dst_subpass(0)
         .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-        .src_access_mask(vk::AccessFlags::empty())
+        .src_access_mask(if load { vk::AccessFlags::COLOR_ATTACHMENT_WRITE } else { vk::AccessFlags::empty() })
         .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-        .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE);
-
-    let attachments = [color_attachment];
+        .dst_access_mask(if load {
+            vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+        } else {
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+        });
+
+    let attachments = [color_attachment, resolve_attachment];
+    let attachment_count = if multisampled { 2 } else { 1 };
     let subpasses = [subpass];
     let dependencies = [dependency];
 
     let render_pass_info = vk::RenderPassCreateInfo::default()
-        .attachments(&attachments)
+        .attachments(&attachments[..attachment_count])
         .subpasses(&subpasses)
         .dependencies(&dependencies);
 
     unsafe { device.create_render_pass(&render_pass_info, None) }
 }
 
+/// Pick the MSAA sample count: the highest supported count not above `requested`
+///
+/// `requested` values of 0 or 1 disable multisampling.
+pub fn select_sample_count(requested: u32, supported: vk::SampleCountFlags) -> vk::SampleCountFlags {
+    [
+        vk::SampleCountFlags::TYPE_64,
+        vk::SampleCountFlags::TYPE_32,
+        vk::SampleCountFlags::TYPE_16,
+        vk::SampleCountFlags::TYPE_8,
+        vk::SampleCountFlags::TYPE_4,
+        vk::SampleCountFlags::TYPE_2,
+    ]
+    .into_iter()
+    .find(|&count| count.as_raw() <= requested && supported.contains(count))
+    .unwrap_or(vk::SampleCountFlags::TYPE_1)
+}
+
 /// Create descriptor set layout for uniforms
 pub fn create_descriptor_set_layout(
     device: &ash::Device,
@@ -231,3 +357,25 @@ fn create_shader_module(device: &ash::Device, path: &str) -> Result<vk::ShaderMo
 
     unsafe { device.create_shader_module(&create_info, None) }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_sample_count() {
+        let supported = vk::SampleCountFlags::TYPE_1
+            | vk::SampleCountFlags::TYPE_2
+            | vk::SampleCountFlags::TYPE_4
+            | vk::SampleCountFlags::TYPE_8;
+
+        assert_eq!(select_sample_count(0, supported), vk::SampleCountFlags::TYPE_1);
+        assert_eq!(select_sample_count(1, supported), vk::SampleCountFlags::TYPE_1);
+        assert_eq!(select_sample_count(4, supported), vk::SampleCountFlags::TYPE_4);
+        // Not a power of two, or above the device limit: round down
+        assert_eq!(select_sample_count(6, supported), vk::SampleCountFlags::TYPE_4);
+        assert_eq!(select_sample_count(16, supported), vk::SampleCountFlags::TYPE_8);
+        // Software rasterizers may only offer single-sample
+        assert_eq!(select_sample_count(4, vk::SampleCountFlags::TYPE_1), vk::SampleCountFlags::TYPE_1);
+    }
+}
@@ -0,0 +1,396 @@
+use super::renderer::VulkanRenderer;
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Pool of Vulkan renderers
+///
+/// Any renderer serves any tile size, see `VulkanRenderer::set_tile_size`,
+/// so items aren't keyed.
+pub type RendererPool = Pool<(), VulkanRenderer>;
+
+/// Bounded pool of expensive, keyed items (renderers)
+///
+/// At most `size` items exist at once. Checkouts beyond that wait for a
+/// checkin instead of failing, optionally up to `acquire_timeout`. Items
+/// are created lazily; when all slots are taken by idle items of another
+/// key, one of those is dropped to make room. Checkouts own a reference to
+/// the pool, so they can move to blocking threads.
+pub struct Pool<K, T> {
+    size: usize,
+    acquire_timeout: Option<Duration>,
+    permits: Arc<Semaphore>,
+    slots: Mutex<Slots<K, T>>,
+    busy: AtomicUsize,
+    acquisitions: AtomicU64,
+    wait_total_us: AtomicU64,
+    wait_max_us: AtomicU64,
+    timeouts: AtomicU64,
+}
+
+struct Slots<K, T> {
+    idle: Vec<(K, T)>,
+    // Idle plus checked out items
+    alive: usize,
+}
+
+/// Snapshot of pool utilization, see `Pool::metrics`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PoolMetrics {
+    pub size: usize,
+    pub busy: usize,
+    pub idle: usize,
+    pub acquisitions: u64,
+    /// Total time spent waiting for a free slot
+    pub wait_total: Duration,
+    pub wait_max: Duration,
+    pub timeouts: u64,
+}
+
+/// Pool checkout errors
+#[derive(Debug, thiserror::Error)]
+pub enum PoolError<E: std::error::Error + 'static> {
+    #[error("Timed out after {0:?} waiting for a free renderer")]
+    Timeout(Duration),
+
+    #[error("Failed to create renderer: {0}")]
+    Create(#[source] E),
+}
+
+impl<K: PartialEq, T> Pool<K, T> {
+    pub fn new(size: usize, acquire_timeout: Option<Duration>) -> Self {
+        let size = size.max(1);
+        Pool {
+            size,
+            acquire_timeout,
+            permits: Arc::new(Semaphore::new(size)),
+            slots: Mutex::new(Slots {
+                idle: Vec::new(),
+                alive: 0,
+            }),
+            busy: AtomicUsize::new(0),
+            acquisitions: AtomicU64::new(0),
+            wait_total_us: AtomicU64::new(0),
+            wait_max_us: AtomicU64::new(0),
+            timeouts: AtomicU64::new(0),
+        }
+    }
+
+    /// Check out an item for `key`, creating it with `create` if none is idle
+    ///
+    /// Waits while all `size` items are checked out.
+    pub async fn checkout<E: std::error::Error + 'static, F: Future<Output = Result<T, E>>>(
+        self: &Arc<Self>,
+        key: K,
+        create: impl FnOnce() -> F,
+    ) -> Result<PoolGuard<K, T>, PoolError<E>> {
+        self.checkout_with(key, create, true).await
+    }
+
+    /// Check out a newly created item for `key`, never an idle one
+    ///
+    /// For retries after a device loss, where idle items may be broken too.
+    /// Evicts an idle item if all slots are taken.
+    pub async fn checkout_new<E: std::error::Error + 'static, F: Future<Output = Result<T, E>>>(
+        self: &Arc<Self>,
+        key: K,
+        create: impl FnOnce() -> F,
+    ) -> Result<PoolGuard<K, T>, PoolError<E>> {
+        self.checkout_with(key, create, false).await
+    }
+
+    async fn checkout_with<E: std::error::Error + 'static, F: Future<Output = Result<T, E>>>(
+        self: &Arc<Self>,
+        key: K,
+        create: impl FnOnce() -> F,
+        reuse: bool,
+    ) -> Result<PoolGuard<K, T>, PoolError<E>> {
+        let start = Instant::now();
+        let permit = match self.acquire_timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, self.permits.clone().acquire_owned()).await {
+                Ok(permit) => permit,
+                Err(_) => {
+                    self.timeouts.fetch_add(1, Ordering::Relaxed);
+                    log::warn!("Timed out after {:?} waiting for a free renderer", timeout);
+                    return Err(PoolError::Timeout(timeout));
+                }
+            },
+            None => self.permits.clone().acquire_owned().await,
+        }
+        .expect("pool semaphore is never closed");
+
+        let wait_us = start.elapsed().as_micros() as u64;
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        self.wait_total_us.fetch_add(wait_us, Ordering::Relaxed);
+        self.wait_max_us.fetch_max(wait_us, Ordering::Relaxed);
+        if wait_us > 0 {
+            log::debug!("Waited {}us for a free renderer", wait_us);
+        }
+
+        // The permit guarantees a matching idle item, a free slot or an idle item to evict
+        let (item, evicted) = {
+            let mut slots = self.slots.lock().unwrap();
+            match slots.idle.iter().position(|(k, _)| reuse && *k == key) {
+                Some(i) => (Some(slots.idle.swap_remove(i).1), None),
+                None => {
+                    let evicted = if slots.alive >= self.size {
+                        slots.alive -= 1;
+                        slots.idle.pop()
+                    } else {
+                        None
+                    };
+                    slots.alive += 1;
+                    (None, evicted)
+                }
+            }
+        };
+        drop(evicted);
+
+        let item = match item {
+            Some(item) => item,
+            None => {
+                // Frees the slot if creating fails, or the checkout is dropped meanwhile
+                let reserved = ReservedSlot(self);
+                let item = create().await.map_err(PoolError::Create)?;
+                std::mem::forget(reserved);
+                item
+            }
+        };
+
+        self.busy.fetch_add(1, Ordering::Relaxed);
+        Ok(PoolGuard {
+            pool: self.clone(),
+            item: Some((key, item)),
+            _permit: permit,
+        })
+    }
+
+    /// Wait until no item is checked out, then drop all items
+    ///
+    /// Returns how many were dropped. Used on shutdown, so renderers are
+    /// destroyed after their last render; later checkouts create new items.
+    pub async fn drain(&self) -> usize {
+        let _all = self
+            .permits
+            .acquire_many(self.size as u32)
+            .await
+            .expect("pool semaphore is never closed");
+        let idle = {
+            let mut slots = self.slots.lock().unwrap();
+            slots.alive -= slots.idle.len();
+            std::mem::take(&mut slots.idle)
+        };
+        idle.len()
+    }
+
+    /// Drop all idle items, leaving checked out ones alone
+    ///
+    /// Returns how many were dropped. Unlike `drain` this doesn't wait, so
+    /// it can run while holding a checkout.
+    pub fn discard_idle(&self) -> usize {
+        let idle = {
+            let mut slots = self.slots.lock().unwrap();
+            slots.alive -= slots.idle.len();
+            std::mem::take(&mut slots.idle)
+        };
+        idle.len()
+    }
+
+    pub fn metrics(&self) -> PoolMetrics {
+        PoolMetrics {
+            size: self.size,
+            busy: self.busy.load(Ordering::Relaxed),
+            idle: self.slots.lock().unwrap().idle.len(),
+            acquisitions: self.acquisitions.load(Ordering::Relaxed),
+            wait_total: Duration::from_micros(self.wait_total_us.load(Ordering::Relaxed)),
+            wait_max: Duration::from_micros(self.wait_max_us.load(Ordering::Relaxed)),
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Slot of an item being created, freed on drop
+struct ReservedSlot<'a, K, T>(&'a Pool<K, T>);
+
+impl<K, T> Drop for ReservedSlot<'_, K, T> {
+    fn drop(&mut self) {
+        self.0.slots.lock().unwrap().alive -= 1;
+    }
+}
+
+/// Checked out pool item, returned to the pool on drop
+pub struct PoolGuard<K, T> {
+    pool: Arc<Pool<K, T>>,
+    item: Option<(K, T)>,
+    // Released after the item is back in the pool
+    _permit: OwnedSemaphorePermit,
+}
+
+impl<K, T> PoolGuard<K, T> {
+    /// Drop the item instead of returning it, freeing its slot for a new one
+    ///
+    /// For items that can't be used anymore, like renderers whose GPU hung.
+    /// An associated function so it doesn't shadow methods of `T`.
+    pub fn discard(mut guard: Self) {
+        if let Some(item) = guard.item.take() {
+            guard.pool.slots.lock().unwrap().alive -= 1;
+            drop(item);
+        }
+    }
+}
+
+impl<K, T> Deref for PoolGuard<K, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.item.as_ref().unwrap().1
+    }
+}
+
+impl<K, T> DerefMut for PoolGuard<K, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.item.as_mut().unwrap().1
+    }
+}
+
+impl<K, T> Drop for PoolGuard<K, T> {
+    fn drop(&mut self) {
+        if let Some(item) = self.item.take() {
+            self.pool.slots.lock().unwrap().idle.push(item);
+        }
+        self.pool.busy.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+
+    #[tokio::test]
+    async fn test_pool_queues_beyond_size() {
+        let pool: Arc<Pool<u32, u32>> = Arc::new(Pool::new(2, None));
+        let created = Arc::new(AtomicUsize::new(0));
+        let max_busy = Arc::new(AtomicUsize::new(0));
+
+        // Six concurrent requests against two slots
+        let tasks: Vec<_> = (0..6)
+            .map(|_| {
+                let (pool, created, max_busy) = (pool.clone(), created.clone(), max_busy.clone());
+                tokio::spawn(async move {
+                    let guard = pool
+                        .checkout(256, || async {
+                            created.fetch_add(1, Ordering::SeqCst);
+                            Ok::<_, Infallible>(0)
+                        })
+                        .await
+                        .unwrap();
+                    max_busy.fetch_max(pool.metrics().busy, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    drop(guard);
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let metrics = pool.metrics();
+        assert_eq!(metrics.acquisitions, 6, "every request is served");
+        assert_eq!(metrics.timeouts, 0);
+        assert!(metrics.wait_total > Duration::ZERO && metrics.wait_max > Duration::ZERO);
+        assert!(max_busy.load(Ordering::SeqCst) <= 2);
+        assert_eq!(created.load(Ordering::SeqCst), 2, "items are reused");
+        assert_eq!((metrics.busy, metrics.idle), (0, 2));
+    }
+
+    #[tokio::test]
+    async fn test_pool_timeout_and_eviction() {
+        let pool: Arc<Pool<u32, u32>> = Arc::new(Pool::new(1, Some(Duration::from_millis(10))));
+
+        let guard = pool.checkout(256, || async { Ok::<_, Infallible>(1) }).await.unwrap();
+        assert_eq!(*guard, 1);
+        let result = pool.checkout(256, || async { Ok::<_, Infallible>(2) }).await;
+        assert!(matches!(result, Err(PoolError::Timeout(_))));
+        assert_eq!(pool.metrics().timeouts, 1);
+        drop(guard);
+
+        // The only slot holds a 256 item, so a 512 checkout replaces it
+        let guard = pool.checkout(512, || async { Ok::<_, Infallible>(3) }).await.unwrap();
+        assert_eq!(*guard, 3);
+        drop(guard);
+        assert_eq!(pool.metrics().idle, 1);
+    }
+
+    #[tokio::test]
+    async fn test_discarded_item_is_replaced() {
+        let pool: Arc<Pool<u32, u32>> = Arc::new(Pool::new(1, Some(Duration::from_millis(10))));
+
+        let guard = pool.checkout(256, || async { Ok::<_, Infallible>(1) }).await.unwrap();
+        PoolGuard::discard(guard);
+        assert_eq!((pool.metrics().busy, pool.metrics().idle), (0, 0));
+
+        // The slot is free again, for a new item
+        let guard = pool.checkout(256, || async { Ok::<_, Infallible>(2) }).await.unwrap();
+        assert_eq!(*guard, 2);
+        drop(guard);
+        assert_eq!(*pool.checkout(256, || async { Ok::<_, Infallible>(3) }).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_checkout_and_moved_guard() {
+        let pool: Arc<Pool<u32, u32>> = Arc::new(Pool::new(1, Some(Duration::from_millis(10))));
+
+        // A checkout dropped while creating its item frees the slot
+        let creating = pool.checkout(256, std::future::pending::<Result<u32, Infallible>>);
+        assert!(tokio::time::timeout(Duration::from_millis(10), creating).await.is_err());
+        let guard = pool.checkout(256, || async { Ok::<_, Infallible>(1) }).await.unwrap();
+
+        // Guards return their item from any thread
+        tokio::task::spawn_blocking(move || drop(guard)).await.unwrap();
+        assert_eq!((pool.metrics().busy, pool.metrics().idle), (0, 1));
+        assert_eq!(*pool.checkout(256, || async { Ok::<_, Infallible>(2) }).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_checkout_new_skips_idle_items() {
+        let pool: Arc<Pool<u32, u32>> = Arc::new(Pool::new(2, None));
+        drop(pool.checkout(256, || async { Ok::<_, Infallible>(1) }).await.unwrap());
+        drop(pool.checkout_new(256, || async { Ok::<_, Infallible>(2) }).await.unwrap());
+        assert_eq!(pool.metrics().idle, 2);
+
+        // Both slots are taken by idle items, so one is evicted for the new one
+        let guard = pool.checkout_new(256, || async { Ok::<_, Infallible>(3) }).await.unwrap();
+        assert_eq!(*guard, 3);
+        assert_eq!(pool.metrics().idle, 1);
+
+        assert_eq!(pool.discard_idle(), 1);
+        assert_eq!((pool.metrics().busy, pool.metrics().idle), (1, 0));
+        drop(guard);
+        assert_eq!(*pool.checkout(256, || async { Ok::<_, Infallible>(4) }).await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_drain_waits_for_checkouts() {
+        let pool: Arc<Pool<u32, u32>> = Arc::new(Pool::new(2, None));
+        drop(pool.checkout(256, || async { Ok::<_, Infallible>(1) }).await.unwrap());
+        let guard = pool.checkout(512, || async { Ok::<_, Infallible>(2) }).await.unwrap();
+
+        let drain = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.drain().await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!drain.is_finished(), "drained while an item is checked out");
+        drop(guard);
+        assert_eq!(drain.await.unwrap(), 2);
+        assert_eq!(pool.metrics().idle, 0);
+
+        // The pool stays usable
+        let guard = pool.checkout(256, || async { Ok::<_, Infallible>(3) }).await.unwrap();
+        assert_eq!(*guard, 3);
+    }
+}
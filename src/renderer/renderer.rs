@@ -1,16 +1,67 @@
+use super::clip::ClipRegion;
 use super::command::*;
+use super::decimate::decimate_points;
+use super::color_format::{linear_to_srgb, srgb_to_linear, ColorFormat};
+use super::diff::{diff_tile, DiffStatus};
+use super::extrude::extrude_line;
+use super::line_clip::{clip_polyline, clip_segment, ClipRect, CLIP_MARGIN_PX};
+use super::lod::{pixel_extent, simplify, Lod, LodThresholds};
 use super::memory::*;
 use super::pipeline::*;
-use super::vulkan::{VulkanContext, VulkanError};
+use super::triangulate::triangulate;
+use super::vertex_budget::VertexBudgets;
+use super::vulkan::{ContextOptions, VulkanContext, VulkanError};
 use crate::data::mmap::{MappedData, MapObjectView};
 use crate::data::spatial::TileIndex;
-use crate::data::types::{BoundingBox, Tile};
-use crate::projection::get_bounding_box;
+use crate::data::source::TileSource;
+use crate::encoding::png::encode_png_rgba;
+use crate::data::types::{BoundingBox, MapObject, MapObjectOffset, ObjectKind, Pixel, Point, Tile};
+use crate::filter::TagFilter;
+use crate::projection::{deg2num, get_buffered_bounding_box, pixel_to_tile, snap_to_pixel_centers, tile_to_pixel, TileOrigin};
+use crate::style::layer::layer_for;
+use crate::style::line_colors::{casing_color, LineColors};
+use crate::style::line_width::{line_width_for, CASING_WIDTH};
+use crate::style::MapStyle;
 use ash::vk;
 use gpu_allocator::vulkan::{Allocation, Allocator};
 use gpu_allocator::MemoryLocation;
 use image::RgbaImage;
+use std::borrow::Cow;
+use std::mem::ManuallyDrop;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Highest zoom level stored in the tile index, and the default `--max-zoom`
+/// Higher zoom levels render from their ancestor at the index's `max_zoom`
+pub const MAX_INDEXED_ZOOM: u32 = 15;
+
+/// Largest metatile side in tiles, see `VulkanRenderer::render_metatile`
+pub const MAX_METATILE_SIZE: u32 = 8;
+
+/// Vertices the vertex buffer holds initially (12 bytes each, 60MB)
+pub const VERTEX_BUFFER_CAPACITY: usize = 5_000_000;
+
+/// Vertices the vertex buffer grows to at most on dense tiles, unless
+/// `RendererOptions::max_vertex_buffer_capacity` says otherwise
+pub const MAX_VERTEX_BUFFER_CAPACITY: usize = 20_000_000;
+
+/// Longest wait for the GPU to finish a render, unless
+/// `RendererOptions::render_timeout` says otherwise
+pub const DEFAULT_RENDER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// RGBA color of rendered lines, unless `RendererOptions::line_colors` says otherwise
+pub const LINE_COLOR: [u8; 4] = [0, 0, 0, 255];
+
+/// RGBA color tiles are cleared to (`NODATA_COLOR` with `RendererOptions::overlay`)
+pub const BACKGROUND_COLOR: [u8; 4] = [255, 255, 255, 255];
+
+/// RGBA color of pixels without data: outside the data bounds or the clip region (transparent)
+pub const NODATA_COLOR: [u8; 4] = [0, 0, 0, 0];
+
+/// Side in pixels of the squares POI nodes are drawn as
+///
+/// Devices without the `largePoints` feature draw them 1px.
+pub const POINT_SIZE: f32 = 4.0;
 
 /// Uniform buffer object matching the shader layout
 #[repr(C, align(256))]
@@ -18,14 +69,40 @@ use std::sync::{Arc, Mutex};
 struct UniformBufferObject {
     bbox: [f32; 4],          // minLon, minLat, maxLon, maxLat
     tile_size: f32,          // 256.0
-    _padding: [f32; 11],     // Padding to 64 bytes
+    point_size: f32,         // POINT_SIZE, or 1.0 without largePoints
+    _padding: [f32; 2],      // std140: mat4 starts at the next 16-byte boundary
     projection: [[f32; 4]; 4], // 4x4 matrix
 }
 
 /// Vulkan renderer for OSM tiles
 pub struct VulkanRenderer {
-    // Tile size (256 or 512)
+    // Rendered image size (the tile size plus the buffer on both sides), see `set_tile_size`
     tile_size: u32,
+    // Pixels of surrounding data rendered on each side of the tile
+    buffer_px: u32,
+    // Fraction of the tile size `buffer_px` is derived from, see `set_tile_size`
+    buffer_fraction: f64,
+    // MSAA sample count (TYPE_1 when disabled)
+    samples: vk::SampleCountFlags,
+    lod: LodThresholds,
+    vertex_budgets: VertexBudgets,
+    wrap_antimeridian: bool,
+    tile_origin: TileOrigin,
+    style: Option<Arc<MapStyle>>,
+    clip_region: Option<Arc<ClipRegion>>,
+    overlay: bool,
+    crisp_lines: bool,
+    line_widths: bool,
+    casing: bool,
+    line_colors: Arc<LineColors>,
+    point_size: f32,
+    point_decimation_px: f64,
+    precise_overlap: bool,
+    background: [u8; 4],
+    render_timeout: Duration,
+    // The GPU timed out or was lost, see `is_broken`
+    broken: bool,
+    color_format: ColorFormat,
 
     // Reusable resources
     command_buffer: vk::CommandBuffer,
@@ -38,22 +115,228 @@ pub struct VulkanRenderer {
     vertex_buffer: Option<vk::Buffer>,
     vertex_buffer_allocation: Option<Allocation>,
     vertex_buffer_capacity: usize,
+    // Capacity the vertex buffer grows to at most, see `grow_vertex_buffer`
+    max_vertex_buffer_capacity: usize,
+    // Mapped buffer vertices are copied from when the vertex buffer isn't mapped
+    vertex_staging: Option<(vk::Buffer, Allocation)>,
+    // Vertices drawn by the last render, and how many of them were fills
+    last_vertex_count: usize,
+    last_fill_vertex_count: usize,
+    // Objects read for the last render, drawn or not
+    last_object_count: usize,
+
+    // Mapped uniform buffer, rewritten before each render, and the descriptor set binding it
+    uniform_buffer: Option<(vk::Buffer, Allocation)>,
+    descriptor_set: vk::DescriptorSet,
 
     // Vulkan pipeline resources
     render_pass: vk::RenderPass,
     descriptor_set_layout: vk::DescriptorSetLayout,
     pipeline_layout: vk::PipelineLayout,
     pipeline: vk::Pipeline,
+    // POINT_LIST pipeline for point objects, drawn after the lines
+    point_pipeline_layout: vk::PipelineLayout,
+    point_pipeline: vk::Pipeline,
+    // Pipeline for polygon fills, drawn before the lines (`fill_polygons` only)
+    fill: Option<FillPipeline>,
+    fill_max_objects: Option<usize>,
     descriptor_pool: vk::DescriptorPool,
 
-    // Memory manager must be dropped before context
-    memory_manager: Arc<Mutex<Allocator>>,
+    // Memory manager must be dropped before context; both are dropped by
+    // hand, see `Drop`
+    memory_manager: ManuallyDrop<Arc<Mutex<Allocator>>>,
     // Context should be dropped last
-    context: VulkanContext,
+    context: ManuallyDrop<VulkanContext>,
+}
+
+/// Options for creating a `VulkanRenderer`
+#[derive(Debug, Clone, Default)]
+pub struct RendererOptions {
+    /// Vulkan device and API version selection
+    pub context: ContextOptions,
+    /// Surrounding data to render on each side, as a fraction of the tile size
+    ///
+    /// The image is returned uncropped: a 256px tile with 0.25 renders as
+    /// 384px. Clamped to 0..=1 and rounded to whole pixels.
+    pub buffer_fraction: f64,
+    /// MSAA samples per pixel; 0 or 1 disables multisampling
+    ///
+    /// Rounded down to a count the device supports.
+    pub msaa_samples: u32,
+    /// Per-object level of detail by projected size (disabled by default)
+    pub lod: LodThresholds,
+    /// Per-zoom caps on the vertices and objects drawn (disabled by default)
+    ///
+    /// Also bounds the vertex buffer size when every zoom is capped.
+    pub vertex_budgets: VertexBudgets,
+    /// Also draw the objects of the opposite edge column, shifted by ±360°,
+    /// in tiles at the antimeridian so the seam is continuous
+    pub wrap_antimeridian: bool,
+    /// Image orientation; `BottomLeft` draws north at the bottom
+    ///
+    /// Tiles are still passed in XYZ numbering, see `TileOrigin::to_xyz`.
+    pub tile_origin: TileOrigin,
+    /// Draw objects in their style class color and priority order
+    ///
+    /// Only applies to indexes loaded with the same style, see
+    /// `TileIndex::classes`. Class widths apply with `line_widths`.
+    pub style: Option<Arc<MapStyle>>,
+    /// Mask rendered tiles to this region, see `ClipRegion::apply`
+    pub clip_region: Option<Arc<ClipRegion>>,
+    /// Clear to transparent instead of `BACKGROUND_COLOR`, for tiles
+    /// composited over another basemap (`--overlay`)
+    ///
+    /// Features blend onto the transparent background premultiplied; the
+    /// readback is converted back to straight alpha.
+    pub overlay: bool,
+    /// Snap line vertices to pixel centers on the CPU before drawing, for
+    /// sharp 1px lines (`--crisp-lines`)
+    ///
+    /// Needs the Mercator shader, whose projection the snapping inverts;
+    /// ignored with the others. Downscaling (`--supersample`) blurs them again.
+    pub crisp_lines: bool,
+    /// Draw lines as quads of their width instead of 1px lines (`--line-widths`)
+    ///
+    /// Classed ways get their class width, others `line_width_for` their
+    /// tags and the zoom. Like `crisp_lines` this needs the Mercator shader.
+    pub line_widths: bool,
+    /// Draw a darker casing `CASING_WIDTH` wider on each side under lines
+    /// drawn with `line_widths` (`--casing`), ignored without them
+    ///
+    /// Casings of a batch, or of a layer of unclassed ways, are drawn before
+    /// their lines, so they don't cover the lines they cross.
+    pub casing: bool,
+    /// Colors of ways without a style class, `LINE_COLOR` if `None`
+    pub line_colors: Option<Arc<LineColors>>,
+    /// Keep at most one point object per cell of this many pixels
+    /// (`--point-decimation-px`), see `decimate::decimate_points`
+    ///
+    /// Points of later batches, drawn on top, win a cell. 0 keeps all.
+    pub point_decimation_px: f64,
+    /// Skip lines whose bounding box overlaps the tile but none of whose
+    /// segments do (`--precise-overlap`), see `BoundingBox::intersects_polyline`
+    ///
+    /// Clipping drops their vertices anyway, so this mostly keeps them from
+    /// counting against the object budgets, at the cost of a pass over the points.
+    pub precise_overlap: bool,
+    /// Color tiles are cleared to, `BACKGROUND_COLOR` if `None`
+    ///
+    /// Ignored with `overlay`, which clears to `NODATA_COLOR`.
+    pub background: Option<[u8; 4]>,
+    /// Vertices the vertex buffer may grow to when a tile doesn't fit,
+    /// `MAX_VERTEX_BUFFER_CAPACITY` if `None`
+    ///
+    /// The buffer doubles from `VERTEX_BUFFER_CAPACITY` as needed; tiles
+    /// needing more are truncated.
+    pub max_vertex_buffer_capacity: Option<usize>,
+    /// Longest wait for the GPU to finish a render, `DEFAULT_RENDER_TIMEOUT`
+    /// if `None`
+    ///
+    /// Renders taking longer fail with `VulkanError::RenderTimeout` and
+    /// leave the renderer broken, see `VulkanRenderer::is_broken`.
+    pub render_timeout: Option<Duration>,
+    /// Format of the color attachment, falling back to `ColorFormat::Unorm`
+    /// if the device can't render `ColorFormat::Srgb`
+    pub color_format: ColorFormat,
+    /// Fill closed ways (`ObjectKind::Polygon`) in their color instead of
+    /// drawing their outline (`--fill-polygons`)
+    ///
+    /// Fills are drawn under all lines and points, triangulated by
+    /// `triangulate::triangulate`.
+    pub fill_polygons: bool,
+    /// MSAA samples per pixel of polygon fills (`--fill-msaa`),
+    /// `msaa_samples` if `None`
+    ///
+    /// With the lines' sample count fills share their multisample
+    /// attachment. With another they're drawn in a render pass of their own,
+    /// resolved into the readback image, which the line pass then loads and
+    /// draws over. A multisample attachment can't load a resolved image, so
+    /// that needs single-sample lines; with line MSAA fills use its count.
+    pub fill_msaa_samples: Option<u32>,
+    /// Draw polygons as outlines on tiles reading more objects than this
+    /// (`--fill-max-objects`), see `last_object_count`; always fill if `None`
+    ///
+    /// Skips the triangulation and fill pass where they cost the most,
+    /// falling back to the line emission of open ways.
+    pub fill_max_objects: Option<usize>,
+}
+
+/// Basic settings for embedding a renderer, see `VulkanRenderer::with_config`
+///
+/// `shader_type`, `msaa_samples`, `line_widths` and `color_format` are baked
+/// into the render pass and pipelines, so changing them takes a new renderer.
+/// `tile_size` can be changed later with `VulkanRenderer::set_tile_size`.
+/// For everything else see `RendererOptions` and `new_with_options`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RendererConfig {
+    /// Most points of a way in the data (`TileIndex::max_points`)
+    pub max_points: usize,
+    pub shader_type: ShaderType,
+    /// Nominal tile size in pixels
+    pub tile_size: u32,
+    /// MSAA samples per pixel; 0 or 1 disables multisampling
+    pub msaa_samples: u32,
+    /// Clear color, `BACKGROUND_COLOR` if `None`
+    pub background: Option<[u8; 4]>,
+    /// Draw lines as quads of their width instead of 1px lines; needs the Mercator shader
+    pub line_widths: bool,
+    /// Color attachment format, see `RendererOptions::color_format`
+    pub color_format: ColorFormat,
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        RendererConfig {
+            max_points: 0,
+            shader_type: ShaderType::default(),
+            tile_size: TILE_SIZE,
+            msaa_samples: 0,
+            background: None,
+            line_widths: false,
+            color_format: ColorFormat::Unorm,
+        }
+    }
+}
+
+impl RendererConfig {
+    /// The `RendererOptions` of this config, defaults for all others
+    pub fn options(&self) -> RendererOptions {
+        RendererOptions {
+            msaa_samples: self.msaa_samples,
+            background: self.background,
+            line_widths: self.line_widths,
+            color_format: self.color_format,
+            ..RendererOptions::default()
+        }
+    }
+}
+
+/// Buffer width in pixels on each side of a `tile_size` tile
+pub fn buffer_pixels(tile_size: u32, buffer_fraction: f64) -> u32 {
+    (tile_size as f64 * buffer_fraction.clamp(0.0, 1.0)).round() as u32
+}
+
+/// Offsets of a tile and of its neighbours across the antimeridian, see `VulkanRenderer::tile_offsets`
+type TileOffsets<'a> = (Cow<'a, [MapObjectOffset]>, Vec<(Cow<'a, [MapObjectOffset]>, f64)>);
+
+/// Pipeline polygon fills are drawn with, see `RendererOptions::fill_polygons`
+struct FillPipeline {
+    pipeline: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    samples: vk::SampleCountFlags,
+    // Render pass of its own when `samples` differs from the lines', see
+    // `RendererOptions::fill_msaa_samples`
+    render_pass: Option<vk::RenderPass>,
 }
 
 struct RenderTarget {
     framebuffer: vk::Framebuffer,
+    // Multisample color image, resolved into `color_image` (MSAA only)
+    msaa_image: Option<(vk::Image, vk::ImageView, Allocation)>,
+    // Framebuffer and multisample image of the fill pass, resolved into
+    // `color_image` (`FillPipeline::render_pass` only)
+    fill_target: Option<(vk::Framebuffer, vk::Image, vk::ImageView, Allocation)>,
+    // Single-sample image that is copied to the staging buffer
     color_image: vk::Image,
     color_image_view: vk::ImageView,
     color_image_allocation: Allocation,
@@ -64,17 +347,42 @@ struct RenderTarget {
 impl VulkanRenderer {
     /// Create a new Vulkan renderer
     pub fn new(max_points: usize, shader_type: ShaderType) -> Result<Self, VulkanError> {
-        Self::new_with_tile_size(max_points, shader_type, TILE_SIZE)
+        Self::with_config(RendererConfig { max_points, shader_type, ..RendererConfig::default() })
     }
 
     /// Create a new Vulkan renderer with custom tile size
     pub fn new_with_tile_size(max_points: usize, shader_type: ShaderType, tile_size: u32) -> Result<Self, VulkanError> {
+        Self::with_config(RendererConfig { max_points, shader_type, tile_size, ..RendererConfig::default() })
+    }
+
+    /// Create a new Vulkan renderer from a `RendererConfig`
+    pub fn with_config(config: RendererConfig) -> Result<Self, VulkanError> {
+        Self::new_with_options(config.max_points, config.shader_type, config.tile_size, config.options())
+    }
+
+    /// Create a new Vulkan renderer with explicit options
+    ///
+    /// See `RendererOptions` and `VulkanContext::new_with_options`.
+    pub fn new_with_options(
+        max_points: usize,
+        shader_type: ShaderType,
+        tile_size: u32,
+        options: RendererOptions,
+    ) -> Result<Self, VulkanError> {
         // Ensure we have a minimum buffer size even with no data
         let max_points = max_points.max(1000); // Minimum 1000 points
 
         log::info!("Creating Vulkan renderer with {:?} shader", shader_type);
 
-        let context = VulkanContext::new()?;
+        // Buffered tiles render into a larger image
+        let buffer_px = buffer_pixels(tile_size, options.buffer_fraction);
+        let nominal_size = tile_size;
+        let tile_size = nominal_size + 2 * buffer_px;
+        if buffer_px > 0 {
+            log::info!("Rendering {}px tiles with a {}px buffer ({}px images)", nominal_size, buffer_px, tile_size);
+        }
+
+        let context = VulkanContext::new_with_options(options.context)?;
 
         let memory_manager = {
             // ash Instance and Device wrap raw handles and are cheap to clone
@@ -89,19 +397,126 @@ impl VulkanRenderer {
             Arc::new(Mutex::new(allocator))
         };
 
+        // Negotiate the MSAA sample count with the device
+        let device_properties = unsafe {
+            context.instance.get_physical_device_properties(context.physical_device)
+        };
+        let samples = select_sample_count(
+            options.msaa_samples,
+            device_properties.limits.framebuffer_color_sample_counts,
+        );
+        if options.msaa_samples > 1 {
+            if samples.as_raw() < options.msaa_samples {
+                log::warn!("{}x MSAA requested but using {}x (device limit)", options.msaa_samples, samples.as_raw());
+            } else {
+                log::info!("Using {}x MSAA", samples.as_raw());
+            }
+        }
+
+        let crisp_lines = options.crisp_lines && shader_type == ShaderType::Mercator;
+        if options.crisp_lines && !crisp_lines {
+            log::warn!("Crisp lines need the Mercator shader, ignoring them with {:?}", shader_type);
+        }
+        let line_widths = options.line_widths && shader_type == ShaderType::Mercator;
+        if options.line_widths && !line_widths {
+            log::warn!("Line widths need the Mercator shader, drawing 1px lines with {:?}", shader_type);
+        }
+        if options.casing && !line_widths {
+            log::warn!("Casings are drawn around wide lines, ignoring them without line widths");
+        }
+        // Fills have their own pass for another sample count, see `RendererOptions::fill_msaa_samples`
+        let fill_samples = options.fill_polygons.then(|| {
+            let requested = options.fill_msaa_samples.unwrap_or(options.msaa_samples);
+            let fill_samples = select_sample_count(requested, device_properties.limits.framebuffer_color_sample_counts);
+            if fill_samples != samples && samples != vk::SampleCountFlags::TYPE_1 {
+                log::warn!("Fills need single-sample lines for a sample count of their own, using {}x for both", samples.as_raw());
+                samples
+            } else {
+                log::info!("Filling polygons with {}x MSAA", fill_samples.as_raw());
+                fill_samples
+            }
+        });
+        let fill_pass = fill_samples.is_some_and(|fill_samples| fill_samples != samples);
+        let color_format = if options.color_format.is_supported(&context.instance, context.physical_device) {
+            options.color_format
+        } else {
+            log::warn!("Device can't render {:?} images, falling back to {:?}", options.color_format.vk_format(), ColorFormat::Unorm.vk_format());
+            ColorFormat::Unorm
+        };
+        let topology = if line_widths {
+            vk::PrimitiveTopology::TRIANGLE_LIST
+        } else {
+            vk::PrimitiveTopology::LINE_LIST
+        };
+
         // Create render pass and pipeline
         let descriptor_set_layout = create_descriptor_set_layout(&context.device)?;
-        let render_pass = create_render_pass(&context.device, vk::Format::R8G8B8A8_UNORM)?;
+        let render_pass = create_render_pass(&context.device, color_format.vk_format(), samples, fill_pass)?;
         let (pipeline, pipeline_layout) = create_graphics_pipeline(
             &context.device,
             render_pass,
             descriptor_set_layout,
             shader_type,
-            tile_size,
+            samples,
+            topology,
+            color_format,
         )?;
+        let (point_pipeline, point_pipeline_layout) = create_graphics_pipeline(
+            &context.device,
+            render_pass,
+            descriptor_set_layout,
+            shader_type,
+            samples,
+            vk::PrimitiveTopology::POINT_LIST,
+            color_format,
+        )?;
+        let fill = match fill_samples {
+            Some(fill_samples) => {
+                let fill_render_pass = if fill_pass {
+                    Some(create_fill_render_pass(&context.device, color_format.vk_format(), fill_samples)?)
+                } else {
+                    None
+                };
+                let (pipeline, layout) = create_graphics_pipeline(
+                    &context.device,
+                    fill_render_pass.unwrap_or(render_pass),
+                    descriptor_set_layout,
+                    shader_type,
+                    fill_samples,
+                    vk::PrimitiveTopology::TRIANGLE_LIST,
+                    color_format,
+                )?;
+                Some(FillPipeline { pipeline, layout, samples: fill_samples, render_pass: fill_render_pass })
+            }
+            None => None,
+        };
+        let point_size = if context.large_points {
+            POINT_SIZE
+        } else {
+            log::warn!("Device doesn't support large points, drawing POI nodes 1px");
+            1.0
+        };
 
-        // Create descriptor pool
+        // Create descriptor pool, and the one descriptor set all renders use
         let descriptor_pool = create_descriptor_pool(&context.device)?;
+        let (uniform_buffer, uniform_allocation) = {
+            let mut allocator = memory_manager.lock().unwrap();
+            let (buffer, allocation) = create_buffer(
+                &context.device,
+                &mut allocator,
+                std::mem::size_of::<UniformBufferObject>() as vk::DeviceSize,
+                vk::BufferUsageFlags::UNIFORM_BUFFER,
+                MemoryLocation::CpuToGpu,
+                "uniform_buffer",
+            )?;
+            if allocation.mapped_ptr().is_none() {
+                unsafe { context.device.destroy_buffer(buffer, None) };
+                allocator.free(allocation).ok();
+                return Err(VulkanError::NotMappable("uniform_buffer"));
+            }
+            (buffer, allocation)
+        };
+        let descriptor_set = create_descriptor_set(&context.device, descriptor_pool, descriptor_set_layout, uniform_buffer)?;
 
         // Allocate command buffer
         let command_buffer = allocate_command_buffer(&context.device, context.command_pool)?;
@@ -111,28 +526,53 @@ impl VulkanRenderer {
 
         // Pre-allocate vertex buffer
         // Tiles can have tens of thousands of objects with complex geometry
-        // Allocate a large fixed buffer (10M floats = 40MB)
-        let vertex_buffer_capacity = 10_000_000; // Fixed large allocation
-        let (vertex_buffer, vertex_buffer_allocation) = {
+        // Allocate a large buffer, unless the vertex budgets need less; it grows for denser tiles
+        let vertex_buffer_capacity = options.vertex_budgets.buffer_capacity(VERTEX_BUFFER_CAPACITY);
+        let max_vertex_buffer_capacity = options
+            .max_vertex_buffer_capacity
+            .unwrap_or(MAX_VERTEX_BUFFER_CAPACITY)
+            .max(vertex_buffer_capacity);
+        let ((vertex_buffer, vertex_buffer_allocation), vertex_staging) = {
             let mut allocator = memory_manager.lock().unwrap();
-            create_buffer(
-                &context.device,
-                &mut allocator,
-                (vertex_buffer_capacity * std::mem::size_of::<f32>()) as vk::DeviceSize,
-                vk::BufferUsageFlags::VERTEX_BUFFER,
-                MemoryLocation::CpuToGpu,
-                "vertex_buffer",
-            )?
+            create_vertex_buffers(&context.device, &mut allocator, vertex_buffer_capacity)?
         };
+        if vertex_staging.is_some() {
+            log::warn!("Vertex buffer memory is not host-visible, uploading through a staging buffer");
+        }
 
         Ok(VulkanRenderer {
             tile_size,
-            context,
-            memory_manager,
+            buffer_px,
+            buffer_fraction: options.buffer_fraction,
+            samples,
+            lod: options.lod,
+            vertex_budgets: options.vertex_budgets,
+            wrap_antimeridian: options.wrap_antimeridian,
+            tile_origin: options.tile_origin,
+            style: options.style,
+            clip_region: options.clip_region,
+            overlay: options.overlay,
+            crisp_lines,
+            line_widths,
+            casing: options.casing && line_widths,
+            line_colors: options.line_colors.unwrap_or_default(),
+            point_size,
+            point_decimation_px: options.point_decimation_px,
+            precise_overlap: options.precise_overlap,
+            background: options.background.unwrap_or(BACKGROUND_COLOR),
+            render_timeout: options.render_timeout.unwrap_or(DEFAULT_RENDER_TIMEOUT),
+            broken: false,
+            color_format,
+            context: ManuallyDrop::new(context),
+            memory_manager: ManuallyDrop::new(memory_manager),
             render_pass,
             descriptor_set_layout,
             pipeline_layout,
             pipeline,
+            point_pipeline_layout,
+            point_pipeline,
+            fill,
+            fill_max_objects: options.fill_max_objects,
             descriptor_pool,
             command_buffer,
             fence,
@@ -140,9 +580,38 @@ impl VulkanRenderer {
             vertex_buffer: Some(vertex_buffer),
             vertex_buffer_allocation: Some(vertex_buffer_allocation),
             vertex_buffer_capacity,
+            max_vertex_buffer_capacity,
+            vertex_staging,
+            last_vertex_count: 0,
+            last_fill_vertex_count: 0,
+            last_object_count: 0,
+            uniform_buffer: Some((uniform_buffer, uniform_allocation)),
+            descriptor_set,
         })
     }
 
+    /// Nominal size of the tiles rendered, without the buffer
+    pub fn tile_size(&self) -> u32 {
+        self.tile_size - 2 * self.buffer_px
+    }
+
+    /// Render `tile_size` tiles from now on
+    ///
+    /// The render target is recreated at the new size by the next render;
+    /// pipelines don't depend on the size, so this is cheap compared to
+    /// creating another renderer.
+    pub fn set_tile_size(&mut self, tile_size: u32) {
+        let buffer_px = buffer_pixels(tile_size, self.buffer_fraction);
+        let image_size = tile_size + 2 * buffer_px;
+        if image_size == self.tile_size {
+            return;
+        }
+        log::debug!("Resizing renderer from {}px to {}px images", self.tile_size, image_size);
+        self.destroy_render_target();
+        self.tile_size = image_size;
+        self.buffer_px = buffer_px;
+    }
+
     /// Render a tile and return the image
     pub fn render_tile(
         &mut self,
@@ -150,33 +619,456 @@ impl VulkanRenderer {
         tile_index: &TileIndex,
         mmap_data: &MappedData,
     ) -> Result<RgbaImage, VulkanError> {
-        // For zoom levels > 15, use the parent tile's data at zoom 15
-        // The bounding box filtering will select only relevant objects
-        const MAX_INDEXED_ZOOM: u32 = 15;
-        let lookup_tile = if tile.z > MAX_INDEXED_ZOOM {
-            let ancestor = tile.get_ancestor(MAX_INDEXED_ZOOM)
-                .expect("get_ancestor should always succeed for lower zoom");
-            log::info!("Tile {:?} is above max indexed zoom, using ancestor {:?}", tile, ancestor);
-            ancestor
-        } else {
-            *tile
+        self.render_tile_with_detail(tile, 0, tile_index, mmap_data)
+    }
+
+    /// Render a tile using index data from `detail` zoom levels deeper
+    ///
+    /// The objects of all descendants at `tile.z + detail` are drawn into the
+    /// requested tile's bounding box, so features that are only indexed at
+    /// higher zooms appear. The detail zoom is clamped to `TileIndex::max_zoom`.
+    pub fn render_tile_with_detail(
+        &mut self,
+        tile: &Tile,
+        detail: u32,
+        tile_index: &TileIndex,
+        mmap_data: &MappedData,
+    ) -> Result<RgbaImage, VulkanError> {
+        self.render_tile_filtered(tile, detail, None, tile_index, mmap_data)
+    }
+
+    /// Render a tile like `render_tile_with_detail`, drawing only objects
+    /// whose retained tags (`MapObjectView::tags`) match `filter`
+    pub fn render_tile_filtered(
+        &mut self,
+        tile: &Tile,
+        detail: u32,
+        filter: Option<&TagFilter>,
+        tile_index: &TileIndex,
+        mmap_data: &MappedData,
+    ) -> Result<RgbaImage, VulkanError> {
+        let Some((offsets, wrapped)) = self.tile_offsets(tile, detail, filter, tile_index, mmap_data) else {
+            // No data for this tile, return a blank image
+            return Ok(self.empty_tile(tile));
         };
+        let bbox = self.image_bounding_box(tile);
+        self.render_offsets(&bbox, tile.z, &offsets, &wrapped, tile_index, mmap_data)
+    }
 
-        // Get map object offsets for the lookup tile
-        let offsets = match tile_index.get(&lookup_tile) {
-            Some(offsets) => offsets,
-            None => {
-                log::warn!("No tile index data for tile {:?}", lookup_tile);
-                // No data for this tile, return empty white image
-                return Ok(RgbaImage::from_pixel(self.tile_size, self.tile_size, image::Rgba([255, 255, 255, 255])));
-            }
+    /// Render a tile like `render_tile` and encode it as PNG
+    ///
+    /// The PNG is encoded straight from the mapped staging buffer, skipping
+    /// the copy into an `RgbaImage` that `render_tile` makes. Overlay and
+    /// clip region tiles, whose pixels are processed after reading them
+    /// back, are encoded from `render_tile`'s image instead. Renders in
+    /// `ColorFormat::Srgb` are marked as sRGB, see `encode_png_rgba`.
+    pub fn render_tile_to_png(
+        &mut self,
+        tile: &Tile,
+        tile_index: &TileIndex,
+        mmap_data: &MappedData,
+    ) -> Result<Vec<u8>, VulkanError> {
+        let Some((offsets, wrapped)) = self.tile_offsets(tile, 0, None, tile_index, mmap_data) else {
+            let image = self.empty_tile(tile);
+            return self.encode_png(&image);
         };
+        let bbox = self.image_bounding_box(tile);
+        self.draw_offsets(&offsets, &wrapped, tile_index, mmap_data, |renderer, batches| {
+            if renderer.overlay || renderer.clip_region.is_some() {
+                let image = renderer.render_batches_in(&bbox, tile.z, batches)?;
+                return renderer.encode_png(&image);
+            }
+            if !renderer.draw_batches(&bbox, tile.z, batches)? {
+                let image = renderer.empty_image(&bbox);
+                return renderer.encode_png(&image);
+            }
+            let size = renderer.tile_size;
+            Ok(encode_png_rgba(renderer.staged_pixels()?, size, size, renderer.color_format.is_srgb())?)
+        })
+    }
+
+    /// PNG of `image` as `render_tile_to_png` encodes it
+    fn encode_png(&self, image: &RgbaImage) -> Result<Vec<u8>, VulkanError> {
+        Ok(encode_png_rgba(image.as_raw(), image.width(), image.height(), self.color_format.is_srgb())?)
+    }
+
+    /// Offsets of the objects to draw into `tile`, and of those across the
+    /// antimeridian (see `wrapped_offsets`), or `None` without any
+    fn tile_offsets<'a>(
+        &self,
+        tile: &Tile,
+        detail: u32,
+        filter: Option<&TagFilter>,
+        tile_index: &'a TileIndex,
+        mmap_data: &MappedData,
+    ) -> Option<TileOffsets<'a>> {
+        let lookup_tile = indexed_lookup_tile(tile, tile_index);
+        let mut offsets = self.lookup_offsets(&lookup_tile, detail, tile_index);
+        let mut wrapped = self.wrapped_offsets(&lookup_tile, detail, tile_index);
+        if let Some(filter) = filter {
+            let matches = |offset: &MapObjectOffset| {
+                filter.matches(&mmap_data.read_map_object(*offset).tags().collect::<Vec<_>>())
+            };
+            offsets.to_mut().retain(matches);
+            for (offsets, _) in &mut wrapped {
+                offsets.to_mut().retain(matches);
+            }
+        }
+        if offsets.is_empty() && wrapped.iter().all(|(offsets, _)| offsets.is_empty()) {
+            log::warn!("No tile index data for tile {:?}", lookup_tile);
+            return None;
+        }
 
         log::info!("Rendering tile {:?} with {} map objects from lookup tile {:?}",
                    tile, offsets.len(), lookup_tile);
+        Some((offsets, wrapped))
+    }
+
+    /// Render the `n`×`n` tiles from `origin` in one pass and cut them apart
+    ///
+    /// The renderer must be created with `n` times the tile size. The
+    /// objects of all covered tiles are drawn once into a framebuffer
+    /// covering their union, plus the buffer, which saves the per-tile
+    /// vertex buffer builds and submissions of neighbouring tiles. Returns
+    /// the tile images without the buffer, row by row from `origin` in XYZ
+    /// numbering; all tiles must lie in the grid.
+    pub fn render_metatile(
+        &mut self,
+        origin: &Tile,
+        n: u32,
+        tile_index: &TileIndex,
+        mmap_data: &MappedData,
+    ) -> Result<Vec<RgbaImage>, VulkanError> {
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+        let tile_px = (self.tile_size - 2 * self.buffer_px) / n;
+        let tiles: Vec<Tile> = (0..n)
+            .flat_map(|j| (0..n).map(move |i| Tile::new(origin.x + i, origin.y + j, origin.z)))
+            .collect();
+
+        // Tiles above the indexed zooms share their ancestor's offsets
+        let mut offsets: Vec<MapObjectOffset> = tiles
+            .iter()
+            .flat_map(|tile| self.lookup_offsets(&lookup_tile(tile, tile_index.max_zoom), 0, tile_index).into_owned())
+            .collect();
+        offsets.sort_unstable();
+        offsets.dedup();
+        let mut wrapped: Vec<(Cow<[MapObjectOffset]>, f64)> = Vec::new();
+        for (tile_offsets, lon_offset) in tiles.iter().flat_map(|tile| self.wrapped_offsets(&lookup_tile(tile, tile_index.max_zoom), 0, tile_index)) {
+            match wrapped.iter_mut().find(|(_, offset)| *offset == lon_offset) {
+                Some((all, _)) => all.to_mut().extend_from_slice(&tile_offsets),
+                None => wrapped.push((tile_offsets, lon_offset)),
+            }
+        }
+
+        let buffer = self.buffer_px as f64 / tile_px as f64;
+        let last = Tile::new(origin.x + n - 1, origin.y + n - 1, origin.z);
+        let bbox = get_buffered_bounding_box(origin, buffer).union(&get_buffered_bounding_box(&last, buffer));
+        log::info!("Rendering {}x{} metatile from {:?} with {} map objects", n, n, origin, offsets.len());
+        let image = if offsets.is_empty() && wrapped.iter().all(|(offsets, _)| offsets.is_empty()) {
+            self.empty_image(&bbox)
+        } else {
+            self.render_offsets(&bbox, origin.z, &offsets, &wrapped, tile_index, mmap_data)?
+        };
+
+        Ok(tiles
+            .iter()
+            .map(|tile| {
+                let (i, j) = (tile.x - origin.x, tile.y - origin.y);
+                // Bottom-left images have the southernmost row at the top
+                let row = match self.tile_origin {
+                    TileOrigin::TopLeft => j,
+                    TileOrigin::BottomLeft => n - 1 - j,
+                };
+                let (x, y) = (self.buffer_px + i * tile_px, self.buffer_px + row * tile_px);
+                image::imageops::crop_imm(&image, x, y, tile_px, tile_px).to_image()
+            })
+            .collect())
+    }
+
+    /// Draw mapped objects into `bbox` in style class batches
+    ///
+    /// `wrapped` holds the objects of tiles across the antimeridian with
+    /// their longitude shift, see `wrapped_offsets`.
+    fn render_offsets(
+        &mut self,
+        bbox: &BoundingBox,
+        zoom: u32,
+        offsets: &[MapObjectOffset],
+        wrapped: &[(Cow<[MapObjectOffset]>, f64)],
+        tile_index: &TileIndex,
+        mmap_data: &MappedData,
+    ) -> Result<RgbaImage, VulkanError> {
+        self.draw_offsets(offsets, wrapped, tile_index, mmap_data, |renderer, batches| {
+            renderer.render_batches_in(bbox, zoom, batches)
+        })
+    }
+
+    /// Group mapped objects into style class batches and hand them to `draw`
+    fn draw_offsets<T>(
+        &mut self,
+        offsets: &[MapObjectOffset],
+        wrapped: &[(Cow<[MapObjectOffset]>, f64)],
+        tile_index: &TileIndex,
+        mmap_data: &MappedData,
+        draw: impl FnOnce(&mut Self, &[LineBatch]) -> Result<T, VulkanError>,
+    ) -> Result<T, VulkanError> {
+        let groups: Vec<_> = std::iter::once((offsets, 0.0))
+            .chain(wrapped.iter().map(|(offsets, lon_offset)| (&**offsets, *lon_offset)))
+            .flat_map(|(offsets, lon_offset)| {
+                self.style_groups(offsets, tile_index)
+                    .into_iter()
+                    .map(move |(offsets, color, width)| (offsets, color, width, lon_offset))
+            })
+            .collect();
+        let batches: Vec<LineBatch> = groups
+            .iter()
+            .map(|(offsets, color, width, lon_offset)| LineBatch {
+                objects: BatchObjects::mapped(offsets, mmap_data),
+                color: *color,
+                width: *width,
+                lon_offset: *lon_offset,
+            })
+            .collect();
+        draw(self, &batches)
+    }
+
+    /// Render a tile from the objects of any `TileSource`
+    ///
+    /// Unlike `render_tile` this copies the objects out of the source, so
+    /// the memory-mapped path stays on `render_tile` and is also available
+    /// as `MmapTileSource` for callers that only deal in sources.
+    pub fn render_tile_from_source(&mut self, tile: &Tile, source: &dyn TileSource) -> Result<RgbaImage, VulkanError> {
+        let objects = source.objects_for_tile(tile)?;
+        if objects.is_empty() {
+            log::warn!("No source data for tile {:?}", tile);
+            return Ok(self.empty_tile(tile));
+        }
+
+        log::info!("Rendering tile {:?} with {} map objects from source", tile, objects.len());
+
+        self.render_batches(tile, &[LineBatch {
+            objects: BatchObjects::Owned(&objects),
+            color: BatchColor::Unclassed,
+            width: None,
+            lon_offset: 0.0,
+        }])
+    }
+
+    /// Render a diff tile comparing `current` against `base` by way id
+    ///
+    /// Unchanged ways are drawn gray, removed ways (only in `base`) red and
+    /// added ways (only in `current`) green, in that order. Both indexes need
+    /// way ids, see `TileIndex::has_way_ids`.
+    pub fn render_diff_tile(
+        &mut self,
+        tile: &Tile,
+        detail: u32,
+        current: (&TileIndex, &MappedData),
+        base: (&TileIndex, &MappedData),
+    ) -> Result<RgbaImage, VulkanError> {
+        let (current_index, current_mmap) = current;
+        let (base_index, base_mmap) = base;
+
+        let lookup_tile = lookup_tile(tile, current_index.max_zoom);
+        let current_offsets = self.lookup_offsets(&lookup_tile, detail, current_index);
+        let base_offsets = self.lookup_offsets(&lookup_tile, detail, base_index);
+        let diff = diff_tile(&current_offsets, current_index, &base_offsets, base_index);
+        if diff.is_empty() {
+            log::warn!("No tile index data for diff tile {:?}", lookup_tile);
+            return Ok(self.empty_tile(tile));
+        }
+
+        log::info!("Rendering diff tile {:?}: {} added, {} removed, {} unchanged",
+                   tile, diff.added.len(), diff.removed.len(), diff.unchanged.len());
+
+        self.render_batches(tile, &[
+            LineBatch {
+                objects: BatchObjects::mapped(&diff.unchanged, current_mmap),
+                color: BatchColor::Fixed(DiffStatus::Unchanged.color()),
+                width: None,
+                lon_offset: 0.0,
+            },
+            LineBatch {
+                objects: BatchObjects::mapped(&diff.removed, base_mmap),
+                color: BatchColor::Fixed(DiffStatus::Removed.color()),
+                width: None,
+                lon_offset: 0.0,
+            },
+            LineBatch {
+                objects: BatchObjects::mapped(&diff.added, current_mmap),
+                color: BatchColor::Fixed(DiffStatus::Added.color()),
+                width: None,
+                lon_offset: 0.0,
+            },
+        ])
+    }
+
+    /// Map object offsets to draw for `lookup_tile`
+    ///
+    /// Buffered renders also need the objects of the neighbouring tiles.
+    fn lookup_offsets<'a>(&self, lookup_tile: &Tile, detail: u32, tile_index: &'a TileIndex) -> Cow<'a, [MapObjectOffset]> {
+        if self.buffer_px == 0 {
+            return lookup_offsets(lookup_tile, detail, tile_index);
+        }
 
-        // Get bounding box for tile
-        let bbox = get_bounding_box(tile);
+        let mut offsets: Vec<MapObjectOffset> = lookup_tile
+            .neighborhood()
+            .iter()
+            .flat_map(|neighbor| lookup_offsets(neighbor, detail, tile_index).into_owned())
+            .collect();
+        offsets.sort_unstable();
+        offsets.dedup();
+        Cow::Owned(offsets)
+    }
+
+    /// Offsets of the tiles across the antimeridian with their longitude shift
+    ///
+    /// Only tiles in the first and last column have such neighbours, and
+    /// only if `wrap_antimeridian` is enabled.
+    fn wrapped_offsets<'a>(&self, lookup_tile: &Tile, detail: u32, tile_index: &'a TileIndex) -> Vec<(Cow<'a, [MapObjectOffset]>, f64)> {
+        if !self.wrap_antimeridian {
+            return Vec::new();
+        }
+        let rows = if self.buffer_px > 0 { 1 } else { 0 };
+        lookup_tile
+            .wrapped_neighbors(rows)
+            .into_iter()
+            .map(|(neighbor, lon_offset)| (lookup_offsets(&neighbor, detail, tile_index), lon_offset))
+            .collect()
+    }
+
+    /// Split `offsets` by style class in draw order, each with its class color and width
+    ///
+    /// Without a style, or for objects without a class, this is a single
+    /// `BatchColor::Unclassed` group without a width drawn first.
+    fn style_groups<'a>(&self, offsets: &'a [MapObjectOffset], tile_index: &TileIndex) -> Vec<(Cow<'a, [MapObjectOffset]>, BatchColor, Option<f32>)> {
+        let style = match &self.style {
+            Some(style) if !tile_index.classes.is_empty() => style,
+            _ => return vec![(Cow::Borrowed(offsets), BatchColor::Unclassed, None)],
+        };
+
+        let mut unclassed = Vec::new();
+        let mut by_class: Vec<Vec<MapObjectOffset>> = vec![Vec::new(); style.classes.len()];
+        for &offset in offsets {
+            match tile_index.class(offset).and_then(|class| by_class.get_mut(class as usize)) {
+                Some(class_offsets) => class_offsets.push(offset),
+                None => unclassed.push(offset),
+            }
+        }
+
+        let mut groups = Vec::new();
+        if !unclassed.is_empty() {
+            groups.push((Cow::Owned(unclassed), BatchColor::Unclassed, None));
+        }
+        for class in style.draw_order() {
+            let class_offsets = std::mem::take(&mut by_class[class as usize]);
+            if !class_offsets.is_empty() {
+                let class_style = style.class(class);
+                groups.push((Cow::Owned(class_offsets), BatchColor::Fixed(class_style.rgba()), Some(class_style.width)));
+            }
+        }
+        groups
+    }
+
+    /// Vertices drawn by the last render, 0 for tiles without data
+    pub fn last_vertex_count(&self) -> usize {
+        self.last_vertex_count
+    }
+
+    /// Vertices of polygon fills among `last_vertex_count`, 0 when polygons
+    /// were drawn as outlines, see `RendererOptions::fill_max_objects`
+    pub fn last_fill_vertex_count(&self) -> usize {
+        self.last_fill_vertex_count
+    }
+
+    /// Whether a render timed out or lost the device, so this renderer
+    /// can't render anymore and should be replaced
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    /// Format of the rendered images, `RendererOptions::color_format` unless
+    /// the device didn't support it
+    pub fn color_format(&self) -> ColorFormat {
+        self.color_format
+    }
+
+    /// Map objects read for the last render, including those outside the
+    /// tile or over budget, 0 for tiles without data
+    pub fn last_object_count(&self) -> usize {
+        self.last_object_count
+    }
+
+    /// Blank image for a tile without data
+    fn empty_tile(&mut self, tile: &Tile) -> RgbaImage {
+        self.empty_image(&self.image_bounding_box(tile))
+    }
+
+    /// Blank image of `bbox`, masked to the clip region
+    fn empty_image(&mut self, bbox: &BoundingBox) -> RgbaImage {
+        self.last_vertex_count = 0;
+        self.last_fill_vertex_count = 0;
+        self.last_object_count = 0;
+        let mut image = self.blank_image();
+        self.clip(&mut image, bbox);
+        image
+    }
+
+    /// Bounding box of the rendered image of `tile`, including the buffer
+    fn image_bounding_box(&self, tile: &Tile) -> BoundingBox {
+        let nominal_size = self.tile_size - 2 * self.buffer_px;
+        get_buffered_bounding_box(tile, self.buffer_px as f64 / nominal_size as f64)
+    }
+
+    /// Mask a rendered image to the clip region, if any
+    fn clip(&self, image: &mut RgbaImage, bbox: &BoundingBox) {
+        if let Some(clip_region) = &self.clip_region {
+            clip_region.apply(image, bbox, self.tile_origin);
+        }
+    }
+
+    fn blank_image(&self) -> RgbaImage {
+        RgbaImage::from_pixel(self.tile_size, self.tile_size, image::Rgba(self.background_color()))
+    }
+
+    /// Color tiles are cleared to: the configured background (`BACKGROUND_COLOR`
+    /// by default), or `NODATA_COLOR` as an overlay
+    pub fn background_color(&self) -> [u8; 4] {
+        if self.overlay {
+            NODATA_COLOR
+        } else {
+            self.background
+        }
+    }
+
+    /// Draw the batches in order into the tile's bounding box and read back the image
+    fn render_batches(&mut self, tile: &Tile, batches: &[LineBatch]) -> Result<RgbaImage, VulkanError> {
+        // Get bounding box for tile, including the buffer
+        let bbox = self.image_bounding_box(tile);
+        self.render_batches_in(&bbox, tile.z, batches)
+    }
+
+    /// Draw the batches in order into `bbox` at `zoom` and read back the image
+    fn render_batches_in(&mut self, bbox: &BoundingBox, zoom: u32, batches: &[LineBatch]) -> Result<RgbaImage, VulkanError> {
+        if !self.draw_batches(bbox, zoom, batches)? {
+            return Ok(self.empty_image(bbox));
+        }
+
+        // Read back image
+        let mut image = self.read_framebuffer()?;
+        if self.overlay {
+            unpremultiply(&mut image, self.color_format);
+        }
+        self.clip(&mut image, bbox);
+
+        Ok(image)
+    }
+
+    /// Draw the batches in order into `bbox` at `zoom`, leaving the image in
+    /// the staging buffer; `false` if there was nothing to draw
+    fn draw_batches(&mut self, bbox: &BoundingBox, zoom: u32, batches: &[LineBatch]) -> Result<bool, VulkanError> {
         log::info!("Tile bbox: min=({}, {}), max=({}, {})",
                    bbox.min.lon, bbox.min.lat, bbox.max.lon, bbox.max.lat);
 
@@ -185,54 +1077,59 @@ impl VulkanRenderer {
             self.render_target = Some(self.create_render_target()?);
         }
 
-        // Build vertex buffer
-        let vertex_count = self.build_vertex_buffer(&offsets, mmap_data, &bbox)?;
+        // Build vertex buffer, growing it while the tile doesn't fit
+        let VertexCounts { lines: line_vertices, fills: fill_vertices, points: point_vertices, objects, .. } = loop {
+            let counts = self.build_vertex_buffer(batches, bbox, zoom)?;
+            if !counts.overflowed {
+                break counts;
+            }
+            if self.vertex_buffer_capacity < self.max_vertex_buffer_capacity {
+                match self.grow_vertex_buffer() {
+                    Ok(()) => continue,
+                    Err(e) => {
+                        log::warn!("Failed to grow the vertex buffer, keeping {} vertices: {}", self.vertex_buffer_capacity, e);
+                        self.max_vertex_buffer_capacity = self.vertex_buffer_capacity;
+                    }
+                }
+            }
+            let center = bbox.center();
+            let (x, y) = deg2num(center.lat, center.lon, zoom);
+            log::warn!("Vertex buffer of {} vertices full, truncating tile {}/{}/{}", self.vertex_buffer_capacity, zoom, x, y);
+            break counts;
+        };
+        let vertex_count = line_vertices + fill_vertices + point_vertices;
+        self.last_vertex_count = vertex_count;
+        self.last_fill_vertex_count = fill_vertices;
+        self.last_object_count = objects;
 
-        log::info!("Built vertex buffer with {} vertices", vertex_count);
+        log::info!("Built vertex buffer with {} vertices ({} fill, {} points)", vertex_count, fill_vertices, point_vertices);
 
         if vertex_count == 0 {
-            log::warn!("No visible vertices, returning white image");
-            // No visible vertices, return white image
-            return Ok(RgbaImage::from_pixel(self.tile_size, self.tile_size, image::Rgba([255, 255, 255, 255])));
+            log::warn!("No visible vertices, returning blank image");
+            return Ok(false);
         }
 
-        // Create uniform buffer
-        let (uniform_buffer, uniform_allocation) = self.create_uniform_buffer(&bbox)?;
-
-        // Create descriptor set
-        let descriptor_set = self.create_descriptor_set(uniform_buffer)?;
+        // Update uniform buffer
+        self.write_uniforms(bbox)?;
 
         // Record and submit commands
-        self.record_and_submit_commands(vertex_count, descriptor_set)?;
-
-        // Read back image
-        let image = self.read_framebuffer()?;
+        self.record_and_submit_commands(line_vertices, fill_vertices, point_vertices)?;
 
-        // Cleanup
-        unsafe {
-            // Free descriptor set first (return it to the pool)
-            self.context.device.free_descriptor_sets(self.descriptor_pool, &[descriptor_set])?;
-            // Then destroy the uniform buffer
-            self.context.device.destroy_buffer(uniform_buffer, None);
-        }
-        {
-            let mut allocator = self.memory_manager.lock().unwrap();
-            allocator.free(uniform_allocation)?;
-        }
-
-        Ok(image)
+        Ok(true)
     }
 
     fn create_render_target(&self) -> Result<RenderTarget, VulkanError> {
         let mut allocator = self.memory_manager.lock().unwrap();
 
+        let extent = vk::Extent2D { width: self.tile_size, height: self.tile_size };
+
         // Create color image
         let (color_image, color_image_allocation) = create_image(
             &self.context.device,
             &mut allocator,
-            self.tile_size,
-            self.tile_size,
-            vk::Format::R8G8B8A8_UNORM,
+            extent,
+            vk::SampleCountFlags::TYPE_1,
+            self.color_format.vk_format(),
             vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
             MemoryLocation::GpuOnly,
             "color_image",
@@ -242,11 +1139,59 @@ impl VulkanRenderer {
         let color_image_view = create_image_view(
             &self.context.device,
             color_image,
-            vk::Format::R8G8B8A8_UNORM,
+            self.color_format.vk_format(),
         )?;
 
-        // Create framebuffer
-        let attachments = [color_image_view];
+        // Create the multisample image the render pass resolves from
+        let msaa_image = if self.samples != vk::SampleCountFlags::TYPE_1 {
+            let (image, allocation) = create_image(
+                &self.context.device,
+                &mut allocator,
+                extent,
+                self.samples,
+                self.color_format.vk_format(),
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+                MemoryLocation::GpuOnly,
+                "msaa_color_image",
+            )?;
+            let view = create_image_view(&self.context.device, image, self.color_format.vk_format())?;
+            Some((image, view, allocation))
+        } else {
+            None
+        };
+
+        // Create the fill pass's multisample image and framebuffer, resolving into the color image
+        let fill_target = match self.fill.as_ref().and_then(|fill| fill.render_pass.map(|pass| (pass, fill.samples))) {
+            Some((fill_render_pass, fill_samples)) => {
+                let (image, allocation) = create_image(
+                    &self.context.device,
+                    &mut allocator,
+                    extent,
+                    fill_samples,
+                    self.color_format.vk_format(),
+                    vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+                    MemoryLocation::GpuOnly,
+                    "fill_msaa_color_image",
+                )?;
+                let view = create_image_view(&self.context.device, image, self.color_format.vk_format())?;
+                let attachments = [view, color_image_view];
+                let framebuffer_info = vk::FramebufferCreateInfo::default()
+                    .render_pass(fill_render_pass)
+                    .attachments(&attachments)
+                    .width(self.tile_size)
+                    .height(self.tile_size)
+                    .layers(1);
+                let framebuffer = unsafe { self.context.device.create_framebuffer(&framebuffer_info, None)? };
+                Some((framebuffer, image, view, allocation))
+            }
+            None => None,
+        };
+
+        // Create framebuffer (multisample image first, see `create_render_pass`)
+        let attachments: Vec<vk::ImageView> = match &msaa_image {
+            Some((_, msaa_view, _)) => vec![*msaa_view, color_image_view],
+            None => vec![color_image_view],
+        };
         let framebuffer_info = vk::FramebufferCreateInfo::default()
             .render_pass(self.render_pass)
             .attachments(&attachments)
@@ -271,6 +1216,8 @@ impl VulkanRenderer {
 
         Ok(RenderTarget {
             framebuffer,
+            msaa_image,
+            fill_target,
             color_image,
             color_image_view,
             color_image_allocation,
@@ -279,75 +1226,331 @@ impl VulkanRenderer {
         })
     }
 
+    /// Write the vertices of the batches and return the number of line,
+    /// fill and point vertices
+    ///
+    /// Line (or triangle) vertices come first, followed by the polygon fill
+    /// triangles, drawn first with the `fill` pipeline, and the point
+    /// vertices, drawn last with `point_pipeline`. Stops when the buffer is
+    /// full, see `VertexCounts::overflowed`.
     fn build_vertex_buffer(
         &mut self,
-        offsets: &[u64],
-        mmap_data: &MappedData,
+        batches: &[LineBatch],
         bbox: &BoundingBox,
-    ) -> Result<usize, VulkanError> {
-        let vertex_buffer_allocation = self.vertex_buffer_allocation.as_ref().unwrap();
-
-        // Map vertex buffer
-        let data_ptr = vertex_buffer_allocation.mapped_ptr().unwrap().as_ptr() as *mut f32;
+        zoom: u32,
+    ) -> Result<VertexCounts, VulkanError> {
+        // Write into the vertex buffer, or the staging buffer it is copied from
+        let data_ptr = match &self.vertex_staging {
+            Some((_, allocation)) => mapped_ptr(allocation, "vertex_staging_buffer")?,
+            None => mapped_ptr(self.vertex_buffer_allocation.as_ref().unwrap(), "vertex_buffer")?,
+        } as *mut Vertex;
         let mut vertex_count = 0;
+        let budget = self.vertex_budgets.for_zoom(zoom);
+        let vertex_limit = budget.vertex_limit(self.vertex_buffer_capacity);
+        // Reaching the limit means the buffer is full rather than the budget used up
+        let buffer_limited = budget.max_vertices.is_none_or(|max| max > self.vertex_buffer_capacity);
+        let mut objects_drawn = 0;
+        let mut objects_read = 0;
+        let mut overflowed = false;
+        // Position, importance (batch index) and vertex of each point, for decimation
+        let mut points_drawn: Vec<(Pixel, u32, Vertex)> = Vec::new();
+        // Line vertices held back with `casing` until the casings they cover are written
+        let mut pending_lines: Vec<Vertex> = Vec::new();
+        // Fill triangles, written after all lines; dense tiles get outlines instead
+        let tile_objects: usize = batches.iter().map(|batch| batch.objects.len()).sum();
+        let fill_polygons = self.fill.is_some() && self.fill_max_objects.is_none_or(|max| tile_objects <= max);
+        if self.fill.is_some() && !fill_polygons {
+            log::debug!("{} objects, drawing polygons as outlines", tile_objects);
+        }
+        let mut fills: Vec<Vertex> = Vec::new();
 
         unsafe {
             let vertices = std::slice::from_raw_parts_mut(data_ptr, self.vertex_buffer_capacity);
-            let mut index = 0;
-
-            for (i, &offset) in offsets.iter().enumerate() {
-                let map_object = mmap_data.read_map_object(offset);
-                let obj_bbox = map_object.bounding_box();
-                let points = map_object.points();
-
-                log::debug!("Map object {}: bbox=({}, {}) to ({}, {}), {} points",
-                          i, obj_bbox.min.lon, obj_bbox.min.lat,
-                          obj_bbox.max.lon, obj_bbox.max.lat, points.len());
 
-                // Check if bounding box overlaps
-                if !bbox.overlaps(obj_bbox) {
-                    log::debug!("  -> Skipped (no overlap)");
-                    continue;
+            'batches: for (batch_index, batch) in batches.iter().enumerate() {
+                // Unclassed objects colored by tag need theirs looked up
+                let line_colors = &*self.line_colors;
+                let object_color = |value: Option<&str>| match batch.color {
+                    BatchColor::Fixed(color) => color,
+                    BatchColor::Unclassed => line_colors.color(value),
+                };
+                let by_tag = matches!(batch.color, BatchColor::Unclassed) && line_colors.by_tag();
+                // Likewise widths, which only matter with `line_widths`
+                let width_by_tags = self.line_widths && batch.width.is_none();
+                let batch_width = batch.width.unwrap_or(1.0);
+                // Unclassed objects are drawn in the order of their layer, classes by priority
+                let by_layer = matches!(batch.color, BatchColor::Unclassed);
+                let mut objects: Vec<DrawObject> = match batch.objects {
+                    BatchObjects::Mapped { offsets, mmap_data } => {
+                        // Offsets are unique per tile once the index is finalized (`TileIndex::finalize`)
+                        offsets
+                            .iter()
+                            .map(|&offset| {
+                                let view = mmap_data.read_map_object(offset);
+                                let value = if by_tag { view.tag(&line_colors.key) } else { None };
+                                let tags: Vec<(&str, &str)> = if width_by_tags || by_layer {
+                                    view.tags().collect()
+                                } else {
+                                    Vec::new()
+                                };
+                                let width = if width_by_tags { line_width_for(&tags, zoom) } else { batch_width };
+                                DrawObject {
+                                    bbox: view.bbox,
+                                    points: view.points,
+                                    kind: view.kind,
+                                    color: object_color(value),
+                                    width,
+                                    layer: if by_layer { layer_for(&tags) } else { 0 },
+                                }
+                            })
+                            .collect()
+                    }
+                    BatchObjects::Owned(objects) => objects
+                        .iter()
+                        .map(|object| {
+                            let value = object.tags.iter().find(|(key, _)| *key == line_colors.key);
+                            let color = object_color(value.map(|(_, value)| value.as_str()));
+                            let width = if width_by_tags { line_width_for(&object.tags, zoom) } else { batch_width };
+                            DrawObject {
+                                bbox: &object.bounding_box,
+                                points: object.points.as_slice(),
+                                kind: object.kind,
+                                color,
+                                width,
+                                layer: if by_layer { layer_for(&object.tags) } else { 0 },
+                            }
+                        })
+                        .collect(),
+                };
+                // Stable, so objects of a layer keep their index order
+                if by_layer {
+                    objects.sort_by_key(|object| object.layer);
                 }
+                objects_read += objects.len();
 
-                // Add line segments (pairs of points)
-                if points.len() < 2 {
-                    log::debug!("  -> Skipped (not enough points: {})", points.len());
-                    continue;
-                }
+                for (i, &DrawObject { bbox: obj_bbox, points, kind, color, width, layer }) in objects.iter().enumerate() {
+                    // Casings of a layer go under all of its lines
+                    if i > 0 && layer != objects[i - 1].layer {
+                        flush_lines(vertices, &mut vertex_count, &mut pending_lines);
+                    }
+                    let shifted_bbox;
+                    let shifted_points: Vec<Point>;
+                    let (obj_bbox, points) = if batch.lon_offset == 0.0 {
+                        (obj_bbox, points)
+                    } else {
+                        shifted_bbox = BoundingBox::new(
+                            Point::new(obj_bbox.min.lon + batch.lon_offset, obj_bbox.min.lat),
+                            Point::new(obj_bbox.max.lon + batch.lon_offset, obj_bbox.max.lat),
+                        );
+                        if !bbox.overlaps(&shifted_bbox) {
+                            continue;
+                        }
+                        shifted_points = points
+                            .iter()
+                            .map(|p| Point::new(p.lon + batch.lon_offset, p.lat))
+                            .collect();
+                        (&shifted_bbox, shifted_points.as_slice())
+                    };
+
+                    log::debug!("Map object {}: bbox=({}, {}) to ({}, {}), {} points",
+                              i, obj_bbox.min.lon, obj_bbox.min.lat,
+                              obj_bbox.max.lon, obj_bbox.max.lat, points.len());
+
+                    // Check if bounding box overlaps
+                    if !bbox.overlaps(obj_bbox) {
+                        log::debug!("  -> Skipped (no overlap)");
+                        continue;
+                    }
+                    // A fill can cover the tile without any of its edges crossing it
+                    let filled = fill_polygons && kind == ObjectKind::Polygon;
+                    if self.precise_overlap && kind != ObjectKind::Point && !filled && !bbox.intersects_polyline(points) {
+                        log::debug!("  -> Skipped (no segment overlaps)");
+                        continue;
+                    }
 
-                for i in 1..points.len() {
-                    if index + 4 > self.vertex_buffer_capacity {
-                        log::warn!("Vertex buffer overflow, stopping");
-                        break;
+                    if kind == ObjectKind::Point {
+                        if !budget.allows_object(objects_drawn) {
+                            log::info!("Object budget of {} reached at zoom {}, stopping", objects_drawn, zoom);
+                            break 'batches;
+                        }
+                        objects_drawn += 1;
+                        let point = points[0];
+                        let vertex = Vertex {
+                            position: [point.lon as f32, point.lat as f32],
+                            color,
+                        };
+                        points_drawn.push((tile_to_pixel(&point, bbox, self.tile_size), batch_index as u32, vertex));
+                        continue;
                     }
 
-                    // Previous point
-                    vertices[index] = points[i - 1].lon as f32;
-                    vertices[index + 1] = points[i - 1].lat as f32;
+                    // Add line segments (pairs of points)
+                    if points.len() < 2 {
+                        log::debug!("  -> Skipped (not enough points: {})", points.len());
+                        continue;
+                    }
 
-                    // Current point
-                    vertices[index + 2] = points[i].lon as f32;
-                    vertices[index + 3] = points[i].lat as f32;
+                    let simplified;
+                    let points = match self.lod.select(pixel_extent(obj_bbox, bbox, self.tile_size)) {
+                        Lod::Skip => {
+                            log::debug!("  -> Skipped (below LOD threshold)");
+                            continue;
+                        }
+                        Lod::Simplified => {
+                            simplified = simplify(points);
+                            &simplified[..]
+                        }
+                        Lod::Full => points,
+                    };
+                    let snapped;
+                    let points = if self.crisp_lines {
+                        snapped = snap_to_pixel_centers(points, bbox, self.tile_size);
+                        &snapped[..]
+                    } else {
+                        points
+                    };
+
+                    if !budget.allows_object(objects_drawn) {
+                        log::info!("Object budget of {} reached at zoom {}, stopping", objects_drawn, zoom);
+                        break 'batches;
+                    }
+                    objects_drawn += 1;
+
+                    // Only the parts of the way inside the tile are drawn
+                    let pixels: Vec<_> = points.iter().map(|p| tile_to_pixel(p, bbox, self.tile_size)).collect();
+
+                    if filled {
+                        // Triangulated in pixels, where the ring is a plane polygon; the GPU clips the triangles
+                        let triangles = triangulate(&pixels);
+                        if vertex_count + pending_lines.len() + fills.len() + triangles.len() > vertex_limit {
+                            if buffer_limited {
+                                overflowed = true;
+                            } else {
+                                log::info!("Vertex budget of {} reached at zoom {}, stopping", vertex_limit, zoom);
+                            }
+                            break 'batches;
+                        }
+                        fills.extend(triangles.iter().map(|&i| Vertex {
+                            position: [points[i].lon as f32, points[i].lat as f32],
+                            color,
+                        }));
+                        log::debug!("  -> Added {} fill triangles", triangles.len() / 3);
+                        continue;
+                    }
 
-                    if vertex_count < 6 {  // Log first 3 lines only
-                        log::info!("    Line {}: ({}, {}) -> ({}, {})",
-                                  vertex_count / 2,
-                                  points[i - 1].lon, points[i - 1].lat,
-                                  points[i].lon, points[i].lat);
+                    let casing_width = if self.casing { width + 2.0 * CASING_WIDTH } else { width };
+                    let clip_rect = ClipRect::around_tile(self.tile_size, CLIP_MARGIN_PX + casing_width as f64 / 2.0);
+
+                    if self.line_widths {
+                        // Extrude in pixels, where the width is, and project the corners back
+                        let parts = clip_polyline(&pixels, &clip_rect);
+                        let triangles: Vec<_> = parts.iter().flat_map(|part| extrude_line(part, width as f64)).collect();
+                        let casing: Vec<_> = if self.casing {
+                            parts.iter().flat_map(|part| extrude_line(part, casing_width as f64)).collect()
+                        } else {
+                            Vec::new()
+                        };
+                        if vertex_count + pending_lines.len() + fills.len() + casing.len() + triangles.len() > vertex_limit {
+                            if buffer_limited {
+                                overflowed = true;
+                            } else {
+                                log::info!("Vertex budget of {} reached at zoom {}, stopping", vertex_limit, zoom);
+                            }
+                            break 'batches;
+                        }
+                        let vertex = |pixel: &Pixel, color: [u8; 4]| {
+                            let point = pixel_to_tile(pixel, bbox, self.tile_size);
+                            Vertex { position: [point.lon as f32, point.lat as f32], color }
+                        };
+                        for pixel in &casing {
+                            vertices[vertex_count] = vertex(pixel, casing_color(color));
+                            vertex_count += 1;
+                        }
+                        if self.casing {
+                            pending_lines.extend(triangles.iter().map(|pixel| vertex(pixel, color)));
+                        } else {
+                            for pixel in &triangles {
+                                vertices[vertex_count] = vertex(pixel, color);
+                                vertex_count += 1;
+                            }
+                        }
+                        log::debug!("  -> Added {} triangles {}px wide", triangles.len() / 3, width);
+                        continue;
                     }
 
-                    index += 4;
-                    vertex_count += 2;
+                    let mut segments = 0;
+                    for i in 1..points.len() {
+                        let Some((start, end)) = clip_segment(pixels[i - 1], pixels[i], &clip_rect) else {
+                            continue;
+                        };
+                        if vertex_count + fills.len() + 2 > vertex_limit {
+                            if buffer_limited {
+                                overflowed = true;
+                            } else {
+                                log::info!("Vertex budget of {} reached at zoom {}, stopping", vertex_limit, zoom);
+                            }
+                            break 'batches;
+                        }
+
+                        // Endpoints that weren't cut keep their exact position
+                        let previous = if start == pixels[i - 1] { points[i - 1] } else { pixel_to_tile(&start, bbox, self.tile_size) };
+                        let current = if end == pixels[i] { points[i] } else { pixel_to_tile(&end, bbox, self.tile_size) };
+
+                        vertices[vertex_count] = Vertex {
+                            position: [previous.lon as f32, previous.lat as f32],
+                            color,
+                        };
+                        vertices[vertex_count + 1] = Vertex {
+                            position: [current.lon as f32, current.lat as f32],
+                            color,
+                        };
+
+                        if vertex_count < 6 {  // Log first 3 lines only
+                            log::info!("    Line {}: ({}, {}) -> ({}, {})",
+                                      vertex_count / 2,
+                                      previous.lon, previous.lat,
+                                      current.lon, current.lat);
+                        }
+
+                        vertex_count += 2;
+                        segments += 1;
+                    }
+                    log::debug!("  -> Added {} of {} line segments", segments, points.len() - 1);
                 }
-                log::debug!("  -> Added {} line segments", points.len() - 1);
+                flush_lines(vertices, &mut vertex_count, &mut pending_lines);
             }
+            // Lines of the batch the budget stopped in
+            flush_lines(vertices, &mut vertex_count, &mut pending_lines);
+            // Buffer space for the fills was checked as they were added
+            let fill_count = fills.len();
+            vertices[vertex_count..vertex_count + fill_count].copy_from_slice(&fills);
+
+            let candidates: Vec<(Pixel, u32)> = points_drawn.iter().map(|&(pixel, importance, _)| (pixel, importance)).collect();
+            let kept = decimate_points(&candidates, self.point_decimation_px);
+            if kept.len() < points_drawn.len() {
+                log::debug!("Decimated {} of {} points", points_drawn.len() - kept.len(), points_drawn.len());
+            }
+            let mut point_count = 0;
+            for i in kept {
+                if vertex_count + fill_count + point_count >= vertex_limit {
+                    if buffer_limited {
+                        overflowed = true;
+                    } else {
+                        log::info!("Vertex budget of {} reached at zoom {}, dropping the remaining points", vertex_limit, zoom);
+                    }
+                    break;
+                }
+                vertices[vertex_count + fill_count + point_count] = points_drawn[i].2;
+                point_count += 1;
+            }
+            Ok(VertexCounts { lines: vertex_count, fills: fill_count, points: point_count, objects: objects_read, overflowed })
         }
-
-        Ok(vertex_count)
     }
 
-    fn create_uniform_buffer(&self, bbox: &BoundingBox) -> Result<(vk::Buffer, Allocation), VulkanError> {
+    /// Write the uniforms for drawing `bbox` into the uniform buffer
+    ///
+    /// The previous render has finished (its fence was waited on), so the
+    /// buffer is free to overwrite.
+    fn write_uniforms(&self, bbox: &BoundingBox) -> Result<(), VulkanError> {
         let ubo = UniformBufferObject {
             bbox: [
                 bbox.min.lon as f32,
@@ -356,26 +1559,19 @@ impl VulkanRenderer {
                 bbox.max.lat as f32,
             ],
             tile_size: self.tile_size as f32,
-            _padding: [0.0; 11],
-            projection: create_orthographic_projection(self.tile_size),
+            point_size: self.point_size,
+            _padding: [0.0; 2],
+            projection: create_orthographic_projection(self.tile_size, self.tile_origin),
         };
 
         log::info!("UBO: bbox=({}, {}, {}, {}), tileSize={}",
                    ubo.bbox[0], ubo.bbox[1], ubo.bbox[2], ubo.bbox[3], ubo.tile_size);
 
-        let mut allocator = self.memory_manager.lock().unwrap();
-        let (buffer, allocation) = create_buffer(
-            &self.context.device,
-            &mut allocator,
-            std::mem::size_of::<UniformBufferObject>() as vk::DeviceSize,
-            vk::BufferUsageFlags::UNIFORM_BUFFER,
-            MemoryLocation::CpuToGpu,
-            "uniform_buffer",
-        )?;
+        let (_, allocation) = self.uniform_buffer.as_ref().expect("uniform buffer lives as long as the renderer");
+        let data_ptr = mapped_ptr(allocation, "uniform_buffer")?;
 
         // Copy data using byte-wise copy for safety
         unsafe {
-            let data_ptr = allocation.mapped_ptr().unwrap().as_ptr() as *mut u8;
             let ubo_bytes = std::slice::from_raw_parts(
                 &ubo as *const UniformBufferObject as *const u8,
                 std::mem::size_of::<UniformBufferObject>(),
@@ -383,42 +1579,35 @@ impl VulkanRenderer {
             std::ptr::copy_nonoverlapping(ubo_bytes.as_ptr(), data_ptr, ubo_bytes.len());
         }
 
-        Ok((buffer, allocation))
+        Ok(())
     }
 
-    fn create_descriptor_set(&self, uniform_buffer: vk::Buffer) -> Result<vk::DescriptorSet, VulkanError> {
-        let set_layouts = [self.descriptor_set_layout];
-        let alloc_info = vk::DescriptorSetAllocateInfo::default()
-            .descriptor_pool(self.descriptor_pool)
-            .set_layouts(&set_layouts);
-
-        let descriptor_sets = unsafe {
-            self.context.device.allocate_descriptor_sets(&alloc_info)?
-        };
-        let descriptor_set = descriptor_sets[0];
-
-        let buffer_info = vk::DescriptorBufferInfo::default()
-            .buffer(uniform_buffer)
-            .offset(0)
-            .range(std::mem::size_of::<UniformBufferObject>() as vk::DeviceSize);
-
-        let buffer_infos = [buffer_info];
-        let descriptor_write = vk::WriteDescriptorSet::default()
-            .dst_set(descriptor_set)
-            .dst_binding(0)
-            .dst_array_element(0)
-            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-            .buffer_info(&buffer_infos);
-
-        let descriptor_writes = [descriptor_write];
-        unsafe {
-            self.context.device.update_descriptor_sets(&descriptor_writes, &[]);
+    /// Record, submit and wait for the draw commands, see `submit_and_wait`
+    ///
+    /// A lost device fails with `VulkanError::DeviceLost` and leaves the
+    /// renderer broken.
+    fn record_and_submit_commands(
+        &mut self,
+        line_vertices: usize,
+        fill_vertices: usize,
+        point_vertices: usize,
+    ) -> Result<(), VulkanError> {
+        match self.submit_and_wait(line_vertices, fill_vertices, point_vertices) {
+            Err(VulkanError::VkError(vk::Result::ERROR_DEVICE_LOST)) => {
+                log::error!("Vulkan device lost, the renderer needs recreating");
+                self.broken = true;
+                Err(VulkanError::DeviceLost)
+            }
+            result => result,
         }
-
-        Ok(descriptor_set)
     }
 
-    fn record_and_submit_commands(&mut self, vertex_count: usize, descriptor_set: vk::DescriptorSet) -> Result<(), VulkanError> {
+    fn submit_and_wait(
+        &mut self,
+        line_vertices: usize,
+        fill_vertices: usize,
+        point_vertices: usize,
+    ) -> Result<(), VulkanError> {
         let render_target = self.render_target.as_ref().unwrap();
 
         reset_fence(&self.context.device, self.fence)?;
@@ -432,34 +1621,59 @@ impl VulkanRenderer {
 
         begin_command_buffer(&self.context.device, self.command_buffer)?;
 
-        // Begin render pass (it will transition from UNDEFINED to COLOR_ATTACHMENT_OPTIMAL automatically)
+        // Copy the staged vertices into the device-local vertex buffer
+        if let Some((staging_buffer, _)) = &self.vertex_staging {
+            let region = vk::BufferCopy::default()
+                .size(((line_vertices + fill_vertices + point_vertices) * std::mem::size_of::<Vertex>()) as vk::DeviceSize);
+            let barrier = vk::BufferMemoryBarrier::default()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::VERTEX_ATTRIBUTE_READ)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .buffer(self.vertex_buffer.unwrap())
+                .size(vk::WHOLE_SIZE);
+            unsafe {
+                self.context.device.cmd_copy_buffer(
+                    self.command_buffer,
+                    *staging_buffer,
+                    self.vertex_buffer.unwrap(),
+                    &[region],
+                );
+                self.context.device.cmd_pipeline_barrier(
+                    self.command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::VERTEX_INPUT,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[barrier],
+                    &[],
+                );
+            }
+        }
+
         let clear_values = [vk::ClearValue {
             color: vk::ClearColorValue {
-                float32: [1.0, 1.0, 1.0, 1.0], // White background
+                float32: self.color_format.clear_color(self.background_color()),
             },
         }];
-
-        let render_pass_info = vk::RenderPassBeginInfo::default()
-            .render_pass(self.render_pass)
-            .framebuffer(render_target.framebuffer)
-            .render_area(vk::Rect2D {
-                offset: vk::Offset2D { x: 0, y: 0 },
-                extent: vk::Extent2D { width: self.tile_size, height: self.tile_size },
-            })
-            .clear_values(&clear_values);
+        let render_area = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: vk::Extent2D { width: self.tile_size, height: self.tile_size },
+        };
 
         unsafe {
-            self.context.device.cmd_begin_render_pass(
-                self.command_buffer,
-                &render_pass_info,
-                vk::SubpassContents::INLINE,
-            );
-
-            self.context.device.cmd_bind_pipeline(
-                self.command_buffer,
-                vk::PipelineBindPoint::GRAPHICS,
-                self.pipeline,
-            );
+            // Dynamic state of all pipelines, see `create_graphics_pipeline`;
+            // it and the bindings last across both render passes
+            let viewport = vk::Viewport {
+                x: 0.0,
+                y: 0.0,
+                width: self.tile_size as f32,
+                height: self.tile_size as f32,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            };
+            self.context.device.cmd_set_viewport(self.command_buffer, 0, &[viewport]);
+            self.context.device.cmd_set_scissor(self.command_buffer, 0, &[render_area]);
 
             self.context.device.cmd_bind_vertex_buffers(
                 self.command_buffer,
@@ -468,21 +1682,77 @@ impl VulkanRenderer {
                 &[0],
             );
 
+            // All layouts share the descriptor set layout, so the set stays bound
             self.context.device.cmd_bind_descriptor_sets(
                 self.command_buffer,
                 vk::PipelineBindPoint::GRAPHICS,
                 self.pipeline_layout,
                 0,
-                &[descriptor_set],
+                &[self.descriptor_set],
                 &[],
             );
 
-            self.context.device.cmd_draw(self.command_buffer, vertex_count as u32, 1, 0, 0);
+            // Fills under everything: in their own pass, which clears and
+            // resolves into the color image the line pass loads, or first in the line pass
+            let fill_pipeline = match (&self.fill, &render_target.fill_target) {
+                (Some(fill), Some((fill_framebuffer, ..))) => {
+                    let fill_pass_info = vk::RenderPassBeginInfo::default()
+                        .render_pass(fill.render_pass.unwrap())
+                        .framebuffer(*fill_framebuffer)
+                        .render_area(render_area)
+                        .clear_values(&clear_values);
+                    self.context.device.cmd_begin_render_pass(self.command_buffer, &fill_pass_info, vk::SubpassContents::INLINE);
+                    if fill_vertices > 0 {
+                        self.context.device.cmd_bind_pipeline(self.command_buffer, vk::PipelineBindPoint::GRAPHICS, fill.pipeline);
+                        self.context.device.cmd_draw(self.command_buffer, fill_vertices as u32, 1, line_vertices as u32, 0);
+                    }
+                    self.context.device.cmd_end_render_pass(self.command_buffer);
+                    None
+                }
+                (Some(fill), None) if fill_vertices > 0 => Some(fill.pipeline),
+                _ => None,
+            };
+
+            // Begin render pass (it will transition from UNDEFINED to COLOR_ATTACHMENT_OPTIMAL
+            // automatically, or load the fills)
+            let render_pass_info = vk::RenderPassBeginInfo::default()
+                .render_pass(self.render_pass)
+                .framebuffer(render_target.framebuffer)
+                .render_area(render_area)
+                .clear_values(&clear_values);
+            self.context.device.cmd_begin_render_pass(
+                self.command_buffer,
+                &render_pass_info,
+                vk::SubpassContents::INLINE,
+            );
+
+            if let Some(fill_pipeline) = fill_pipeline {
+                self.context.device.cmd_bind_pipeline(self.command_buffer, vk::PipelineBindPoint::GRAPHICS, fill_pipeline);
+                self.context.device.cmd_draw(self.command_buffer, fill_vertices as u32, 1, line_vertices as u32, 0);
+            }
+
+            self.context.device.cmd_bind_pipeline(
+                self.command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            self.context.device.cmd_draw(self.command_buffer, line_vertices as u32, 1, 0, 0);
+
+            // Points on top
+            if point_vertices > 0 {
+                self.context.device.cmd_bind_pipeline(
+                    self.command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.point_pipeline,
+                );
+                self.context.device.cmd_draw(self.command_buffer, point_vertices as u32, 1, (line_vertices + fill_vertices) as u32, 0);
+            }
 
             self.context.device.cmd_end_render_pass(self.command_buffer);
         }
 
         // Copy image to staging buffer (already in TRANSFER_SRC_OPTIMAL layout from render pass)
+        // With MSAA this is the resolved single-sample image
         copy_image_to_buffer(
             &self.context.device,
             self.command_buffer,
@@ -502,25 +1772,96 @@ impl VulkanRenderer {
             self.fence,
         )?;
 
-        // Wait
-        wait_for_fence(&self.context.device, self.fence, u64::MAX)?;
+        // Wait, but not forever on a hung GPU
+        match self.wait_for_render() {
+            Ok(()) => Ok(()),
+            Err(vk::Result::TIMEOUT) => {
+                // Another wait happens when the broken renderer is dropped, bounded too
+                log::error!("GPU didn't finish rendering within {:?}, the renderer needs recreating", self.render_timeout);
+                self.broken = true;
+                Err(VulkanError::RenderTimeout(self.render_timeout))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
 
-        Ok(())
+    /// Wait up to `render_timeout` for the last submitted render
+    fn wait_for_render(&self) -> Result<(), vk::Result> {
+        let timeout_ns = self.render_timeout.as_nanos().min(u64::MAX as u128) as u64;
+        wait_for_fence(&self.context.device, self.fence, timeout_ns)
     }
 
-    fn read_framebuffer(&self) -> Result<RgbaImage, VulkanError> {
-        let render_target = self.render_target.as_ref().unwrap();
+    /// Free the render target, if any
+    ///
+    /// Renders wait for their commands, so the target is no longer in use.
+    fn destroy_render_target(&mut self) {
+        let Some(render_target) = self.render_target.take() else {
+            return;
+        };
+        unsafe {
+            self.context.device.destroy_framebuffer(render_target.framebuffer, None);
+            self.context.device.destroy_image_view(render_target.color_image_view, None);
+            self.context.device.destroy_image(render_target.color_image, None);
+            self.context.device.destroy_buffer(render_target.staging_buffer, None);
 
-        let staging_ptr = render_target.staging_buffer_allocation.mapped_ptr().unwrap().as_ptr();
+            let mut allocator = self.memory_manager.lock().unwrap();
+            if let Some((image, view, allocation)) = render_target.msaa_image {
+                self.context.device.destroy_image_view(view, None);
+                self.context.device.destroy_image(image, None);
+                allocator.free(allocation).ok();
+            }
+            if let Some((framebuffer, image, view, allocation)) = render_target.fill_target {
+                self.context.device.destroy_framebuffer(framebuffer, None);
+                self.context.device.destroy_image_view(view, None);
+                self.context.device.destroy_image(image, None);
+                allocator.free(allocation).ok();
+            }
+            allocator.free(render_target.color_image_allocation).ok();
+            allocator.free(render_target.staging_buffer_allocation).ok();
+        }
+    }
 
-        let image_data = unsafe {
-            std::slice::from_raw_parts(
-                staging_ptr as *const u8,
-                (self.tile_size * self.tile_size * 4) as usize,
-            )
+    /// Replace the vertex buffer with one of twice the capacity, at most
+    /// `max_vertex_buffer_capacity`
+    ///
+    /// The new buffer is allocated before the old one is freed, so if that
+    /// fails the renderer keeps the old one.
+    fn grow_vertex_buffer(&mut self) -> Result<(), VulkanError> {
+        let capacity = (self.vertex_buffer_capacity * 2).min(self.max_vertex_buffer_capacity);
+        let ((buffer, allocation), staging) = {
+            let mut allocator = self.memory_manager.lock().unwrap();
+            create_vertex_buffers(&self.context.device, &mut allocator, capacity)?
         };
+        log::info!("Growing the vertex buffer from {} to {} vertices", self.vertex_buffer_capacity, capacity);
+        self.destroy_vertex_buffers();
+        self.vertex_buffer = Some(buffer);
+        self.vertex_buffer_allocation = Some(allocation);
+        self.vertex_staging = staging;
+        self.vertex_buffer_capacity = capacity;
+        Ok(())
+    }
+
+    /// Free the vertex buffer and its staging buffer, if any
+    ///
+    /// Like `destroy_render_target`, only once no render uses them.
+    fn destroy_vertex_buffers(&mut self) {
+        let mut allocator = self.memory_manager.lock().unwrap();
+        unsafe {
+            if let Some(vertex_buffer) = self.vertex_buffer.take() {
+                self.context.device.destroy_buffer(vertex_buffer, None);
+            }
+            if let Some(allocation) = self.vertex_buffer_allocation.take() {
+                allocator.free(allocation).ok();
+            }
+            if let Some((buffer, allocation)) = self.vertex_staging.take() {
+                self.context.device.destroy_buffer(buffer, None);
+                allocator.free(allocation).ok();
+            }
+        }
+    }
 
-        let image = RgbaImage::from_raw(self.tile_size, self.tile_size, image_data.to_vec())
+    fn read_framebuffer(&self) -> Result<RgbaImage, VulkanError> {
+        let image = RgbaImage::from_raw(self.tile_size, self.tile_size, self.staged_pixels()?.to_vec())
             .ok_or_else(|| VulkanError::IoError(std::io::Error::new(
                 std::io::ErrorKind::Other,
                 "Failed to create image from buffer",
@@ -528,67 +1869,411 @@ impl VulkanRenderer {
 
         Ok(image)
     }
+
+    /// Pixels of the last render in the mapped staging buffer, row by row
+    fn staged_pixels(&self) -> Result<&[u8], VulkanError> {
+        let render_target = self.render_target.as_ref().unwrap();
+
+        let staging_ptr = mapped_ptr(&render_target.staging_buffer_allocation, "staging_buffer")?;
+
+        Ok(unsafe {
+            std::slice::from_raw_parts(
+                staging_ptr as *const u8,
+                (self.tile_size * self.tile_size * 4) as usize,
+            )
+        })
+    }
 }
 
 impl Drop for VulkanRenderer {
     fn drop(&mut self) {
+        // A render that timed out may still run: destroying its resources
+        // under it is undefined and waiting for the device may never end,
+        // so they are leaked unless it finished (or the device was lost) by now
+        if self.broken && self.wait_for_render() == Err(vk::Result::TIMEOUT) {
+            log::error!("GPU still busy after timing out, leaking the renderer's Vulkan resources");
+            return;
+        }
         unsafe {
             self.context.device.device_wait_idle().ok();
-
-            if let Some(render_target) = self.render_target.take() {
-                self.context.device.destroy_framebuffer(render_target.framebuffer, None);
-                self.context.device.destroy_image_view(render_target.color_image_view, None);
-                self.context.device.destroy_image(render_target.color_image, None);
-                self.context.device.destroy_buffer(render_target.staging_buffer, None);
-
-                let mut allocator = self.memory_manager.lock().unwrap();
-                allocator.free(render_target.color_image_allocation).ok();
-                allocator.free(render_target.staging_buffer_allocation).ok();
-            }
-
-            if let Some(vertex_buffer) = self.vertex_buffer.take() {
-                self.context.device.destroy_buffer(vertex_buffer, None);
-                if let Some(allocation) = self.vertex_buffer_allocation.take() {
-                    let mut allocator = self.memory_manager.lock().unwrap();
-                    allocator.free(allocation).ok();
-                }
+        }
+        self.destroy_render_target();
+        self.destroy_vertex_buffers();
+        unsafe {
+            // The descriptor set goes with its pool
+            if let Some((buffer, allocation)) = self.uniform_buffer.take() {
+                self.context.device.destroy_buffer(buffer, None);
+                self.memory_manager.lock().unwrap().free(allocation).ok();
             }
 
             self.context.device.destroy_fence(self.fence, None);
             self.context.device.destroy_descriptor_pool(self.descriptor_pool, None);
             self.context.device.destroy_pipeline(self.pipeline, None);
             self.context.device.destroy_pipeline_layout(self.pipeline_layout, None);
+            self.context.device.destroy_pipeline(self.point_pipeline, None);
+            self.context.device.destroy_pipeline_layout(self.point_pipeline_layout, None);
+            if let Some(fill) = self.fill.take() {
+                self.context.device.destroy_pipeline(fill.pipeline, None);
+                self.context.device.destroy_pipeline_layout(fill.layout, None);
+                if let Some(render_pass) = fill.render_pass {
+                    self.context.device.destroy_render_pass(render_pass, None);
+                }
+            }
             self.context.device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
             self.context.device.destroy_render_pass(self.render_pass, None);
+
+            ManuallyDrop::drop(&mut self.memory_manager);
+            ManuallyDrop::drop(&mut self.context);
+        }
+    }
+}
+
+/// Vertices written by `build_vertex_buffer`
+struct VertexCounts {
+    lines: usize,
+    fills: usize,
+    points: usize,
+    /// Objects of the batches read, whether they were drawn or not
+    objects: usize,
+    /// The buffer filled up before all vertices were written
+    overflowed: bool,
+}
+
+/// Objects drawn with one line color, or by tag
+struct LineBatch<'a> {
+    objects: BatchObjects<'a>,
+    color: BatchColor,
+    /// Line width in pixels with `RendererOptions::line_widths`, by tag if `None`
+    width: Option<f32>,
+    /// Longitude shift applied to every object (±360° for wrapped objects)
+    lon_offset: f64,
+}
+
+/// An object of a `LineBatch` with its color and width resolved
+struct DrawObject<'a> {
+    bbox: &'a BoundingBox,
+    points: &'a [Point],
+    kind: ObjectKind,
+    color: [u8; 4],
+    width: f32,
+    /// Draw order within the batch, see `style::layer`
+    layer: i16,
+}
+
+/// Line color of a `LineBatch`
+#[derive(Debug, Clone, Copy)]
+enum BatchColor {
+    Fixed([u8; 4]),
+    /// Objects without a style class, see `RendererOptions::line_colors`
+    Unclassed,
+}
+
+/// Where the objects of a `LineBatch` come from
+enum BatchObjects<'a> {
+    /// Offsets into a memory-mapped data file (zero-copy)
+    Mapped {
+        offsets: &'a [MapObjectOffset],
+        mmap_data: &'a MappedData,
+    },
+    /// Objects fetched from a `TileSource`
+    Owned(&'a [MapObject]),
+}
+
+impl<'a> BatchObjects<'a> {
+    fn mapped(offsets: &'a [MapObjectOffset], mmap_data: &'a MappedData) -> Self {
+        BatchObjects::Mapped { offsets, mmap_data }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            BatchObjects::Mapped { offsets, .. } => offsets.len(),
+            BatchObjects::Owned(objects) => objects.len(),
+        }
+    }
+}
+
+/// Tile whose index entries are used to render `tile` from an index up to `max_zoom`
+///
+/// For zoom levels above `max_zoom` (`TileIndex::max_zoom`), use the
+/// ancestor's data at `max_zoom`. The bounding box filtering will select
+/// only relevant objects.
+pub fn lookup_tile(tile: &Tile, max_zoom: u32) -> Tile {
+    if tile.z > max_zoom {
+        let ancestor = tile.get_ancestor(max_zoom)
+            .expect("get_ancestor should always succeed for lower zoom");
+        log::info!("Tile {:?} is above max indexed zoom, using ancestor {:?}", tile, ancestor);
+        ancestor
+    } else {
+        *tile
+    }
+}
+
+/// `lookup_tile`, or its nearest ancestor with index entries if it has none
+///
+/// The loader indexes every object at each zoom up to `max_zoom`, so this
+/// only walks further up for indexes with gaps, e.g. built by hand at lower
+/// zooms. If no ancestor down to zoom 0 is indexed, `lookup_tile` is returned.
+pub fn indexed_lookup_tile(tile: &Tile, tile_index: &TileIndex) -> Tile {
+    let lookup = lookup_tile(tile, tile_index.max_zoom);
+    let indexed = (0..=lookup.z)
+        .rev()
+        .filter_map(|z| lookup.get_ancestor(z))
+        .find(|ancestor| tile_index.get(ancestor).is_some());
+    match indexed {
+        Some(ancestor) if ancestor != lookup => {
+            log::debug!("Tile {:?} isn't indexed, using ancestor {:?}", lookup, ancestor);
+            ancestor
         }
+        _ => lookup,
     }
 }
 
-fn create_orthographic_projection(tile_size: u32) -> [[f32; 4]; 4] {
+/// Map object offsets for `lookup_tile`, aggregated from `detail` zoom levels deeper
+fn lookup_offsets<'a>(lookup_tile: &Tile, detail: u32, tile_index: &'a TileIndex) -> Cow<'a, [MapObjectOffset]> {
+    let detail_z = lookup_tile.z.saturating_add(detail).min(tile_index.max_zoom).max(lookup_tile.z);
+    if detail_z > lookup_tile.z {
+        log::info!("Aggregating descendants of {:?} at zoom {}", lookup_tile, detail_z);
+        Cow::Owned(tile_index.get_descendants(lookup_tile, detail_z))
+    } else {
+        match tile_index.get(lookup_tile) {
+            Some(offsets) => Cow::Borrowed(offsets.as_slice()),
+            None => Cow::Owned(Vec::new()),
+        }
+    }
+}
+
+/// Append `pending` to the `count` vertices written so far and clear it
+///
+/// The caller has checked that they fit, see `build_vertex_buffer`.
+fn flush_lines(vertices: &mut [Vertex], count: &mut usize, pending: &mut Vec<Vertex>) {
+    vertices[*count..*count + pending.len()].copy_from_slice(pending);
+    *count += pending.len();
+    pending.clear();
+}
+
+fn create_orthographic_projection(tile_size: u32, origin: TileOrigin) -> [[f32; 4]; 4] {
     // Orthographic projection matching Go implementation
-    // Maps 0-{tile_size} pixel space to NDC (-1 to 1)
+    // Maps 0-{tile_size} pixel space (y down from the north edge) to NDC (-1 to 1)
     // NOTE: GLSL uses column-major, so we need to transpose
     let size = tile_size as f32;
 
+    // Vulkan NDC y = -1 is the top row of the image
+    let (y_scale, y_offset) = match origin {
+        TileOrigin::TopLeft => (2.0 / size, -1.0),
+        TileOrigin::BottomLeft => (-2.0 / size, 1.0),
+    };
+
     // TRANSPOSED for column-major GLSL
     [
         [2.0 / size, 0.0, 0.0, -1.0],      // Column 0
-        [0.0, -2.0 / size, 0.0, 1.0],       // Column 1
+        [0.0, y_scale, 0.0, y_offset],     // Column 1
         [0.0, 0.0, 1.0, 0.0],                // Column 2
         [0.0, 0.0, 0.0, 1.0],                // Column 3
     ]
 }
 
+/// A buffer and the memory bound to it
+type BufferAllocation = (vk::Buffer, Allocation);
+
+/// Create a vertex buffer of `capacity` vertices, and the staging buffer
+/// vertices are written to if it isn't host-visible, see `VertexUpload`
+fn create_vertex_buffers(
+    device: &ash::Device,
+    allocator: &mut Allocator,
+    capacity: usize,
+) -> Result<(BufferAllocation, Option<BufferAllocation>), VulkanError> {
+    let size = (capacity * std::mem::size_of::<Vertex>()) as vk::DeviceSize;
+    let (vertex_buffer, vertex_buffer_allocation) = create_buffer(
+        device,
+        allocator,
+        size,
+        vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+        MemoryLocation::CpuToGpu,
+        "vertex_buffer",
+    )?;
+
+    let upload = VertexUpload::for_vertex_buffer(vertex_buffer_allocation.mapped_ptr().is_some());
+    let staging = match upload {
+        VertexUpload::Direct => None,
+        VertexUpload::Staged => {
+            // GpuToCpu memory is host-visible and cached, which suits the CPU writes
+            let (buffer, allocation) = create_buffer(
+                device,
+                allocator,
+                size,
+                vk::BufferUsageFlags::TRANSFER_SRC,
+                MemoryLocation::GpuToCpu,
+                "vertex_staging_buffer",
+            )?;
+            if allocation.mapped_ptr().is_none() {
+                unsafe {
+                    device.destroy_buffer(buffer, None);
+                    device.destroy_buffer(vertex_buffer, None);
+                }
+                allocator.free(allocation).ok();
+                allocator.free(vertex_buffer_allocation).ok();
+                return Err(VulkanError::NotMappable("vertex_staging_buffer"));
+            }
+            Some((buffer, allocation))
+        }
+    };
+    Ok(((vertex_buffer, vertex_buffer_allocation), staging))
+}
+
+/// Pool for the renderer's single uniform buffer descriptor set
 fn create_descriptor_pool(device: &ash::Device) -> Result<vk::DescriptorPool, vk::Result> {
     let pool_size = vk::DescriptorPoolSize::default()
         .ty(vk::DescriptorType::UNIFORM_BUFFER)
-        .descriptor_count(10);
+        .descriptor_count(1);
 
     let pool_sizes = [pool_size];
     let pool_info = vk::DescriptorPoolCreateInfo::default()
         .pool_sizes(&pool_sizes)
-        .max_sets(10)
-        .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET);
+        .max_sets(1);
 
     unsafe { device.create_descriptor_pool(&pool_info, None) }
 }
+
+/// Allocate a descriptor set binding `uniform_buffer` from `pool`
+fn create_descriptor_set(
+    device: &ash::Device,
+    pool: vk::DescriptorPool,
+    layout: vk::DescriptorSetLayout,
+    uniform_buffer: vk::Buffer,
+) -> Result<vk::DescriptorSet, vk::Result> {
+    let set_layouts = [layout];
+    let alloc_info = vk::DescriptorSetAllocateInfo::default()
+        .descriptor_pool(pool)
+        .set_layouts(&set_layouts);
+
+    let descriptor_set = unsafe { device.allocate_descriptor_sets(&alloc_info)?[0] };
+
+    let buffer_info = vk::DescriptorBufferInfo::default()
+        .buffer(uniform_buffer)
+        .offset(0)
+        .range(std::mem::size_of::<UniformBufferObject>() as vk::DeviceSize);
+
+    let buffer_infos = [buffer_info];
+    let descriptor_write = vk::WriteDescriptorSet::default()
+        .dst_set(descriptor_set)
+        .dst_binding(0)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+        .buffer_info(&buffer_infos);
+
+    unsafe { device.update_descriptor_sets(&[descriptor_write], &[]) };
+
+    Ok(descriptor_set)
+}
+
+/// Convert premultiplied pixels (blended onto a transparent background) to straight alpha
+///
+/// With `ColorFormat::Srgb` the colors were premultiplied in linear light,
+/// so they are divided there too.
+fn unpremultiply(image: &mut RgbaImage, color_format: ColorFormat) {
+    for pixel in image.pixels_mut() {
+        let [r, g, b, a] = pixel.0;
+        if a == 0 {
+            pixel.0 = NODATA_COLOR;
+        } else if a < 255 {
+            let channel = |c: u8| match color_format {
+                ColorFormat::Unorm => ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8,
+                ColorFormat::Srgb => linear_to_srgb(srgb_to_linear(c) * 255.0 / a as f32),
+            };
+            pixel.0 = [channel(r), channel(g), channel(b), a];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unpremultiply() {
+        let mut image = RgbaImage::from_fn(4, 1, |x, _| {
+            image::Rgba([[0, 0, 0, 255], [100, 50, 0, 128], [3, 2, 1, 0], [200, 0, 0, 100]][x as usize])
+        });
+        unpremultiply(&mut image, ColorFormat::Unorm);
+        let pixels: Vec<[u8; 4]> = image.pixels().map(|p| p.0).collect();
+        assert_eq!(pixels, vec![[0, 0, 0, 255], [199, 100, 0, 128], NODATA_COLOR, [255, 0, 0, 100]]);
+    }
+
+    #[test]
+    fn test_unpremultiply_srgb() {
+        // A fifth of 0.5 linear (188), stored as sRGB and off by one after rounding
+        assert_eq!(linear_to_srgb(0.1), 89);
+        let mut image = RgbaImage::from_fn(3, 1, |x, _| {
+            image::Rgba([[100, 50, 0, 255], [89, 0, 0, 51], [3, 2, 1, 0]][x as usize])
+        });
+        unpremultiply(&mut image, ColorFormat::Srgb);
+        let pixels: Vec<[u8; 4]> = image.pixels().map(|p| p.0).collect();
+        assert_eq!(pixels, vec![[100, 50, 0, 255], [187, 0, 0, 51], NODATA_COLOR]);
+    }
+
+    #[test]
+    fn test_buffer_pixels() {
+        assert_eq!(buffer_pixels(256, 0.0), 0);
+        assert_eq!(buffer_pixels(256, 0.25), 64);
+        assert_eq!(buffer_pixels(512, 0.125), 64);
+        assert_eq!(buffer_pixels(256, 0.1), 26);
+        // Clamped
+        assert_eq!(buffer_pixels(256, -1.0), 0);
+        assert_eq!(buffer_pixels(256, 4.0), 256);
+    }
+
+    #[test]
+    fn test_config_options() {
+        let config = RendererConfig {
+            msaa_samples: 4,
+            background: Some([0, 0, 32, 255]),
+            line_widths: true,
+            ..RendererConfig::default()
+        };
+        assert_eq!(config.tile_size, TILE_SIZE);
+        let options = config.options();
+        assert_eq!((options.msaa_samples, options.background, options.line_widths), (4, Some([0, 0, 32, 255]), true));
+        // The rest keeps the defaults
+        assert_eq!(options.buffer_fraction, 0.0);
+        assert!(!options.overlay && options.style.is_none());
+    }
+
+    #[test]
+    fn test_orthographic_projection_origin() {
+        // The shader computes `vec4(px, py, 0, 1) * projection`
+        let project = |m: [[f32; 4]; 4], px: f32, py: f32| {
+            let v = [px, py, 0.0, 1.0];
+            let dot = |c: [f32; 4]| c.iter().zip(v).map(|(a, b)| a * b).sum::<f32>();
+            (dot(m[0]), dot(m[1]))
+        };
+
+        let top_left = create_orthographic_projection(256, TileOrigin::TopLeft);
+        assert_eq!(project(top_left, 0.0, 0.0), (-1.0, -1.0));
+        assert_eq!(project(top_left, 256.0, 256.0), (1.0, 1.0));
+
+        // North edge at the bottom of the image
+        let bottom_left = create_orthographic_projection(256, TileOrigin::BottomLeft);
+        assert_eq!(project(bottom_left, 0.0, 0.0), (-1.0, 1.0));
+        assert_eq!(project(bottom_left, 256.0, 256.0), (1.0, -1.0));
+    }
+
+    #[test]
+    fn test_indexed_lookup_tile() {
+        let tile = Tile::new(34603, 21156, 16);
+        let z15 = tile.get_ancestor(15).unwrap();
+        let z12 = tile.get_ancestor(12).unwrap();
+
+        // Over-zoomed tiles use their ancestor at the index's max zoom
+        let mut tile_index = TileIndex::new();
+        tile_index.insert(z12, 8);
+        tile_index.insert(z15, 8);
+        assert_eq!(indexed_lookup_tile(&tile, &tile_index), z15);
+        assert_eq!(indexed_lookup_tile(&z15, &tile_index), z15);
+
+        // Zooms missing from the index are skipped up to the nearest indexed one
+        let mut sparse = TileIndex::new();
+        sparse.insert(z12, 8);
+        assert_eq!(indexed_lookup_tile(&tile, &sparse), z12);
+        assert_eq!(indexed_lookup_tile(&Tile::new(0, 0, 1), &sparse), Tile::new(0, 0, 1));
+    }
+}
@@ -0,0 +1,126 @@
+use crate::data::types::Pixel;
+
+/// Triangles covering the polygon `ring`, by ear clipping
+///
+/// Returns indices into `ring` for `TRIANGLE_LIST` topology, three per
+/// triangle. A closing point equal to the first is ignored, and either
+/// winding works. Collinear vertices are dropped without a triangle. Rings
+/// that intersect themselves (broken OSM areas) run out of ears; the
+/// triangles found until then are returned, so they're partly filled.
+pub fn triangulate(ring: &[Pixel]) -> Vec<usize> {
+    let mut n = ring.len();
+    if n > 1 && ring[0] == ring[n - 1] {
+        n -= 1;
+    }
+    if n < 3 {
+        return Vec::new();
+    }
+
+    // Walk the ring counterclockwise (positive area), so ears turn left
+    let mut remaining: Vec<usize> = (0..n).collect();
+    if signed_area(ring, &remaining) < 0.0 {
+        remaining.reverse();
+    }
+
+    let mut triangles = Vec::with_capacity((n - 2) * 3);
+    let mut i = 0;
+    // Vertices checked since the last one was removed
+    let mut checked = 0;
+    while remaining.len() > 3 && checked < remaining.len() {
+        let len = remaining.len();
+        let (prev, cur, next) = (remaining[(i + len - 1) % len], remaining[i % len], remaining[(i + 1) % len]);
+        let turn = cross(&ring[prev], &ring[cur], &ring[next]);
+        if turn == 0.0 {
+            remaining.remove(i % len);
+            checked = 0;
+            continue;
+        }
+        let is_ear = turn > 0.0
+            && !remaining
+                .iter()
+                .filter(|&&j| j != prev && j != cur && j != next)
+                .any(|&j| in_triangle(&ring[j], &ring[prev], &ring[cur], &ring[next]));
+        if is_ear {
+            triangles.extend([prev, cur, next]);
+            remaining.remove(i % len);
+            checked = 0;
+        } else {
+            i += 1;
+            checked += 1;
+        }
+    }
+    if let [a, b, c] = remaining[..] {
+        if cross(&ring[a], &ring[b], &ring[c]) > 0.0 {
+            triangles.extend([a, b, c]);
+        }
+    }
+    triangles
+}
+
+/// Twice the area enclosed by `ring[indices]`, positive if counterclockwise
+fn signed_area(ring: &[Pixel], indices: &[usize]) -> f64 {
+    let len = indices.len();
+    (0..len)
+        .map(|k| {
+            let (a, b) = (&ring[indices[k]], &ring[indices[(k + 1) % len]]);
+            a.x * b.y - b.x * a.y
+        })
+        .sum()
+}
+
+/// Positive if `a`, `b`, `c` turn left (counterclockwise)
+fn cross(a: &Pixel, b: &Pixel, c: &Pixel) -> f64 {
+    (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x)
+}
+
+/// Whether `p` is inside or on the counterclockwise triangle `a`, `b`, `c`
+fn in_triangle(p: &Pixel, a: &Pixel, b: &Pixel, c: &Pixel) -> bool {
+    cross(a, b, p) >= 0.0 && cross(b, c, p) >= 0.0 && cross(c, a, p) >= 0.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ring(points: &[(f64, f64)]) -> Vec<Pixel> {
+        points.iter().map(|&(x, y)| Pixel { x, y }).collect()
+    }
+
+    /// Total area of the triangles
+    fn area(ring: &[Pixel], triangles: &[usize]) -> f64 {
+        triangles.chunks(3).map(|t| signed_area(ring, t).abs() / 2.0).sum()
+    }
+
+    #[test]
+    fn test_triangulate_square() {
+        // Closed way, clockwise in pixel space
+        let square = ring(&[(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0), (0.0, 0.0)]);
+        let triangles = triangulate(&square);
+        assert_eq!(triangles.len(), 6);
+        assert!(triangles.iter().all(|&i| i < 4), "the closing point is skipped");
+        assert_eq!(area(&square, &triangles), 100.0);
+
+        let reversed: Vec<Pixel> = square.iter().rev().copied().collect();
+        assert_eq!(area(&reversed, &triangulate(&reversed)), 100.0);
+    }
+
+    #[test]
+    fn test_triangulate_concave() {
+        // L-shape: the reflex corner at (5, 5) must not be cut across
+        let l_shape = ring(&[(0.0, 0.0), (10.0, 0.0), (10.0, 5.0), (5.0, 5.0), (5.0, 10.0), (0.0, 10.0)]);
+        let triangles = triangulate(&l_shape);
+        assert_eq!(triangles.len(), 12);
+        assert_eq!(area(&l_shape, &triangles), 75.0);
+    }
+
+    #[test]
+    fn test_triangulate_degenerate() {
+        assert!(triangulate(&ring(&[(0.0, 0.0), (1.0, 1.0), (0.0, 0.0)])).is_empty());
+        assert!(triangulate(&ring(&[(0.0, 0.0), (1.0, 1.0), (2.0, 2.0), (0.0, 0.0)])).is_empty());
+        // A collinear vertex on an edge adds no empty triangle
+        let triangle = ring(&[(0.0, 0.0), (5.0, 0.0), (10.0, 0.0), (0.0, 10.0)]);
+        let triangles = triangulate(&triangle);
+        assert!(triangles.chunks(3).all(|t| signed_area(&triangle, t) != 0.0));
+        assert_eq!(area(&triangle, &triangles), 50.0);
+    }
+}
@@ -0,0 +1,154 @@
+use std::str::FromStr;
+
+/// Caps on what is drawn into a single tile; `None` is unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ZoomBudget {
+    /// Vertices (two per line segment)
+    pub max_vertices: Option<usize>,
+    /// Map objects with at least one segment drawn
+    pub max_objects: Option<usize>,
+}
+
+impl ZoomBudget {
+    /// Vertex limit within a buffer of `capacity` vertices
+    pub fn vertex_limit(&self, capacity: usize) -> usize {
+        self.max_vertices.map_or(capacity, |max| max.min(capacity))
+    }
+
+    pub fn allows_object(&self, objects_drawn: usize) -> bool {
+        self.max_objects.is_none_or(|max| objects_drawn < max)
+    }
+}
+
+/// Per-zoom vertex and object budgets
+///
+/// Each entry applies from its zoom up to the next entry's zoom; zooms
+/// below the first entry are unlimited. Empty (the default) disables
+/// budgets.
+///
+/// Parsed from a comma separated table of `zoom=vertices[/objects]`,
+/// with `*` for no cap, e.g. `0=20000/2000,10=200000,14=*`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VertexBudgets {
+    // Sorted by zoom, unique
+    entries: Vec<(u32, ZoomBudget)>,
+}
+
+impl VertexBudgets {
+    pub fn new(mut entries: Vec<(u32, ZoomBudget)>) -> Result<Self, String> {
+        entries.sort_by_key(|(zoom, _)| *zoom);
+        if let Some(pair) = entries.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+            return Err(format!("duplicate vertex budget for zoom {}", pair[0].0));
+        }
+        Ok(VertexBudgets { entries })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.entries.is_empty()
+    }
+
+    /// Budget for tiles at `zoom`
+    pub fn for_zoom(&self, zoom: u32) -> ZoomBudget {
+        self.entries
+            .iter()
+            .rev()
+            .find(|(from, _)| *from <= zoom)
+            .map(|(_, budget)| *budget)
+            .unwrap_or_default()
+    }
+
+    /// Vertex buffer size needed to render every zoom within its budget
+    ///
+    /// `default` unless every zoom has a vertex cap below it.
+    pub fn buffer_capacity(&self, default: usize) -> usize {
+        match self.entries.first() {
+            Some((0, _)) => self
+                .entries
+                .iter()
+                .map(|(_, budget)| budget.vertex_limit(default))
+                .max()
+                .unwrap_or(default),
+            _ => default,
+        }
+    }
+}
+
+impl FromStr for VertexBudgets {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let limit = |value: &str| -> Result<Option<usize>, String> {
+            match value.trim() {
+                "*" => Ok(None),
+                value => value
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| format!("invalid limit '{}', expected a count or *", value)),
+            }
+        };
+
+        let entries = s
+            .split(',')
+            .map(|entry| {
+                let (zoom, limits) = entry
+                    .split_once('=')
+                    .ok_or_else(|| format!("invalid vertex budget '{}', expected zoom=vertices[/objects]", entry))?;
+                let zoom = zoom
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("invalid zoom '{}'", zoom.trim()))?;
+                let (vertices, objects) = match limits.split_once('/') {
+                    Some((vertices, objects)) => (limit(vertices)?, limit(objects)?),
+                    None => (limit(limits)?, None),
+                };
+                Ok((zoom, ZoomBudget { max_vertices: vertices, max_objects: objects }))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        VertexBudgets::new(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget_lookup_by_zoom() {
+        let budgets: VertexBudgets = "10=200000, 0=20000/2000,14=*".parse().unwrap();
+        let capped = ZoomBudget { max_vertices: Some(20_000), max_objects: Some(2_000) };
+        assert_eq!(budgets.for_zoom(0), capped);
+        assert_eq!(budgets.for_zoom(6), capped);
+        assert_eq!(budgets.for_zoom(12).max_vertices, Some(200_000));
+        assert_eq!(budgets.for_zoom(12).max_objects, None);
+        assert_eq!(budgets.for_zoom(18), ZoomBudget::default());
+
+        // Zoom 14 and above are unlimited, so the buffer keeps its default size
+        assert_eq!(budgets.buffer_capacity(5_000_000), 5_000_000);
+        let capped_everywhere: VertexBudgets = "0=1000,8=50000".parse().unwrap();
+        assert_eq!(capped_everywhere.buffer_capacity(5_000_000), 50_000);
+        assert_eq!(capped_everywhere.buffer_capacity(10_000), 10_000);
+        let from_z8: VertexBudgets = "8=50000".parse().unwrap();
+        assert_eq!(from_z8.for_zoom(7), ZoomBudget::default());
+        assert_eq!(from_z8.buffer_capacity(5_000_000), 5_000_000);
+
+        assert!(!VertexBudgets::default().is_enabled());
+        assert_eq!(VertexBudgets::default().for_zoom(3), ZoomBudget::default());
+    }
+
+    #[test]
+    fn test_zoom_budget_limits() {
+        let budget = ZoomBudget { max_vertices: Some(100), max_objects: Some(2) };
+        assert_eq!(budget.vertex_limit(1_000), 100);
+        assert_eq!(budget.vertex_limit(50), 50);
+        assert!(budget.allows_object(1));
+        assert!(!budget.allows_object(2));
+        assert!(ZoomBudget::default().allows_object(usize::MAX - 1));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for bad in ["", "6", "x=100", "6=lots", "6=100/", "6=100,6=200"] {
+            assert!(bad.parse::<VertexBudgets>().is_err(), "{:?} parsed", bad);
+        }
+    }
+}
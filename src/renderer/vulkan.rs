@@ -12,26 +12,88 @@ pub struct VulkanContext {
     pub queue: vk::Queue,
     pub command_pool: vk::CommandPool,
     pub memory_properties: vk::PhysicalDeviceMemoryProperties,
+    /// Negotiated instance API version; check this before relying on
+    /// anything newer than Vulkan 1.0
+    pub api_version: u32,
+    /// Whether the `largePoints` feature is enabled, so points can be drawn
+    /// more than 1px wide
+    pub large_points: bool,
 }
 
+/// Options for creating a `VulkanContext`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ContextOptions {
+    /// Use only this device (disables device fallback)
+    pub device_index: Option<usize>,
+    /// Highest API version to request instead of `PREFERRED_API_VERSION`
+    pub api_version: Option<u32>,
+}
+
+/// Highest API version requested when no override is given
+pub const PREFERRED_API_VERSION: u32 = vk::API_VERSION_1_2;
+
 impl VulkanContext {
     /// Create a new Vulkan context for headless rendering
     pub fn new() -> Result<Self, VulkanError> {
+        Self::new_with_options(ContextOptions::default())
+    }
+
+    /// Create a new Vulkan context with explicit device and API version options
+    ///
+    /// With `device_index: None`, devices are tried in preference order
+    /// (discrete → integrated → virtual → CPU) and the next one is used if
+    /// device creation fails. An explicit index disables this fallback.
+    ///
+    /// The instance requests the highest version supported by the loader, capped
+    /// at `api_version` (or `PREFERRED_API_VERSION`) and never below 1.0.
+    pub fn new_with_options(options: ContextOptions) -> Result<Self, VulkanError> {
         let entry = unsafe { ash::Entry::load()? };
 
+        // Negotiate the API version (loaders without vkEnumerateInstanceVersion are 1.0)
+        let supported = unsafe { entry.try_enumerate_instance_version()? }
+            .unwrap_or(vk::API_VERSION_1_0);
+        let api_version = select_api_version(supported, options.api_version);
+        if let Some(requested) = options.api_version {
+            if api_version < requested {
+                log::warn!(
+                    "Requested Vulkan {} but the loader only supports {}",
+                    format_api_version(requested),
+                    format_api_version(supported)
+                );
+            }
+        }
+        log::info!(
+            "Negotiated Vulkan API version {} (loader supports {})",
+            format_api_version(api_version),
+            format_api_version(supported)
+        );
+
         // Create Vulkan instance
-        let instance = create_instance(&entry)?;
+        let instance = create_instance(&entry, api_version)?;
+
+        // Collect candidate physical devices
+        let mut candidates = enumerate_candidates(&instance)?;
+        match options.device_index {
+            Some(index) => {
+                candidates.retain(|c| c.index == index);
+                if candidates.is_empty() {
+                    return Err(VulkanError::DeviceNotFound(index));
+                }
+            }
+            None => order_candidates(&mut candidates),
+        }
 
-        // Find physical device
-        let (physical_device, queue_family_index) = find_suitable_physical_device(&instance)?;
+        // Create logical device, falling back to the next candidate on failure
+        let (selected, (device, queue, large_points)) = select_with_fallback(&candidates, |candidate| {
+            create_device(&instance, candidate.physical_device, candidate.queue_family_index)
+        })?;
+        let physical_device = selected.physical_device;
+        let queue_family_index = selected.queue_family_index;
 
         // Get memory properties
         let memory_properties =
             unsafe { instance.get_physical_device_memory_properties(physical_device) };
 
-        // Create logical device
-        let (device, queue) = create_device(&instance, physical_device, queue_family_index)?;
-
         // Create command pool
         let command_pool = create_command_pool(&device, queue_family_index)?;
 
@@ -44,6 +106,8 @@ impl VulkanContext {
             queue,
             command_pool,
             memory_properties,
+            api_version,
+            large_points,
         })
     }
 
@@ -76,8 +140,41 @@ impl Drop for VulkanContext {
     }
 }
 
+/// Pick the instance API version to request
+///
+/// Returns the lower of the loader's `supported` version and the `requested`
+/// ceiling (default `PREFERRED_API_VERSION`), ignoring patch levels and never
+/// going below 1.0.
+pub fn select_api_version(supported: u32, requested: Option<u32>) -> u32 {
+    let without_patch =
+        |v: u32| vk::make_api_version(0, vk::api_version_major(v), vk::api_version_minor(v), 0);
+    let ceiling = without_patch(requested.unwrap_or(PREFERRED_API_VERSION));
+    without_patch(supported).min(ceiling).max(vk::API_VERSION_1_0)
+}
+
+/// Parse a `major.minor` version string such as `"1.1"`
+pub fn parse_api_version(s: &str) -> Option<u32> {
+    let (major, minor) = s.trim().split_once('.')?;
+    let major: u32 = major.parse().ok()?;
+    let minor: u32 = minor.parse().ok()?;
+    if major == 0 || major > 0x7f || minor > 0x3ff {
+        return None;
+    }
+    Some(vk::make_api_version(0, major, minor, 0))
+}
+
+/// Format an API version as `major.minor.patch`
+pub fn format_api_version(version: u32) -> String {
+    format!(
+        "{}.{}.{}",
+        vk::api_version_major(version),
+        vk::api_version_minor(version),
+        vk::api_version_patch(version)
+    )
+}
+
 /// Create Vulkan instance
-fn create_instance(entry: &ash::Entry) -> Result<ash::Instance, VulkanError> {
+fn create_instance(entry: &ash::Entry, api_version: u32) -> Result<ash::Instance, VulkanError> {
     let app_name = std::ffi::CString::new("Rust OSM Renderer").unwrap();
     let engine_name = std::ffi::CString::new("No Engine").unwrap();
 
@@ -86,7 +183,7 @@ fn create_instance(entry: &ash::Entry) -> Result<ash::Instance, VulkanError> {
         .application_version(vk::make_api_version(0, 1, 0, 0))
         .engine_name(&engine_name)
         .engine_version(vk::make_api_version(0, 1, 0, 0))
-        .api_version(vk::API_VERSION_1_2);
+        .api_version(api_version);
 
     // Enable validation layers in debug mode
     #[cfg(debug_assertions)]
@@ -109,22 +206,100 @@ fn create_instance(entry: &ash::Entry) -> Result<ash::Instance, VulkanError> {
     Ok(instance)
 }
 
-/// Find a suitable physical device (GPU) for rendering
-fn find_suitable_physical_device(
-    instance: &ash::Instance,
-) -> Result<(vk::PhysicalDevice, u32), VulkanError> {
+/// A physical device with a graphics queue that could be used for rendering
+#[derive(Debug, Clone)]
+struct DeviceCandidate {
+    /// Position in the instance's enumeration order
+    index: usize,
+    physical_device: vk::PhysicalDevice,
+    queue_family_index: u32,
+    device_type: vk::PhysicalDeviceType,
+    name: String,
+}
+
+/// Preference rank of a device type (lower is preferred)
+fn device_type_rank(device_type: vk::PhysicalDeviceType) -> u32 {
+    match device_type {
+        vk::PhysicalDeviceType::DISCRETE_GPU => 0,
+        vk::PhysicalDeviceType::INTEGRATED_GPU => 1,
+        vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+        vk::PhysicalDeviceType::CPU => 3,
+        _ => 4,
+    }
+}
+
+/// Sort candidates by device type preference, keeping enumeration order for ties
+fn order_candidates(candidates: &mut [DeviceCandidate]) {
+    candidates.sort_by_key(|c| device_type_rank(c.device_type));
+}
+
+/// Try `create` on each candidate in order and return the first success
+///
+/// Logs a warning for every failed candidate and when a fallback device ends up being used.
+/// If all fail, returns `NoUsableDevice` with the last candidate's error.
+fn select_with_fallback<T, F>(
+    candidates: &[DeviceCandidate],
+    mut create: F,
+) -> Result<(&DeviceCandidate, T), VulkanError>
+where
+    F: FnMut(&DeviceCandidate) -> Result<T, VulkanError>,
+{
+    let mut last_error = None;
+
+    for (attempt, candidate) in candidates.iter().enumerate() {
+        match create(candidate) {
+            Ok(value) => {
+                if attempt > 0 {
+                    log::warn!(
+                        "Falling back to device {:?} (type: {:?}) after {} failed attempt(s)",
+                        candidate.name,
+                        candidate.device_type,
+                        attempt
+                    );
+                }
+                log::info!(
+                    "Selected device {}: {:?} (type: {:?}), queue family: {}",
+                    candidate.index,
+                    candidate.name,
+                    candidate.device_type,
+                    candidate.queue_family_index
+                );
+                return Ok((candidate, value));
+            }
+            Err(e) => {
+                log::warn!(
+                    "Failed to create device on {:?} (type: {:?}): {}",
+                    candidate.name,
+                    candidate.device_type,
+                    e
+                );
+                last_error = Some(e);
+            }
+        }
+    }
+
+    match last_error {
+        Some(e) => Err(VulkanError::NoUsableDevice(candidates.len(), Box::new(e))),
+        None => Err(VulkanError::NoPhysicalDevice),
+    }
+}
+
+/// Enumerate physical devices (GPUs) that have a graphics queue
+fn enumerate_candidates(instance: &ash::Instance) -> Result<Vec<DeviceCandidate>, VulkanError> {
     let physical_devices = unsafe { instance.enumerate_physical_devices()? };
 
     if physical_devices.is_empty() {
         return Err(VulkanError::NoPhysicalDevice);
     }
 
-    for physical_device in physical_devices {
+    let mut candidates = Vec::new();
+    for (index, physical_device) in physical_devices.into_iter().enumerate() {
         let properties = unsafe { instance.get_physical_device_properties(physical_device) };
         let device_name = unsafe { CStr::from_ptr(properties.device_name.as_ptr()) };
 
         log::info!(
-            "Found physical device: {:?} (type: {:?})",
+            "Found physical device {}: {:?} (type: {:?})",
+            index,
             device_name,
             properties.device_type
         );
@@ -133,42 +308,55 @@ fn find_suitable_physical_device(
         let queue_families =
             unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
 
-        for (index, queue_family) in queue_families.iter().enumerate() {
-            if queue_family.queue_flags.contains(vk::QueueFlags::GRAPHICS) {
-                log::info!(
-                    "Selected device: {:?}, queue family: {}",
-                    device_name,
-                    index
-                );
-                return Ok((physical_device, index as u32));
-            }
+        if let Some(queue_family_index) = queue_families
+            .iter()
+            .position(|queue_family| queue_family.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+        {
+            candidates.push(DeviceCandidate {
+                index,
+                physical_device,
+                queue_family_index: queue_family_index as u32,
+                device_type: properties.device_type,
+                name: device_name.to_string_lossy().into_owned(),
+            });
         }
     }
 
-    Err(VulkanError::NoSuitableQueueFamily)
+    if candidates.is_empty() {
+        return Err(VulkanError::NoSuitableQueueFamily);
+    }
+
+    Ok(candidates)
 }
 
 /// Create logical device and queue
+///
+/// Enables `largePoints` if the device supports it and returns whether it did.
 fn create_device(
     instance: &ash::Instance,
     physical_device: vk::PhysicalDevice,
     queue_family_index: u32,
-) -> Result<(ash::Device, vk::Queue), VulkanError> {
+) -> Result<(ash::Device, vk::Queue, bool), VulkanError> {
     let queue_priorities = [1.0f32];
 
     let queue_create_info = vk::DeviceQueueCreateInfo::default()
         .queue_family_index(queue_family_index)
         .queue_priorities(&queue_priorities);
 
+    let supported = unsafe { instance.get_physical_device_features(physical_device) };
+    let large_points = supported.large_points == vk::TRUE;
+    let features = vk::PhysicalDeviceFeatures::default().large_points(large_points);
+
     let queue_create_infos = [queue_create_info];
-    let device_create_info =
-        vk::DeviceCreateInfo::default().queue_create_infos(&queue_create_infos);
+    let device_create_info = vk::DeviceCreateInfo::default()
+        .queue_create_infos(&queue_create_infos)
+        .enabled_features(&features);
 
     let device = unsafe { instance.create_device(physical_device, &device_create_info, None)? };
 
     let queue = unsafe { device.get_device_queue(queue_family_index, 0) };
 
-    Ok((device, queue))
+    Ok((device, queue, large_points))
 }
 
 /// Create command pool
@@ -200,6 +388,12 @@ pub enum VulkanError {
     #[error("No suitable queue family found")]
     NoSuitableQueueFamily,
 
+    #[error("Vulkan device {0} not found or without a graphics queue")]
+    DeviceNotFound(usize),
+
+    #[error("None of the {0} Vulkan devices could be used, last error: {1}")]
+    NoUsableDevice(usize, #[source] Box<VulkanError>),
+
     #[error("Failed to find suitable memory type")]
     NoSuitableMemoryType,
 
@@ -208,7 +402,126 @@ pub enum VulkanError {
 
     #[error("GPU allocator error: {0}")]
     AllocationError(#[from] gpu_allocator::AllocationError),
+
+    #[error("Allocation {0} is not host-visible and can't be mapped")]
+    NotMappable(&'static str),
+
+    #[error("Tile source error: {0}")]
+    TileSourceError(#[from] crate::data::source::TileSourceError),
+
+    #[error("GPU didn't finish rendering within {0:?}")]
+    RenderTimeout(std::time::Duration),
+
+    #[error("Vulkan device lost")]
+    DeviceLost,
+
+    #[error("Failed to encode PNG: {0}")]
+    EncodeError(#[from] image::ImageError),
 }
 
 // Placeholder for complete rendering functionality
 // This will be implemented in the full render_tile() function
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+
+    fn candidate(index: usize, device_type: vk::PhysicalDeviceType) -> DeviceCandidate {
+        DeviceCandidate {
+            index,
+            physical_device: vk::PhysicalDevice::from_raw(index as u64 + 1),
+            queue_family_index: 0,
+            device_type,
+            name: format!("device{}", index),
+        }
+    }
+
+    #[test]
+    fn test_order_candidates() {
+        let mut candidates = vec![
+            candidate(0, vk::PhysicalDeviceType::CPU),
+            candidate(1, vk::PhysicalDeviceType::INTEGRATED_GPU),
+            candidate(2, vk::PhysicalDeviceType::DISCRETE_GPU),
+            candidate(3, vk::PhysicalDeviceType::INTEGRATED_GPU),
+        ];
+        order_candidates(&mut candidates);
+
+        let order: Vec<usize> = candidates.iter().map(|c| c.index).collect();
+        // Discrete first, integrated in enumeration order, software last
+        assert_eq!(order, vec![2, 1, 3, 0]);
+    }
+
+    #[test]
+    fn test_select_api_version() {
+        let v1_3_250 = vk::make_api_version(0, 1, 3, 250);
+
+        // Default ceiling on a modern loader
+        assert_eq!(select_api_version(v1_3_250, None), PREFERRED_API_VERSION);
+        // Older loader: use what it has, without the patch level
+        assert_eq!(
+            select_api_version(vk::make_api_version(0, 1, 1, 100), None),
+            vk::API_VERSION_1_1
+        );
+        assert_eq!(select_api_version(vk::API_VERSION_1_0, None), vk::API_VERSION_1_0);
+        // Override lowers or raises the ceiling
+        assert_eq!(
+            select_api_version(v1_3_250, Some(vk::API_VERSION_1_0)),
+            vk::API_VERSION_1_0
+        );
+        assert_eq!(
+            select_api_version(v1_3_250, Some(vk::API_VERSION_1_3)),
+            vk::API_VERSION_1_3
+        );
+        // Override above what the loader supports is capped
+        assert_eq!(
+            select_api_version(vk::API_VERSION_1_1, Some(vk::API_VERSION_1_3)),
+            vk::API_VERSION_1_1
+        );
+    }
+
+    #[test]
+    fn test_parse_api_version() {
+        assert_eq!(parse_api_version("1.0"), Some(vk::API_VERSION_1_0));
+        assert_eq!(parse_api_version(" 1.3 "), Some(vk::API_VERSION_1_3));
+        assert_eq!(parse_api_version("1"), None);
+        assert_eq!(parse_api_version("0.9"), None);
+        assert_eq!(parse_api_version("1.x"), None);
+        assert_eq!(format_api_version(vk::make_api_version(0, 1, 2, 7)), "1.2.7");
+    }
+
+    #[test]
+    fn test_select_with_fallback() {
+        let candidates = vec![
+            candidate(0, vk::PhysicalDeviceType::DISCRETE_GPU),
+            candidate(1, vk::PhysicalDeviceType::INTEGRATED_GPU),
+            candidate(2, vk::PhysicalDeviceType::CPU),
+        ];
+
+        // Discrete GPU fails device creation, integrated succeeds
+        let mut attempts = Vec::new();
+        let (selected, value) = select_with_fallback(&candidates, |c| {
+            attempts.push(c.index);
+            if c.device_type == vk::PhysicalDeviceType::DISCRETE_GPU {
+                Err(VulkanError::VkError(vk::Result::ERROR_INITIALIZATION_FAILED))
+            } else {
+                Ok(c.index * 10)
+            }
+        })
+        .unwrap();
+        assert_eq!(selected.index, 1);
+        assert_eq!(value, 10);
+        assert_eq!(attempts, vec![0, 1]);
+
+        // All devices fail: the last error is returned with the number tried
+        let result: Result<(&DeviceCandidate, ()), _> = select_with_fallback(&candidates, |_| {
+            Err(VulkanError::VkError(vk::Result::ERROR_DEVICE_LOST))
+        });
+        assert!(matches!(
+            result,
+            Err(VulkanError::NoUsableDevice(3, e)) if matches!(*e, VulkanError::VkError(vk::Result::ERROR_DEVICE_LOST))
+        ));
+        let result: Result<(&DeviceCandidate, ()), _> = select_with_fallback(&[], |_| Ok(()));
+        assert!(matches!(result, Err(VulkanError::NoPhysicalDevice)));
+    }
+}
@@ -0,0 +1,223 @@
+use crate::data::mmap::MappedData;
+use crate::data::serialization::write_map_object;
+use crate::data::spatial::TileIndex;
+use crate::data::types::{BoundingBox, MapObject, ObjectKind, Point, Tile};
+use crate::renderer::pipeline::TILE_SIZE;
+use crate::renderer::renderer::BACKGROUND_COLOR;
+use crate::renderer::vulkan::ContextOptions;
+use crate::renderer::{RendererOptions, ShaderType, VulkanRenderer};
+use image::RgbaImage;
+use std::fmt;
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+/// Non-background pixels expected for the self test cross in tile 0/0/0
+///
+/// The two 40 degree lines span about 57 pixels with 1px lines; the upper
+/// bound catches broken projections that fill the tile.
+pub const EXPECTED_PIXELS: RangeInclusive<usize> = 32..=1024;
+
+/// Outcome of one self test step
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub name: &'static str,
+    /// Details on success, the error on failure
+    pub result: Result<String, String>,
+}
+
+/// Steps run by `run`, in order, up to and including the first failure
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SelfTestReport {
+    pub steps: Vec<Step>,
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        !self.steps.is_empty() && self.steps.iter().all(|step| step.result.is_ok())
+    }
+
+    fn record<T>(&mut self, name: &'static str, result: Result<(T, String), String>) -> Option<T> {
+        match result {
+            Ok((value, details)) => {
+                self.steps.push(Step { name, result: Ok(details) });
+                Some(value)
+            }
+            Err(e) => {
+                self.steps.push(Step { name, result: Err(e) });
+                None
+            }
+        }
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in &self.steps {
+            match &step.result {
+                Ok(details) => writeln!(f, "PASS  {}: {}", step.name, details)?,
+                Err(e) => writeln!(f, "FAIL  {}: {}", step.name, e)?,
+            }
+        }
+        if self.passed() {
+            writeln!(f, "Self test passed")
+        } else {
+            writeln!(f, "Self test FAILED")
+        }
+    }
+}
+
+/// Render a synthetic cross through the whole pipeline and check the result
+///
+/// Writes the data file, builds the index, mmaps and reads it back, creates
+/// a renderer and renders tile 0/0/0, like `tests/renderer_test.rs` but
+/// without a test harness or PBF. Stops at the first failing step.
+pub fn run(shader_type: ShaderType, context: ContextOptions) -> SelfTestReport {
+    let mut report = SelfTestReport::default();
+    let data_path = std::env::temp_dir().join(format!("rust-osm-renderer-selftest-{}.bin", std::process::id()));
+    run_steps(&mut report, &data_path, shader_type, context);
+    let _ = std::fs::remove_file(&data_path);
+    report
+}
+
+fn run_steps(report: &mut SelfTestReport, data_path: &Path, shader_type: ShaderType, context: ContextOptions) {
+    let tile = Tile::new(0, 0, 0);
+    let objects = cross(20.0);
+
+    let Some(tile_index) = report.record("serialize", write_objects(data_path, &tile, &objects)) else {
+        return;
+    };
+    let Some(mmap_data) = report.record("mmap", read_back(data_path, &tile, &tile_index, &objects)) else {
+        return;
+    };
+
+    let options = RendererOptions {
+        context,
+        ..Default::default()
+    };
+    let renderer = VulkanRenderer::new_with_options(tile_index.max_points, shader_type, TILE_SIZE, options)
+        .map(|renderer| (renderer, format!("{:?} shader", shader_type)))
+        .map_err(|e| e.to_string());
+    let Some(mut renderer) = report.record("renderer", renderer) else {
+        return;
+    };
+
+    let image = renderer
+        .render_tile(&tile, &tile_index, &mmap_data)
+        .map(|image| {
+            let details = format!("{} {}x{}", tile, image.width(), image.height());
+            (image, details)
+        })
+        .map_err(|e| e.to_string());
+    let Some(image) = report.record("render", image) else {
+        return;
+    };
+
+    report.record("pixels", check_pixels(&image).map(|details| ((), details)));
+}
+
+/// A horizontal and a vertical line of `size` degrees each way through 0,0
+fn cross(size: f64) -> Vec<MapObject> {
+    let line = |from: Point, to: Point| MapObject {
+        bounding_box: BoundingBox::from_points(&[from, to]).unwrap(),
+        points: vec![from, to],
+        kind: ObjectKind::Line,
+        tags: Vec::new(),
+    };
+    vec![
+        line(Point::new(-size, 0.0), Point::new(size, 0.0)),
+        line(Point::new(0.0, -size), Point::new(0.0, size)),
+    ]
+}
+
+fn write_objects(path: &Path, tile: &Tile, objects: &[MapObject]) -> Result<(TileIndex, String), String> {
+    let write = || -> std::io::Result<TileIndex> {
+        let mut file = std::fs::File::create(path)?;
+        let mut tile_index = TileIndex::new();
+        for object in objects {
+            let offset = write_map_object(&mut file, object)?;
+            tile_index.insert(*tile, offset);
+            tile_index.max_points = tile_index.max_points.max(object.points.len());
+        }
+        file.flush()?;
+        Ok(tile_index)
+    };
+    let tile_index = write().map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    let details = format!("{} objects in {} tiles", objects.len(), tile_index.len());
+    Ok((tile_index, details))
+}
+
+fn read_back(
+    path: &Path,
+    tile: &Tile,
+    tile_index: &TileIndex,
+    objects: &[MapObject],
+) -> Result<(MappedData, String), String> {
+    let mmap_data = MappedData::new(path).map_err(|e| format!("Failed to map {}: {}", path.display(), e))?;
+    let offsets = tile_index.get(tile).map(|offsets| offsets.as_slice()).unwrap_or_default();
+    if offsets.len() != objects.len() {
+        return Err(format!("Index has {} objects for {}, expected {}", offsets.len(), tile, objects.len()));
+    }
+    for (offset, object) in offsets.iter().zip(objects) {
+        if mmap_data.read_map_object(*offset).points() != object.points.as_slice() {
+            return Err(format!("Object at offset {} doesn't match what was written", offset));
+        }
+    }
+    let details = format!("{} bytes", mmap_data.len());
+    Ok((mmap_data, details))
+}
+
+/// Check the non-background pixel count against `EXPECTED_PIXELS`
+pub fn check_pixels(image: &RgbaImage) -> Result<String, String> {
+    let drawn = image.pixels().filter(|p| p.0 != BACKGROUND_COLOR).count();
+    let details = format!(
+        "{} non-background pixels (expected {}..={})",
+        drawn,
+        EXPECTED_PIXELS.start(),
+        EXPECTED_PIXELS.end()
+    );
+    if EXPECTED_PIXELS.contains(&drawn) {
+        Ok(details)
+    } else {
+        Err(details)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_check_pixels_and_report() {
+        let mut image = RgbaImage::from_pixel(256, 256, Rgba(BACKGROUND_COLOR));
+        assert!(check_pixels(&image).is_err(), "blank tile passes");
+        for i in 100..156 {
+            image.put_pixel(i, 128, Rgba([0, 0, 0, 255]));
+            image.put_pixel(128, i, Rgba([0, 0, 0, 255]));
+        }
+        assert!(check_pixels(&image).is_ok());
+        let filled = RgbaImage::from_pixel(256, 256, Rgba([0, 0, 0, 255]));
+        assert!(check_pixels(&filled).is_err(), "filled tile passes");
+
+        let mut report = SelfTestReport::default();
+        assert!(!report.passed(), "empty report passes");
+        assert_eq!(report.record("mmap", Ok((1, "ok".to_string()))), Some(1));
+        assert!(report.passed());
+        assert_eq!(report.record::<()>("render", Err("lost device".to_string())), None);
+        assert!(!report.passed());
+        assert_eq!(report.to_string(), "PASS  mmap: ok\nFAIL  render: lost device\nSelf test FAILED\n");
+    }
+
+    #[test]
+    fn test_serialize_and_read_back() {
+        let path = std::env::temp_dir().join(format!("rust-osm-renderer-selftest-test-{}.bin", std::process::id()));
+        let tile = Tile::new(0, 0, 0);
+        let objects = cross(20.0);
+        let (tile_index, _) = write_objects(&path, &tile, &objects).unwrap();
+        assert_eq!(tile_index.max_points, 2);
+        let result = read_back(&path, &tile, &tile_index, &objects);
+        let _ = std::fs::remove_file(&path);
+        assert!(result.is_ok(), "{:?}", result.err());
+    }
+}
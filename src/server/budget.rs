@@ -0,0 +1,121 @@
+use crate::data::types::Tile;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Wall-clock budget for rendering and encoding a tile (`--render-budget-ms`)
+///
+/// Overruns are only observed: they are logged and counted for `/metrics`,
+/// the tile is still served.
+#[derive(Debug, Default)]
+pub struct RenderBudget {
+    budget: Option<Duration>,
+    renders: AtomicU64,
+    overruns: AtomicU64,
+}
+
+/// Snapshot of budget counters, see `RenderBudget::metrics`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct BudgetMetrics {
+    pub budget: Duration,
+    pub renders: u64,
+    pub overruns: u64,
+}
+
+impl RenderBudget {
+    pub fn new(budget: Option<Duration>) -> Self {
+        RenderBudget {
+            budget,
+            ..Default::default()
+        }
+    }
+
+    /// Record a tile that took `elapsed` to draw `vertex_count` vertices
+    ///
+    /// Returns whether it overran the budget; always false without one.
+    pub fn record(&self, tile: &Tile, elapsed: Duration, vertex_count: usize) -> bool {
+        let Some(budget) = self.budget else {
+            return false;
+        };
+        self.renders.fetch_add(1, Ordering::Relaxed);
+        if elapsed <= budget {
+            return false;
+        }
+
+        self.overruns.fetch_add(1, Ordering::Relaxed);
+        log::warn!(
+            "Tile {} took {:?}, over the {:?} render budget ({} vertices)",
+            tile,
+            elapsed,
+            budget,
+            vertex_count
+        );
+        true
+    }
+
+    /// Counters, or `None` without a budget
+    pub fn metrics(&self) -> Option<BudgetMetrics> {
+        self.budget.map(|budget| BudgetMetrics {
+            budget,
+            renders: self.renders.load(Ordering::Relaxed),
+            overruns: self.overruns.load(Ordering::Relaxed),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    /// Collects warnings so the test can assert on them
+    struct CaptureLogger(Mutex<Vec<String>>);
+
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= log::Level::Warn
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                self.0.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: CaptureLogger = CaptureLogger(Mutex::new(Vec::new()));
+
+    #[test]
+    fn test_budget_overrun_is_counted_and_logged() {
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(log::LevelFilter::Warn);
+
+        let budget = RenderBudget::new(Some(Duration::from_millis(5)));
+        let tile = Tile::new(1081, 660, 11);
+
+        // A render well within the budget
+        assert!(!budget.record(&tile, Duration::from_millis(1), 10));
+
+        // A mocked slow render
+        let started = Instant::now();
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(budget.record(&tile, started.elapsed(), 1234));
+
+        let metrics = budget.metrics().unwrap();
+        assert_eq!((metrics.renders, metrics.overruns), (2, 1));
+
+        let warnings = LOGGER.0.lock().unwrap();
+        assert!(
+            warnings.iter().any(|w| w.contains("Tile 11/1081/660") && w.contains("1234 vertices")),
+            "{:?}",
+            warnings
+        );
+
+        // Without a budget nothing is recorded
+        let unbudgeted = RenderBudget::new(None);
+        assert!(!unbudgeted.record(&tile, Duration::from_secs(10), 1));
+        assert_eq!(unbudgeted.metrics(), None);
+    }
+}
@@ -0,0 +1,334 @@
+use crate::data::types::Tile;
+use crate::encoding::format::TileFormat;
+use axum::body::Bytes;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+use tokio::task::JoinHandle;
+
+/// Tile cache entries when neither `--tile-cache` nor `TILE_CACHE_ENV` is set
+pub const DEFAULT_TILE_CACHE_ENTRIES: usize = 4096;
+
+/// Environment variable with the tile cache capacity, 0 to disable it
+pub const TILE_CACHE_ENV: &str = "TILE_CACHE_ENTRIES";
+
+/// Everything that makes one encoded tile response differ from another
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TileCacheKey {
+    /// Tile in XYZ numbering
+    pub tile: Tile,
+    pub tile_size: u32,
+    pub detail: u32,
+    pub mask: bool,
+    pub format: TileFormat,
+}
+
+/// Result of a cache lookup
+#[derive(Debug, Clone, PartialEq)]
+pub enum CacheLookup {
+    Fresh(Bytes),
+    /// Rendered from an older version of the data file
+    Stale(Bytes),
+    Miss,
+}
+
+/// In-memory cache of encoded tiles (`--tile-cache`, on by default)
+///
+/// Entries remember the data version they were rendered from, see
+/// `data_version`; after the data file changes they are stale until
+/// re-rendered. The least recently used entry is evicted when full.
+///
+/// Entries of pinned tiles are never evicted. They count towards the
+/// capacity, but if only pinned entries are left the cache grows past it.
+/// Tiles without data are stored as empty entries, see `NoDataTiles`.
+pub struct TileCache {
+    capacity: usize,
+    stale_while_revalidate: bool,
+    // Data file whose modification time is the data version
+    data_file: Option<PathBuf>,
+    inner: Mutex<CacheEntries>,
+}
+
+#[derive(Default)]
+struct CacheEntries {
+    entries: HashMap<TileCacheKey, CacheEntry>,
+    // Incremented on every access, for LRU eviction
+    clock: u64,
+    // Keys with a background refresh in flight
+    refreshing: HashSet<TileCacheKey>,
+    // Tiles (XYZ) whose entries are exempt from eviction
+    pinned: HashSet<Tile>,
+}
+
+struct CacheEntry {
+    png: Bytes,
+    version: u64,
+    last_used: u64,
+}
+
+impl TileCache {
+    pub fn new(capacity: usize, stale_while_revalidate: bool, data_file: Option<PathBuf>) -> Self {
+        TileCache {
+            capacity: capacity.max(1),
+            stale_while_revalidate,
+            data_file,
+            inner: Mutex::new(CacheEntries::default()),
+        }
+    }
+
+    /// Serve stale entries and refresh them in the background (`--stale-while-revalidate`)
+    ///
+    /// Otherwise stale entries are re-rendered before responding.
+    pub fn stale_while_revalidate(&self) -> bool {
+        self.stale_while_revalidate
+    }
+
+    /// Current data version: the data file's modification time in nanoseconds
+    ///
+    /// 0 without a data file or if its modification time is unavailable.
+    pub fn data_version(&self) -> u64 {
+        self.data_file
+            .as_ref()
+            .and_then(|path| std::fs::metadata(path).ok())
+            .and_then(|metadata| metadata.modified().ok())
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |since_epoch| since_epoch.as_nanos() as u64)
+    }
+
+    /// Look up `key`, comparing its entry against the data `version`
+    pub fn get(&self, key: &TileCacheKey, version: u64) -> CacheLookup {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clock += 1;
+        let clock = inner.clock;
+        match inner.entries.get_mut(key) {
+            Some(entry) => {
+                entry.last_used = clock;
+                if entry.version == version {
+                    CacheLookup::Fresh(entry.png.clone())
+                } else {
+                    CacheLookup::Stale(entry.png.clone())
+                }
+            }
+            None => CacheLookup::Miss,
+        }
+    }
+
+    /// Store a tile rendered from data `version`, evicting the least recently used if full
+    pub fn insert(&self, key: TileCacheKey, png: Bytes, version: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clock += 1;
+        let last_used = inner.clock;
+        if inner.entries.len() >= self.capacity && !inner.entries.contains_key(&key) {
+            let oldest = inner
+                .entries
+                .iter()
+                .filter(|(key, _)| !inner.pinned.contains(&key.tile))
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                inner.entries.remove(&oldest);
+            }
+        }
+        inner.entries.insert(key, CacheEntry { png, version, last_used });
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Exempt all entries of `tile` from eviction; returns whether it was newly pinned
+    pub fn pin(&self, tile: Tile) -> bool {
+        self.inner.lock().unwrap().pinned.insert(tile)
+    }
+
+    /// Make the entries of `tile` evictable again; returns whether it was pinned
+    pub fn unpin(&self, tile: &Tile) -> bool {
+        self.inner.lock().unwrap().pinned.remove(tile)
+    }
+
+    pub fn pinned_count(&self) -> usize {
+        self.inner.lock().unwrap().pinned.len()
+    }
+
+    /// Re-render `key` in the background and store the result as data `version`
+    ///
+    /// `render` yields the encoded tile, or `None` if rendering failed, which
+    /// keeps the stale entry. Returns `None` without spawning if a refresh of
+    /// `key` is already in flight.
+    pub fn spawn_refresh<F>(self: &Arc<Self>, key: TileCacheKey, version: u64, render: F) -> Option<JoinHandle<()>>
+    where
+        F: Future<Output = Option<Bytes>> + Send + 'static,
+    {
+        if !self.inner.lock().unwrap().refreshing.insert(key) {
+            return None;
+        }
+
+        let cache = self.clone();
+        Some(tokio::spawn(async move {
+            match render.await {
+                Some(png) => {
+                    log::debug!("Refreshed stale tile {} in the cache", key.tile);
+                    cache.insert(key, png, version);
+                }
+                None => log::warn!("Failed to refresh stale tile {}, keeping the cached one", key.tile),
+            }
+            cache.inner.lock().unwrap().refreshing.remove(&key);
+        }))
+    }
+}
+
+/// Encoded transparent tiles served for tiles without data, by size, mask and format
+///
+/// They don't depend on the tile, so each is encoded once.
+#[derive(Debug, Default)]
+pub struct NoDataTiles {
+    tiles: Mutex<HashMap<(u32, bool, TileFormat), Bytes>>,
+}
+
+impl NoDataTiles {
+    /// The tile of `size` pixels, encoding it with `encode` the first time
+    pub fn get_or_encode<E>(
+        &self,
+        size: u32,
+        mask: bool,
+        format: TileFormat,
+        encode: impl FnOnce() -> Result<Vec<u8>, E>,
+    ) -> Result<Bytes, E> {
+        if let Some(data) = self.tiles.lock().unwrap().get(&(size, mask, format)) {
+            return Ok(data.clone());
+        }
+        let data = Bytes::from(encode()?);
+        self.tiles.lock().unwrap().insert((size, mask, format), data.clone());
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn key(x: u32) -> TileCacheKey {
+        TileCacheKey {
+            tile: Tile::new(x, 0, 1),
+            tile_size: 256,
+            detail: 0,
+            mask: false,
+            format: TileFormat::Png,
+        }
+    }
+
+    #[test]
+    fn test_cache_versions_and_eviction() {
+        let cache = TileCache::new(2, false, None);
+        assert_eq!(cache.data_version(), 0);
+        assert_eq!(cache.get(&key(0), 1), CacheLookup::Miss);
+
+        cache.insert(key(0), Bytes::from_static(b"a"), 1);
+        cache.insert(key(1), Bytes::from_static(b"b"), 1);
+        assert_eq!(cache.get(&key(0), 1), CacheLookup::Fresh(Bytes::from_static(b"a")));
+        assert_eq!(cache.get(&key(0), 2), CacheLookup::Stale(Bytes::from_static(b"a")));
+
+        // key(1) is the least recently used
+        cache.insert(key(2), Bytes::from_static(b"c"), 1);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&key(1), 1), CacheLookup::Miss);
+        assert!(matches!(cache.get(&key(0), 1), CacheLookup::Fresh(_)));
+    }
+
+    #[test]
+    fn test_nodata_tiles_encoded_once() {
+        let tiles = NoDataTiles::default();
+        let mut encoded = 0;
+        for _ in 0..3 {
+            let data = tiles.get_or_encode(256, false, TileFormat::Png, || {
+                encoded += 1;
+                Ok::<_, ()>(b"png".to_vec())
+            });
+            assert_eq!(data, Ok(Bytes::from_static(b"png")));
+        }
+        assert_eq!(encoded, 1);
+        // Other sizes are separate tiles
+        let data = tiles.get_or_encode(512, false, TileFormat::Png, || Ok::<_, ()>(b"big".to_vec()));
+        assert_eq!(data, Ok(Bytes::from_static(b"big")));
+    }
+
+    #[test]
+    fn test_pinned_tiles_survive_eviction() {
+        let cache = TileCache::new(3, false, None);
+        assert!(cache.pin(key(0).tile));
+        assert!(!cache.pin(key(0).tile));
+        cache.insert(key(0), Bytes::from_static(b"pinned"), 1);
+
+        // Far more inserts than capacity; key(0) is never used again
+        for x in 1..20 {
+            cache.insert(key(x), Bytes::from_static(b"x"), 1);
+        }
+        assert_eq!(cache.len(), 3);
+        assert!(matches!(cache.get(&key(0), 1), CacheLookup::Fresh(_)));
+        assert_eq!(cache.get(&key(1), 1), CacheLookup::Miss, "unpinned tile survived");
+        assert!(matches!(cache.get(&key(19), 1), CacheLookup::Fresh(_)));
+
+        // All pinned: grows past capacity instead of evicting
+        for x in 20..23 {
+            cache.pin(key(x).tile);
+            cache.insert(key(x), Bytes::from_static(b"x"), 1);
+        }
+        assert_eq!(cache.len(), 4);
+        assert_eq!(cache.pinned_count(), 4);
+
+        // Unpinned tiles are evicted again
+        assert!(cache.unpin(&key(0).tile));
+        assert!(!cache.unpin(&key(0).tile));
+        cache.insert(key(30), Bytes::from_static(b"x"), 1);
+        assert_eq!(cache.get(&key(0), 1), CacheLookup::Miss);
+    }
+
+    #[test]
+    fn test_data_version_follows_file_mtime() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let cache = TileCache::new(1, false, Some(file.path().to_path_buf()));
+        let modified = |secs| file.as_file().set_modified(UNIX_EPOCH + Duration::from_secs(secs)).unwrap();
+
+        modified(1_000);
+        let version = cache.data_version();
+        assert_eq!(version, 1_000_000_000_000);
+        cache.insert(key(0), Bytes::from_static(b"a"), version);
+
+        modified(2_000);
+        assert!(matches!(cache.get(&key(0), cache.data_version()), CacheLookup::Stale(_)));
+    }
+
+    #[tokio::test]
+    async fn test_stale_hit_is_refreshed_in_background() {
+        let cache = Arc::new(TileCache::new(4, true, None));
+        cache.insert(key(0), Bytes::from_static(b"old"), 1);
+
+        // The stale tile is available right away while the refresh renders
+        let started = Instant::now();
+        assert_eq!(cache.get(&key(0), 2), CacheLookup::Stale(Bytes::from_static(b"old")));
+        let refresh = cache
+            .spawn_refresh(key(0), 2, async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Some(Bytes::from_static(b"new"))
+            })
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_millis(50));
+        assert!(cache.spawn_refresh(key(0), 2, async { None }).is_none(), "refresh started twice");
+        assert!(matches!(cache.get(&key(0), 2), CacheLookup::Stale(_)));
+
+        refresh.await.unwrap();
+        assert_eq!(cache.get(&key(0), 2), CacheLookup::Fresh(Bytes::from_static(b"new")));
+
+        // A failed refresh keeps the stale entry and can be retried
+        cache.spawn_refresh(key(0), 3, async { None }).unwrap().await.unwrap();
+        assert_eq!(cache.get(&key(0), 3), CacheLookup::Stale(Bytes::from_static(b"new")));
+        assert!(cache.spawn_refresh(key(0), 3, async { None }).is_some());
+    }
+}
@@ -0,0 +1,207 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+use tokio::sync::{oneshot, Semaphore, SemaphorePermit};
+
+/// Encode slots per encode thread, see `EncodePool`
+pub const QUEUE_SLOTS_PER_THREAD: usize = 2;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Encoding stage on its own threads (`--encode-threads`)
+///
+/// Encoding is CPU bound and would otherwise run on the async workers
+/// behind the GPU. A request reserves one of `capacity` slots before it
+/// renders and releases it once its tile is encoded, so at most `capacity`
+/// rendered framebuffers wait for or are in encoding; further requests
+/// wait for a slot before they render.
+pub struct EncodePool {
+    threads: usize,
+    capacity: usize,
+    slots: Semaphore,
+    // `None` once shutting down
+    jobs: Mutex<Option<mpsc::Sender<Job>>>,
+    workers: Mutex<Vec<JoinHandle<()>>>,
+    depth: AtomicUsize,
+    depth_max: AtomicUsize,
+    waiting: AtomicUsize,
+    encoded: AtomicU64,
+}
+
+/// Snapshot of the encode stage, see `EncodePool::metrics`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct EncodeMetrics {
+    pub threads: usize,
+    pub capacity: usize,
+    /// Reserved slots: tiles rendering for, waiting for or in encoding
+    pub depth: usize,
+    pub depth_max: usize,
+    /// Requests waiting for a slot
+    pub waiting: usize,
+    pub encoded: u64,
+}
+
+/// A reserved encode slot, released when dropped
+pub struct EncodeSlot<'a> {
+    pool: &'a EncodePool,
+    _permit: SemaphorePermit<'a>,
+}
+
+impl EncodePool {
+    pub fn new(threads: usize, capacity: usize) -> Self {
+        let threads = threads.max(1);
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..threads)
+            .map(|i| {
+                let receiver = receiver.clone();
+                std::thread::Builder::new()
+                    .name(format!("encode-{}", i))
+                    .spawn(move || loop {
+                        let job = receiver.lock().unwrap().recv();
+                        match job {
+                            // A panicking job fails its request, not the thread
+                            Ok(job) => panic::catch_unwind(AssertUnwindSafe(job)).unwrap_or(()),
+                            Err(_) => break,
+                        }
+                    })
+                    .expect("failed to spawn encode thread")
+            })
+            .collect();
+
+        let capacity = capacity.max(1);
+        EncodePool {
+            threads,
+            capacity,
+            slots: Semaphore::new(capacity),
+            jobs: Mutex::new(Some(sender)),
+            workers: Mutex::new(workers),
+            depth: AtomicUsize::new(0),
+            depth_max: AtomicUsize::new(0),
+            waiting: AtomicUsize::new(0),
+            encoded: AtomicU64::new(0),
+        }
+    }
+
+    /// Reserve a slot, waiting while all are taken
+    pub async fn reserve(&self) -> EncodeSlot<'_> {
+        self.waiting.fetch_add(1, Ordering::Relaxed);
+        let permit = self.slots.acquire().await.expect("encode slots are never closed");
+        self.waiting.fetch_sub(1, Ordering::Relaxed);
+        let depth = self.depth.fetch_add(1, Ordering::Relaxed) + 1;
+        self.depth_max.fetch_max(depth, Ordering::Relaxed);
+        EncodeSlot { pool: self, _permit: permit }
+    }
+
+    pub fn metrics(&self) -> EncodeMetrics {
+        EncodeMetrics {
+            threads: self.threads,
+            capacity: self.capacity,
+            depth: self.depth.load(Ordering::Relaxed),
+            depth_max: self.depth_max.load(Ordering::Relaxed),
+            waiting: self.waiting.load(Ordering::Relaxed),
+            encoded: self.encoded.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl EncodeSlot<'_> {
+    /// Run `encode` on an encode thread and release the slot when it's done
+    pub async fn encode<F, T>(self, encode: F) -> T
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let job: Job = Box::new(move || {
+            sender.send(encode()).ok();
+        });
+        self.pool
+            .jobs
+            .lock()
+            .unwrap()
+            .as_ref()
+            .expect("encode pool is running")
+            .send(job)
+            .expect("encode threads are running");
+        let result = receiver.await.expect("encode job panicked");
+        self.pool.encoded.fetch_add(1, Ordering::Relaxed);
+        result
+    }
+}
+
+impl Drop for EncodeSlot<'_> {
+    fn drop(&mut self) {
+        self.pool.depth.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for EncodePool {
+    fn drop(&mut self) {
+        // Closing the channel stops the threads after their current job
+        self.jobs.lock().unwrap().take();
+        for worker in self.workers.lock().unwrap().drain(..) {
+            worker.join().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_burst_is_bounded_by_capacity() {
+        let pool = Arc::new(EncodePool::new(1, 3));
+        // Framebuffers rendered but not yet encoded
+        let alive = Arc::new(AtomicUsize::new(0));
+        let alive_max = Arc::new(AtomicUsize::new(0));
+
+        let requests: Vec<_> = (0..24)
+            .map(|i| {
+                let (pool, alive, alive_max) = (pool.clone(), alive.clone(), alive_max.clone());
+                tokio::spawn(async move {
+                    let slot = pool.reserve().await;
+                    let framebuffer = vec![i as u8; 256 * 256 * 4];
+                    alive_max.fetch_max(alive.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                    slot.encode(move || {
+                        std::thread::sleep(Duration::from_millis(5));
+                        let first = framebuffer[0];
+                        drop(framebuffer);
+                        alive.fetch_sub(1, Ordering::SeqCst);
+                        first
+                    })
+                    .await
+                })
+            })
+            .collect();
+
+        // The burst backs up behind the slots instead of piling up framebuffers
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let metrics = pool.metrics();
+        assert!(metrics.depth <= 3 && metrics.waiting > 0, "{:?}", metrics);
+
+        for (i, request) in requests.into_iter().enumerate() {
+            assert_eq!(request.await.unwrap(), i as u8);
+        }
+        assert!(alive_max.load(Ordering::SeqCst) <= 3, "{} framebuffers at once", alive_max.load(Ordering::SeqCst));
+        let metrics = pool.metrics();
+        assert_eq!((metrics.depth, metrics.depth_max, metrics.waiting, metrics.encoded), (0, 3, 0, 24));
+    }
+
+    #[tokio::test]
+    async fn test_panicking_job_keeps_threads() {
+        let pool = Arc::new(EncodePool::new(1, 1));
+        let failing = pool.clone();
+        let failed = tokio::spawn(async move { failing.reserve().await.encode(|| panic!("encoder bug")).await });
+        assert!(failed.await.is_err());
+        assert_eq!(pool.metrics().depth, 0, "slot of the failed request released");
+
+        let slot = pool.reserve().await;
+        assert_eq!(pool.metrics().depth, 1);
+        drop(slot);
+        assert_eq!(pool.reserve().await.encode(|| 42).await, 42);
+    }
+}
@@ -1,103 +1,2215 @@
-use crate::data::types::Tile;
-use crate::encoding::png::encode_png;
-use crate::renderer::{VulkanRenderer, ShaderType};
-use crate::renderer::pipeline::{TILE_SIZE, TILE_SIZE_2X};
-use crate::server::AppState;
+use crate::data::mmap::MapObjectView;
+use crate::data::types::{MapObjectOffset, ObjectKind, Tile};
+use crate::encoding::datauri::{data_uri, data_uri_page};
+use crate::encoding::format::{FormatPreference, TileFormat};
+use crate::encoding::mvt::{MvtLayer, MVT_LAYER_NAME, MVT_MIME_TYPE};
+use crate::encoding::vector::{geojson_coordinates, round_coordinate, MVT_EXTENT};
+use crate::filter::TagFilter;
+use crate::encoding::png::{encode_png, encode_png_indexed, encode_png_indexed_srgb, encode_png_srgb};
+use crate::projection::{get_bounding_box, get_buffered_bounding_box, TileScheme, MAX_LAT};
+use crate::renderer::color_format::ColorFormat;
+use crate::renderer::downscale::downscale;
+use crate::renderer::mask::coverage_mask;
+use crate::renderer::pool::{Pool, PoolError, PoolGuard, PoolMetrics};
+use crate::renderer::renderer::{buffer_pixels, lookup_tile, MAX_METATILE_SIZE};
+use crate::renderer::text::{self, draw_text, wrap_text};
+use crate::renderer::{RendererOptions, VulkanRenderer};
+use crate::renderer::pipeline::TILE_SIZE;
+use crate::renderer::vulkan::VulkanError;
+use crate::server::budget::BudgetMetrics;
+use crate::server::encode::EncodeMetrics;
+use crate::server::metrics::{TileMetricsSnapshot, RENDER_SECONDS_BUCKETS};
+use crate::server::{AppState, OutOfCoverage};
+use crate::server::cache::{CacheLookup, TileCacheKey};
 use axum::{
-    extract::{Path, State},
-    http::{header, StatusCode},
-    response::IntoResponse,
+    body::Bytes,
+    extract::{Path, Query, State},
+    Json,
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
 };
-use std::sync::Mutex;
+use image::{GrayImage, RgbaImage};
+use std::collections::{HashMap, HashSet};
+use std::hash::Hasher;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// RGBA color of nodata tiles outside the data bounds (transparent)
+pub use crate::renderer::renderer::NODATA_COLOR;
+
+/// Largest accepted `detail` offset (each level multiplies the tiles aggregated by 4)
+pub const MAX_DETAIL_OFFSET: u32 = 3;
+
+/// Default `max-age` of tile responses in seconds, see `--max-age`
+pub const DEFAULT_MAX_AGE_SECS: u32 = 300;
+
+/// Highest zoom level accepted in tile paths, so tile coordinates fit in a u32
+///
+/// `--max-request-zoom` can lower it.
+pub const MAX_ZOOM: u32 = 30;
+
+/// Highest `@{n}x` scale accepted in tile paths, bounding the renderer size
+pub const MAX_TILE_SCALE: u32 = 4;
+
+/// Rejected tile path, answered with 400 and an RFC 9457 problem+json body
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum TilePathError {
+    #[error("Malformed tile path {0:?}, expected /tile/{{z}}/{{x}}/{{y}}.png or /tile/{{z}}/{{x}}/{{y}}@{{n}}x.png (or .webp) with non-negative integers")]
+    Malformed(String),
+
+    #[error("Zoom {0} is out of range, expected 0 to {1}")]
+    ZoomOutOfRange(u32, u32),
+
+    #[error("Tile {0} is outside the zoom {z} grid, expected x and y from 0 to {max}", z = .0.z, max = (1u64 << .0.z) - 1)]
+    OutOfGrid(Tile),
+
+    #[error("Metatile size {0} is out of range, expected 1 to {MAX_METATILE_SIZE} tiles")]
+    MetatileSizeOutOfRange(u32),
+
+    #[error("Tile scale @{0}x is out of range, expected @1x to @{MAX_TILE_SCALE}x")]
+    ScaleOutOfRange(u32),
+}
+
+impl IntoResponse for TilePathError {
+    fn into_response(self) -> Response {
+        let title = match self {
+            TilePathError::Malformed(_) => "Malformed tile path",
+            TilePathError::ZoomOutOfRange(..) | TilePathError::OutOfGrid(_) => "Tile coordinates out of range",
+            TilePathError::MetatileSizeOutOfRange(_) => "Metatile size out of range",
+            TilePathError::ScaleOutOfRange(_) => "Tile scale out of range",
+        };
+        let body = serde_json::json!({
+            "type": "about:blank",
+            "title": title,
+            "status": StatusCode::BAD_REQUEST.as_u16(),
+            "detail": self.to_string(),
+        });
+        (
+            StatusCode::BAD_REQUEST,
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            body.to_string(),
+        )
+            .into_response()
+    }
+}
+
+/// Parse the `/tile/{z}/{x}/{y}.png` path segments into a tile and its pixel size
+///
+/// `.webp` paths are accepted as well, see `path_format`. Syntax errors,
+/// zooms above `max_zoom` and coordinates outside the tile grid are
+/// reported separately, so clients can tell them apart from empty tiles.
+pub fn parse_tile_path(z: &str, x: &str, y_png: &str, max_zoom: u32) -> Result<(Tile, u32), TilePathError> {
+    let malformed = || TilePathError::Malformed(format!("/tile/{}/{}/{}", z, x, y_png));
+    let y_size = y_png
+        .strip_suffix(".png")
+        .or_else(|| y_png.strip_suffix(".webp"))
+        .ok_or_else(malformed)?;
+    let parse = |s: &str| s.parse::<u32>().map_err(|_| malformed());
+    // Check for an @{n}x suffix for high-resolution tiles
+    let (y, tile_size) = match y_size.split_once('@') {
+        Some((y, scale)) => {
+            let scale = parse(scale.strip_suffix('x').ok_or_else(malformed)?)?;
+            if scale == 0 || scale > MAX_TILE_SCALE {
+                return Err(TilePathError::ScaleOutOfRange(scale));
+            }
+            (y, scale * TILE_SIZE)
+        }
+        None => (y_size, TILE_SIZE),
+    };
+    let tile = Tile::new(parse(x)?, parse(y)?, parse(z)?);
+
+    let max_zoom = max_zoom.min(MAX_ZOOM);
+    if tile.z > max_zoom {
+        return Err(TilePathError::ZoomOutOfRange(tile.z, max_zoom));
+    }
+    let n = 1u32 << tile.z;
+    if tile.x >= n || tile.y >= n {
+        return Err(TilePathError::OutOfGrid(tile));
+    }
+    Ok((tile, tile_size))
+}
+
+/// Format requested by the tile path's extension
+///
+/// `.webp` tiles are always WebP; `.png` tiles are PNG unless
+/// `--format-preference` and the `Accept` header pick another format.
+pub fn path_format(y_png: &str) -> Option<TileFormat> {
+    y_png.ends_with(".webp").then_some(TileFormat::WebP)
+}
+
+/// Format to encode a tile response in: the one named by the path's
+/// extension, else the one `preference` negotiates with the `Accept` header
+///
+/// Clients that don't name an image type get PNG (the end of the chain).
+pub fn negotiate_format(preference: &FormatPreference, headers: &HeaderMap, y_png: &str) -> TileFormat {
+    path_format(y_png).unwrap_or_else(|| {
+        let accept = headers.get(header::ACCEPT).and_then(|value| value.to_str().ok());
+        preference.negotiate(accept)
+    })
+}
+
+/// Parse the `/metatile/{z}/{x}/{y}/{n}.png` path segments into the origin
+/// tile and the metatile size in tiles
+///
+/// All `n`×`n` tiles from the origin must lie in the grid.
+pub fn parse_metatile_path(z: &str, x: &str, y: &str, n_png: &str, max_zoom: u32) -> Result<(Tile, u32), TilePathError> {
+    let malformed = || TilePathError::Malformed(format!("/metatile/{}/{}/{}/{}", z, x, y, n_png));
+    let (origin, _) = parse_tile_path(z, x, &format!("{}.png", y), max_zoom).map_err(|e| match e {
+        TilePathError::Malformed(_) => malformed(),
+        e => e,
+    })?;
+    let n = n_png.strip_suffix(".png").and_then(|n| n.parse::<u32>().ok()).ok_or_else(malformed)?;
+    if n == 0 || n > MAX_METATILE_SIZE {
+        return Err(TilePathError::MetatileSizeOutOfRange(n));
+    }
+    let last = Tile::new(origin.x + n - 1, origin.y + n - 1, origin.z);
+    if u64::from(last.x.max(last.y)) >= 1u64 << origin.z {
+        return Err(TilePathError::OutOfGrid(last));
+    }
+    Ok((origin, n))
+}
+
+/// Parse `z/x/y` tile coordinates, numbered like tile paths
+pub fn parse_tile_coords(coords: &str, max_zoom: u32) -> Result<Tile, TilePathError> {
+    match coords.trim().split('/').collect::<Vec<_>>().as_slice() {
+        [z, x, y] => parse_tile_path(z, x, &format!("{}.png", y), max_zoom).map(|(tile, _)| tile),
+        _ => Err(TilePathError::Malformed(coords.to_string())),
+    }
+}
+
+/// Parse the `detail` query parameter, e.g. `?detail=+1`
+///
+/// A literal `+` in a query string decodes to a space, so surrounding
+/// whitespace is ignored. Values are clamped to `MAX_DETAIL_OFFSET`.
+pub fn parse_detail(value: Option<&str>) -> Result<u32, StatusCode> {
+    match value {
+        None => Ok(0),
+        Some(value) => value
+            .trim()
+            .trim_start_matches('+')
+            .parse::<u32>()
+            .map(|detail| detail.min(MAX_DETAIL_OFFSET))
+            .map_err(|_| StatusCode::BAD_REQUEST),
+    }
+}
+
+/// Parse the `filter` query parameter
+///
+/// 400 for syntax errors, for keys that aren't in `--retain-tags` (every
+/// way would look untagged) and for diff tiles, which don't support filters.
+pub fn parse_filter(state: &AppState, value: Option<&str>) -> Result<Option<TagFilter>, StatusCode> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    let filter: TagFilter = value.parse().map_err(|e| {
+        log::info!("Rejected tile filter: {}", e);
+        StatusCode::BAD_REQUEST
+    })?;
+    if state.diff_base.is_some() {
+        log::info!("Rejected tile filter {}: not supported for diff tiles", filter);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let retained = state.data.retained_tags.as_ref();
+    if let Some(key) = filter.keys().into_iter().find(|key| !retained.is_some_and(|r| r.retains(key))) {
+        log::info!("Rejected tile filter {}: tag {:?} isn't retained (--retain-tags)", filter, key);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(Some(filter))
+}
+
+/// Parse a boolean query parameter such as `?mask=1`
+pub fn parse_flag(value: Option<&str>) -> Result<bool, StatusCode> {
+    match value.map(str::trim) {
+        None | Some("0") | Some("false") => Ok(false),
+        Some("") | Some("1") | Some("true") => Ok(true),
+        Some(_) => Err(StatusCode::BAD_REQUEST),
+    }
+}
 
 /// Handle tile request
-/// Path: /tile/:z/:x/:y.png or /tile/:z/:x/:y@2x.png
+/// Path: /tile/:z/:x/:y.png or /tile/:z/:x/:y@{n}x.png
+///
+/// `?mask=1` returns a grayscale coverage mask instead of the tile: 255
+/// where any feature was drawn, 0 elsewhere.
+///
+/// Tiles are encoded in the first `--format-preference` format the
+/// `Accept` header allows, or as WebP for `.webp` paths; masks are always PNG.
+///
+/// `?datauri=1` wraps the encoded tile in a small HTML page as a
+/// `data:image/...;base64,` URI, to paste into a browser or document.
+///
+/// `?filter=<expr>` draws only ways matching a tag filter (see `filter`)
+/// on their `--retain-tags` tags; see `parse_filter`. Filtered tiles
+/// bypass the tile cache.
+///
+/// With `--scheme tms` the row is numbered from the south edge and
+/// converted to XYZ before anything else.
+///
+/// Malformed paths and coordinates outside the grid are 400 problem+json
+/// responses (`TilePathError`); valid tiles without data follow the
+/// out-of-coverage policy.
 pub async fn handle_tile_request(
     State(state): State<AppState>,
-    Path((z, x, y_png)): Path<(u32, u32, String)>,
-) -> Result<impl IntoResponse, StatusCode> {
-    // Check for @2x suffix for high-resolution tiles
-    let (y, tile_size) = if let Some(y_str) = y_png.strip_suffix("@2x.png") {
-        let y = y_str.parse::<u32>().ok().ok_or(StatusCode::BAD_REQUEST)?;
-        (y, TILE_SIZE_2X)
-    } else {
-        let y = y_png
-            .strip_suffix(".png")
-            .and_then(|s| s.parse::<u32>().ok())
-            .ok_or(StatusCode::BAD_REQUEST)?;
-        (y, TILE_SIZE)
-    };
-
-    log::info!("Rendering tile {}/{}/{} at {}px", z, x, y, tile_size);
-
-    let tile = Tile::new(x, y, z);
-
-    // Thread-local renderers for both 256px and 512px tiles
-    thread_local! {
-        static RENDERER_256: Mutex<Option<VulkanRenderer>> = Mutex::new(None);
-        static RENDERER_512: Mutex<Option<VulkanRenderer>> = Mutex::new(None);
-    }
-
-    // Choose the appropriate renderer based on tile size
-    let image = if tile_size == TILE_SIZE_2X {
-        RENDERER_512.with(|renderer_cell| {
-            let mut renderer_opt = renderer_cell.lock().unwrap();
-
-            // Initialize 512px renderer if not yet created
-            if renderer_opt.is_none() {
-                let max_points = state.data.max_points;
-                match VulkanRenderer::new_with_tile_size(max_points, state.shader_type, TILE_SIZE_2X) {
-                    Ok(renderer) => {
-                        *renderer_opt = Some(renderer);
-                    }
-                    Err(e) => {
-                        log::error!("Failed to create 512px Vulkan renderer: {}", e);
-                        return Err(StatusCode::INTERNAL_SERVER_ERROR);
-                    }
+    Path((z, x, y_png)): Path<(String, String, String)>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Response {
+    let if_none_match = headers.get(header::IF_NONE_MATCH).and_then(|value| value.to_str().ok());
+    match parse_tile_path(&z, &x, &y_png, state.max_request_zoom) {
+        Ok((tile, tile_size)) => {
+            let tile = state.tile_scheme.to_xyz(&tile);
+            let format = negotiate_format(&state.format_preference, &headers, &y_png);
+            let response = tile_response(&state, tile, tile_size, format, &params).await.into_response();
+            not_modified_response(response, if_none_match)
+        }
+        Err(e) => {
+            log::info!("Rejected tile request: {}", e);
+            e.into_response()
+        }
+    }
+}
+
+/// Header with the number of indexed objects of a tile, see `handle_tile_head`;
+/// rendered tiles carry the objects read for them
+pub const OBJECT_COUNT_HEADER: HeaderName = HeaderName::from_static("x-object-count");
+
+/// Handle tile probe request without rendering
+/// Path: HEAD /tile/:z/:x/:y.png
+///
+/// Counts the distinct objects indexed for the tile (its ancestor above
+/// the indexed zooms) and returns them in `OBJECT_COUNT_HEADER`: 200 if
+/// there are any, 204 if not. No renderer is used and no image produced.
+pub async fn handle_tile_head(
+    State(state): State<AppState>,
+    Path((z, x, y_png)): Path<(String, String, String)>,
+) -> Response {
+    let tile = match parse_tile_path(&z, &x, &y_png, state.max_request_zoom) {
+        Ok((tile, _)) => state.tile_origin.to_xyz(&state.tile_scheme.to_xyz(&tile)),
+        Err(e) => {
+            log::info!("Rejected tile probe: {}", e);
+            return e.into_response();
+        }
+    };
+
+    let count = state
+        .data
+        .get(&lookup_tile(&tile, state.data.max_zoom))
+        .map_or(0, |offsets| offsets.iter().collect::<HashSet<_>>().len());
+    log::debug!("Tile probe {}: {} objects", tile, count);
+    let status = if count > 0 { StatusCode::OK } else { StatusCode::NO_CONTENT };
+    (status, [(OBJECT_COUNT_HEADER, count.to_string())]).into_response()
+}
+
+/// Handle vector tile request
+/// Path: /vt/:z/:x/:y.mvt
+///
+/// Encodes the objects indexed for the tile (its ancestor above the
+/// indexed zooms) as a Mapbox Vector Tile with one `MVT_LAYER_NAME` layer,
+/// their retained tags as properties. No renderer is used; style, filters
+/// and the diff base don't apply.
+pub async fn handle_vector_tile_request(
+    State(state): State<AppState>,
+    Path((z, x, y_mvt)): Path<(String, String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    let tile = match parse_object_tile_path(&state, "vt", (&z, &x, &y_mvt), ".mvt") {
+        Ok(tile) => tile,
+        Err(e) => {
+            log::info!("Rejected vector tile request: {}", e);
+            return e.into_response();
+        }
+    };
+
+    let offsets = indexed_offsets(&state, &tile);
+    let bbox = get_bounding_box(&tile);
+    let mut layer = MvtLayer::new(MVT_LAYER_NAME, MVT_EXTENT);
+    for &offset in &offsets {
+        let object = state.mmap.read_map_object(offset);
+        layer.add_object(object.kind, object.points(), &bbox, object.tags());
+    }
+    log::debug!("Vector tile {}: {} of {} objects", tile, layer.len(), offsets.len());
+
+    object_data_response(&state, MVT_MIME_TYPE, layer.encode_tile().into(), &headers)
+}
+
+/// Handle tile contents debug request
+/// Path: /debug/:z/:x/:y.geojson
+///
+/// Returns the objects indexed for the tile (its ancestor above the
+/// indexed zooms) as a GeoJSON `FeatureCollection`: lines as `LineString`,
+/// closed ways as `Polygon` and nodes as `Point`, with their bounding box,
+/// data file offset and retained tags in the properties. Positions are
+/// rounded to `--vector-precision` decimals; objects that collapse are left out.
+pub async fn handle_debug_geojson_request(
+    State(state): State<AppState>,
+    Path((z, x, y_geojson)): Path<(String, String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    let tile = match parse_object_tile_path(&state, "debug", (&z, &x, &y_geojson), ".geojson") {
+        Ok(tile) => tile,
+        Err(e) => {
+            log::info!("Rejected debug request: {}", e);
+            return e.into_response();
+        }
+    };
+
+    let features: Vec<_> = indexed_offsets(&state, &tile)
+        .into_iter()
+        .filter_map(|offset| geojson_feature(&state.mmap.read_map_object(offset), offset, state.vector_precision))
+        .collect();
+    let collection = serde_json::json!({ "type": "FeatureCollection", "features": features });
+    object_data_response(&state, "application/geo+json", collection.to_string().into(), &headers)
+}
+
+/// GeoJSON feature of a map object, `None` if its geometry collapses at `decimals`
+fn geojson_feature(object: &MapObjectView, offset: MapObjectOffset, decimals: Option<u32>) -> Option<serde_json::Value> {
+    let geometry = match object.kind {
+        ObjectKind::Point => {
+            let point = object.points().first()?;
+            let round = |value: f64| decimals.map_or(value, |decimals| round_coordinate(value, decimals));
+            serde_json::json!({ "type": "Point", "coordinates": [round(point.lon), round(point.lat)] })
+        }
+        kind => {
+            let coordinates = geojson_coordinates(object.points(), decimals)?;
+            let closed = object.points().first() == object.points().last();
+            if kind == ObjectKind::Polygon && closed {
+                serde_json::json!({ "type": "Polygon", "coordinates": [coordinates] })
+            } else {
+                serde_json::json!({ "type": "LineString", "coordinates": coordinates })
+            }
+        }
+    };
+    let bbox = object.bounding_box();
+    let tags: serde_json::Map<String, serde_json::Value> =
+        object.tags().map(|(key, value)| (key.to_string(), value.into())).collect();
+    Some(serde_json::json!({
+        "type": "Feature",
+        "geometry": geometry,
+        "properties": {
+            "bbox": [bbox.min.lon, bbox.min.lat, bbox.max.lon, bbox.max.lat],
+            "offset": offset,
+            "tags": tags,
+        },
+    }))
+}
+
+/// Parse `/{prefix}/{z}/{x}/{y}{extension}` path segments into an XYZ tile
+fn parse_object_tile_path(
+    state: &AppState,
+    prefix: &str,
+    (z, x, y_ext): (&str, &str, &str),
+    extension: &str,
+) -> Result<Tile, TilePathError> {
+    let malformed = || TilePathError::Malformed(format!("/{}/{}/{}/{}", prefix, z, x, y_ext));
+    let y = y_ext.strip_suffix(extension).ok_or_else(malformed)?;
+    let (tile, _) = parse_tile_path(z, x, &format!("{}.png", y), state.max_request_zoom).map_err(|e| match e {
+        TilePathError::Malformed(_) => malformed(),
+        e => e,
+    })?;
+    Ok(state.tile_origin.to_xyz(&state.tile_scheme.to_xyz(&tile)))
+}
+
+/// Distinct objects indexed for `tile` (its ancestor above the indexed zooms), in file order
+fn indexed_offsets(state: &AppState, tile: &Tile) -> Vec<MapObjectOffset> {
+    let mut offsets = state.data.get(&lookup_tile(tile, state.data.max_zoom)).cloned().unwrap_or_default();
+    offsets.sort_unstable();
+    offsets.dedup();
+    offsets
+}
+
+/// Response with data read from the index, with the tile caching headers
+fn object_data_response(state: &AppState, content_type: &'static str, data: Bytes, headers: &HeaderMap) -> Response {
+    let cache_control = format!("public, max-age={}", state.max_age_secs);
+    let response = (
+        [(header::CONTENT_TYPE, content_type), (header::CACHE_CONTROL, &cache_control), (header::ETAG, &etag(&data))],
+        data,
+    )
+        .into_response();
+    let if_none_match = headers.get(header::IF_NONE_MATCH).and_then(|value| value.to_str().ok());
+    not_modified_response(response, if_none_match)
+}
+
+/// Header telling clients how a tile was served with `--tile-cache`: `hit`,
+/// `miss`, or `stale` for a stale tile that is being refreshed
+pub const TILE_CACHE_HEADER: HeaderName = HeaderName::from_static("x-tile-cache");
+
+/// Milliseconds the renderer took for a tile, on responses that rendered it
+/// (not on cache hits)
+pub const RENDER_TIME_HEADER: HeaderName = HeaderName::from_static("x-render-time-ms");
+
+/// Serve a valid tile, as an image or with `datauri` as an HTML page
+async fn tile_response(
+    state: &AppState,
+    tile: Tile,
+    tile_size: u32,
+    format: TileFormat,
+    params: &HashMap<String, String>,
+) -> Result<Response, StatusCode> {
+    let datauri = parse_flag(params.get("datauri").map(|s| s.as_str()))?;
+    let response = encoded_tile_response(state, tile, tile_size, format, params).await?;
+    if !datauri {
+        return Ok(response);
+    }
+    let title = format!("Tile {}/{}/{}", tile.z, tile.x, tile.y);
+    data_uri_response(response, &title).await
+}
+
+/// Serve a valid tile from the cache, or render and encode it, or answer
+/// per the out-of-coverage policy
+///
+/// `format` is from `negotiate_format`; masks are always PNG. Tiles without
+/// data are a transparent tile, or 204 No Content with `?nocontent=1`.
+async fn encoded_tile_response(
+    state: &AppState,
+    tile: Tile,
+    tile_size: u32,
+    format: TileFormat,
+    params: &HashMap<String, String>,
+) -> Result<Response, StatusCode> {
+    let detail = parse_detail(params.get("detail").map(|s| s.as_str()))?;
+    let mask = parse_flag(params.get("mask").map(|s| s.as_str()))?;
+    let filter = parse_filter(state, params.get("filter").map(|s| s.as_str()))?;
+    let no_content = parse_flag(params.get("nocontent").map(|s| s.as_str()))?;
+    let format = if mask { TileFormat::Png } else { format };
+
+    let tile = state.tile_origin.to_xyz(&tile);
+
+    if let Some(response) = out_of_coverage_response(state, &tile, tile_size, mask, format) {
+        let data = response?;
+        return Ok(tile_data_response(state, data, format, None));
+    }
+
+    let key = TileCacheKey { tile, tile_size, detail, mask, format };
+    let (data, cache_status, stats) = match &state.tile_cache {
+        Some(cache) if filter.is_none() => {
+            // Read before rendering, so data changes during the render leave the entry stale
+            let version = cache.data_version();
+            match cache.get(&key, version) {
+                CacheLookup::Fresh(data) => {
+                    state.tile_metrics.record_cache_lookup(true);
+                    (data, Some("hit"), None)
+                }
+                CacheLookup::Stale(data) if cache.stale_while_revalidate() => {
+                    state.tile_metrics.record_cache_lookup(true);
+                    let refresh_state = state.clone();
+                    cache.spawn_refresh(key, version, async move {
+                        render_tile_data(&refresh_state, &key, None).await.ok().map(|rendered| rendered.data)
+                    });
+                    (data, Some("stale"), None)
+                }
+                CacheLookup::Stale(_) | CacheLookup::Miss => {
+                    state.tile_metrics.record_cache_lookup(false);
+                    let rendered = match render_tile_data(state, &key, None).await {
+                        Ok(rendered) => rendered,
+                        Err(failure) => return failure_response(state, &key, failure),
+                    };
+                    cache.insert(key, rendered.data.clone(), version);
+                    (rendered.data, Some("miss"), Some(rendered.stats))
                 }
             }
+        }
+        _ => match render_tile_data(state, &key, filter.as_ref()).await {
+            Ok(rendered) => (rendered.data, None, Some(rendered.stats)),
+            Err(failure) => return failure_response(state, &key, failure),
+        },
+    };
 
-            let renderer = renderer_opt.as_mut().unwrap();
-            renderer
-                .render_tile(&tile, &state.data, &state.mmap)
-                .map_err(|e| {
-                    log::error!("Failed to render 512px tile: {}", e);
-                    StatusCode::INTERNAL_SERVER_ERROR
-                })
-        })?
+    let mut response = if !data.is_empty() {
+        tile_data_response(state, data, format, cache_status)
+    } else if no_content {
+        let cache_control = format!("public, max-age={}", state.max_age_secs);
+        (StatusCode::NO_CONTENT, [(header::CACHE_CONTROL, cache_control)]).into_response()
     } else {
-        RENDERER_256.with(|renderer_cell| {
-            let mut renderer_opt = renderer_cell.lock().unwrap();
-
-            // Initialize 256px renderer if not yet created
-            if renderer_opt.is_none() {
-                let max_points = state.data.max_points;
-                match VulkanRenderer::new(max_points, state.shader_type) {
-                    Ok(renderer) => {
-                        *renderer_opt = Some(renderer);
-                    }
-                    Err(e) => {
-                        log::error!("Failed to create 256px Vulkan renderer: {}", e);
-                        return Err(StatusCode::INTERNAL_SERVER_ERROR);
-                    }
-                }
+        let data = nodata_tile(state, tile_size, mask, format)?;
+        tile_data_response(state, data, format, cache_status)
+    };
+    if let Some(stats) = stats {
+        stats.insert_headers(response.headers_mut());
+    }
+    Ok(response)
+}
+
+/// Handle metatile request
+/// Path: /metatile/:z/:x/:y/:n.png
+///
+/// Renders the `n`×`n` tiles from `x`, `y` (towards increasing tile
+/// numbers in the configured tile origin and scheme) in one pass, see
+/// `VulkanRenderer::render_metatile`, and returns the center tile as PNG.
+/// Meant for checking metatiles against `/tile` renders: plain tiles only,
+/// uncached, without out-of-coverage handling.
+pub async fn handle_metatile_request(
+    State(state): State<AppState>,
+    Path((z, x, y, n_png)): Path<(String, String, String, String)>,
+) -> Response {
+    let (origin, n) = match parse_metatile_path(&z, &x, &y, &n_png, state.max_request_zoom) {
+        Ok(metatile) => metatile,
+        Err(e) => {
+            log::info!("Rejected metatile request: {}", e);
+            return e.into_response();
+        }
+    };
+    match render_metatile_center(&state, &origin, n).await {
+        Ok(data) => ([(header::CONTENT_TYPE, TileFormat::Png.mime_type())], data).into_response(),
+        Err(failure) => failure.status.into_response(),
+    }
+}
+
+/// Render a metatile with a pooled renderer and encode its center tile
+async fn render_metatile_center(state: &AppState, origin: &Tile, n: u32) -> Result<Vec<u8>, RenderFailure> {
+    let (xyz_origin, center) = metatile_layout(state, origin, n);
+    log::info!("Rendering {}x{} metatile {}/{}/{}", n, n, origin.z, origin.x, origin.y);
+
+    let encode_slot = state.encoders.reserve().await;
+    let render_size = n * TILE_SIZE * state.supersample;
+    let (data, mmap) = (state.data.clone(), state.mmap.clone());
+    let (renderer, result) =
+        render_pooled(state, render_size, move |renderer| renderer.render_metatile(&xyz_origin, n, &data, &mmap)).await?;
+    let mut images = match result {
+        Ok(images) => images,
+        Err(e) => {
+            discard_if_broken(renderer);
+            return Err(RenderFailure::new(render_error_status(&e), format!("Failed to render {}x{} metatile: {}", n, n, e)));
+        }
+    };
+    let png = PngEncoding::of(state, renderer.color_format());
+    drop(renderer);
+    let image = images.swap_remove(center);
+
+    let downscale_filter = state.downscale_filter;
+    encode_slot
+        .encode(move || {
+            let image = if image.width() > TILE_SIZE {
+                downscale(&image, TILE_SIZE, downscale_filter)
+            } else {
+                image
+            };
+            encode_rgba(png, &image, TileFormat::Png)
+        })
+        .await
+        .map_err(|e| RenderFailure::new(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to encode PNG: {}", e)))
+}
+
+/// The XYZ tile in the northwest corner of the `n`×`n` metatile requested
+/// at `origin`, and the index of its center tile in the rendered images
+///
+/// Requested rows run from `origin.y` up, which is southwards or northwards
+/// depending on the tile origin and scheme, so the northwest corner is the
+/// smaller XYZ row of the first and last requested ones. Either way the
+/// rendered rows are the requested ones, which `parse_metatile_path` has
+/// checked to lie in the grid.
+fn metatile_layout(state: &AppState, origin: &Tile, n: u32) -> (Tile, usize) {
+    let to_xyz = |x, y| state.tile_origin.to_xyz(&state.tile_scheme.to_xyz(&Tile::new(x, y, origin.z)));
+    let (first, last) = (to_xyz(origin.x, origin.y), to_xyz(origin.x + n - 1, origin.y + n - 1));
+    let northwest = Tile::new(first.x, first.y.min(last.y), origin.z);
+    let center = to_xyz(origin.x + n / 2, origin.y + n / 2);
+    (northwest, ((center.y - northwest.y) * n + center.x - northwest.x) as usize)
+}
+
+/// Failed render of a tile, see `render_tile_data`
+#[derive(Debug)]
+struct RenderFailure {
+    status: StatusCode,
+    /// What went wrong, as logged and drawn on debug error tiles
+    message: String,
+}
+
+impl RenderFailure {
+    fn new(status: StatusCode, message: String) -> Self {
+        log::error!("{}", message);
+        RenderFailure { status, message }
+    }
+}
+
+/// Check out a pooled renderer, creating it if needed, and size it for `render_size` pixel tiles
+async fn checkout_renderer(state: &AppState, render_size: u32) -> Result<PoolGuard<(), VulkanRenderer>, RenderFailure> {
+    checkout_from(&state.renderers, render_size, false, new_renderer(state, render_size)).await
+}
+
+/// Creates renderers for `render_size` pixel tiles with the state's
+/// options, on whichever thread it's called
+fn new_renderer(state: &AppState, render_size: u32) -> impl Fn() -> Result<VulkanRenderer, VulkanError> + Clone + Send + 'static {
+    let (max_points, shader_type, options) = (state.data.max_points, state.shader_type, renderer_options(state));
+    move || VulkanRenderer::new_with_options(max_points, shader_type, render_size, options.clone())
+}
+
+/// See `checkout_renderer`; with `new` the renderer is always created, see
+/// `Pool::checkout_new`
+async fn checkout_from<R: PooledRenderer + Send + 'static>(
+    pool: &Arc<Pool<(), R>>,
+    render_size: u32,
+    new: bool,
+    create: impl FnOnce() -> Result<R, VulkanError> + Send + 'static,
+) -> Result<PoolGuard<(), R>, RenderFailure> {
+    let create = || run_blocking(create);
+    let checkout = if new { pool.checkout_new((), create).await } else { pool.checkout((), create).await };
+    let mut renderer = checkout.map_err(|e| {
+        let status = match e {
+            PoolError::Timeout(_) => StatusCode::SERVICE_UNAVAILABLE,
+            PoolError::Create(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        RenderFailure::new(status, format!("Failed to get {}px renderer: {}", render_size, e))
+    })?;
+    renderer.set_tile_size(render_size);
+    Ok(renderer)
+}
+
+/// Render with a pooled renderer sized for `render_size` pixel tiles,
+/// without stalling the async worker
+///
+/// When the device was lost the renderer is dropped and `render` retried
+/// once on a new one with its own Vulkan context, so a GPU reset costs a
+/// slow tile rather than an error. Idle renderers are dropped too, as they
+/// predate the reset. Returns the renderer used with the result, see
+/// `discard_if_broken` for failed renders.
+async fn render_pooled<T: Send + 'static>(
+    state: &AppState,
+    render_size: u32,
+    render: impl FnMut(&mut VulkanRenderer) -> Result<T, VulkanError> + Send + 'static,
+) -> Result<(PoolGuard<(), VulkanRenderer>, Result<T, VulkanError>), RenderFailure> {
+    render_pooled_in(&state.renderers, render_size, new_renderer(state, render_size), render).await
+}
+
+/// See `render_pooled`
+async fn render_pooled_in<R: PooledRenderer + Send + 'static, T: Send + 'static>(
+    pool: &Arc<Pool<(), R>>,
+    render_size: u32,
+    create: impl Fn() -> Result<R, VulkanError> + Clone + Send + 'static,
+    render: impl FnMut(&mut R) -> Result<T, VulkanError> + Send + 'static,
+) -> Result<(PoolGuard<(), R>, Result<T, VulkanError>), RenderFailure> {
+    let renderer = checkout_from(pool, render_size, false, create.clone()).await?;
+    let (renderer, render, result) = render_blocking(renderer, render).await;
+    if !matches!(result, Err(VulkanError::DeviceLost)) {
+        return Ok((renderer, result));
+    }
+    log::warn!("Vulkan device lost, retrying the render on a new renderer");
+    PoolGuard::discard(renderer);
+    let dropped = pool.discard_idle();
+    if dropped > 0 {
+        log::warn!("Dropped {} idle renderers created before the device loss", dropped);
+    }
+    let renderer = checkout_from(pool, render_size, true, create).await?;
+    let (renderer, _, result) = render_blocking(renderer, render).await;
+    Ok((renderer, result))
+}
+
+/// Run `render` with a checked out renderer on a blocking thread, see
+/// `run_blocking`, and hand both back with its result
+///
+/// The renderer stays checked out until the render is done, even if the
+/// request is dropped meanwhile.
+async fn render_blocking<R, T, F>(mut renderer: PoolGuard<(), R>, mut render: F) -> (PoolGuard<(), R>, F, Result<T, VulkanError>)
+where
+    R: Send + 'static,
+    T: Send + 'static,
+    F: FnMut(&mut R) -> Result<T, VulkanError> + Send + 'static,
+{
+    run_blocking(move || {
+        let result = render(&mut renderer);
+        (renderer, render, result)
+    })
+    .await
+}
+
+/// A renderer as the pool handling sees it, so tests can stand in for the GPU
+trait PooledRenderer {
+    /// See `VulkanRenderer::set_tile_size`
+    fn set_tile_size(&mut self, tile_size: u32);
+
+    /// See `VulkanRenderer::is_broken`
+    fn is_broken(&self) -> bool;
+}
+
+impl PooledRenderer for VulkanRenderer {
+    fn set_tile_size(&mut self, tile_size: u32) {
+        VulkanRenderer::set_tile_size(self, tile_size)
+    }
+
+    fn is_broken(&self) -> bool {
+        VulkanRenderer::is_broken(self)
+    }
+}
+
+/// Drop a renderer that failed a render if it can't render anymore, so the
+/// pool creates a new one, see `VulkanRenderer::is_broken`
+fn discard_if_broken<R: PooledRenderer>(renderer: PoolGuard<(), R>) {
+    if renderer.is_broken() {
+        log::warn!("Replacing broken renderer");
+        PoolGuard::discard(renderer);
+    }
+}
+
+/// Status of a failed render: 503 when the GPU timed out or was lost
+/// (again), so clients retry
+fn render_error_status(error: &VulkanError) -> StatusCode {
+    match error {
+        VulkanError::RenderTimeout(_) | VulkanError::DeviceLost => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// How long a tile took to render and how many objects it read
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct RenderStats {
+    render_time: Duration,
+    objects: usize,
+}
+
+impl RenderStats {
+    /// Add `RENDER_TIME_HEADER` and `OBJECT_COUNT_HEADER`
+    fn insert_headers(&self, headers: &mut HeaderMap) {
+        let millis = format!("{:.1}", self.render_time.as_secs_f64() * 1000.0);
+        headers.insert(RENDER_TIME_HEADER, HeaderValue::from_str(&millis).expect("a number is a valid header value"));
+        headers.insert(OBJECT_COUNT_HEADER, HeaderValue::from(self.objects));
+    }
+}
+
+/// Encoded tile from `render_tile_data`
+struct RenderedTile {
+    /// Empty for tiles without data, see `nodata_tile`
+    data: Bytes,
+    stats: RenderStats,
+}
+
+/// Render a tile with a pooled renderer and encode it on the encode threads
+///
+/// Tiles where nothing was drawn are empty, see `nodata_tile`.
+async fn render_tile_data(state: &AppState, key: &TileCacheKey, filter: Option<&TagFilter>) -> Result<RenderedTile, RenderFailure> {
+    let TileCacheKey { tile, tile_size, detail, mask, format } = *key;
+    log::info!("Rendering tile {}/{}/{} at {}px (detail +{})", tile.z, tile.x, tile.y, tile_size, detail);
+
+    // Reserve encoding first, so rendered framebuffers can't pile up behind it
+    let encode_slot = state.encoders.reserve().await;
+
+    // Check out a renderer for this tile size, waiting while all are busy
+    let render_size = tile_size * state.supersample;
+    let (render_state, filter) = (state.clone(), filter.cloned());
+    let (renderer, result) = render_pooled(state, render_size, move |renderer| {
+        // Render and encode count against the budget; waiting for a renderer doesn't
+        let started = Instant::now();
+        render_with_state(renderer, &render_state, &tile, detail, filter.as_ref()).map(|image| (image, started))
+    })
+    .await?;
+    let (image, started) = match result {
+        Ok(rendered) => rendered,
+        Err(e) => {
+            state.tile_metrics.record_render_error();
+            discard_if_broken(renderer);
+            return Err(RenderFailure::new(render_error_status(&e), format!("Failed to render {}px tile: {}", tile_size, e)));
+        }
+    };
+    let stats = RenderStats { render_time: started.elapsed(), objects: renderer.last_object_count() };
+    state.tile_metrics.record_render(stats.render_time);
+    let vertex_count = renderer.last_vertex_count();
+    let png = PngEncoding::of(state, renderer.color_format());
+    drop(renderer);
+    log::debug!("Rendered tile {} in {:?} from {} objects", tile, stats.render_time, stats.objects);
+    if vertex_count == 0 {
+        // Nothing drawn: no encoding, the response is the shared no-data tile
+        log::debug!("Tile {} has no data", tile);
+        state.tile_metrics.record_empty();
+        state.render_budget.record(&tile, started.elapsed(), vertex_count);
+        return Ok(RenderedTile { data: Bytes::new(), stats });
+    }
+
+    let downscale_filter = state.downscale_filter;
+    let encoded = encode_slot
+        .encode(move || {
+            let image = if render_size > tile_size {
+                downscale(&image, tile_size, downscale_filter)
+            } else {
+                image
+            };
+            if mask {
+                encode_png(&coverage_mask(&image))
+            } else {
+                encode_rgba(png, &image, format)
             }
+        })
+        .await;
+    let data = encoded
+        .map_err(|e| RenderFailure::new(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to encode {}: {}", format, e)))?;
+    state.render_budget.record(&tile, started.elapsed(), vertex_count);
+
+    Ok(RenderedTile { data: data.into(), stats })
+}
+
+/// Background of debug error tiles (light red)
+pub const ERROR_TILE_COLOR: [u8; 4] = [255, 224, 224, 255];
+
+/// Text color of debug error tiles (dark red)
+pub const ERROR_TEXT_COLOR: [u8; 4] = [160, 0, 0, 255];
+
+/// Respond to a failed render with its status
+///
+/// With `--debug-error-tiles` the body is a tile in the requested format
+/// showing the tile coordinates and the error, so a broken tile explains
+/// itself in a map view. Error tiles are never cached.
+fn failure_response(state: &AppState, key: &TileCacheKey, failure: RenderFailure) -> Result<Response, StatusCode> {
+    if !state.debug_error_tiles {
+        return Err(failure.status);
+    }
+    let size = key.tile_size + 2 * buffer_pixels(key.tile_size, state.buffer_fraction);
+    // Coordinates as requested, in the request's tile origin
+    let tile = state.tile_origin.to_xyz(&key.tile);
+    let image = debug_error_tile(size, &format!("Tile {}", tile), &failure);
+    let data = encode_rgba(PngEncoding::default(), &image, key.format).map_err(|e| {
+        log::error!("Failed to encode error tile: {}", e);
+        failure.status
+    })?;
+    Ok((
+        failure.status,
+        [(header::CONTENT_TYPE, key.format.mime_type()), (header::CACHE_CONTROL, "no-store")],
+        data,
+    )
+        .into_response())
+}
+
+/// Image of a `size` pixel error tile titled `title`
+fn debug_error_tile(size: u32, title: &str, failure: &RenderFailure) -> RgbaImage {
+    let mut image = RgbaImage::from_pixel(size, size, image::Rgba(ERROR_TILE_COLOR));
+    let scale = (size / TILE_SIZE).max(1);
+    let margin = 4 * scale;
+    let columns = ((size - 2 * margin) / (text::ADVANCE * scale)) as usize;
+
+    let lines = std::iter::once(title.to_string())
+        .chain(wrap_text(&failure.status.to_string(), columns))
+        .chain(wrap_text(&failure.message, columns));
+    for (i, line) in lines.enumerate() {
+        let y = margin + i as u32 * text::LINE_HEIGHT * scale;
+        draw_text(&mut image, margin, y, scale, &line, image::Rgba(ERROR_TEXT_COLOR));
+    }
+    image
+}
+
+/// Encoded tile response, with `TILE_CACHE_HEADER` if the tile cache was
+/// consulted and `Vary: Accept` if the format was negotiated
+///
+/// Tiles are public for `--max-age` seconds and tagged with a hash of their
+/// bytes, so clients and CDNs can revalidate them, see `not_modified_response`.
+fn tile_data_response(state: &AppState, data: Bytes, format: TileFormat, cache_status: Option<&'static str>) -> Response {
+    let cache_control = format!("public, max-age={}", state.max_age_secs);
+    let mut response = (
+        [(header::CONTENT_TYPE, format.mime_type()), (header::CACHE_CONTROL, &cache_control), (header::ETAG, &etag(&data))],
+        data,
+    )
+        .into_response();
+    if let Some(status) = cache_status {
+        response.headers_mut().insert(TILE_CACHE_HEADER, HeaderValue::from_static(status));
+    }
+    if state.format_preference.is_negotiated() {
+        response.headers_mut().insert(header::VARY, HeaderValue::from_static("accept"));
+    }
+    response
+}
 
-            let renderer = renderer_opt.as_mut().unwrap();
-            renderer
-                .render_tile(&tile, &state.data, &state.mmap)
-                .map_err(|e| {
-                    log::error!("Failed to render 256px tile: {}", e);
-                    StatusCode::INTERNAL_SERVER_ERROR
-                })
-        })?
+/// Strong entity tag of a response body
+///
+/// `FxHasher` has no random seed, so tags stay the same across restarts
+/// and servers as long as the bytes do.
+fn etag(data: &[u8]) -> String {
+    let mut hasher = rustc_hash::FxHasher::default();
+    hasher.write(data);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// Whether an `If-None-Match` header value lists `etag` (or is `*`)
+///
+/// Uses weak comparison, as required for `If-None-Match`.
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match.trim() == "*"
+        || if_none_match.split(',').any(|tag| {
+            let tag = tag.trim();
+            tag.strip_prefix("W/").unwrap_or(tag) == etag
+        })
+}
+
+/// Turn a successful response whose `ETag` the client already has into
+/// 304 Not Modified without a body
+fn not_modified_response(mut response: Response, if_none_match: Option<&str>) -> Response {
+    let matches = match (if_none_match, response.headers().get(header::ETAG).and_then(|value| value.to_str().ok())) {
+        (Some(if_none_match), Some(etag)) => response.status() == StatusCode::OK && etag_matches(if_none_match, etag),
+        _ => false,
     };
+    if matches {
+        *response.status_mut() = StatusCode::NOT_MODIFIED;
+        *response.body_mut() = axum::body::Body::empty();
+        response.headers_mut().remove(header::CONTENT_TYPE);
+    }
+    response
+}
 
-    // Encode to PNG
-    let png_data = encode_png(&image).map_err(|e| {
-        log::error!("Failed to encode PNG: {}", e);
+/// Replace an encoded tile response body by an HTML page embedding it as a `data:` URI
+///
+/// Other headers such as `TILE_CACHE_HEADER` and `Vary` are kept.
+async fn data_uri_response(response: Response, title: &str) -> Result<Response, StatusCode> {
+    let (mut parts, body) = response.into_parts();
+    let mime_type = parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+    let data = axum::body::to_bytes(body, usize::MAX).await.map_err(|e| {
+        log::error!("Failed to read encoded tile: {}", e);
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
+    let page = data_uri_page(title, &data_uri(&mime_type, &data));
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"));
+    Ok(Response::from_parts(parts, page.into()))
+}
+
+/// Short-circuit tiles outside the data bounds according to the coverage policy
+///
+/// Returns `None` if the tile should be rendered, otherwise the 404 error or
+/// the encoded nodata tile (an empty coverage mask with `mask`).
+fn out_of_coverage_response(
+    state: &AppState,
+    tile: &Tile,
+    tile_size: u32,
+    mask: bool,
+    format: TileFormat,
+) -> Option<Result<Bytes, StatusCode>> {
+    if state.out_of_coverage == OutOfCoverage::Render {
+        return None;
+    }
+
+    let mut bounds = state.data.bounds;
+    if let Some(base) = &state.diff_base {
+        bounds = match (bounds, base.data.bounds) {
+            (Some(a), Some(b)) => Some(a.union(&b)),
+            (a, b) => a.or(b),
+        };
+    }
+
+    let buffer_px = buffer_pixels(tile_size, state.buffer_fraction);
+    let tile_bbox = get_buffered_bounding_box(tile, buffer_px as f64 / tile_size as f64);
+    if bounds.is_some_and(|bounds| bounds.overlaps(&tile_bbox)) {
+        return None;
+    }
+
+    log::info!("Tile {} is outside the data bounds ({:?})", tile, state.out_of_coverage);
+    match state.out_of_coverage {
+        OutOfCoverage::NotFound => Some(Err(StatusCode::NOT_FOUND)),
+        OutOfCoverage::NoData => Some(nodata_tile(state, tile_size, mask, format)),
+        OutOfCoverage::Render => None,
+    }
+}
+
+/// Transparent tile (or empty mask) including the buffer, encoded once per size and format
+fn nodata_tile(state: &AppState, tile_size: u32, mask: bool, format: TileFormat) -> Result<Bytes, StatusCode> {
+    let size = tile_size + 2 * buffer_pixels(tile_size, state.buffer_fraction);
+    state
+        .nodata_tiles
+        .get_or_encode(size, mask, format, || {
+            if mask {
+                encode_png(&GrayImage::new(size, size))
+            } else {
+                // Fully transparent, so there's no color space to tag
+                let png = PngEncoding::of(state, ColorFormat::Unorm);
+                encode_rgba(png, &RgbaImage::from_pixel(size, size, image::Rgba(NODATA_COLOR)), format)
+            }
+        })
+        .map_err(|e| {
+            log::error!("Failed to encode {}: {}", format, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// How tiles are encoded as PNG, see `encode_rgba`
+#[derive(Debug, Clone, Copy, Default)]
+struct PngEncoding {
+    /// Palette PNG if the tile has few enough colors (`--png-indexed`)
+    indexed: bool,
+    /// Mark the PNG as sRGB, for images rendered with `ColorFormat::Srgb`
+    srgb: bool,
+}
+
+impl PngEncoding {
+    /// Encoding of images in `color_format`, as rendered: the renderer's
+    /// `color_format()` rather than `--color-format`, which it may not support
+    fn of(state: &AppState, color_format: ColorFormat) -> PngEncoding {
+        PngEncoding { indexed: state.png_indexed, srgb: color_format.is_srgb() }
+    }
+}
 
-    Ok(([(header::CONTENT_TYPE, "image/png")], png_data))
+/// Encode a tile in `format`; PNG is truecolor or, with `--png-indexed`, palette
+fn encode_rgba(png: PngEncoding, image: &RgbaImage, format: TileFormat) -> Result<Vec<u8>, image::ImageError> {
+    match format {
+        TileFormat::Png => match (png.indexed, png.srgb) {
+            (true, true) => encode_png_indexed_srgb(image),
+            (true, false) => encode_png_indexed(image),
+            (false, true) => encode_png_srgb(image),
+            (false, false) => encode_png(image),
+        },
+        _ => format.encode(image),
+    }
+}
+
+fn renderer_options(state: &AppState) -> RendererOptions {
+    RendererOptions {
+        context: state.vulkan,
+        buffer_fraction: state.buffer_fraction,
+        msaa_samples: state.msaa_samples,
+        lod: state.lod,
+        vertex_budgets: state.vertex_budgets.clone(),
+        max_vertex_buffer_capacity: Some(state.max_vertex_buffer),
+        wrap_antimeridian: state.wrap_antimeridian,
+        tile_origin: state.tile_origin,
+        style: state.style.clone(),
+        line_colors: state.line_colors.clone(),
+        clip_region: state.clip_region.clone(),
+        overlay: state.overlay,
+        crisp_lines: state.crisp_lines,
+        line_widths: state.line_widths,
+        casing: state.casing,
+        point_decimation_px: state.point_decimation_px,
+        precise_overlap: state.precise_overlap,
+        background: None,
+        render_timeout: Some(state.render_timeout),
+        color_format: state.color_format,
+        fill_polygons: state.fill_polygons,
+        fill_msaa_samples: state.fill_msaa_samples,
+        fill_max_objects: state.fill_max_objects,
+    }
+}
+
+/// Run blocking GPU work (renderer creation, rendering) on Tokio's blocking
+/// threads, so the async workers keep serving other requests meanwhile
+///
+/// Up to `--renderer-pool-size` tiles render at once whatever the number of
+/// workers and the runtime flavor. A panic in `f` resumes in the caller.
+async fn run_blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(value) => value,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => panic!("blocking task failed: {}", e),
+    }
+}
+
+/// Render a regular tile, or a diff tile when a diff base is configured
+fn render_with_state(
+    renderer: &mut VulkanRenderer,
+    state: &AppState,
+    tile: &Tile,
+    detail: u32,
+    filter: Option<&TagFilter>,
+) -> Result<RgbaImage, VulkanError> {
+    match &state.diff_base {
+        Some(base) => renderer.render_diff_tile(
+            tile,
+            detail,
+            (&state.data, &state.mmap),
+            (&base.data, &base.mmap),
+        ),
+        None => renderer.render_tile_filtered(tile, detail, filter, &state.data, &state.mmap),
+    }
+}
+
+/// Handle metrics request in the Prometheus text format
+/// Path: /metrics
+pub async fn handle_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let metrics = state.renderers.metrics();
+    log::debug!("Renderer pool: {:?}", metrics);
+    let mut body = format_pool_metrics(&metrics);
+    body.push_str(&format_encode_metrics(&state.encoders.metrics()));
+    body.push_str(&format_tile_metrics(&state.tile_metrics.metrics()));
+    if let Some(budget) = state.render_budget.metrics() {
+        body.push_str(&format_budget_metrics(&budget));
+    }
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        body,
+    )
+}
+
+/// Append one metric in the Prometheus text format
+fn push_metric(out: &mut String, name: &str, kind: &str, help: &str, value: String) {
+    out.push_str(&format!("# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"));
+}
+
+fn format_pool_metrics(metrics: &PoolMetrics) -> String {
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: String| push_metric(&mut out, name, kind, help, value);
+    metric("renderer_pool_size", "gauge", "Maximum number of renderers", metrics.size.to_string());
+    metric("renderer_pool_busy", "gauge", "Renderers currently checked out", metrics.busy.to_string());
+    metric("renderer_pool_idle", "gauge", "Created renderers waiting for requests", metrics.idle.to_string());
+    metric("renderer_pool_acquisitions_total", "counter", "Renderer checkouts", metrics.acquisitions.to_string());
+    metric(
+        "renderer_pool_acquire_wait_seconds_total",
+        "counter",
+        "Time spent waiting for a free renderer",
+        metrics.wait_total.as_secs_f64().to_string(),
+    );
+    metric(
+        "renderer_pool_acquire_wait_seconds_max",
+        "gauge",
+        "Longest wait for a free renderer",
+        metrics.wait_max.as_secs_f64().to_string(),
+    );
+    metric(
+        "renderer_pool_acquire_timeouts_total",
+        "counter",
+        "Requests that gave up waiting for a renderer",
+        metrics.timeouts.to_string(),
+    );
+    out
+}
+
+fn format_encode_metrics(metrics: &EncodeMetrics) -> String {
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: String| push_metric(&mut out, name, kind, help, value);
+    metric("encode_threads", "gauge", "Threads encoding tiles", metrics.threads.to_string());
+    metric("encode_queue_capacity", "gauge", "Tiles that may be rendered for or in encoding", metrics.capacity.to_string());
+    metric("encode_queue_depth", "gauge", "Tiles rendered for, waiting for or in encoding", metrics.depth.to_string());
+    metric("encode_queue_depth_max", "gauge", "Highest encode queue depth", metrics.depth_max.to_string());
+    metric("encode_queue_waiting", "gauge", "Requests waiting for encode capacity", metrics.waiting.to_string());
+    metric("encode_jobs_total", "counter", "Tiles encoded", metrics.encoded.to_string());
+    out
+}
+
+fn format_tile_metrics(metrics: &TileMetricsSnapshot) -> String {
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: String| push_metric(&mut out, name, kind, help, value);
+    metric("tiles_rendered_total", "counter", "Tiles rendered", metrics.rendered.to_string());
+    metric("tile_render_errors_total", "counter", "Tiles that failed to render", metrics.render_errors.to_string());
+    metric("tiles_empty_total", "counter", "Rendered tiles without data, served transparent or as 204", metrics.empty.to_string());
+    metric("tile_cache_hits_total", "counter", "Tiles served from the tile cache", metrics.cache_hits.to_string());
+    metric("tile_cache_misses_total", "counter", "Tile cache lookups that had to render", metrics.cache_misses.to_string());
+
+    // The histogram has one sample per bucket, the sum and the count
+    let name = "tile_render_duration_seconds";
+    out.push_str(&format!("# HELP {name} Time spent rendering a tile, without encoding
+# TYPE {name} histogram
+"));
+    for (bound, count) in RENDER_SECONDS_BUCKETS.iter().zip(&metrics.render_buckets) {
+        out.push_str(&format!("{name}_bucket{{le=\"{bound}\"}} {count}\n"));
+    }
+    out.push_str(&format!("{name}_bucket{{le=\"+Inf\"}} {}\n", metrics.rendered));
+    out.push_str(&format!("{name}_sum {}\n{name}_count {}\n", metrics.render_sum.as_secs_f64(), metrics.rendered));
+    out
+}
+
+fn format_budget_metrics(metrics: &BudgetMetrics) -> String {
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: String| push_metric(&mut out, name, kind, help, value);
+    metric(
+        "render_budget_seconds",
+        "gauge",
+        "Render and encode time budget per tile",
+        metrics.budget.as_secs_f64().to_string(),
+    );
+    metric("render_budget_renders_total", "counter", "Tiles checked against the budget", metrics.renders.to_string());
+    metric("render_budget_overruns_total", "counter", "Tiles that took longer than the budget", metrics.overruns.to_string());
+    out
+}
+
+/// Check the `Authorization: Bearer` token of an admin request against `--admin-token`
+///
+/// 403 if no admin token is configured, 401 if the token is missing or wrong.
+fn check_admin(state: &AppState, headers: &HeaderMap) -> Result<(), StatusCode> {
+    let Some(expected) = &state.admin_token else {
+        return Err(StatusCode::FORBIDDEN);
+    };
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    // Compare in constant time
+    let matches = token.len() == expected.len()
+        && token.bytes().zip(expected.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0;
+    if matches {
+        Ok(())
+    } else {
+        Err(StatusCode::UNAUTHORIZED)
+    }
+}
+
+/// Tiles of a pin or unpin request, in URL numbering, converted to XYZ
+fn pin_request_tiles(state: &AppState, coords: &[String]) -> Result<Vec<Tile>, TilePathError> {
+    coords
+        .iter()
+        .map(|coords| parse_tile_coords(coords, state.max_request_zoom).map(|tile| state.tile_origin.to_xyz(&tile)))
+        .collect()
+}
+
+/// Handle cache pin request
+/// Path: POST /cache/pin with a JSON array of `z/x/y` strings
+///
+/// Pins the tiles in the tile cache and renders their 256px tiles in every
+/// `--format-preference` format, unless fresh ones are cached. Other
+/// variants (@2x, masks) are pinned once requested. Tiles stay pinned if
+/// rendering fails and are rendered on their next request instead.
+///
+/// Requires `--admin-token` (see `check_admin`) and `--tile-cache`, 409
+/// without a cache. Responds with the number of pinned tiles.
+pub async fn handle_cache_pin(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(coords): Json<Vec<String>>,
+) -> Result<Response, StatusCode> {
+    check_admin(&state, &headers)?;
+    let Some(cache) = &state.tile_cache else {
+        return Err(StatusCode::CONFLICT);
+    };
+    let tiles = match pin_request_tiles(&state, &coords) {
+        Ok(tiles) => tiles,
+        Err(e) => return Ok(e.into_response()),
+    };
+
+    for &tile in &tiles {
+        cache.pin(tile);
+    }
+    for &tile in &tiles {
+        for &format in state.format_preference.formats() {
+            let key = TileCacheKey { tile, tile_size: TILE_SIZE, detail: 0, mask: false, format };
+            let version = cache.data_version();
+            if !matches!(cache.get(&key, version), CacheLookup::Fresh(_)) {
+                let rendered = render_tile_data(&state, &key, None).await.map_err(|failure| failure.status)?;
+                cache.insert(key, rendered.data, version);
+            }
+        }
+    }
+    log::info!("Pinned {} tiles, {} pinned in total", tiles.len(), cache.pinned_count());
+    Ok(Json(serde_json::json!({ "pinned": cache.pinned_count() })).into_response())
+}
+
+/// Handle cache unpin request
+/// Path: POST /cache/unpin with a JSON array of `z/x/y` strings
+///
+/// The tiles' entries stay cached but can be evicted again. Same
+/// requirements and response as `handle_cache_pin`.
+pub async fn handle_cache_unpin(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(coords): Json<Vec<String>>,
+) -> Result<Response, StatusCode> {
+    check_admin(&state, &headers)?;
+    let Some(cache) = &state.tile_cache else {
+        return Err(StatusCode::CONFLICT);
+    };
+    let tiles = match pin_request_tiles(&state, &coords) {
+        Ok(tiles) => tiles,
+        Err(e) => return Ok(e.into_response()),
+    };
+
+    for tile in &tiles {
+        cache.unpin(tile);
+    }
+    log::info!("Unpinned {} tiles, {} pinned in total", tiles.len(), cache.pinned_count());
+    Ok(Json(serde_json::json!({ "pinned": cache.pinned_count() })).into_response())
+}
+
+/// Handle readiness probe: renders tile 0/0/0 without encoding it
+/// Path: /healthz
+///
+/// 200 if a renderer could be checked out and rendered, 503 with the error
+/// otherwise, e.g. when the GPU went away.
+pub async fn handle_healthz(State(state): State<AppState>) -> Response {
+    let render_size = TILE_SIZE * state.supersample;
+    let result = match checkout_renderer(&state, render_size).await {
+        Ok(renderer) => {
+            let probe_state = state.clone();
+            let probe = move |renderer: &mut VulkanRenderer| render_with_state(renderer, &probe_state, &Tile::new(0, 0, 0), 0, None);
+            match render_blocking(renderer, probe).await {
+                (_, _, Ok(_)) => Ok(()),
+                (renderer, _, Err(e)) => {
+                    discard_if_broken(renderer);
+                    Err(format!("Failed to render probe tile: {}", e))
+                }
+            }
+        }
+        Err(failure) => Err(failure.message),
+    };
+    match result {
+        Ok(()) => (StatusCode::OK, "ok").into_response(),
+        Err(message) => {
+            log::warn!("Health check failed: {}", message);
+            (StatusCode::SERVICE_UNAVAILABLE, message).into_response()
+        }
+    }
+}
+
+/// Handle liveness probe, without touching Vulkan
+/// Path: /livez
+pub async fn handle_livez() -> &'static str {
+    "ok"
+}
+
+/// Handle TileJSON metadata request, for map clients to bootstrap from
+/// Path: /tiles.json
+///
+/// The tile URL is built from the request's `Host`, and its scheme from
+/// `X-Forwarded-Proto` behind a proxy.
+pub async fn handle_tilejson(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let Some(host) = headers.get(header::HOST).and_then(|host| host.to_str().ok()) else {
+        return (StatusCode::BAD_REQUEST, "Missing Host header").into_response();
+    };
+    let protocol = headers
+        .get("x-forwarded-proto")
+        .and_then(|proto| proto.to_str().ok())
+        .unwrap_or("http");
+    Json(tilejson(&state, &format!("{}://{}", protocol, host))).into_response()
+}
+
+/// TileJSON 3.0 document of the served tiles below `base_url`
+///
+/// Zooms are those of the index, clients overzoom above its highest; the
+/// bounds are the data bounds clamped to the Mercator grid, or the whole
+/// grid without data.
+fn tilejson(state: &AppState, base_url: &str) -> serde_json::Value {
+    let (min_zoom, max_zoom) = state.data.zoom_range().unwrap_or((0, state.data.max_zoom));
+    let bounds = match &state.data.bounds {
+        Some(bounds) => [
+            bounds.min.lon.max(-180.0),
+            bounds.min.lat.max(-MAX_LAT),
+            bounds.max.lon.min(180.0),
+            bounds.max.lat.min(MAX_LAT),
+        ],
+        None => [-180.0, -MAX_LAT, 180.0, MAX_LAT],
+    };
+    let scheme = match state.tile_scheme {
+        TileScheme::Xyz => "xyz",
+        TileScheme::Tms => "tms",
+    };
+    serde_json::json!({
+        "tilejson": "3.0.0",
+        "tiles": [format!("{}/tile/{{z}}/{{x}}/{{y}}.png", base_url)],
+        "scheme": scheme,
+        "minzoom": min_zoom,
+        "maxzoom": max_zoom,
+        "bounds": bounds,
+    })
+}
+
+/// Handle index statistics request
+/// Path: /stats/index
+pub async fn handle_index_stats(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        state.data.report().to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::mmap::MappedData;
+    use crate::data::spatial::TileIndex;
+    use crate::data::types::{BoundingBox, MapObject, Point};
+    use crate::projection::{TileOrigin, TileScheme};
+    use crate::renderer::pipeline::TILE_SIZE_2X;
+    use crate::renderer::renderer::{DEFAULT_RENDER_TIMEOUT, MAX_VERTEX_BUFFER_CAPACITY};
+    use crate::renderer::ShaderType;
+    use crate::server::cache::TileCache;
+    use crate::server::encode::EncodePool;
+
+    /// App state with data bounds around Hamburg
+    fn test_state(out_of_coverage: OutOfCoverage) -> (AppState, tempfile::NamedTempFile) {
+        let data_file = tempfile::NamedTempFile::new().unwrap();
+        let mut data = Vec::new();
+        crate::data::serialization::write_data_header(&mut data).unwrap();
+        data.resize(64, 0);
+        std::fs::write(data_file.path(), data).unwrap();
+
+        let mut index = TileIndex::new();
+        index.extend_bounds(&BoundingBox::new(Point::new(9.9, 53.5), Point::new(10.1, 53.6)));
+
+        let state = AppState {
+            data: Arc::new(index),
+            mmap: Arc::new(MappedData::new(data_file.path()).unwrap()),
+            shader_type: ShaderType::Mercator,
+            vulkan: Default::default(),
+            buffer_fraction: 0.0,
+            diff_base: None,
+            out_of_coverage,
+            lod: Default::default(),
+            vertex_budgets: Default::default(),
+            max_vertex_buffer: MAX_VERTEX_BUFFER_CAPACITY,
+            render_timeout: DEFAULT_RENDER_TIMEOUT,
+            wrap_antimeridian: false,
+            png_indexed: false,
+            overlay: false,
+            crisp_lines: false,
+            line_widths: false,
+            casing: false,
+            color_format: ColorFormat::default(),
+            point_decimation_px: 0.0,
+            precise_overlap: false,
+            format_preference: Default::default(),
+            tile_origin: TileOrigin::TopLeft,
+            tile_scheme: TileScheme::Xyz,
+            renderers: Arc::new(Pool::new(1, None)),
+            render_budget: Default::default(),
+            tile_metrics: Default::default(),
+            nodata_tiles: Default::default(),
+            style: None,
+            line_colors: None,
+            tile_cache: None,
+            clip_region: None,
+            vector_precision: None,
+            admin_token: None,
+            msaa_samples: 1,
+            fill_polygons: false,
+            fill_msaa_samples: None,
+            fill_max_objects: None,
+            supersample: 1,
+            downscale_filter: Default::default(),
+            encoders: Arc::new(EncodePool::new(1, 2)),
+            debug_error_tiles: false,
+            max_age_secs: DEFAULT_MAX_AGE_SECS,
+            max_request_zoom: MAX_ZOOM,
+        };
+        (state, data_file)
+    }
+
+    #[test]
+    fn test_out_of_coverage_policies() {
+        // Far from the data (Pacific), and the tile containing Hamburg
+        let outside = Tile::new(0, 0, 10);
+        let (x, y) = crate::projection::deg2num(53.55, 10.0, 10);
+        let inside = Tile::new(x, y, 10);
+
+        let (state, _file) = test_state(OutOfCoverage::NotFound);
+        assert_eq!(out_of_coverage_response(&state, &outside, TILE_SIZE, false, TileFormat::Png), Some(Err(StatusCode::NOT_FOUND)));
+        assert_eq!(out_of_coverage_response(&state, &inside, TILE_SIZE, false, TileFormat::Png), None);
+
+        let (state, _file) = test_state(OutOfCoverage::NoData);
+        let png = out_of_coverage_response(&state, &outside, TILE_SIZE, false, TileFormat::Png).unwrap().unwrap();
+        let image = image::load_from_memory(&png).unwrap().to_rgba8();
+        assert_eq!(image.dimensions(), (TILE_SIZE, TILE_SIZE));
+        assert!(image.pixels().all(|p| p.0 == NODATA_COLOR));
+        let png = out_of_coverage_response(&state, &outside, TILE_SIZE, true, TileFormat::Png).unwrap().unwrap();
+        let mask = image::load_from_memory(&png).unwrap();
+        assert_eq!(mask.color(), image::ColorType::L8);
+        assert!(mask.to_luma8().pixels().all(|p| p.0 == [0]));
+        assert_eq!(out_of_coverage_response(&state, &inside, TILE_SIZE, false, TileFormat::Png), None);
+
+        // Default: always render
+        let (state, _file) = test_state(OutOfCoverage::default());
+        assert_eq!(out_of_coverage_response(&state, &outside, TILE_SIZE, false, TileFormat::Png), None);
+    }
+
+    #[test]
+    fn test_out_of_coverage_from_str() {
+        assert_eq!("notfound".parse(), Ok(OutOfCoverage::NotFound));
+        assert_eq!("nodata".parse(), Ok(OutOfCoverage::NoData));
+        assert_eq!("render".parse(), Ok(OutOfCoverage::Render));
+        assert!("404".parse::<OutOfCoverage>().is_err());
+    }
+
+    #[test]
+    fn test_format_pool_metrics() {
+        let metrics = PoolMetrics {
+            size: 4,
+            busy: 1,
+            wait_total: std::time::Duration::from_millis(1500),
+            ..Default::default()
+        };
+        let text = format_pool_metrics(&metrics);
+        assert!(text.contains("# TYPE renderer_pool_size gauge\nrenderer_pool_size 4\n"));
+        assert!(text.contains("\nrenderer_pool_busy 1\n"));
+        assert!(text.contains("\nrenderer_pool_acquire_wait_seconds_total 1.5\n"));
+        assert!(text.contains("\nrenderer_pool_acquire_timeouts_total 0\n"));
+    }
+
+    #[test]
+    fn test_format_tile_metrics() {
+        let metrics = crate::server::metrics::TileMetrics::default();
+        metrics.record_render(std::time::Duration::from_millis(20));
+        metrics.record_render(std::time::Duration::from_secs(10));
+        metrics.record_cache_lookup(false);
+        let text = format_tile_metrics(&metrics.metrics());
+        assert!(text.contains("# TYPE tiles_rendered_total counter\ntiles_rendered_total 2\n"));
+        assert!(text.contains("\ntile_cache_misses_total 1\n"));
+        assert!(text.contains("# TYPE tile_render_duration_seconds histogram\n"));
+        assert!(text.contains("\ntile_render_duration_seconds_bucket{le=\"0.01\"} 0\n"));
+        assert!(text.contains("\ntile_render_duration_seconds_bucket{le=\"0.025\"} 1\n"));
+        assert!(text.contains("\ntile_render_duration_seconds_bucket{le=\"5\"} 1\n"));
+        assert!(text.contains("\ntile_render_duration_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(text.ends_with("\ntile_render_duration_seconds_sum 10.02\ntile_render_duration_seconds_count 2\n"));
+    }
+
+    #[test]
+    fn test_run_blocking_frees_the_worker() {
+        use std::time::Duration;
+
+        // A long render doesn't hold up other requests on the only worker,
+        // nor on a single-threaded runtime
+        let runtimes = [
+            tokio::runtime::Builder::new_multi_thread().worker_threads(1).enable_time().build().unwrap(),
+            tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap(),
+        ];
+        for runtime in runtimes {
+            runtime.block_on(async {
+                let render = tokio::spawn(run_blocking(|| std::thread::sleep(Duration::from_millis(500))));
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                let request = tokio::spawn(async { 42 });
+                let answered = tokio::time::timeout(Duration::from_millis(250), request).await;
+                assert_eq!(answered.expect("request waited for the render").unwrap(), 42);
+                assert!(!render.is_finished());
+                render.await.unwrap();
+                assert_eq!(run_blocking(|| 7).await, 7);
+            });
+        }
+    }
+
+    #[test]
+    fn test_parse_tile_path() {
+        assert_eq!(parse_tile_path("2", "1", "3.png", MAX_ZOOM), Ok((Tile::new(1, 3, 2), TILE_SIZE)));
+        assert_eq!(parse_tile_path("2", "1", "3@2x.png", MAX_ZOOM), Ok((Tile::new(1, 3, 2), TILE_SIZE_2X)));
+        assert_eq!(parse_tile_path("2", "1", "3@2x.webp", MAX_ZOOM), Ok((Tile::new(1, 3, 2), TILE_SIZE_2X)));
+        assert_eq!((path_format("3.webp"), path_format("3@2x.png")), (Some(TileFormat::WebP), None));
+        assert_eq!(parse_tile_path("2", "1", "3@1x.png", MAX_ZOOM), Ok((Tile::new(1, 3, 2), TILE_SIZE)));
+        assert_eq!(parse_tile_path("2", "1", "3@4x.png", MAX_ZOOM), Ok((Tile::new(1, 3, 2), 4 * TILE_SIZE)));
+        assert_eq!(parse_tile_path("2", "1", "3@5x.png", MAX_ZOOM), Err(TilePathError::ScaleOutOfRange(5)));
+        assert_eq!(parse_tile_path("2", "1", "3@0x.png", MAX_ZOOM), Err(TilePathError::ScaleOutOfRange(0)));
+
+        for (z, x, y) in [("abc", "1", "2.png"), ("2", "-1", "0.png"), ("2", "1", "2.jpg"), ("2", "1", ".png"), ("2", "1", "3@x.png"), ("2", "1", "3@2.png")] {
+            assert!(matches!(parse_tile_path(z, x, y, MAX_ZOOM), Err(TilePathError::Malformed(_))), "{}/{}/{}", z, x, y);
+        }
+
+        assert_eq!(parse_tile_path("2", "99", "0.png", MAX_ZOOM), Err(TilePathError::OutOfGrid(Tile::new(99, 0, 2))));
+        assert_eq!(parse_tile_path("2", "0", "4.png", MAX_ZOOM), Err(TilePathError::OutOfGrid(Tile::new(0, 4, 2))));
+        assert_eq!(parse_tile_path("31", "0", "0.png", MAX_ZOOM), Err(TilePathError::ZoomOutOfRange(31, MAX_ZOOM)));
+
+        // The grid edge, and a lowered --max-request-zoom
+        assert_eq!(parse_tile_path("3", "7", "7.png", MAX_ZOOM), Ok((Tile::new(7, 7, 3), TILE_SIZE)));
+        assert_eq!(parse_tile_path("3", "8", "0.png", MAX_ZOOM), Err(TilePathError::OutOfGrid(Tile::new(8, 0, 3))));
+        assert_eq!(parse_tile_path("3", "0", "0.png", 2), Err(TilePathError::ZoomOutOfRange(3, 2)));
+        assert_eq!(parse_tile_path("2", "0", "0.png", 2), Ok((Tile::new(0, 0, 2), TILE_SIZE)));
+    }
+
+    #[test]
+    fn test_parse_metatile_path() {
+        assert_eq!(parse_metatile_path("3", "2", "4", "4.png", MAX_ZOOM), Ok((Tile::new(2, 4, 3), 4)));
+        assert_eq!(parse_metatile_path("0", "0", "0", "1.png", MAX_ZOOM), Ok((Tile::new(0, 0, 0), 1)));
+
+        for n in ["4", "x.png", "4@2x.png"] {
+            assert!(matches!(parse_metatile_path("3", "2", "4", n, MAX_ZOOM), Err(TilePathError::Malformed(_))), "{}", n);
+        }
+        assert!(matches!(parse_metatile_path("3", "2", "a", "4.png", MAX_ZOOM), Err(TilePathError::Malformed(path)) if path.starts_with("/metatile/")));
+        assert_eq!(parse_metatile_path("3", "2", "4", "0.png", MAX_ZOOM), Err(TilePathError::MetatileSizeOutOfRange(0)));
+        assert_eq!(parse_metatile_path("5", "0", "0", "9.png", MAX_ZOOM), Err(TilePathError::MetatileSizeOutOfRange(9)));
+        // The far corner has to be in the grid too
+        assert_eq!(parse_metatile_path("3", "5", "4", "4.png", MAX_ZOOM), Err(TilePathError::OutOfGrid(Tile::new(8, 7, 3))));
+        assert_eq!(parse_metatile_path("3", "9", "0", "1.png", MAX_ZOOM), Err(TilePathError::OutOfGrid(Tile::new(9, 0, 3))));
+    }
+
+    #[test]
+    fn test_metatile_layout() {
+        let (mut state, _file) = test_state(OutOfCoverage::Render);
+        // Rows numbered from the north: the origin is the northwest corner
+        assert_eq!(metatile_layout(&state, &Tile::new(2, 4, 3), 3), (Tile::new(2, 4, 3), 4));
+        assert_eq!(metatile_layout(&state, &Tile::new(2, 4, 3), 2), (Tile::new(2, 4, 3), 3));
+        assert_eq!(metatile_layout(&state, &Tile::new(0, 0, 0), 1), (Tile::new(0, 0, 0), 0));
+
+        // Rows numbered from the south: the last row is the northernmost
+        state.tile_origin = TileOrigin::BottomLeft;
+        assert_eq!(metatile_layout(&state, &Tile::new(2, 1, 3), 3), (Tile::new(2, 4, 3), 4));
+        assert_eq!(metatile_layout(&state, &Tile::new(2, 1, 3), 2), (Tile::new(2, 5, 3), 1));
+        // Flipped twice, rows are XYZ rows again
+        state.tile_scheme = TileScheme::Tms;
+        assert_eq!(metatile_layout(&state, &Tile::new(2, 4, 3), 3), (Tile::new(2, 4, 3), 4));
+        state.tile_origin = TileOrigin::TopLeft;
+        assert_eq!(metatile_layout(&state, &Tile::new(2, 1, 3), 3), (Tile::new(2, 4, 3), 4));
+
+        // Metatiles at the grid edge render rows in the grid
+        for (tile_origin, tile_scheme) in [(TileOrigin::TopLeft, TileScheme::Xyz), (TileOrigin::BottomLeft, TileScheme::Xyz)] {
+            (state.tile_origin, state.tile_scheme) = (tile_origin, tile_scheme);
+            for n in 1..=MAX_METATILE_SIZE {
+                let z = 4;
+                let (origin, _) = parse_metatile_path(&z.to_string(), "0", &(16 - n).to_string(), &format!("{}.png", n), MAX_ZOOM).unwrap();
+                let (northwest, center) = metatile_layout(&state, &origin, n);
+                assert!(northwest.y + n <= 1 << z, "{:?} {}", tile_origin, n);
+                assert!(center < (n * n) as usize, "{:?} {}", tile_origin, n);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tile_request_error_responses() {
+        let request = |state: AppState, z: &str, x: &str, y: &str| {
+            let path = Path((z.to_string(), x.to_string(), y.to_string()));
+            handle_tile_request(State(state), path, Query(HashMap::new()), HeaderMap::new())
+        };
+        let body = |response: Response| async {
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+        };
+        let content_type = |response: &Response| response.headers()[header::CONTENT_TYPE].to_str().unwrap().to_string();
+
+        let (state, _file) = test_state(OutOfCoverage::NoData);
+
+        let malformed = request(state.clone(), "abc", "1", "2.png").await;
+        assert_eq!(malformed.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(content_type(&malformed), "application/problem+json");
+        let problem = body(malformed).await;
+        assert_eq!(problem["title"], "Malformed tile path");
+        assert!(problem["detail"].as_str().unwrap().contains("/tile/{z}/{x}/{y}.png"));
+
+        let out_of_grid = request(state.clone(), "2", "99", "0.png").await;
+        assert_eq!(out_of_grid.status(), StatusCode::BAD_REQUEST);
+        let problem = body(out_of_grid).await;
+        assert_eq!(problem["title"], "Tile coordinates out of range");
+        assert!(problem["detail"].as_str().unwrap().contains("from 0 to 3"));
+
+        // A valid tile without data is answered by the out-of-coverage policy
+        let empty = request(state, "10", "0", "0.png").await;
+        assert_eq!(empty.status(), StatusCode::OK);
+        assert_eq!(content_type(&empty), "image/png");
+
+        let (state, _file) = test_state(OutOfCoverage::NotFound);
+        assert_eq!(request(state, "10", "0", "0.png").await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_conditional_get_by_etag() {
+        let (state, _file) = test_state(OutOfCoverage::NoData);
+        let request = |if_none_match: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(tag) = if_none_match {
+                headers.insert(header::IF_NONE_MATCH, HeaderValue::from_str(tag).unwrap());
+            }
+            let path = Path(("10".to_string(), "0".to_string(), "0.png".to_string()));
+            handle_tile_request(State(state.clone()), path, Query(HashMap::new()), headers)
+        };
+
+        let response = request(None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "public, max-age=300");
+        let etag = response.headers()[header::ETAG].to_str().unwrap().to_string();
+        assert!(etag.starts_with('"') && etag.ends_with('"'), "{}", etag);
+        assert_eq!(request(None).await.headers()[header::ETAG], etag.as_str(), "stable across renders");
+
+        for if_none_match in [etag.clone(), format!("W/{}", etag), format!("\"other\", {}", etag), "*".to_string()] {
+            let response = request(Some(&if_none_match)).await;
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED, "{}", if_none_match);
+            assert_eq!(response.headers()[header::ETAG], etag.as_str());
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            assert!(body.is_empty());
+        }
+        assert_eq!(request(Some("\"other\"")).await.status(), StatusCode::OK);
+    }
+
+    /// App state with `objects` indexed for `tile`, each twice
+    fn state_with_objects(objects: &[MapObject], tile: Tile) -> (AppState, Vec<tempfile::NamedTempFile>) {
+        let (mut state, file) = test_state(OutOfCoverage::Render);
+        let mut data_file = tempfile::NamedTempFile::new().unwrap();
+        let mut index = TileIndex::new();
+        for object in objects {
+            let offset = crate::data::serialization::write_map_object(data_file.as_file_mut(), object).unwrap();
+            index.insert(tile, offset);
+            index.insert(tile, offset);
+        }
+        state.data = Arc::new(index);
+        state.mmap = Arc::new(MappedData::new(data_file.path()).unwrap());
+        (state, vec![file, data_file])
+    }
+
+    fn map_object(kind: ObjectKind, points: Vec<Point>, tags: &[(&str, &str)]) -> MapObject {
+        let bounding_box = points.iter().fold(BoundingBox::new(points[0], points[0]), |bbox, &p| bbox.union(&BoundingBox::new(p, p)));
+        MapObject {
+            bounding_box,
+            points,
+            kind,
+            tags: tags.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_vector_tile_from_index() {
+        let (x, y) = crate::projection::deg2num(53.551, 9.995, 14);
+        let tile = Tile::new(x, y, 14);
+        let line = map_object(ObjectKind::Line, vec![Point::new(9.99, 53.55), Point::new(10.0, 53.552)], &[("highway", "primary")]);
+        let (state, _files) = state_with_objects(&[line.clone()], tile);
+
+        let request = |state: AppState, z: u32, x: u32, y: String| {
+            let path = Path((z.to_string(), x.to_string(), y));
+            handle_vector_tile_request(State(state), path, HeaderMap::new())
+        };
+        let response = request(state.clone(), 14, x, format!("{}.mvt", y)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], MVT_MIME_TYPE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let mut expected = MvtLayer::new(MVT_LAYER_NAME, MVT_EXTENT);
+        expected.add_object(ObjectKind::Line, &line.points, &get_bounding_box(&tile), [("highway", "primary")]);
+        assert_eq!(&body[..], &expected.encode_tile()[..], "one feature, duplicates dropped");
+
+        // Tiles without objects have no layers
+        let response = request(state.clone(), 14, x + 1, format!("{}.mvt", y)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap().is_empty());
+
+        let response = request(state.clone(), 14, x, format!("{}.png", y)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(request(state, 3, 8, "0.mvt".to_string()).await.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_debug_geojson_of_tile() {
+        let (x, y) = crate::projection::deg2num(53.551, 9.995, 14);
+        let p = Point::new;
+        let objects = [
+            map_object(ObjectKind::Line, vec![p(9.99, 53.55), p(10.0, 53.552)], &[("highway", "primary")]),
+            map_object(ObjectKind::Polygon, vec![p(9.99, 53.55), p(9.9901, 53.55), p(9.9901, 53.5501), p(9.99, 53.55)], &[]),
+            map_object(ObjectKind::Point, vec![p(9.995_123_4, 53.551)], &[("amenity", "cafe")]),
+        ];
+        let (mut state, _files) = state_with_objects(&objects, Tile::new(x, y, 14));
+        state.vector_precision = Some(3);
+
+        let request = |state: AppState, y: String| {
+            let path = Path(("14".to_string(), x.to_string(), y));
+            handle_debug_geojson_request(State(state), path, HeaderMap::new())
+        };
+        let response = request(state.clone(), format!("{}.geojson", y)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/geo+json");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let collection: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(collection["type"], "FeatureCollection");
+        let features = collection["features"].as_array().unwrap();
+        let types: Vec<_> = features.iter().map(|f| f["geometry"]["type"].as_str().unwrap()).collect();
+        // Rounding to 3 decimals collapses the small polygon
+        assert_eq!(types, ["LineString", "Point"]);
+        assert_eq!(features[0]["geometry"]["coordinates"], serde_json::json!([[9.99, 53.55], [10.0, 53.552]]));
+        assert_eq!(features[0]["properties"]["bbox"], serde_json::json!([9.99, 53.55, 10.0, 53.552]));
+        assert_eq!(features[0]["properties"]["tags"]["highway"], "primary");
+        assert_eq!(features[1]["geometry"]["coordinates"], serde_json::json!([9.995, 53.551]));
+
+        state.vector_precision = None;
+        let body = axum::body::to_bytes(request(state.clone(), format!("{}.geojson", y)).await.into_body(), usize::MAX).await.unwrap();
+        let collection: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let polygon = &collection["features"][1]["geometry"];
+        assert_eq!(polygon["type"], "Polygon");
+        assert_eq!(polygon["coordinates"][0].as_array().unwrap().len(), 4);
+
+        assert_eq!(request(state, format!("{}.json", y)).await.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_head_counts_objects_without_rendering() {
+        let (mut state, _file) = test_state(OutOfCoverage::Render);
+        let mut index = TileIndex::new();
+        index.insert(Tile::new(1, 0, 1), 0);
+        index.insert(Tile::new(1, 0, 1), 24);
+        index.insert(Tile::new(1, 0, 1), 24);
+        index.insert(Tile::new(24576, 0, 15), 48);
+        state.data = Arc::new(index);
+
+        let head = |state: AppState, path: (&str, &str, &str)| {
+            let path = Path((path.0.to_string(), path.1.to_string(), path.2.to_string()));
+            handle_tile_head(State(state), path)
+        };
+        let response = head(state.clone(), ("1", "1", "0.png")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[OBJECT_COUNT_HEADER], "2");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(body.is_empty(), "probe produced a body");
+
+        // Above the indexed zooms the ancestor's objects count
+        let response = head(state.clone(), ("17", "98305", "3@2x.png")).await;
+        assert_eq!(response.headers()[OBJECT_COUNT_HEADER], "1");
+        let response = head(state.clone(), ("1", "0", "0.png")).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()[OBJECT_COUNT_HEADER], "0");
+        assert_eq!(head(state.clone(), ("1", "5", "0.png")).await.status(), StatusCode::BAD_REQUEST);
+
+        // HEAD is routed to the probe, not to a render
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let renderers = state.renderers.clone();
+        let app = crate::server::create_app(state);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        stream
+            .write_all(b"HEAD /tile/1/1/0.png HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.to_lowercase().contains("x-object-count: 2\r\n"), "{}", response);
+        assert_eq!(renderers.metrics().acquisitions, 0);
+    }
+
+    #[tokio::test]
+    async fn test_tilejson() {
+        let (mut state, _file) = test_state(OutOfCoverage::Render);
+        let mut index = TileIndex::new();
+        index.insert(Tile::new(0, 0, 0), 0);
+        index.insert(Tile::new(8606, 5287, 14), 0);
+        index.extend_bounds(&BoundingBox::new(Point::new(9.9, 53.5), Point::new(10.1, 53.6)));
+        state.data = Arc::new(index);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, HeaderValue::from_static("tiles.example.com:8080"));
+        let response = handle_tilejson(State(state.clone()), headers.clone()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let document: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(document["tilejson"], "3.0.0");
+        assert_eq!(document["tiles"], serde_json::json!(["http://tiles.example.com:8080/tile/{z}/{x}/{y}.png"]));
+        assert_eq!(document["scheme"], "xyz");
+        assert_eq!((document["minzoom"].as_u64(), document["maxzoom"].as_u64()), (Some(0), Some(14)));
+        assert_eq!(document["bounds"], serde_json::json!([9.9, 53.5, 10.1, 53.6]));
+
+        // Behind a TLS proxy, and without data: the whole grid
+        headers.insert("x-forwarded-proto", HeaderValue::from_static("https"));
+        state.data = Arc::new(TileIndex::new());
+        let document = tilejson(&state, "https://tiles.example.com");
+        assert_eq!(document["tiles"][0], "https://tiles.example.com/tile/{z}/{x}/{y}.png");
+        assert_eq!(document["bounds"], serde_json::json!([-180.0, -MAX_LAT, 180.0, MAX_LAT]));
+        let response = handle_tilejson(State(state.clone()), headers).await;
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&bytes).contains("https://tiles.example.com:8080/tile/"));
+
+        let response = handle_tilejson(State(state), HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_health_probes() {
+        let (mut state, _file) = test_state(OutOfCoverage::Render);
+        let response = handle_livez().await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(state.renderers.metrics().acquisitions, 0);
+
+        // No such device: the renderer can't be created
+        state.vulkan.device_index = Some(usize::MAX);
+        let response = handle_healthz(State(state)).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let message = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(message.starts_with("Failed to get 256px renderer"), "{}", message);
+    }
+
+    #[test]
+    fn test_render_error_status() {
+        assert_eq!(render_error_status(&VulkanError::RenderTimeout(DEFAULT_RENDER_TIMEOUT)), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(render_error_status(&VulkanError::DeviceLost), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(render_error_status(&VulkanError::NoSuitableMemoryType), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn test_png_srgb_tag_follows_renderer() {
+        let (mut state, _file) = test_state(OutOfCoverage::Render);
+        state.color_format = ColorFormat::Srgb;
+        let image = RgbaImage::from_pixel(4, 4, image::Rgba([10, 20, 30, 255]));
+        let has_srgb_chunk = |color_format| {
+            let png = encode_rgba(PngEncoding::of(&state, color_format), &image, TileFormat::Png).unwrap();
+            png.windows(4).any(|w| w == b"sRGB")
+        };
+
+        // A renderer that fell back to UNORM produces untagged tiles
+        assert!(!has_srgb_chunk(ColorFormat::Unorm));
+        assert!(has_srgb_chunk(ColorFormat::Srgb));
+    }
+
+    #[test]
+    fn test_render_stats_headers() {
+        let mut headers = HeaderMap::new();
+        RenderStats { render_time: Duration::from_micros(12_345), objects: 42 }.insert_headers(&mut headers);
+        assert_eq!(headers[RENDER_TIME_HEADER], "12.3");
+        assert_eq!(headers[OBJECT_COUNT_HEADER], "42");
+    }
+
+    /// Stand-in for a `VulkanRenderer`, broken by a render that timed out
+    struct FakeRenderer {
+        id: usize,
+        broken: bool,
+    }
+
+    impl PooledRenderer for FakeRenderer {
+        fn set_tile_size(&mut self, _tile_size: u32) {}
+
+        fn is_broken(&self) -> bool {
+            self.broken
+        }
+    }
+
+    #[tokio::test]
+    async fn test_broken_renderer_is_discarded() {
+        let pool: Arc<Pool<(), FakeRenderer>> = Arc::new(Pool::new(2, None));
+        let create = |id| move || async move { Ok::<_, std::convert::Infallible>(FakeRenderer { id, broken: false }) };
+
+        // A failed render that left the renderer usable returns it to the pool
+        let renderer = pool.checkout((), create(0)).await.unwrap();
+        discard_if_broken(renderer);
+        assert_eq!(pool.metrics().idle, 1);
+
+        // One that timed out is dropped, and the next checkout creates a new one
+        let mut renderer = pool.checkout((), create(1)).await.unwrap();
+        assert_eq!(renderer.id, 0);
+        renderer.broken = true;
+        discard_if_broken(renderer);
+        assert_eq!(pool.metrics().idle, 0);
+        assert_eq!(pool.checkout((), create(2)).await.unwrap().id, 2);
+    }
+
+    #[tokio::test]
+    async fn test_device_lost_retries_on_new_renderer() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let pool: Arc<Pool<(), FakeRenderer>> = Arc::new(Pool::new(2, None));
+        let created = Arc::new(AtomicUsize::new(0));
+        let create = {
+            let created = created.clone();
+            move || Ok(FakeRenderer { id: created.fetch_add(1, Ordering::SeqCst), broken: false })
+        };
+        // Two idle renderers from before the device loss
+        let first = checkout_from(&pool, TILE_SIZE, false, create.clone()).await.unwrap();
+        drop((first, checkout_from(&pool, TILE_SIZE, false, create.clone()).await.unwrap()));
+
+        // Renderers from before the loss fail, so only a new one succeeds
+        let (renderer, result) = render_pooled_in(&pool, TILE_SIZE, create.clone(), |renderer| {
+            if renderer.id < 2 { Err(VulkanError::DeviceLost) } else { Ok(renderer.id) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(result.unwrap(), 2);
+        assert_eq!(renderer.id, 2);
+        drop(renderer);
+        assert_eq!(created.load(Ordering::SeqCst), 3);
+        assert_eq!(pool.metrics().idle, 1, "the stale idle renderer is dropped");
+
+        // A second loss is reported rather than retried again
+        let (_renderer, result) =
+            render_pooled_in(&pool, TILE_SIZE, create, |_| Err::<(), _>(VulkanError::DeviceLost)).await.unwrap();
+        assert!(matches!(result, Err(VulkanError::DeviceLost)));
+    }
+
+    #[tokio::test]
+    async fn test_cached_tile_skips_renderer() {
+        let (mut state, file) = test_state(OutOfCoverage::Render);
+        let cache = Arc::new(TileCache::new(8, true, Some(file.path().to_path_buf())));
+        state.tile_cache = Some(cache.clone());
+        let key = TileCacheKey { tile: Tile::new(1, 2, 3), tile_size: TILE_SIZE, detail: 0, mask: false, format: TileFormat::Png };
+        cache.insert(key, Bytes::from_static(b"cached"), cache.data_version());
+
+        // The pool has no renderer and Vulkan isn't needed
+        let response = tile_response(&state, key.tile, TILE_SIZE, TileFormat::Png, &HashMap::new()).await.unwrap();
+        assert_eq!(response.headers()[TILE_CACHE_HEADER], "hit");
+        assert!(!response.headers().contains_key(RENDER_TIME_HEADER));
+        assert!(!response.headers().contains_key(OBJECT_COUNT_HEADER));
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&bytes[..], b"cached");
+        assert_eq!(state.renderers.metrics().acquisitions, 0);
+    }
+
+    #[tokio::test]
+    async fn test_empty_tile_is_transparent_or_no_content() {
+        let (mut state, file) = test_state(OutOfCoverage::Render);
+        let cache = Arc::new(TileCache::new(8, false, Some(file.path().to_path_buf())));
+        state.tile_cache = Some(cache.clone());
+        // A tile rendered without data, served from the cache without Vulkan
+        let key = TileCacheKey { tile: Tile::new(1, 2, 3), tile_size: TILE_SIZE, detail: 0, mask: false, format: TileFormat::Png };
+        cache.insert(key, Bytes::new(), cache.data_version());
+
+        let response = tile_response(&state, key.tile, TILE_SIZE, TileFormat::Png, &HashMap::new()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[TILE_CACHE_HEADER], "hit");
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let image = image::load_from_memory(&bytes).unwrap().to_rgba8();
+        assert_eq!(image.dimensions(), (TILE_SIZE, TILE_SIZE));
+        assert!(image.pixels().all(|p| p.0 == NODATA_COLOR));
+
+        let params = HashMap::from([("nocontent".to_string(), "1".to_string())]);
+        let response = tile_response(&state, key.tile, TILE_SIZE, TileFormat::Png, &params).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(bytes.is_empty());
+        assert_eq!(state.renderers.metrics().acquisitions, 0);
+    }
+
+    #[tokio::test]
+    async fn test_accept_header_selects_format() {
+        let (mut state, _file) = test_state(OutOfCoverage::NoData);
+        state.format_preference = "avif,webp,png".parse().unwrap();
+        let request = |state: AppState, accept: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::ACCEPT, HeaderValue::from_str(accept).unwrap());
+            let path = Path(("10".to_string(), "0".to_string(), "0.png".to_string()));
+            handle_tile_request(State(state), path, Query(HashMap::new()), headers)
+        };
+
+        // WebP ranks above PNG, AVIF isn't accepted
+        let response = request(state.clone(), "image/png,image/webp").await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/webp");
+        assert_eq!(response.headers()[header::VARY], "accept");
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let image = image::load_from_memory_with_format(&bytes, image::ImageFormat::WebP).unwrap();
+        assert!(image.to_rgba8().pixels().all(|p| p.0 == NODATA_COLOR));
+
+        let response = request(state.clone(), "image/png").await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+        // Clients naming no image type get PNG
+        let response = request(state.clone(), "*/*").await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+        let headers = HeaderMap::new();
+        assert_eq!(negotiate_format(&state.format_preference, &headers, "0.png"), TileFormat::Png);
+        assert_eq!(negotiate_format(&state.format_preference, &headers, "0.webp"), TileFormat::WebP);
+
+        // A .webp path is WebP whatever the preference and Accept header
+        let mut png_only = state.clone();
+        png_only.format_preference = "png".parse().unwrap();
+        let path = Path(("10".to_string(), "0".to_string(), "0.webp".to_string()));
+        let response = handle_tile_request(State(png_only), path, Query(HashMap::new()), HeaderMap::new()).await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/webp");
+
+        // The format is part of the cache key
+        let key = |format| TileCacheKey { tile: Tile::new(1, 2, 3), tile_size: TILE_SIZE, detail: 0, mask: false, format };
+        let cache = Arc::new(TileCache::new(8, false, None));
+        cache.insert(key(TileFormat::Png), Bytes::from_static(b"png"), 0);
+        cache.insert(key(TileFormat::WebP), Bytes::from_static(b"webp"), 0);
+        state.out_of_coverage = OutOfCoverage::Render;
+        state.tile_cache = Some(cache);
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_static("image/webp,image/png"));
+        let format = negotiate_format(&state.format_preference, &headers, "3.png");
+        let response = tile_response(&state, Tile::new(1, 2, 3), TILE_SIZE, format, &HashMap::new()).await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/webp");
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&bytes[..], b"webp");
+        assert_eq!(state.tile_metrics.metrics().cache_hits, 1);
+    }
+
+    #[tokio::test]
+    async fn test_datauri_embeds_png() {
+        let (state, _file) = test_state(OutOfCoverage::NoData);
+        let params = HashMap::from([("datauri".to_string(), "1".to_string())]);
+        let response = tile_response(&state, Tile::new(0, 0, 10), TILE_SIZE, TileFormat::Png, &params).await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/html; charset=utf-8");
+        let page = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let page = std::str::from_utf8(&page).unwrap();
+
+        let prefix = "data:image/png;base64,";
+        let start = page.find(prefix).expect("data URI") + prefix.len();
+        let end = start + page[start..].find('"').unwrap();
+        let png = crate::encoding::datauri::decode_base64(&page[start..end]).unwrap();
+        let image = image::load_from_memory_with_format(&png, image::ImageFormat::Png).unwrap();
+        assert_eq!(image.width(), TILE_SIZE);
+        assert!(image.to_rgba8().pixels().all(|p| p.0 == NODATA_COLOR));
+        assert!(page.contains("<title>Tile 10/0/0</title>"), "{}", page);
+
+        let params = HashMap::from([("datauri".to_string(), "yes".to_string())]);
+        let rejected = tile_response(&state, Tile::new(0, 0, 10), TILE_SIZE, TileFormat::Png, &params).await;
+        assert_eq!(rejected.unwrap_err(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_debug_error_tile_shows_error() {
+        // No such GPU: creating the renderer fails, with or without Vulkan
+        let (mut state, _file) = test_state(OutOfCoverage::Render);
+        state.vulkan.device_index = Some(usize::MAX);
+        let tile = Tile::new(1, 2, 3);
+        assert_eq!(
+            tile_response(&state, tile, TILE_SIZE, TileFormat::Png, &HashMap::new()).await.unwrap_err(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+
+        state.debug_error_tiles = true;
+        let response = tile_response(&state, tile, TILE_SIZE, TileFormat::Png, &HashMap::new()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+        let png = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let image = image::load_from_memory_with_format(&png, image::ImageFormat::Png).unwrap().to_rgba8();
+        assert_eq!(image.dimensions(), (TILE_SIZE, TILE_SIZE));
+
+        // Text in the top lines, plain background below
+        let text = |rows: std::ops::Range<u32>| {
+            image.enumerate_pixels().filter(|(_, y, p)| rows.contains(y) && p.0 == ERROR_TEXT_COLOR).count()
+        };
+        assert!(text(0..4 + text::LINE_HEIGHT) > 0, "no title drawn");
+        assert!(text(4 + 2 * text::LINE_HEIGHT..4 + 3 * text::LINE_HEIGHT) > 0, "no message drawn");
+        assert_eq!(text(TILE_SIZE - 16..TILE_SIZE), 0);
+        assert!(image.pixels().all(|p| p.0 == ERROR_TILE_COLOR || p.0 == ERROR_TEXT_COLOR));
+    }
+
+    #[tokio::test]
+    async fn test_pin_requires_admin_token() {
+        let (mut state, file) = test_state(OutOfCoverage::Render);
+        let pin = |state: AppState, token: Option<&str>, coords: &[&str]| {
+            let mut headers = HeaderMap::new();
+            if let Some(token) = token {
+                headers.insert(header::AUTHORIZATION, HeaderValue::from_str(token).unwrap());
+            }
+            let coords = coords.iter().map(|s| s.to_string()).collect();
+            async move { handle_cache_pin(State(state), headers, Json(coords)).await.into_response() }
+        };
+        let status = |response: Response| response.status();
+
+        assert_eq!(status(pin(state.clone(), Some("Bearer secret"), &["3/1/2"]).await), StatusCode::FORBIDDEN);
+        state.admin_token = Some("secret".to_string());
+        assert_eq!(status(pin(state.clone(), None, &["3/1/2"]).await), StatusCode::UNAUTHORIZED);
+        assert_eq!(status(pin(state.clone(), Some("Bearer secreT"), &["3/1/2"]).await), StatusCode::UNAUTHORIZED);
+        assert_eq!(status(pin(state.clone(), Some("secret"), &["3/1/2"]).await), StatusCode::UNAUTHORIZED);
+        assert_eq!(status(pin(state.clone(), Some("Bearer secret"), &["3/1/2"]).await), StatusCode::CONFLICT);
+
+        let cache = Arc::new(TileCache::new(1, false, Some(file.path().to_path_buf())));
+        state.tile_cache = Some(cache.clone());
+        assert_eq!(status(pin(state.clone(), Some("Bearer secret"), &["3/1"]).await), StatusCode::BAD_REQUEST);
+        assert_eq!(status(pin(state.clone(), Some("Bearer secret"), &["3/8/0"]).await), StatusCode::BAD_REQUEST);
+        assert_eq!(cache.pinned_count(), 0);
+
+        // A fresh cached tile is pinned without rendering (no renderer in the pool)
+        let key = TileCacheKey { tile: Tile::new(1, 2, 3), tile_size: TILE_SIZE, detail: 0, mask: false, format: TileFormat::Png };
+        cache.insert(key, Bytes::from_static(b"hot"), cache.data_version());
+        let response = pin(state.clone(), Some("Bearer secret"), &["3/1/2"]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap()["pinned"], 1);
+        cache.insert(TileCacheKey { tile: Tile::new(0, 0, 1), ..key }, Bytes::from_static(b"x"), 0);
+        assert!(matches!(cache.get(&key, cache.data_version()), CacheLookup::Fresh(_)));
+        assert_eq!(state.renderers.metrics().acquisitions, 0);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer secret"));
+        let response = handle_cache_unpin(State(state), headers, Json(vec!["3/1/2".to_string()])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(cache.pinned_count(), 0);
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        let (mut state, _file) = test_state(OutOfCoverage::Render);
+        assert_eq!(parse_filter(&state, None), Ok(None));
+        // Nothing retained
+        assert_eq!(parse_filter(&state, Some("highway")), Err(StatusCode::BAD_REQUEST));
+
+        let mut index = TileIndex::new();
+        index.retained_tags = Some("highway,name".parse().unwrap());
+        state.data = Arc::new(index);
+        let filter = parse_filter(&state, Some("highway=primary OR name")).unwrap().unwrap();
+        assert_eq!(filter.to_string(), "(highway=primary OR name)");
+        assert_eq!(parse_filter(&state, Some("waterway OR name")), Err(StatusCode::BAD_REQUEST));
+        assert_eq!(parse_filter(&state, Some("highway AND")), Err(StatusCode::BAD_REQUEST));
+    }
+
+    #[test]
+    fn test_parse_flag() {
+        assert_eq!(parse_flag(None), Ok(false));
+        assert_eq!(parse_flag(Some("1")), Ok(true));
+        assert_eq!(parse_flag(Some("true")), Ok(true));
+        assert_eq!(parse_flag(Some("")), Ok(true));
+        assert_eq!(parse_flag(Some("0")), Ok(false));
+        assert_eq!(parse_flag(Some("yes")), Err(StatusCode::BAD_REQUEST));
+    }
+
+    #[test]
+    fn test_parse_detail() {
+        assert_eq!(parse_detail(None), Ok(0));
+        assert_eq!(parse_detail(Some("1")), Ok(1));
+        assert_eq!(parse_detail(Some("+2")), Ok(2));
+        // `?detail=+1` arrives as " 1" after query decoding
+        assert_eq!(parse_detail(Some(" 1")), Ok(1));
+        assert_eq!(parse_detail(Some("10")), Ok(MAX_DETAIL_OFFSET));
+        assert_eq!(parse_detail(Some("-1")), Err(StatusCode::BAD_REQUEST));
+        assert_eq!(parse_detail(Some("abc")), Err(StatusCode::BAD_REQUEST));
+    }
 }
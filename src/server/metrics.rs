@@ -0,0 +1,108 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds in seconds of the render duration histogram buckets
+pub const RENDER_SECONDS_BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+/// Tile counters for `/metrics`: renders, their errors and durations, tiles
+/// without data and tile cache lookups
+#[derive(Debug, Default)]
+pub struct TileMetrics {
+    render_errors: AtomicU64,
+    empty: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    // Renders per bucket, the last one for renders above all bounds
+    render_buckets: [AtomicU64; RENDER_SECONDS_BUCKETS.len() + 1],
+    render_sum_us: AtomicU64,
+}
+
+/// Snapshot of tile counters, see `TileMetrics::metrics`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TileMetricsSnapshot {
+    pub rendered: u64,
+    pub render_errors: u64,
+    /// Renders that drew nothing, served as the no-data tile
+    pub empty: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    /// Renders at most as long as each of `RENDER_SECONDS_BUCKETS`, cumulative
+    pub render_buckets: Vec<u64>,
+    /// Total time spent rendering
+    pub render_sum: Duration,
+}
+
+impl TileMetrics {
+    /// Record a tile rendered in `elapsed`
+    pub fn record_render(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let bucket = RENDER_SECONDS_BUCKETS
+            .iter()
+            .position(|&bound| seconds <= bound)
+            .unwrap_or(RENDER_SECONDS_BUCKETS.len());
+        self.render_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.render_sum_us.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Record a failed render
+    pub fn record_render_error(&self) {
+        self.render_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a render that drew nothing
+    pub fn record_empty(&self) {
+        self.empty.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a tile cache lookup; stale tiles served while revalidating count as hits
+    pub fn record_cache_lookup(&self, hit: bool) {
+        let counter = if hit { &self.cache_hits } else { &self.cache_misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn metrics(&self) -> TileMetricsSnapshot {
+        let mut rendered = 0;
+        let mut render_buckets = Vec::with_capacity(RENDER_SECONDS_BUCKETS.len());
+        for (i, count) in self.render_buckets.iter().enumerate() {
+            rendered += count.load(Ordering::Relaxed);
+            if i < RENDER_SECONDS_BUCKETS.len() {
+                render_buckets.push(rendered);
+            }
+        }
+        TileMetricsSnapshot {
+            rendered,
+            render_errors: self.render_errors.load(Ordering::Relaxed),
+            empty: self.empty.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            cache_misses: self.cache_misses.load(Ordering::Relaxed),
+            render_buckets,
+            render_sum: Duration::from_micros(self.render_sum_us.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_histogram_is_cumulative() {
+        let metrics = TileMetrics::default();
+        metrics.record_render(Duration::from_millis(3));
+        metrics.record_render(Duration::from_millis(10));
+        metrics.record_render(Duration::from_millis(400));
+        metrics.record_render(Duration::from_secs(30));
+        metrics.record_render_error();
+        metrics.record_empty();
+        metrics.record_cache_lookup(true);
+        metrics.record_cache_lookup(false);
+        metrics.record_cache_lookup(false);
+
+        let snapshot = metrics.metrics();
+        assert_eq!(snapshot.rendered, 4);
+        assert_eq!(snapshot.render_buckets, vec![1, 2, 2, 2, 2, 2, 3, 3, 3, 3]);
+        assert_eq!(snapshot.render_sum, Duration::from_micros(30_413_000));
+        assert_eq!((snapshot.render_errors, snapshot.cache_hits, snapshot.cache_misses), (1, 1, 2));
+        assert_eq!(snapshot.empty, 1);
+    }
+}
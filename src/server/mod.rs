@@ -1,23 +1,168 @@
+pub mod budget;
+pub mod cache;
+pub mod encode;
 pub mod handlers;
+pub mod metrics;
 
-use axum::{Router, routing::get};
+use axum::{Router, routing::{get, post}};
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 use tower_http::services::ServeDir;
+use budget::RenderBudget;
+use cache::{NoDataTiles, TileCache};
+use encode::EncodePool;
+use metrics::TileMetrics;
 use crate::data::spatial::TileIndex;
 use crate::data::mmap::MappedData;
+use crate::encoding::format::FormatPreference;
+use crate::projection::{TileOrigin, TileScheme};
+use crate::renderer::clip::ClipRegion;
+use crate::renderer::color_format::ColorFormat;
+use crate::renderer::downscale::DownscaleFilter;
+use crate::renderer::lod::LodThresholds;
+use crate::renderer::vertex_budget::VertexBudgets;
+use crate::renderer::pool::RendererPool;
 use crate::renderer::ShaderType;
-use handlers::handle_tile_request;
+use crate::renderer::vulkan::ContextOptions;
+use crate::style::line_colors::LineColors;
+use crate::style::MapStyle;
+use handlers::{handle_cache_pin, handle_cache_unpin, handle_debug_geojson_request, handle_healthz, handle_index_stats, handle_livez, handle_metatile_request, handle_metrics, handle_tile_head, handle_tile_request, handle_tilejson, handle_vector_tile_request};
 
 #[derive(Clone)]
 pub struct AppState {
     pub data: Arc<TileIndex>,
     pub mmap: Arc<MappedData>,
     pub shader_type: ShaderType,
+    /// Vulkan device and API version options for the per-thread renderers
+    pub vulkan: ContextOptions,
+    /// Buffer around each tile as a fraction of the tile size (0 disables)
+    pub buffer_fraction: f64,
+    /// Base data set to diff against; tiles become diff tiles when set
+    pub diff_base: Option<DiffBase>,
+    /// Response for tiles outside the data bounds
+    pub out_of_coverage: OutOfCoverage,
+    /// Per-object level of detail thresholds
+    pub lod: LodThresholds,
+    /// Per-zoom vertex and object caps (`--vertex-budget`)
+    pub vertex_budgets: VertexBudgets,
+    /// Vertices renderers' vertex buffers grow to at most (`--max-vertex-buffer`)
+    pub max_vertex_buffer: usize,
+    /// Longest wait for the GPU per render (`--render-timeout-ms`)
+    pub render_timeout: Duration,
+    /// Draw objects across the antimeridian in edge-column tiles
+    pub wrap_antimeridian: bool,
+    /// Encode tiles as palette PNGs when they have few enough colors
+    pub png_indexed: bool,
+    /// Render transparent overlay tiles, see `RendererOptions::overlay`
+    pub overlay: bool,
+    /// Snap lines to pixel centers, see `RendererOptions::crisp_lines`
+    pub crisp_lines: bool,
+    /// Draw lines as quads of their width, see `RendererOptions::line_widths`
+    pub line_widths: bool,
+    /// Draw casings under wide lines, see `RendererOptions::casing`
+    pub casing: bool,
+    /// Color attachment format, see `RendererOptions::color_format` (`--color-format`)
+    pub color_format: ColorFormat,
+    /// Cell size POI nodes are decimated to (`--point-decimation-px`)
+    pub point_decimation_px: f64,
+    /// Skip ways missing the tile, see `RendererOptions::precise_overlap`
+    pub precise_overlap: bool,
+    /// Tile grid and image orientation of requested tiles
+    pub tile_origin: TileOrigin,
+    /// Row numbering of `/tile`, `/vt` and `/debug` paths (`--scheme`)
+    pub tile_scheme: TileScheme,
+    /// Renderers shared by all requests (`--renderer-pool-size`)
+    pub renderers: Arc<RendererPool>,
+    /// Render time budget per tile (`--render-budget-ms`)
+    pub render_budget: Arc<RenderBudget>,
+    /// Render and tile cache counters for `/metrics`
+    pub tile_metrics: Arc<TileMetrics>,
+    /// Encoded tiles answering tiles without data
+    pub nodata_tiles: Arc<NoDataTiles>,
+    /// Style the data was loaded with (`--style`)
+    pub style: Option<Arc<MapStyle>>,
+    /// Colors of unclassed ways (`--highway-colors`, `--default-line-color`)
+    pub line_colors: Option<Arc<LineColors>>,
+    /// Encoded tiles by request (`--tile-cache`)
+    pub tile_cache: Option<Arc<TileCache>>,
+    /// Region tiles are masked to (`--clip-region`)
+    pub clip_region: Option<Arc<ClipRegion>>,
+    /// Decimals of GeoJSON output coordinates, full precision if `None`
+    /// (`--vector-precision`, see `encoding::vector`)
+    pub vector_precision: Option<u32>,
+    /// Tile formats to negotiate with the `Accept` header (`--format-preference`)
+    pub format_preference: FormatPreference,
+    /// Bearer token required by the admin endpoints (`--admin-token`),
+    /// which are disabled without one
+    pub admin_token: Option<String>,
+    /// MSAA samples per pixel, see `RendererOptions::msaa_samples` (`--msaa`)
+    pub msaa_samples: u32,
+    /// Fill closed ways, see `RendererOptions::fill_polygons` (`--fill-polygons`)
+    pub fill_polygons: bool,
+    /// MSAA samples of polygon fills, see `RendererOptions::fill_msaa_samples` (`--fill-msaa`)
+    pub fill_msaa_samples: Option<u32>,
+    /// Outline polygons on denser tiles, see `RendererOptions::fill_max_objects` (`--fill-max-objects`)
+    pub fill_max_objects: Option<usize>,
+    /// Render tiles at this multiple of their size and downscale (`--supersample`, 1 disables)
+    pub supersample: u32,
+    /// Filter reducing supersampled renders (`--downscale-filter`)
+    pub downscale_filter: DownscaleFilter,
+    /// Encode stage shared by all requests (`--encode-threads`)
+    pub encoders: Arc<EncodePool>,
+    /// Draw render errors onto the failed tiles (`--debug-error-tiles`)
+    pub debug_error_tiles: bool,
+    /// `max-age` of the `Cache-Control` header of tiles (`--max-age`)
+    pub max_age_secs: u32,
+    /// Highest zoom accepted in tile paths (`--max-request-zoom`)
+    pub max_request_zoom: u32,
+}
+
+/// Handling of tiles that don't overlap the data bounds (`--out-of-coverage`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutOfCoverage {
+    /// Respond with 404 Not Found
+    NotFound,
+    /// Respond with a transparent tile without rendering
+    NoData,
+    /// Render the tile as usual
+    #[default]
+    Render,
+}
+
+impl FromStr for OutOfCoverage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "notfound" => Ok(OutOfCoverage::NotFound),
+            "nodata" => Ok(OutOfCoverage::NoData),
+            "render" => Ok(OutOfCoverage::Render),
+            _ => Err(format!("unknown out-of-coverage policy {:?} (expected notfound, nodata or render)", s)),
+        }
+    }
+}
+
+/// Base data set for diff tiles (`--diff-against`)
+#[derive(Clone)]
+pub struct DiffBase {
+    pub data: Arc<TileIndex>,
+    pub mmap: Arc<MappedData>,
 }
 
 pub fn create_app(state: AppState) -> Router {
     Router::new()
-        .route("/tile/:z/:x/:y.png", get(handle_tile_request))
+        .route("/tile/:z/:x/:y.png", get(handle_tile_request).head(handle_tile_head))
+        .route("/metatile/:z/:x/:y/:n.png", get(handle_metatile_request))
+        .route("/vt/:z/:x/:y.mvt", get(handle_vector_tile_request))
+        .route("/debug/:z/:x/:y.geojson", get(handle_debug_geojson_request))
+        .route("/tiles.json", get(handle_tilejson))
+        .route("/stats/index", get(handle_index_stats))
+        .route("/metrics", get(handle_metrics))
+        .route("/healthz", get(handle_healthz))
+        .route("/livez", get(handle_livez))
+        .route("/cache/pin", post(handle_cache_pin))
+        .route("/cache/unpin", post(handle_cache_unpin))
         .nest_service("/", ServeDir::new("static"))
         .with_state(state)
 }
@@ -0,0 +1,96 @@
+//! Draw order of ways without a style class, by their tags
+//!
+//! The render pass has no depth buffer, so ways drawn later cover earlier
+//! ones. Unclassed ways are drawn by ascending `layer_for`, ways of the
+//! same layer in index order, so the result is the same on every run.
+//! Like line colors by tag this needs the tags in the data file, see
+//! `RetainTags`; without them every way is in `DEFAULT_LAYER`.
+
+use super::line_colors::HIGHWAY_KEY;
+use super::line_width::HIGHWAY_WIDTHS;
+
+/// Landuse, leisure and natural areas other than water
+pub const LANDUSE_LAYER: i16 = 0;
+/// Water areas and waterways
+pub const WATER_LAYER: i16 = 10;
+pub const BUILDING_LAYER: i16 = 20;
+/// Ways matching none of the other layers, including untagged ones
+pub const DEFAULT_LAYER: i16 = 30;
+pub const RAILWAY_LAYER: i16 = 40;
+/// Highways, raised by their rank in `HIGHWAY_WIDTHS` so major roads draw on top
+pub const HIGHWAY_LAYER: i16 = 50;
+
+/// Layers per step of the OSM `layer` tag, above all ground level layers
+pub const LAYER_TAG_STEP: i16 = 100;
+
+/// Range of `layer` tag values, as in the OSM wiki; others are clamped
+const LAYER_TAG_RANGE: std::ops::RangeInclusive<i16> = -5..=5;
+
+/// Draw order of a way with `tags`, lowest first
+///
+/// Bridges (`layer=1`) draw over everything at ground level, tunnels
+/// (`layer=-1`) under it.
+pub fn layer_for<K: AsRef<str>, V: AsRef<str>>(tags: &[(K, V)]) -> i16 {
+    let tag = |key: &str| tags.iter().find(|(k, _)| k.as_ref() == key).map(|(_, value)| value.as_ref());
+    let is_water = tag("waterway").is_some()
+        || tag("water").is_some()
+        || tag("natural") == Some("water")
+        || tag("landuse") == Some("reservoir");
+
+    let base = if let Some(highway) = tag(HIGHWAY_KEY) {
+        let rank = HIGHWAY_WIDTHS
+            .iter()
+            .position(|&(value, _)| value == highway)
+            .map_or(0, |i| (HIGHWAY_WIDTHS.len() - i) as i16);
+        HIGHWAY_LAYER + rank
+    } else if tag("railway").is_some() {
+        RAILWAY_LAYER
+    } else if tag("building").is_some() {
+        BUILDING_LAYER
+    } else if is_water {
+        WATER_LAYER
+    } else if tag("landuse").is_some() || tag("leisure").is_some() || tag("natural").is_some() {
+        LANDUSE_LAYER
+    } else {
+        DEFAULT_LAYER
+    };
+
+    let layer_tag = tag("layer")
+        .and_then(|value| value.trim().parse::<i16>().ok())
+        .map_or(0, |layer| layer.clamp(*LAYER_TAG_RANGE.start(), *LAYER_TAG_RANGE.end()));
+    base + layer_tag * LAYER_TAG_STEP
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layer_order() {
+        let layer = |tags: &[(&str, &str)]| layer_for(tags);
+        let landuse = layer(&[("landuse", "forest")]);
+        let water = layer(&[("natural", "water")]);
+        let building = layer(&[("building", "yes")]);
+        let residential = layer(&[("highway", "residential")]);
+        let motorway = layer(&[("highway", "motorway")]);
+        assert!(landuse < water && water < building && building < residential && residential < motorway);
+        assert_eq!(layer(&[("waterway", "river"), ("name", "Elbe")]), WATER_LAYER);
+        assert_eq!(layer(&[]), DEFAULT_LAYER);
+        assert_eq!(layer(&[("highway", "footway")]), HIGHWAY_LAYER);
+        assert!(layer(&[("railway", "rail")]) < layer(&[("highway", "footway")]));
+    }
+
+    #[test]
+    fn test_layer_tag() {
+        // A bridge over a motorway, a river in a tunnel under a forest
+        let bridge = layer_for(&[("highway", "footway"), ("layer", "1")]);
+        assert!(bridge > layer_for(&[("highway", "motorway")]));
+        let culvert = layer_for(&[("waterway", "stream"), ("layer", "-1")]);
+        assert!(culvert < layer_for(&[("landuse", "forest")]));
+        assert_eq!(culvert, WATER_LAYER - LAYER_TAG_STEP);
+
+        // Out of range values are clamped, invalid ones ignored
+        assert_eq!(layer_for(&[("layer", "12")]), DEFAULT_LAYER + 5 * LAYER_TAG_STEP);
+        assert_eq!(layer_for(&[("layer", "bridge")]), DEFAULT_LAYER);
+    }
+}
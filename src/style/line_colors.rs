@@ -0,0 +1,99 @@
+//! Line colors of ways without a style class, by the value of one tag
+
+use crate::renderer::renderer::LINE_COLOR;
+
+/// Tag whose value `LineColors::highway` colors by
+pub const HIGHWAY_KEY: &str = "highway";
+
+/// `highway` values and their colors (`--highway-colors`)
+pub const HIGHWAY_COLORS: &[(&str, [u8; 4])] = &[
+    ("motorway", [0xf0, 0x8c, 0x28, 0xff]),
+    ("primary", [0xf5, 0xd4, 0x42, 0xff]),
+    ("residential", [0x99, 0x99, 0x99, 0xff]),
+];
+
+/// Share of a line color's brightness its casing keeps (`--casing`)
+pub const CASING_BRIGHTNESS: f32 = 0.6;
+
+/// Color of the casing drawn under a line of `color`: darker, same alpha
+pub fn casing_color(color: [u8; 4]) -> [u8; 4] {
+    let [r, g, b, a] = color;
+    let darken = |channel: u8| (channel as f32 * CASING_BRIGHTNESS).round() as u8;
+    [darken(r), darken(g), darken(b), a]
+}
+
+/// Colors of unclassed ways: by the value of `key`, else `default`
+///
+/// Drawing by tag needs the tag in the data file, see `RetainTags`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineColors {
+    pub key: String,
+    pub colors: Vec<(String, [u8; 4])>,
+    /// Color of ways whose tag value has no color (`--default-line-color`)
+    pub default: [u8; 4],
+}
+
+impl Default for LineColors {
+    fn default() -> Self {
+        LineColors::new(LINE_COLOR)
+    }
+}
+
+impl LineColors {
+    /// Every way in `default`
+    pub fn new(default: [u8; 4]) -> Self {
+        LineColors {
+            key: HIGHWAY_KEY.to_string(),
+            colors: Vec::new(),
+            default,
+        }
+    }
+
+    /// Ways colored by `HIGHWAY_COLORS`, others in `default`
+    pub fn highway(default: [u8; 4]) -> Self {
+        LineColors {
+            key: HIGHWAY_KEY.to_string(),
+            colors: HIGHWAY_COLORS.iter().map(|&(value, color)| (value.to_string(), color)).collect(),
+            default,
+        }
+    }
+
+    /// Whether colors depend on `key` at all
+    pub fn by_tag(&self) -> bool {
+        !self.colors.is_empty()
+    }
+
+    /// Color of a way whose `key` tag is `value`
+    pub fn color(&self, value: Option<&str>) -> [u8; 4] {
+        value
+            .and_then(|value| self.colors.iter().find(|(v, _)| v == value))
+            .map_or(self.default, |&(_, color)| color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highway_colors() {
+        let gray = [0x80, 0x80, 0x80, 0xff];
+        let colors = LineColors::highway(gray);
+        assert!(colors.by_tag());
+        assert_eq!(colors.color(Some("motorway")), [0xf0, 0x8c, 0x28, 0xff]);
+        assert_eq!(colors.color(Some("residential")), [0x99, 0x99, 0x99, 0xff]);
+        assert_eq!(colors.color(Some("footway")), gray);
+        assert_eq!(colors.color(None), gray);
+
+        let plain = LineColors::default();
+        assert!(!plain.by_tag());
+        assert_eq!(plain.color(Some("motorway")), LINE_COLOR);
+    }
+
+    #[test]
+    fn test_casing_color() {
+        assert_eq!(casing_color([0xf0, 0x8c, 0x28, 0xff]), [0x90, 0x54, 0x18, 0xff]);
+        assert_eq!(casing_color([0xff, 0xff, 0xff, 0x80]), [0x99, 0x99, 0x99, 0x80]);
+        assert_eq!(casing_color([0, 0, 0, 0xff]), [0, 0, 0, 0xff]);
+    }
+}
@@ -0,0 +1,54 @@
+//! Line widths of ways without a style class, by road class and zoom
+
+use super::line_colors::HIGHWAY_KEY;
+
+/// `highway` values and their widths in pixels at `FULL_WIDTH_ZOOM`
+pub const HIGHWAY_WIDTHS: &[(&str, f32)] = &[
+    ("motorway", 4.0),
+    ("trunk", 3.5),
+    ("primary", 3.0),
+    ("secondary", 2.5),
+    ("tertiary", 2.0),
+    ("residential", 1.5),
+    ("unclassified", 1.5),
+];
+
+/// Width of ways whose `highway` value isn't in `HIGHWAY_WIDTHS`
+/// (footways, paths, untagged ways), and the minimum at any zoom
+pub const MIN_LINE_WIDTH: f32 = 1.0;
+
+/// Pixels the casing of a line reaches beyond it on each side (`--casing`)
+pub const CASING_WIDTH: f32 = 1.0;
+
+/// Zoom at which roads reach their `HIGHWAY_WIDTHS` width
+pub const FULL_WIDTH_ZOOM: u32 = 15;
+
+/// Line width in pixels of a way with `tags` at `zoom` (`--line-widths`)
+///
+/// Roads narrow by a quarter of their width per zoom level below
+/// `FULL_WIDTH_ZOOM`, down to `MIN_LINE_WIDTH`, and keep their width above.
+pub fn line_width_for<K: AsRef<str>, V: AsRef<str>>(tags: &[(K, V)], zoom: u32) -> f32 {
+    let highway = tags.iter().find(|(key, _)| key.as_ref() == HIGHWAY_KEY).map(|(_, value)| value.as_ref());
+    let width = highway
+        .and_then(|value| HIGHWAY_WIDTHS.iter().find(|(v, _)| *v == value))
+        .map_or(MIN_LINE_WIDTH, |&(_, width)| width);
+    let levels_below = FULL_WIDTH_ZOOM.saturating_sub(zoom) as f32;
+    (width * (1.0 - 0.25 * levels_below)).max(MIN_LINE_WIDTH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_width_by_class_and_zoom() {
+        let tags = |value: &'static str| vec![("name", "x"), ("highway", value)];
+        assert_eq!(line_width_for(&tags("motorway"), 15), 4.0);
+        assert_eq!(line_width_for(&tags("motorway"), 18), 4.0);
+        assert_eq!(line_width_for(&tags("motorway"), 14), 3.0);
+        assert_eq!(line_width_for(&tags("motorway"), 5), MIN_LINE_WIDTH);
+        assert!(line_width_for(&tags("motorway"), 15) > line_width_for(&tags("residential"), 15));
+        assert_eq!(line_width_for(&tags("footway"), 15), MIN_LINE_WIDTH);
+        assert_eq!(line_width_for::<&str, &str>(&[], 15), MIN_LINE_WIDTH);
+    }
+}
@@ -0,0 +1,428 @@
+//! Cartographic style (`mapstyle.toml`)
+//!
+//! One file defines the feature classes: `[[rule]]` entries recognize ways
+//! by their tags and set the lowest zoom they are indexed at, and a
+//! `[style.<class>]` entry per class sets how it is drawn:
+//!
+//! ```toml
+//! [[rule]]
+//! class = "major_road"
+//! match = ["highway=motorway", "highway=trunk_link"]  # any of; "key=*" or "key" match any value
+//! min_zoom = 5
+//!
+//! [style.major_road]
+//! color = "#e892a2"
+//! opacity = 1.0   # default 1
+//! width = 2.0     # pixels with --line-widths; default 1
+//! priority = 10   # draw order, higher on top; default 0
+//! ```
+//!
+//! A rule without `match` matches every way. The first matching rule
+//! classifies a way; ways matching no rule are not loaded. See
+//! `mapstyle.toml` in the repository for the default style.
+
+pub mod layer;
+pub mod line_colors;
+pub mod line_width;
+pub mod toml;
+
+use self::toml::{Table, TomlError, Value};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Index of a class in `MapStyle::classes`
+pub type ClassId = u16;
+
+/// Feature classes, their tag rules and how they are drawn
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapStyle {
+    pub rules: Vec<Rule>,
+    /// Class styles sorted by name
+    pub classes: Vec<ClassStyle>,
+}
+
+/// Tag rule assigning ways to a class
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub class: ClassId,
+    /// The rule matches if any of these does, or always if empty
+    pub matches: Vec<TagMatch>,
+    /// Lowest zoom level the class is indexed at
+    pub min_zoom: u32,
+}
+
+/// `key=value`, or any value of `key` when `value` is `None`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagMatch {
+    pub key: String,
+    pub value: Option<String>,
+}
+
+/// How a class is drawn
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassStyle {
+    pub name: String,
+    pub color: [u8; 3],
+    pub opacity: f32,
+    /// Line width in pixels
+    pub width: f32,
+    /// Draw order, higher on top
+    pub priority: i64,
+}
+
+/// Style loading errors
+#[derive(Debug, thiserror::Error)]
+pub enum StyleError {
+    #[error("Failed to read style {}: {source}", path.display())]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Invalid style TOML: {0}")]
+    Toml(#[from] TomlError),
+
+    #[error("Invalid style: {0}")]
+    Invalid(String),
+
+    #[error("Rule {rule} references class {class:?}, which has no [style.{class}] entry")]
+    UnknownClass { rule: usize, class: String },
+}
+
+impl TagMatch {
+    pub fn matches(&self, tags: &[(String, String)]) -> bool {
+        tags.iter()
+            .any(|(key, value)| *key == self.key && self.value.as_ref().is_none_or(|v| v == value))
+    }
+}
+
+impl FromStr for TagMatch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = match s.split_once('=') {
+            Some((key, "*")) => (key, None),
+            Some((key, value)) => (key, Some(value.to_string())),
+            None => (s, None),
+        };
+        if key.is_empty() || value.as_deref() == Some("") {
+            return Err(format!("invalid tag match {:?} (expected key=value, key=* or key)", s));
+        }
+        Ok(TagMatch {
+            key: key.to_string(),
+            value,
+        })
+    }
+}
+
+impl ClassStyle {
+    /// Color with the opacity as alpha
+    pub fn rgba(&self) -> [u8; 4] {
+        let [r, g, b] = self.color;
+        [r, g, b, (self.opacity * 255.0).round() as u8]
+    }
+}
+
+impl MapStyle {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, StyleError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|source| StyleError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        text.parse()
+    }
+
+    /// The first rule matching `tags`
+    pub fn classify(&self, tags: &[(String, String)]) -> Option<&Rule> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches.is_empty() || rule.matches.iter().any(|m| m.matches(tags)))
+    }
+
+    pub fn class(&self, id: ClassId) -> &ClassStyle {
+        &self.classes[id as usize]
+    }
+
+    pub fn class_id(&self, name: &str) -> Option<ClassId> {
+        self.classes
+            .binary_search_by(|class| class.name.as_str().cmp(name))
+            .ok()
+            .map(|i| i as ClassId)
+    }
+
+    /// Class ids from lowest to highest priority, i.e. in draw order
+    pub fn draw_order(&self) -> Vec<ClassId> {
+        let mut order: Vec<ClassId> = (0..self.classes.len() as ClassId).collect();
+        order.sort_by_key(|&id| self.class(id).priority);
+        order
+    }
+}
+
+impl FromStr for MapStyle {
+    type Err = StyleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let doc = toml::parse(s)?;
+        check_keys(&doc, "the style", &["rule", "style"])?;
+
+        let mut classes = Vec::new();
+        if let Some(styles) = doc.get("style") {
+            let styles = expect_table(styles, "style")?;
+            if styles.len() > ClassId::MAX as usize {
+                return Err(StyleError::Invalid(format!("more than {} classes", ClassId::MAX)));
+            }
+            // BTreeMap order, so `classes` is sorted by name
+            for (name, style) in styles {
+                classes.push(parse_class_style(name, expect_table(style, &format!("style.{}", name))?)?);
+            }
+        }
+
+        let mut style = MapStyle {
+            rules: Vec::new(),
+            classes,
+        };
+        let rules = match doc.get("rule") {
+            Some(Value::Array(rules)) => rules.as_slice(),
+            Some(other) => return Err(invalid_type("rule", "array of tables", other)),
+            None => &[],
+        };
+        for (i, rule) in rules.iter().enumerate() {
+            let rule = parse_rule(&style, i, expect_table(rule, "rule")?)?;
+            style.rules.push(rule);
+        }
+        if style.rules.is_empty() {
+            return Err(StyleError::Invalid("no [[rule]] entries".to_string()));
+        }
+        Ok(style)
+    }
+}
+
+fn parse_class_style(name: &str, table: &Table) -> Result<ClassStyle, StyleError> {
+    let context = format!("[style.{}]", name);
+    check_keys(table, &context, &["color", "opacity", "width", "priority"])?;
+
+    let color = match table.get("color") {
+        Some(value) => {
+            let color = value.as_str().ok_or_else(|| invalid_type("color", "string", value))?;
+            parse_color(color).ok_or_else(|| {
+                StyleError::Invalid(format!("{}: invalid color {:?} (expected #rrggbb or #rgb)", context, color))
+            })?
+        }
+        None => return Err(StyleError::Invalid(format!("{} has no color", context))),
+    };
+    let opacity = optional_float(table, "opacity", 1.0)?;
+    if !(0.0..=1.0).contains(&opacity) {
+        return Err(StyleError::Invalid(format!("{}: opacity {} is not between 0 and 1", context, opacity)));
+    }
+    let width = optional_float(table, "width", 1.0)?;
+    if width <= 0.0 {
+        return Err(StyleError::Invalid(format!("{}: width {} must be positive", context, width)));
+    }
+    let priority = match table.get("priority") {
+        Some(value) => value.as_integer().ok_or_else(|| invalid_type("priority", "integer", value))?,
+        None => 0,
+    };
+
+    Ok(ClassStyle {
+        name: name.to_string(),
+        color,
+        opacity: opacity as f32,
+        width: width as f32,
+        priority,
+    })
+}
+
+fn parse_rule(style: &MapStyle, index: usize, table: &Table) -> Result<Rule, StyleError> {
+    let context = format!("rule {}", index);
+    check_keys(table, &context, &["class", "match", "min_zoom"])?;
+
+    let class_name = match table.get("class") {
+        Some(value) => value.as_str().ok_or_else(|| invalid_type("class", "string", value))?,
+        None => return Err(StyleError::Invalid(format!("{} has no class", context))),
+    };
+    let class = style.class_id(class_name).ok_or_else(|| StyleError::UnknownClass {
+        rule: index,
+        class: class_name.to_string(),
+    })?;
+
+    // A single match string or an array of them; none matches every way
+    let patterns: Vec<&Value> = match table.get("match") {
+        Some(Value::Array(values)) if values.is_empty() => {
+            return Err(StyleError::Invalid(format!("{} has an empty match", context)));
+        }
+        Some(Value::Array(values)) => values.iter().collect(),
+        Some(value) => vec![value],
+        None => Vec::new(),
+    };
+    let matches = patterns
+        .into_iter()
+        .map(|value| {
+            let pattern = value.as_str().ok_or_else(|| invalid_type("match", "string", value))?;
+            pattern.parse::<TagMatch>().map_err(|e| StyleError::Invalid(format!("{}: {}", context, e)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let min_zoom = match table.get("min_zoom") {
+        Some(value) => {
+            let zoom = value.as_integer().ok_or_else(|| invalid_type("min_zoom", "integer", value))?;
+            u32::try_from(zoom)
+                .map_err(|_| StyleError::Invalid(format!("{}: min_zoom {} is negative", context, zoom)))?
+        }
+        None => 0,
+    };
+
+    Ok(Rule { class, matches, min_zoom })
+}
+
+/// Reject unknown keys, which are most likely typos
+fn check_keys(table: &Table, context: &str, known: &[&str]) -> Result<(), StyleError> {
+    match table.keys().find(|key| !known.contains(&key.as_str())) {
+        Some(key) => Err(StyleError::Invalid(format!(
+            "unknown key {:?} in {} (expected {})",
+            key,
+            context,
+            known.join(", ")
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn expect_table<'a>(value: &'a Value, name: &str) -> Result<&'a Table, StyleError> {
+    value.as_table().ok_or_else(|| invalid_type(name, "table", value))
+}
+
+fn optional_float(table: &Table, key: &str, default: f64) -> Result<f64, StyleError> {
+    match table.get(key) {
+        Some(value) => value.as_float().ok_or_else(|| invalid_type(key, "number", value)),
+        None => Ok(default),
+    }
+}
+
+fn invalid_type(key: &str, expected: &str, found: &Value) -> StyleError {
+    StyleError::Invalid(format!("{} must be a {}, found {}", key, expected, found.type_name()))
+}
+
+/// Parse `#rrggbb` or `#rgb`
+pub fn parse_color(s: &str) -> Option<[u8; 3]> {
+    let hex = s.strip_prefix('#')?;
+    if !hex.is_ascii() {
+        return None;
+    }
+    match hex.len() {
+        6 => {
+            let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+            Some([channel(0)?, channel(2)?, channel(4)?])
+        }
+        3 => {
+            let channel = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).ok().map(|c| c * 17);
+            Some([channel(0)?, channel(1)?, channel(2)?])
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STYLE: &str = r##"
+        [[rule]]
+        class = "major_road"
+        match = ["highway=motorway", "highway=trunk"]
+        min_zoom = 5
+
+        [[rule]]
+        class = "water"
+        match = "natural=water"
+        min_zoom = 8
+
+        # Anything else with a highway tag
+        [[rule]]
+        class = "minor_road"
+        match = ["highway=*"]
+        min_zoom = 12
+
+        [style.major_road]
+        color = "#e892a2"
+        width = 3
+        priority = 10
+
+        [style.minor_road]
+        color = "#888"
+
+        [style.water]
+        color = "#aad3df"
+        opacity = 0.5
+        priority = -1
+    "##;
+
+    fn tags(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_parse_and_apply_style() {
+        let style: MapStyle = STYLE.parse().unwrap();
+        assert_eq!(style.rules.len(), 3);
+        let names: Vec<&str> = style.classes.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["major_road", "minor_road", "water"]);
+
+        let major = style.class(style.class_id("major_road").unwrap());
+        assert_eq!((major.color, major.width, major.priority), ([0xe8, 0x92, 0xa2], 3.0, 10));
+        assert_eq!(major.rgba(), [0xe8, 0x92, 0xa2, 255]);
+        let minor = style.class(style.class_id("minor_road").unwrap());
+        assert_eq!((minor.rgba(), minor.width, minor.priority), ([0x88, 0x88, 0x88, 255], 1.0, 0));
+        let water = style.class(style.class_id("water").unwrap());
+        assert_eq!(water.rgba(), [0xaa, 0xd3, 0xdf, 128]);
+
+        // First matching rule wins, "highway=*" catches the rest
+        let classify = |pairs: &[(&str, &str)]| {
+            style.classify(&tags(pairs)).map(|rule| (style.class(rule.class).name.as_str(), rule.min_zoom))
+        };
+        assert_eq!(classify(&[("highway", "motorway"), ("ref", "A7")]), Some(("major_road", 5)));
+        assert_eq!(classify(&[("highway", "trunk")]), Some(("major_road", 5)));
+        assert_eq!(classify(&[("highway", "residential")]), Some(("minor_road", 12)));
+        assert_eq!(classify(&[("highway", "")]), Some(("minor_road", 12)));
+        assert_eq!(classify(&[("natural", "water")]), Some(("water", 8)));
+        assert_eq!(classify(&[("building", "yes")]), None);
+        assert_eq!(classify(&[]), None);
+
+        // Water below roads below major roads
+        let order: Vec<&str> = style.draw_order().into_iter().map(|id| style.class(id).name.as_str()).collect();
+        assert_eq!(order, vec!["water", "minor_road", "major_road"]);
+    }
+
+    #[test]
+    fn test_style_validation() {
+        let unknown = "[[rule]]\nclass = \"rail\"\nmatch = \"railway\"\n[style.road]\ncolor = \"#000\"";
+        match unknown.parse::<MapStyle>() {
+            Err(StyleError::UnknownClass { rule: 0, class }) => assert_eq!(class, "rail"),
+            other => panic!("expected UnknownClass, got {:?}", other),
+        }
+
+        let invalid = |s: &str| match s.parse::<MapStyle>() {
+            Err(StyleError::Invalid(message)) => message,
+            other => panic!("expected Invalid for {:?}, got {:?}", s, other),
+        };
+        let rule = "[[rule]]\nclass = \"road\"\nmatch = \"highway\"\n";
+        assert!(invalid(&format!("{}[style.road]\ncolour = \"#000\"", rule)).contains("unknown key \"colour\""));
+        assert!(invalid(&format!("{}[style.road]\ncolor = \"red\"", rule)).contains("invalid color"));
+        assert!(invalid(&format!("{}[style.road]\ncolor = \"#000\"\nopacity = 2", rule)).contains("opacity"));
+        assert!(invalid(&format!("{}[style.road]\ncolor = \"#000\"\nwidth = 0", rule)).contains("width"));
+        assert!(invalid(&format!("{}[style.road]\nwidth = 1", rule)).contains("no color"));
+        assert!(invalid("[style.road]\ncolor = \"#000\"").contains("no [[rule]]"));
+        assert!(invalid("[[rule]]\nclass = \"road\"\nmatch = \"=x\"\n[style.road]\ncolor = \"#000\"").contains("tag match"));
+        assert!(invalid("[[rule]]\nclass = \"road\"\nmatch = []\n[style.road]\ncolor = \"#000\"").contains("empty match"));
+
+        assert!(matches!("[[rule]\n".parse::<MapStyle>(), Err(StyleError::Toml(_))));
+    }
+
+    #[test]
+    fn test_default_style_file() {
+        let style = MapStyle::from_file(concat!(env!("CARGO_MANIFEST_DIR"), "/mapstyle.toml")).unwrap();
+        assert!(style.classify(&tags(&[("highway", "primary")])).is_some_and(|rule| rule.min_zoom == 0));
+        assert!(style.classify(&tags(&[("highway", "footway")])).is_some_and(|rule| rule.min_zoom == 11));
+        assert!(style.classify(&[]).is_some(), "the default style loads every way");
+    }
+}
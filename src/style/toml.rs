@@ -0,0 +1,450 @@
+//! Minimal TOML reader for style files
+//!
+//! Supports the subset style files need: `[table]` and `[[array]]` headers
+//! with dotted names, `key = value` pairs with bare or quoted keys, basic
+//! and literal strings, integers, floats, booleans, (multi-line) arrays and
+//! comments. Inline tables, dotted keys, dates and multi-line strings are
+//! rejected with an error.
+
+use std::collections::BTreeMap;
+
+/// A TOML table, keys in sorted order
+pub type Table = BTreeMap<String, Value>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Table),
+}
+
+impl Value {
+    /// TOML type name for error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Integer(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Boolean(_) => "boolean",
+            Value::Array(_) => "array",
+            Value::Table(_) => "table",
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    /// Floats, and integers converted to floats
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Value::Float(f) => Some(*f),
+            Value::Integer(i) => Some(*i as f64),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_table(&self) -> Option<&Table> {
+        match self {
+            Value::Table(table) => Some(table),
+            _ => None,
+        }
+    }
+}
+
+/// Syntax error with its 1-based line number
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("line {line}: {message}")]
+pub struct TomlError {
+    pub line: usize,
+    pub message: String,
+}
+
+/// Parse a TOML document into its root table
+pub fn parse(input: &str) -> Result<Table, TomlError> {
+    Parser {
+        chars: input.chars().collect(),
+        pos: 0,
+        line: 1,
+    }
+    .document()
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, TomlError> {
+        Err(TomlError {
+            line: self.line,
+            message: message.into(),
+        })
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), TomlError> {
+        match self.bump() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => self.error(format!("expected {:?}, found {:?}", expected, c)),
+            None => self.error(format!("expected {:?}, found end of file", expected)),
+        }
+    }
+
+    /// Skip spaces and tabs
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.bump();
+        }
+    }
+
+    /// Skip whitespace, newlines and comments
+    fn skip_blank(&mut self) {
+        loop {
+            match self.peek() {
+                Some(' ' | '\t' | '\r' | '\n') => {
+                    self.bump();
+                }
+                Some('#') => {
+                    while !matches!(self.peek(), None | Some('\n')) {
+                        self.bump();
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// Only a comment may follow a header or value on its line
+    fn end_of_line(&mut self) -> Result<(), TomlError> {
+        self.skip_spaces();
+        match self.peek() {
+            None | Some('\n' | '#') => Ok(()),
+            Some('\r') if self.chars.get(self.pos + 1) == Some(&'\n') => Ok(()),
+            Some(c) => self.error(format!("unexpected {:?} after value", c)),
+        }
+    }
+
+    fn document(mut self) -> Result<Table, TomlError> {
+        let mut root = Table::new();
+        // Path of the table keys currently go into
+        let mut current: Vec<String> = Vec::new();
+        let mut defined: Vec<Vec<String>> = Vec::new();
+
+        loop {
+            self.skip_blank();
+            match self.peek() {
+                None => return Ok(root),
+                Some('[') => {
+                    self.bump();
+                    let is_array = self.peek() == Some('[');
+                    if is_array {
+                        self.bump();
+                    }
+                    let path = self.header_path()?;
+                    self.expect(']')?;
+                    if is_array {
+                        self.expect(']')?;
+                    }
+                    self.end_of_line()?;
+
+                    if is_array {
+                        let (last, parents) = path.split_last().unwrap();
+                        let parent = self.table_at(&mut root, parents)?;
+                        match parent.entry(last.clone()).or_insert_with(|| Value::Array(Vec::new())) {
+                            Value::Array(tables) if tables.iter().all(|t| matches!(t, Value::Table(_))) => {
+                                tables.push(Value::Table(Table::new()))
+                            }
+                            _ => return self.error(format!("{} is not an array of tables", path.join("."))),
+                        }
+                        // Tables below this array of tables belong to the new element
+                        defined.retain(|p| !p.starts_with(&path));
+                    } else {
+                        if defined.contains(&path) {
+                            return self.error(format!("table {} is defined twice", path.join(".")));
+                        }
+                        self.table_at(&mut root, &path)?;
+                        defined.push(path.clone());
+                    }
+                    current = path;
+                }
+                Some(_) => {
+                    let key = self.key()?;
+                    self.skip_spaces();
+                    if self.peek() == Some('.') {
+                        return self.error("dotted keys are not supported");
+                    }
+                    self.expect('=')?;
+                    self.skip_spaces();
+                    let value = self.value()?;
+                    self.end_of_line()?;
+
+                    let table = self.table_at(&mut root, &current)?;
+                    if table.contains_key(&key) {
+                        return self.error(format!("key {} is defined twice", key));
+                    }
+                    table.insert(key, value);
+                }
+            }
+        }
+    }
+
+    /// The table at `path`, creating missing tables and descending into the
+    /// last element of arrays of tables
+    fn table_at<'t>(&self, root: &'t mut Table, path: &[String]) -> Result<&'t mut Table, TomlError> {
+        let mut table = root;
+        for key in path {
+            let value = table.entry(key.clone()).or_insert_with(|| Value::Table(Table::new()));
+            table = match value {
+                Value::Table(table) => table,
+                Value::Array(values) => match values.last_mut() {
+                    Some(Value::Table(table)) => table,
+                    _ => return self.error(format!("{} is not a table", key)),
+                },
+                _ => return self.error(format!("{} is not a table", key)),
+            };
+        }
+        Ok(table)
+    }
+
+    fn header_path(&mut self) -> Result<Vec<String>, TomlError> {
+        let mut path = Vec::new();
+        loop {
+            self.skip_spaces();
+            path.push(self.key()?);
+            self.skip_spaces();
+            if self.peek() != Some('.') {
+                return Ok(path);
+            }
+            self.bump();
+        }
+    }
+
+    fn key(&mut self) -> Result<String, TomlError> {
+        match self.peek() {
+            Some('"') => self.basic_string(),
+            Some('\'') => self.literal_string(),
+            _ => {
+                let start = self.pos;
+                while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                    self.bump();
+                }
+                if self.pos == start {
+                    return match self.peek() {
+                        Some(c) => self.error(format!("expected a key, found {:?}", c)),
+                        None => self.error("expected a key, found end of file"),
+                    };
+                }
+                Ok(self.chars[start..self.pos].iter().collect())
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, TomlError> {
+        match self.peek() {
+            Some('"') => {
+                if self.chars[self.pos..].starts_with(&['"', '"', '"']) {
+                    return self.error("multi-line strings are not supported");
+                }
+                self.basic_string().map(Value::String)
+            }
+            Some('\'') => self.literal_string().map(Value::String),
+            Some('[') => self.array(),
+            Some('{') => self.error("inline tables are not supported"),
+            Some(c) if c.is_ascii_alphabetic() => {
+                let word = self.word();
+                match word.as_str() {
+                    "true" => Ok(Value::Boolean(true)),
+                    "false" => Ok(Value::Boolean(false)),
+                    _ => self.error(format!("unexpected {:?}, strings must be quoted", word)),
+                }
+            }
+            Some(_) => self.number(),
+            None => self.error("expected a value, found end of file"),
+        }
+    }
+
+    fn word(&mut self) -> String {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+' | '.')) {
+            self.bump();
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn number(&mut self) -> Result<Value, TomlError> {
+        let word = self.word();
+        let digits = word.replace('_', "");
+        let is_float = digits.contains(['.', 'e', 'E']);
+        let parsed = if is_float {
+            digits.parse::<f64>().ok().filter(|f| f.is_finite()).map(Value::Float)
+        } else {
+            digits.parse::<i64>().ok().map(Value::Integer)
+        };
+        match parsed {
+            Some(value) if !word.is_empty() && !word.starts_with('_') && !word.ends_with('_') => Ok(value),
+            _ => self.error(format!("invalid number {:?}", word)),
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, TomlError> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            let c = match self.peek() {
+                Some('\n') | None => return self.error("unterminated string"),
+                Some(_) => self.bump().unwrap(),
+            };
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let escaped = match self.bump() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some(c) => return self.error(format!("unsupported escape \\{}", c)),
+                        None => return self.error("unterminated string"),
+                    };
+                    s.push(escaped);
+                }
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, TomlError> {
+        self.expect('\'')?;
+        let mut s = String::new();
+        loop {
+            match self.peek() {
+                Some('\'') => {
+                    self.bump();
+                    return Ok(s);
+                }
+                Some('\n') | None => return self.error("unterminated string"),
+                Some(c) => {
+                    self.bump();
+                    s.push(c);
+                }
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, TomlError> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+        loop {
+            self.skip_blank();
+            if self.peek() == Some(']') {
+                self.bump();
+                return Ok(Value::Array(values));
+            }
+            values.push(self.value()?);
+            self.skip_blank();
+            match self.peek() {
+                Some(',') => {
+                    self.bump();
+                }
+                Some(']') => {}
+                Some(c) => return self.error(format!("expected ',' or ']' in array, found {:?}", c)),
+                None => return self.error("unterminated array"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tables_and_values() {
+        let doc = parse(
+            r#"
+            # Comment
+            title = "roads" # trailing comment
+            [style.motorway]
+            color = '#e892a2'
+            width = 2.5
+            priority = -1_0
+            visible = true
+
+            [[rule]]
+            class = "motorway"
+            match = [
+                "highway=motorway",  # multi-line
+                "highway=motorway_link",
+            ]
+
+            [[rule]]
+            "class" = "path"
+            min_zoom = 14
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(doc["title"], Value::String("roads".into()));
+        let motorway = doc["style"].as_table().unwrap()["motorway"].as_table().unwrap();
+        assert_eq!(motorway["color"].as_str(), Some("#e892a2"));
+        assert_eq!(motorway["width"].as_float(), Some(2.5));
+        assert_eq!(motorway["priority"].as_integer(), Some(-10));
+        assert_eq!(motorway["visible"], Value::Boolean(true));
+
+        let rules = doc["rule"].as_array().unwrap();
+        assert_eq!(rules.len(), 2);
+        let first = rules[0].as_table().unwrap();
+        assert_eq!(first["match"].as_array().unwrap().len(), 2);
+        assert_eq!(rules[1].as_table().unwrap()["min_zoom"].as_integer(), Some(14));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |input: &str| parse(input).unwrap_err();
+
+        assert_eq!(error("a = 1\nb = \"open\n").line, 2);
+        assert!(error("a = 1\na = 2").message.contains("defined twice"));
+        assert!(error("[t]\n[t]").message.contains("defined twice"));
+        assert!(error("a = {x = 1}").message.contains("inline tables"));
+        assert!(error("a.b = 1").message.contains("dotted keys"));
+        assert!(error("a = motorway").message.contains("must be quoted"));
+        assert!(error("a = 1 2").message.contains("after value"));
+        assert!(error("a = [1, 2").message.contains("unterminated array"));
+        assert!(error("a = 1__").message.contains("invalid number"));
+    }
+}
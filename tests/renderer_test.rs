@@ -1,10 +1,21 @@
-use rust_osm_renderer::data::serialization::write_map_object;
+use rust_osm_renderer::data::serialization::{write_data_header, write_map_object};
 use rust_osm_renderer::data::spatial::TileIndex;
-use rust_osm_renderer::data::types::{BoundingBox, MapObject, Point, Tile};
+use rust_osm_renderer::data::types::{BoundingBox, MapObject, ObjectKind, Point, Tile};
 use rust_osm_renderer::data::mmap::MappedData;
-use rust_osm_renderer::renderer::{VulkanRenderer, ShaderType};
+use rust_osm_renderer::filter::TagFilter;
+use rust_osm_renderer::renderer::{RendererOptions, VulkanRenderer, ShaderType};
+use rust_osm_renderer::renderer::vertex_budget::VertexBudgets;
+use rust_osm_renderer::renderer::clip::ClipRegion;
+use rust_osm_renderer::renderer::renderer::{BACKGROUND_COLOR, LINE_COLOR, NODATA_COLOR};
 use tempfile::NamedTempFile;
 
+/// Empty data file with the format header, for objects to be appended to
+fn data_file() -> std::io::Result<NamedTempFile> {
+    let mut file = NamedTempFile::new()?;
+    write_data_header(file.as_file_mut())?;
+    Ok(file)
+}
+
 #[test]
 #[ignore] // Ignore by default since it requires Vulkan
 fn test_vulkan_renderer_with_synthetic_data() -> Result<(), Box<dyn std::error::Error>> {
@@ -12,7 +23,7 @@ fn test_vulkan_renderer_with_synthetic_data() -> Result<(), Box<dyn std::error::
     let _ = env_logger::builder().is_test(true).try_init();
 
     // Create synthetic map data - a simple cross pattern
-    let mut temp_file = NamedTempFile::new()?;
+    let mut temp_file = data_file()?;
 
     // Create a cross pattern in the center of tile 0/0/0
     let center_lon = 0.0;
@@ -29,6 +40,8 @@ fn test_vulkan_renderer_with_synthetic_data() -> Result<(), Box<dyn std::error::
             Point::new(center_lon - size, center_lat),
             Point::new(center_lon + size, center_lat),
         ],
+        kind: ObjectKind::Line,
+        tags: Vec::new(),
     };
 
     // Vertical line
@@ -41,6 +54,8 @@ fn test_vulkan_renderer_with_synthetic_data() -> Result<(), Box<dyn std::error::
             Point::new(center_lon, center_lat - size),
             Point::new(center_lon, center_lat + size),
         ],
+        kind: ObjectKind::Line,
+        tags: Vec::new(),
     };
 
     // Write to file
@@ -91,3 +106,1461 @@ fn test_vulkan_renderer_with_synthetic_data() -> Result<(), Box<dyn std::error::
 
     Ok(())
 }
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_render_tile_to_png_matches_render_tile() -> Result<(), Box<dyn std::error::Error>> {
+    use rust_osm_renderer::encoding::png::encode_png;
+
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    let mut temp_file = data_file()?;
+    let diagonal = MapObject {
+        bounding_box: BoundingBox { min: Point::new(-40.0, -30.0), max: Point::new(40.0, 30.0) },
+        points: vec![Point::new(-40.0, -30.0), Point::new(10.0, 5.0), Point::new(40.0, 30.0)],
+        kind: ObjectKind::Line,
+        tags: Vec::new(),
+    };
+    let offset = write_map_object(temp_file.as_file_mut(), &diagonal)?;
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let tile = Tile::new(0, 0, 0);
+    let mut tile_index = TileIndex::new();
+    tile_index.insert(tile, offset);
+    tile_index.max_points = 3;
+
+    let mut renderer = VulkanRenderer::new(tile_index.max_points, ShaderType::Simple)
+        .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+    let image = renderer.render_tile(&tile, &tile_index, &mmap_data)?;
+    let png = renderer.render_tile_to_png(&tile, &tile_index, &mmap_data)?;
+    assert_eq!(png, encode_png(&image)?);
+
+    // Tiles without data are encoded from the blank image
+    let empty = Tile::new(1, 1, 1);
+    let png = renderer.render_tile_to_png(&empty, &tile_index, &mmap_data)?;
+    assert_eq!(png, encode_png(&renderer.render_tile(&empty, &tile_index, &mmap_data)?)?);
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_render_timeout_breaks_renderer() -> Result<(), Box<dyn std::error::Error>> {
+    use rust_osm_renderer::renderer::vulkan::VulkanError;
+    use std::time::Duration;
+
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    // Enough lines that the GPU can't finish within the timeout
+    let mut temp_file = data_file()?;
+    let tile = Tile::new(0, 0, 0);
+    let mut tile_index = TileIndex::new();
+    for i in 0..20_000 {
+        let lat = -60.0 + (i % 1200) as f64 * 0.1;
+        let line = MapObject {
+            bounding_box: BoundingBox { min: Point::new(-170.0, lat), max: Point::new(170.0, lat + 1.0) },
+            points: vec![Point::new(-170.0, lat), Point::new(170.0, lat + 1.0)],
+            kind: ObjectKind::Line,
+            tags: Vec::new(),
+        };
+        tile_index.insert(tile, write_map_object(temp_file.as_file_mut(), &line)?);
+    }
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    tile_index.max_points = 2;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let options = RendererOptions { render_timeout: Some(Duration::from_nanos(1)), ..Default::default() };
+    let mut renderer = VulkanRenderer::new_with_options(2, ShaderType::Simple, 256, options)
+        .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+    assert!(!renderer.is_broken());
+    let result = renderer.render_tile(&tile, &tile_index, &mmap_data);
+    assert!(matches!(result, Err(VulkanError::RenderTimeout(_))), "{:?}", result.map(|_| ()));
+    assert!(renderer.is_broken());
+
+    // Dropping waits for the render at most another timeout
+    drop(renderer);
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_duplicate_offsets_render_like_single() -> Result<(), Box<dyn std::error::Error>> {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    let mut temp_file = data_file()?;
+    let line = MapObject {
+        bounding_box: BoundingBox {
+            min: Point::new(-20.0, -10.0),
+            max: Point::new(20.0, 10.0),
+        },
+        points: vec![Point::new(-20.0, -10.0), Point::new(20.0, 10.0)],
+        kind: ObjectKind::Line,
+        tags: Vec::new(),
+    };
+    let offset = write_map_object(temp_file.as_file_mut(), &line)?;
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let tile = Tile::new(0, 0, 0);
+
+    let mut single = TileIndex::new();
+    single.insert(tile, offset);
+    single.max_points = 2;
+
+    let mut double = TileIndex::new();
+    double.insert(tile, offset);
+    double.insert(tile, offset);
+    double.max_points = 2;
+    // As the loader does; finalizing drops the repeated offset
+    double.finalize();
+
+    let mut renderer = VulkanRenderer::new(2, ShaderType::Simple)
+        .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+    let single_image = renderer.render_tile(&tile, &single, &mmap_data)
+        .map_err(|e| format!("Failed to render tile: {}", e))?;
+    let double_image = renderer.render_tile(&tile, &double, &mmap_data)
+        .map_err(|e| format!("Failed to render tile: {}", e))?;
+
+    assert!(single_image.pixels().any(|p| p[0] != 255));
+    assert_eq!(single_image.as_raw(), double_image.as_raw(), "duplicate offset was blended twice");
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_diff_tile_colors() -> Result<(), Box<dyn std::error::Error>> {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    let line = |from: (f64, f64), to: (f64, f64)| MapObject {
+        bounding_box: BoundingBox::from_points(&[Point::new(from.0, from.1), Point::new(to.0, to.1)]).unwrap(),
+        points: vec![Point::new(from.0, from.1), Point::new(to.0, to.1)],
+        kind: ObjectKind::Line,
+        tags: Vec::new(),
+    };
+    let tile = Tile::new(0, 0, 0);
+    use std::io::Write;
+
+    // Base: way 1 (kept) and way 2 (removed)
+    let mut base_file = data_file()?;
+    let mut base = TileIndex::new();
+    for (way_id, object) in [(1, line((-40.0, 0.0), (40.0, 0.0))), (2, line((0.0, -40.0), (0.0, 40.0)))] {
+        let offset = write_map_object(base_file.as_file_mut(), &object)?;
+        base.insert(tile, offset);
+        base.record_way_id(offset, way_id);
+    }
+    base_file.as_file_mut().flush()?;
+    base.max_points = 2;
+
+    // Current: way 1 and way 3 (added)
+    let mut current_file = data_file()?;
+    let mut current = TileIndex::new();
+    for (way_id, object) in [(1, line((-40.0, 0.0), (40.0, 0.0))), (3, line((-40.0, -40.0), (40.0, 40.0)))] {
+        let offset = write_map_object(current_file.as_file_mut(), &object)?;
+        current.insert(tile, offset);
+        current.record_way_id(offset, way_id);
+    }
+    current_file.as_file_mut().flush()?;
+    current.max_points = 2;
+
+    let base_mmap = MappedData::new(base_file.path())?;
+    let current_mmap = MappedData::new(current_file.path())?;
+
+    let mut renderer = VulkanRenderer::new(2, ShaderType::Simple)
+        .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+    let image = renderer
+        .render_diff_tile(&tile, 0, (&current, &current_mmap), (&base, &base_mmap))
+        .map_err(|e| format!("Failed to render diff tile: {}", e))?;
+
+    let green = image.pixels().filter(|p| p[1] > p[0] && p[1] > p[2]).count();
+    let red = image.pixels().filter(|p| p[0] > p[1] && p[0] > p[2]).count();
+    let gray = image.pixels().filter(|p| p[0] == p[1] && p[1] == p[2] && p[0] < 255).count();
+    assert!(green > 0, "added way not drawn green");
+    assert!(red > 0, "removed way not drawn red");
+    assert!(gray > 0, "unchanged way not drawn gray");
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_buffered_tile_includes_neighbors() -> Result<(), Box<dyn std::error::Error>> {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    // A line just east of tile 1/0/0, indexed only in its neighbour 1/1/0
+    let mut temp_file = data_file()?;
+    let line = MapObject {
+        bounding_box: BoundingBox {
+            min: Point::new(10.0, 40.0),
+            max: Point::new(20.0, 40.0),
+        },
+        points: vec![Point::new(10.0, 40.0), Point::new(20.0, 40.0)],
+        kind: ObjectKind::Line,
+        tags: Vec::new(),
+    };
+    let offset = write_map_object(temp_file.as_file_mut(), &line)?;
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let mut tile_index = TileIndex::new();
+    tile_index.insert(Tile::new(1, 0, 1), offset);
+    tile_index.max_points = 2;
+
+    let options = RendererOptions {
+        buffer_fraction: 0.25,
+        ..Default::default()
+    };
+    let mut renderer = VulkanRenderer::new_with_options(2, ShaderType::Mercator, 256, options)
+        .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+    let image = renderer.render_tile(&Tile::new(0, 0, 1), &tile_index, &mmap_data)
+        .map_err(|e| format!("Failed to render tile: {}", e))?;
+
+    // 64px of buffer on each side, uncropped
+    assert_eq!((image.width(), image.height()), (384, 384));
+
+    // The nominal tile spans x 64..320; the line lands in the east buffer
+    let outside = image
+        .enumerate_pixels()
+        .filter(|(x, _, p)| *x >= 320 && p[0] != 255)
+        .count();
+    assert!(outside > 0, "expected geometry from the neighbouring tile in the buffer");
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_msaa_readback() -> Result<(), Box<dyn std::error::Error>> {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    // A shallow diagonal, which aliases badly without MSAA
+    let mut temp_file = data_file()?;
+    let line = MapObject {
+        bounding_box: BoundingBox {
+            min: Point::new(-60.0, -20.0),
+            max: Point::new(60.0, 20.0),
+        },
+        points: vec![Point::new(-60.0, -20.0), Point::new(60.0, 20.0)],
+        kind: ObjectKind::Line,
+        tags: Vec::new(),
+    };
+    let offset = write_map_object(temp_file.as_file_mut(), &line)?;
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let tile = Tile::new(0, 0, 0);
+    let mut tile_index = TileIndex::new();
+    tile_index.insert(tile, offset);
+    tile_index.max_points = 2;
+
+    let render = |msaa_samples| -> Result<image::RgbaImage, Box<dyn std::error::Error>> {
+        let options = RendererOptions {
+            msaa_samples,
+            ..Default::default()
+        };
+        let mut renderer = VulkanRenderer::new_with_options(2, ShaderType::Simple, 256, options)
+            .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+        Ok(renderer.render_tile(&tile, &tile_index, &mmap_data)
+            .map_err(|e| format!("Failed to render tile: {}", e))?)
+    };
+    let aliased = render(1)?;
+    let smoothed = render(4)?;
+
+    assert_eq!(smoothed.dimensions(), (256, 256));
+    assert!(smoothed.pixels().any(|p| p[0] != 255), "MSAA readback is blank");
+
+    // Resolved edges have partial coverage: gray levels between black and white
+    let partial = |image: &image::RgbaImage| image.pixels().filter(|p| p[0] > 0 && p[0] < 255).count();
+    assert!(
+        partial(&smoothed) > partial(&aliased),
+        "MSAA edges ({}) not smoother than aliased ({})",
+        partial(&smoothed),
+        partial(&aliased)
+    );
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_fill_msaa_smooths_polygon_edges() -> Result<(), Box<dyn std::error::Error>> {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    // A diamond-shaped area, whose diagonal edges alias without MSAA, and
+    // a line above it, drawn over the fills
+    let mut temp_file = data_file()?;
+    let ring = vec![Point::new(0.0, -40.0), Point::new(40.0, 0.0), Point::new(0.0, 40.0), Point::new(-40.0, 0.0), Point::new(0.0, -40.0)];
+    let diamond = MapObject {
+        bounding_box: BoundingBox::from_points(&ring).unwrap(),
+        points: ring,
+        kind: ObjectKind::Polygon,
+        tags: Vec::new(),
+    };
+    let line = MapObject {
+        bounding_box: BoundingBox::new(Point::new(-170.0, 60.0), Point::new(170.0, 60.0)),
+        points: vec![Point::new(-170.0, 60.0), Point::new(170.0, 60.0)],
+        kind: ObjectKind::Line,
+        tags: Vec::new(),
+    };
+    let tile = Tile::new(0, 0, 0);
+    let mut tile_index = TileIndex::new();
+    tile_index.insert(tile, write_map_object(temp_file.as_file_mut(), &diamond)?);
+    tile_index.insert(tile, write_map_object(temp_file.as_file_mut(), &line)?);
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    tile_index.max_points = 5;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    // Single-sample lines either way, so 4x fills get a render pass of their own
+    let render = |fill_msaa_samples| -> Result<image::RgbaImage, Box<dyn std::error::Error>> {
+        let options = RendererOptions {
+            fill_polygons: true,
+            fill_msaa_samples: Some(fill_msaa_samples),
+            ..Default::default()
+        };
+        let mut renderer = VulkanRenderer::new_with_options(5, ShaderType::Simple, 256, options)
+            .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+        Ok(renderer.render_tile(&tile, &tile_index, &mmap_data)
+            .map_err(|e| format!("Failed to render tile: {}", e))?)
+    };
+    let aliased = render(1)?;
+    let smoothed = render(4)?;
+
+    for image in [&aliased, &smoothed] {
+        assert_eq!(image.get_pixel(128, 128).0, LINE_COLOR, "the diamond is filled");
+        assert_eq!(image.get_pixel(0, 0).0, BACKGROUND_COLOR);
+        // Left of the diamond only the line is drawn, over the background the fill pass cleared to
+        assert!((0..256).any(|y| image.get_pixel(10, y).0 == LINE_COLOR), "the line is missing");
+    }
+
+    // Resolved fill edges have partial coverage
+    let partial = |image: &image::RgbaImage| image.pixels().filter(|p| p[0] > 0 && p[0] < 255).count();
+    assert!(
+        partial(&smoothed) > partial(&aliased),
+        "fill MSAA edges ({}) not smoother than aliased ({})",
+        partial(&smoothed),
+        partial(&aliased)
+    );
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_dense_tile_outlines_polygons() -> Result<(), Box<dyn std::error::Error>> {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    // A grid of 8x8 square areas, 10° wide with 10° gaps
+    let mut temp_file = data_file()?;
+    let tile = Tile::new(0, 0, 0);
+    let mut tile_index = TileIndex::new();
+    for i in 0..8 {
+        for j in 0..8 {
+            let (lon, lat) = (-80.0 + i as f64 * 20.0, -80.0 + j as f64 * 20.0);
+            let ring = vec![
+                Point::new(lon, lat),
+                Point::new(lon + 10.0, lat),
+                Point::new(lon + 10.0, lat + 10.0),
+                Point::new(lon, lat + 10.0),
+                Point::new(lon, lat),
+            ];
+            let square = MapObject {
+                bounding_box: BoundingBox::from_points(&ring).unwrap(),
+                points: ring,
+                kind: ObjectKind::Polygon,
+                tags: Vec::new(),
+            };
+            tile_index.insert(tile, write_map_object(temp_file.as_file_mut(), &square)?);
+        }
+    }
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    tile_index.max_points = 5;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let render = |fill_max_objects| -> Result<(image::RgbaImage, usize, usize), Box<dyn std::error::Error>> {
+        let options = RendererOptions { fill_polygons: true, fill_max_objects: Some(fill_max_objects), ..Default::default() };
+        let mut renderer = VulkanRenderer::new_with_options(5, ShaderType::Simple, 256, options)
+            .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+        let image = renderer.render_tile(&tile, &tile_index, &mmap_data)
+            .map_err(|e| format!("Failed to render tile: {}", e))?;
+        Ok((image, renderer.last_vertex_count(), renderer.last_fill_vertex_count()))
+    };
+    // Center of the square at (-75, -75), 14 to 28px in the simple shader's projection
+    let center = |image: &image::RgbaImage| image.get_pixel(21, 21).0;
+
+    // Over the threshold: outlines only, 4 line segments per square
+    let (outlined, vertices, fill_vertices) = render(63)?;
+    assert_eq!(fill_vertices, 0, "no fill triangles over the threshold");
+    assert_eq!(vertices, 64 * 4 * 2);
+    assert_eq!(center(&outlined), BACKGROUND_COLOR);
+
+    // At the threshold: two fill triangles per square and no lines
+    let (filled, vertices, fill_vertices) = render(64)?;
+    assert_eq!(fill_vertices, 64 * 6);
+    assert_eq!(vertices, fill_vertices);
+    assert_eq!(center(&filled), LINE_COLOR);
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_coverage_mask_of_single_line() -> Result<(), Box<dyn std::error::Error>> {
+    use rust_osm_renderer::renderer::mask::{coverage_mask, COVERED};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    // One horizontal line along the equator
+    let mut temp_file = data_file()?;
+    let line = MapObject {
+        bounding_box: BoundingBox {
+            min: Point::new(-20.0, -1.0),
+            max: Point::new(20.0, 1.0),
+        },
+        points: vec![Point::new(-20.0, 0.0), Point::new(20.0, 0.0)],
+        kind: ObjectKind::Line,
+        tags: Vec::new(),
+    };
+    let offset = write_map_object(temp_file.as_file_mut(), &line)?;
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let tile = Tile::new(0, 0, 0);
+    let mut tile_index = TileIndex::new();
+    tile_index.insert(tile, offset);
+    tile_index.max_points = 2;
+
+    let mut renderer = VulkanRenderer::new(2, ShaderType::Mercator)
+        .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+    let image = renderer.render_tile(&tile, &tile_index, &mmap_data)
+        .map_err(|e| format!("Failed to render tile: {}", e))?;
+    let mask = coverage_mask(&image);
+
+    // Exactly the line's pixels are set: one row, ~40/360 of the width
+    let covered: Vec<(u32, u32)> = mask
+        .enumerate_pixels()
+        .filter(|(_, _, p)| p.0 == [COVERED])
+        .map(|(x, y, _)| (x, y))
+        .collect();
+    assert!(mask.pixels().all(|p| p.0 == [0] || p.0 == [COVERED]));
+    assert!(covered.iter().all(|&(_, y)| y == covered[0].1), "line spans several rows");
+    assert!((26..=30).contains(&covered.len()), "{} pixels set", covered.len());
+    for &(x, y) in &covered {
+        assert_ne!(image.get_pixel(x, y)[0], 255);
+    }
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_render_from_mock_source() -> Result<(), Box<dyn std::error::Error>> {
+    use rust_osm_renderer::data::source::{MmapTileSource, TileSource, TileSourceError};
+
+    /// Source serving the same objects for every tile
+    struct MockSource(Vec<MapObject>);
+
+    impl TileSource for MockSource {
+        fn objects_for_tile(&self, _tile: &Tile) -> Result<Vec<MapObject>, TileSourceError> {
+            Ok(self.0.clone())
+        }
+    }
+
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    let line = MapObject {
+        bounding_box: BoundingBox {
+            min: Point::new(-40.0, -20.0),
+            max: Point::new(40.0, 20.0),
+        },
+        points: vec![Point::new(-40.0, -20.0), Point::new(40.0, 20.0)],
+        kind: ObjectKind::Line,
+        tags: Vec::new(),
+    };
+    let mut temp_file = data_file()?;
+    let offset = write_map_object(temp_file.as_file_mut(), &line)?;
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let tile = Tile::new(0, 0, 0);
+    let mut tile_index = TileIndex::new();
+    tile_index.insert(tile, offset);
+    tile_index.max_points = 2;
+
+    let mut renderer = VulkanRenderer::new(2, ShaderType::Mercator)
+        .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+    let from_index = renderer.render_tile(&tile, &tile_index, &mmap_data)?;
+    let from_mock = renderer.render_tile_from_source(&tile, &MockSource(vec![line]))?;
+    let from_mmap_source = renderer.render_tile_from_source(&tile, &MmapTileSource { index: &tile_index, data: &mmap_data })?;
+
+    // All paths draw the same line
+    assert!(from_index.pixels().any(|p| p[0] != 255));
+    assert_eq!(from_mock, from_index);
+    assert_eq!(from_mmap_source, from_index);
+
+    // An empty source renders a blank tile
+    let blank = renderer.render_tile_from_source(&tile, &MockSource(Vec::new()))?;
+    assert!(blank.pixels().all(|p| p[0] == 255));
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_antimeridian_wrap() -> Result<(), Box<dyn std::error::Error>> {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    // A line just west of the antimeridian, indexed only in the last column tile 2/3/1
+    let mut temp_file = data_file()?;
+    let line = MapObject {
+        bounding_box: BoundingBox {
+            min: Point::new(175.0, 10.0),
+            max: Point::new(179.0, 10.0),
+        },
+        points: vec![Point::new(175.0, 10.0), Point::new(179.0, 10.0)],
+        kind: ObjectKind::Line,
+        tags: Vec::new(),
+    };
+    let offset = write_map_object(temp_file.as_file_mut(), &line)?;
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let mut tile_index = TileIndex::new();
+    tile_index.insert(Tile::new(3, 1, 2), offset);
+    tile_index.max_points = 2;
+
+    // With a buffer, the first column tile 2/0/1 shows the line west of -180°
+    let render = |wrap_antimeridian| -> Result<image::RgbaImage, Box<dyn std::error::Error>> {
+        let options = RendererOptions {
+            buffer_fraction: 0.25,
+            wrap_antimeridian,
+            ..Default::default()
+        };
+        let mut renderer = VulkanRenderer::new_with_options(2, ShaderType::Mercator, 256, options)
+            .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+        Ok(renderer.render_tile(&Tile::new(0, 1, 2), &tile_index, &mmap_data)
+            .map_err(|e| format!("Failed to render tile: {}", e))?)
+    };
+
+    // The nominal tile starts at x = 64 (lon -180°); the line spans ~3-14px west of it
+    let wrapped = render(true)?;
+    let west = wrapped
+        .enumerate_pixels()
+        .filter(|(x, _, p)| *x < 64 && p[0] != 255)
+        .count();
+    assert!(west > 0, "expected the wrapped line in the west buffer");
+    assert!(wrapped.enumerate_pixels().all(|(x, _, p)| x < 64 || p[0] == 255));
+
+    let unwrapped = render(false)?;
+    assert!(unwrapped.pixels().all(|p| p[0] == 255));
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_bottom_left_origin_flips_image() -> Result<(), Box<dyn std::error::Error>> {
+    use rust_osm_renderer::projection::{get_bounding_box, TileOrigin};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    // An L in the north-west quadrant: asymmetric both ways
+    let tile = Tile::new(2, 1, 2);
+    let bbox = get_bounding_box(&tile);
+    let at = |fx: f64, fy: f64| {
+        Point::new(
+            bbox.min.lon + fx * (bbox.max.lon - bbox.min.lon),
+            bbox.max.lat - fy * (bbox.max.lat - bbox.min.lat),
+        )
+    };
+    let mut temp_file = data_file()?;
+    let points = vec![at(0.2, 0.05), at(0.2, 0.3), at(0.4, 0.3)];
+    let shape = MapObject {
+        bounding_box: BoundingBox::from_points(&points).unwrap(),
+        points,
+        kind: ObjectKind::Line,
+        tags: Vec::new(),
+    };
+    let offset = write_map_object(temp_file.as_file_mut(), &shape)?;
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let mut tile_index = TileIndex::new();
+    tile_index.insert(tile, offset);
+    tile_index.max_points = 3;
+
+    let render = |tile_origin| -> Result<image::RgbaImage, Box<dyn std::error::Error>> {
+        let options = RendererOptions {
+            tile_origin,
+            ..Default::default()
+        };
+        let mut renderer = VulkanRenderer::new_with_options(3, ShaderType::Mercator, 256, options)
+            .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+        Ok(renderer.render_tile(&tile, &tile_index, &mmap_data)
+            .map_err(|e| format!("Failed to render tile: {}", e))?)
+    };
+
+    let top_left = render(TileOrigin::TopLeft)?;
+    let bottom_left = render(TileOrigin::BottomLeft)?;
+
+    // Top-left draws the L in the top half, bottom-left in the bottom half
+    assert!(top_left.enumerate_pixels().any(|(_, y, p)| y < 128 && p[0] != 255));
+    assert!(top_left.enumerate_pixels().all(|(_, y, p)| y < 128 || p[0] == 255));
+    assert_eq!(bottom_left, image::imageops::flip_vertical(&top_left));
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_selftest_passes() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    let report = rust_osm_renderer::selftest::run(ShaderType::Mercator, Default::default());
+    assert!(report.passed(), "{}", report);
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_vertex_budget_caps_low_zoom() -> Result<(), Box<dyn std::error::Error>> {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    // 500 short lines inside one z14 tile, indexed in that tile and its z6 ancestor
+    let z14 = Tile::new(8645, 5557, 14);
+    let z6 = z14.get_ancestor(6).unwrap();
+    let bbox = rust_osm_renderer::projection::get_bounding_box(&z14);
+    let (width, height) = (bbox.max.lon - bbox.min.lon, bbox.max.lat - bbox.min.lat);
+
+    let mut temp_file = data_file()?;
+    let mut tile_index = TileIndex::new();
+    for i in 0..500 {
+        let (fx, fy) = ((i % 25) as f64 / 25.0, (i / 25) as f64 / 20.0);
+        let from = Point::new(bbox.min.lon + width * fx, bbox.min.lat + height * fy);
+        let to = Point::new(from.lon + width * 0.03, from.lat + height * 0.04);
+        let object = MapObject {
+            bounding_box: BoundingBox::from_points(&[from, to]).unwrap(),
+            points: vec![from, to],
+            kind: ObjectKind::Line,
+            tags: Vec::new(),
+        };
+        let offset = write_map_object(temp_file.as_file_mut(), &object)?;
+        tile_index.insert(z14, offset);
+        tile_index.insert(z6, offset);
+    }
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    tile_index.max_points = 2;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let options = RendererOptions {
+        vertex_budgets: "0=200/50,14=*".parse::<VertexBudgets>()?,
+        ..Default::default()
+    };
+    let mut renderer = VulkanRenderer::new_with_options(2, ShaderType::Mercator, 256, options)
+        .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+
+    renderer.render_tile(&z6, &tile_index, &mmap_data)
+        .map_err(|e| format!("Failed to render tile: {}", e))?;
+    let z6_vertices = renderer.last_vertex_count();
+    renderer.render_tile(&z14, &tile_index, &mmap_data)
+        .map_err(|e| format!("Failed to render tile: {}", e))?;
+    let z14_vertices = renderer.last_vertex_count();
+
+    assert_eq!(z6_vertices, 100, "z6 stops at the 50 object budget");
+    assert_eq!(z14_vertices, 1000, "z14 draws every line");
+    assert!(z6_vertices < z14_vertices);
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_clip_region_masks_outside_geometry() -> Result<(), Box<dyn std::error::Error>> {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    // A horizontal line across the whole tile, clipped to the western hemisphere
+    let mut temp_file = data_file()?;
+    let line = MapObject {
+        bounding_box: BoundingBox::new(Point::new(-170.0, 0.0), Point::new(170.0, 0.0)),
+        points: vec![Point::new(-170.0, 0.0), Point::new(170.0, 0.0)],
+        kind: ObjectKind::Line,
+        tags: Vec::new(),
+    };
+    let offset = write_map_object(temp_file.as_file_mut(), &line)?;
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let tile = Tile::new(0, 0, 0);
+    let mut tile_index = TileIndex::new();
+    tile_index.insert(tile, offset);
+    tile_index.max_points = 2;
+
+    let clip_region: ClipRegion =
+        r#"{"type": "Polygon", "coordinates": [[[-180, -80], [0, -80], [0, 80], [-180, 80], [-180, -80]]]}"#.parse()?;
+    let options = RendererOptions {
+        clip_region: Some(std::sync::Arc::new(clip_region)),
+        ..Default::default()
+    };
+    let mut renderer = VulkanRenderer::new_with_options(2, ShaderType::Mercator, 256, options)
+        .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+    let image = renderer.render_tile(&tile, &tile_index, &mmap_data)
+        .map_err(|e| format!("Failed to render tile: {}", e))?;
+
+    let drawn = |p: &image::Rgba<u8>| p.0 != BACKGROUND_COLOR && p.0 != NODATA_COLOR;
+    let (west, east): (Vec<_>, Vec<_>) = image.enumerate_pixels().partition(|(x, _, _)| *x < 128);
+    assert!(west.iter().any(|(_, _, p)| drawn(p)), "line inside the clip region not drawn");
+    assert!(east.iter().all(|(_, _, p)| p.0 == NODATA_COLOR), "geometry outside the clip region drawn");
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_overlay_tile_is_transparent_except_lines() -> Result<(), Box<dyn std::error::Error>> {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    // A horizontal line across the whole tile
+    let mut temp_file = data_file()?;
+    let line = MapObject {
+        bounding_box: BoundingBox::new(Point::new(-170.0, 0.0), Point::new(170.0, 0.0)),
+        points: vec![Point::new(-170.0, 0.0), Point::new(170.0, 0.0)],
+        kind: ObjectKind::Line,
+        tags: Vec::new(),
+    };
+    let offset = write_map_object(temp_file.as_file_mut(), &line)?;
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let tile = Tile::new(0, 0, 0);
+    let mut tile_index = TileIndex::new();
+    tile_index.insert(tile, offset);
+    tile_index.max_points = 2;
+
+    let options = RendererOptions {
+        overlay: true,
+        ..Default::default()
+    };
+    let mut renderer = VulkanRenderer::new_with_options(2, ShaderType::Mercator, 256, options)
+        .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+    let image = renderer.render_tile(&tile, &tile_index, &mmap_data)
+        .map_err(|e| format!("Failed to render tile: {}", e))?;
+
+    // Opaque line pixels, everything else fully transparent
+    let line_pixels = image.pixels().filter(|p| p.0 == LINE_COLOR).count();
+    assert!(line_pixels > 0, "line not drawn");
+    assert!(image.pixels().all(|p| p.0 == LINE_COLOR || p.0[3] == 0), "background isn't transparent");
+    assert_eq!(image.get_pixel(10, 10).0, NODATA_COLOR);
+
+    // Tiles without data are transparent too
+    let empty = renderer.render_tile(&Tile::new(1, 0, 1), &tile_index, &mmap_data)
+        .map_err(|e| format!("Failed to render tile: {}", e))?;
+    assert!(empty.pixels().all(|p| p.0 == NODATA_COLOR), "empty overlay tile isn't transparent");
+
+    // The alpha channel survives PNG encoding
+    let png = rust_osm_renderer::encoding::png::encode_png(&image)?;
+    assert_eq!(image::load_from_memory(&png)?.to_rgba8(), image);
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_crisp_lines_fill_single_pixel_row() -> Result<(), Box<dyn std::error::Error>> {
+    use rust_osm_renderer::data::types::Pixel;
+    use rust_osm_renderer::projection::{get_bounding_box, pixel_to_tile};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    // A horizontal line at y = 100.8, straddling rows 100 and 101
+    let tile = Tile::new(0, 0, 0);
+    let lat = pixel_to_tile(&Pixel { x: 0.0, y: 100.8 }, &get_bounding_box(&tile), 256).lat;
+    let mut temp_file = data_file()?;
+    let line = MapObject {
+        bounding_box: BoundingBox::new(Point::new(-170.0, lat), Point::new(170.0, lat)),
+        points: vec![Point::new(-170.0, lat), Point::new(170.0, lat)],
+        kind: ObjectKind::Line,
+        tags: Vec::new(),
+    };
+    let offset = write_map_object(temp_file.as_file_mut(), &line)?;
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let mut tile_index = TileIndex::new();
+    tile_index.insert(tile, offset);
+    tile_index.max_points = 2;
+
+    let render = |crisp_lines| -> Result<image::RgbaImage, Box<dyn std::error::Error>> {
+        let options = RendererOptions {
+            msaa_samples: 4,
+            crisp_lines,
+            ..Default::default()
+        };
+        let mut renderer = VulkanRenderer::new_with_options(2, ShaderType::Mercator, 256, options)
+            .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+        Ok(renderer.render_tile(&tile, &tile_index, &mmap_data)
+            .map_err(|e| format!("Failed to render tile: {}", e))?)
+    };
+    let rows = |image: &image::RgbaImage| -> Vec<u32> {
+        (0..256).filter(|&y| (0..256).any(|x| image.get_pixel(x, y).0 != BACKGROUND_COLOR)).collect()
+    };
+
+    // Antialiasing spreads the unsnapped line over two rows, the snapped one fills row 100
+    let blurry = render(false)?;
+    assert_eq!(rows(&blurry).len(), 2, "{:?}", rows(&blurry));
+    let crisp = render(true)?;
+    assert_eq!(rows(&crisp), vec![100]);
+    assert!((10..246).all(|x| crisp.get_pixel(x, 100).0 == LINE_COLOR), "snapped line isn't solid");
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_line_widths_by_highway_class() -> Result<(), Box<dyn std::error::Error>> {
+    use rust_osm_renderer::data::types::Pixel;
+    use rust_osm_renderer::projection::{get_bounding_box, pixel_to_tile};
+    use rust_osm_renderer::style::line_width::{line_width_for, MIN_LINE_WIDTH};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    // At zoom 15 a motorway centered on row boundary 100 and a footway on row center 200.5
+    let tile = Tile::new(17_301, 10_583, 15);
+    let bbox = get_bounding_box(&tile);
+    let mut temp_file = data_file()?;
+    let line = |y: f64, highway: &str| {
+        let (west, east) = (pixel_to_tile(&Pixel { x: -10.0, y }, &bbox, 256), pixel_to_tile(&Pixel { x: 266.0, y }, &bbox, 256));
+        MapObject {
+            bounding_box: BoundingBox::new(west, east),
+            points: vec![west, east],
+            kind: ObjectKind::Line,
+            tags: vec![("highway".to_string(), highway.to_string())],
+        }
+    };
+    let motorway = write_map_object(temp_file.as_file_mut(), &line(100.0, "motorway"))?;
+    let footway = write_map_object(temp_file.as_file_mut(), &line(200.5, "footway"))?;
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let mut tile_index = TileIndex::new();
+    tile_index.insert(tile, motorway);
+    tile_index.insert(tile, footway);
+    tile_index.max_points = 2;
+
+    let options = RendererOptions { line_widths: true, ..Default::default() };
+    let mut renderer = VulkanRenderer::new_with_options(2, ShaderType::Mercator, 256, options)
+        .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+    let image = renderer.render_tile(&tile, &tile_index, &mmap_data)
+        .map_err(|e| format!("Failed to render tile: {}", e))?;
+
+    // Each line covers as many rows as it is wide, solid across the tile
+    let rows: Vec<u32> = (0..256).filter(|&y| image.get_pixel(128, y).0 == LINE_COLOR).collect();
+    assert_eq!(line_width_for(&[("highway", "motorway")], 15), 4.0);
+    assert_eq!(MIN_LINE_WIDTH, 1.0);
+    assert_eq!(rows, vec![98, 99, 100, 101, 200]);
+    assert!((0..256).all(|x| image.get_pixel(x, 99).0 == LINE_COLOR && image.get_pixel(x, 200).0 == LINE_COLOR));
+    assert_eq!(renderer.last_vertex_count(), 12, "two quads without joins");
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_casing_outlines_wide_lines() -> Result<(), Box<dyn std::error::Error>> {
+    use rust_osm_renderer::data::types::Pixel;
+    use rust_osm_renderer::projection::{get_bounding_box, pixel_to_tile};
+    use rust_osm_renderer::style::line_colors::casing_color;
+
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    // A 4px motorway on row boundary 100, crossed by another at column boundary 128
+    let tile = Tile::new(17_301, 10_583, 15);
+    let bbox = get_bounding_box(&tile);
+    let mut temp_file = data_file()?;
+    let line = |from: Pixel, to: Pixel| {
+        let (a, b) = (pixel_to_tile(&from, &bbox, 256), pixel_to_tile(&to, &bbox, 256));
+        MapObject {
+            bounding_box: BoundingBox::from_points(&[a, b]).unwrap(),
+            points: vec![a, b],
+            kind: ObjectKind::Line,
+            tags: vec![("highway".to_string(), "motorway".to_string())],
+        }
+    };
+    let east_west = write_map_object(temp_file.as_file_mut(), &line(Pixel { x: -10.0, y: 100.0 }, Pixel { x: 266.0, y: 100.0 }))?;
+    let north_south = write_map_object(temp_file.as_file_mut(), &line(Pixel { x: 128.0, y: -10.0 }, Pixel { x: 128.0, y: 266.0 }))?;
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let mut tile_index = TileIndex::new();
+    tile_index.insert(tile, east_west);
+    tile_index.insert(tile, north_south);
+    tile_index.max_points = 2;
+
+    let render = |casing: bool| -> Result<_, Box<dyn std::error::Error>> {
+        let options = RendererOptions { line_widths: true, casing, ..Default::default() };
+        let mut renderer = VulkanRenderer::new_with_options(2, ShaderType::Mercator, 256, options)
+            .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+        let image = renderer.render_tile(&tile, &tile_index, &mmap_data)
+            .map_err(|e| format!("Failed to render tile: {}", e))?;
+        Ok((image, renderer.last_vertex_count()))
+    };
+
+    let (plain, plain_vertices) = render(false)?;
+    let (cased, cased_vertices) = render(true)?;
+    let rows = |image: &image::RgbaImage, color: [u8; 4]| (90..110).filter(|&y| image.get_pixel(40, y).0 == color).collect::<Vec<u32>>();
+
+    // The line keeps its rows, the casing takes one more on each side
+    assert_eq!(rows(&plain, LINE_COLOR), vec![98, 99, 100, 101]);
+    assert_eq!(rows(&cased, LINE_COLOR), vec![98, 99, 100, 101]);
+    assert!(rows(&plain, casing_color(LINE_COLOR)).is_empty());
+    assert_eq!(rows(&cased, casing_color(LINE_COLOR)), vec![97, 102]);
+    assert_eq!(cased_vertices, 2 * plain_vertices);
+
+    // Where the lines cross, neither casing covers the other line
+    assert_eq!(cased.get_pixel(127, 100).0, LINE_COLOR);
+    assert_eq!(cased.get_pixel(128, 97).0, LINE_COLOR);
+    assert_eq!(cased.get_pixel(125, 97).0, casing_color(LINE_COLOR));
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_srgb_keeps_opaque_colors() -> Result<(), Box<dyn std::error::Error>> {
+    use rust_osm_renderer::data::types::Pixel;
+    use rust_osm_renderer::projection::{get_bounding_box, pixel_to_tile};
+    use rust_osm_renderer::renderer::color_format::ColorFormat;
+
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    // A 4px motorway on row boundary 100 over a mid gray background
+    let tile = Tile::new(17_301, 10_583, 15);
+    let bbox = get_bounding_box(&tile);
+    let (a, b) = (pixel_to_tile(&Pixel { x: -10.0, y: 100.0 }, &bbox, 256), pixel_to_tile(&Pixel { x: 266.0, y: 100.0 }, &bbox, 256));
+    let mut temp_file = data_file()?;
+    let offset = write_map_object(temp_file.as_file_mut(), &MapObject {
+        bounding_box: BoundingBox::from_points(&[a, b]).unwrap(),
+        points: vec![a, b],
+        kind: ObjectKind::Line,
+        tags: vec![("highway".to_string(), "motorway".to_string())],
+    })?;
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let mut tile_index = TileIndex::new();
+    tile_index.insert(tile, offset);
+    tile_index.max_points = 2;
+
+    let gray = [128, 128, 128, 255];
+    let options = RendererOptions {
+        line_widths: true,
+        background: Some(gray),
+        color_format: ColorFormat::Srgb,
+        ..Default::default()
+    };
+    let mut renderer = VulkanRenderer::new_with_options(2, ShaderType::Mercator, 256, options)
+        .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+    let image = renderer.render_tile(&tile, &tile_index, &mmap_data)
+        .map_err(|e| format!("Failed to render tile: {}", e))?;
+    if renderer.color_format() != ColorFormat::Srgb {
+        println!("Device can't render sRGB images, skipping");
+        return Ok(());
+    }
+
+    // Colors go through linear light and come back unchanged
+    let rows: Vec<u32> = (90..110).filter(|&y| image.get_pixel(40, y).0 == LINE_COLOR).collect();
+    assert_eq!(rows, vec![98, 99, 100, 101]);
+    assert_eq!(image.get_pixel(40, 50).0, gray);
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_tag_filter_selects_objects() -> Result<(), Box<dyn std::error::Error>> {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    // A primary road in the northern and a river in the southern hemisphere
+    let mut temp_file = data_file()?;
+    let line = |lat: f64, key: &str, value: &str| MapObject {
+        bounding_box: BoundingBox::new(Point::new(-170.0, lat), Point::new(170.0, lat)),
+        points: vec![Point::new(-170.0, lat), Point::new(170.0, lat)],
+        kind: ObjectKind::Line,
+        tags: vec![(key.to_string(), value.to_string())],
+    };
+    let road = write_map_object(temp_file.as_file_mut(), &line(40.0, "highway", "primary"))?;
+    let river = write_map_object(temp_file.as_file_mut(), &line(-40.0, "waterway", "river"))?;
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let tile = Tile::new(0, 0, 0);
+    let mut tile_index = TileIndex::new();
+    tile_index.insert(tile, road);
+    tile_index.insert(tile, river);
+    tile_index.max_points = 2;
+    tile_index.retained_tags = Some("highway,waterway".parse()?);
+
+    let mut renderer = VulkanRenderer::new(2, ShaderType::Mercator)
+        .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+    let mut render = |filter: &str| -> Result<(bool, bool), Box<dyn std::error::Error>> {
+        let filter: TagFilter = filter.parse()?;
+        let image = renderer.render_tile_filtered(&tile, 0, Some(&filter), &tile_index, &mmap_data)
+            .map_err(|e| format!("Failed to render tile: {}", e))?;
+        let drawn = |rows: std::ops::Range<u32>| {
+            image.enumerate_pixels().any(|(_, y, p)| rows.contains(&y) && p.0 != BACKGROUND_COLOR)
+        };
+        Ok((drawn(0..128), drawn(128..256)))
+    };
+
+    assert_eq!(render("highway=primary")?, (true, false));
+    assert_eq!(render("NOT highway")?, (false, true));
+    assert_eq!(render("highway OR waterway=river")?, (true, true));
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_highway_colors_per_object() -> Result<(), Box<dyn std::error::Error>> {
+    use rust_osm_renderer::style::line_colors::LineColors;
+
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    // A motorway, a residential road and an untagged way, north to south
+    let mut temp_file = data_file()?;
+    let line = |lat: f64, tags: &[(&str, &str)]| MapObject {
+        bounding_box: BoundingBox::new(Point::new(-170.0, lat), Point::new(170.0, lat)),
+        points: vec![Point::new(-170.0, lat), Point::new(170.0, lat)],
+        kind: ObjectKind::Line,
+        tags: tags.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect(),
+    };
+    let motorway = write_map_object(temp_file.as_file_mut(), &line(50.0, &[("highway", "motorway")]))?;
+    let residential = write_map_object(temp_file.as_file_mut(), &line(0.0, &[("highway", "residential")]))?;
+    let untagged = write_map_object(temp_file.as_file_mut(), &line(-50.0, &[]))?;
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let tile = Tile::new(0, 0, 0);
+    let mut tile_index = TileIndex::new();
+    for offset in [motorway, residential, untagged] {
+        tile_index.insert(tile, offset);
+    }
+    tile_index.max_points = 2;
+
+    let blue = [0, 0, 255, 255];
+    let line_colors = LineColors::highway(blue);
+    let options = RendererOptions {
+        line_colors: Some(std::sync::Arc::new(line_colors.clone())),
+        ..Default::default()
+    };
+    let mut renderer = VulkanRenderer::new_with_options(2, ShaderType::Mercator, 256, options)
+        .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+    let image = renderer.render_tile(&tile, &tile_index, &mmap_data)
+        .map_err(|e| format!("Failed to render tile: {}", e))?;
+
+    // Each band holds only its way's color
+    let colors_in = |rows: std::ops::Range<u32>| {
+        let mut colors: Vec<[u8; 4]> = image
+            .enumerate_pixels()
+            .filter(|(_, y, p)| rows.contains(y) && p.0 != BACKGROUND_COLOR)
+            .map(|(_, _, p)| p.0)
+            .collect();
+        colors.dedup();
+        colors
+    };
+    assert_eq!(colors_in(0..100), vec![line_colors.color(Some("motorway"))]);
+    assert_eq!(colors_in(100..156), vec![line_colors.color(Some("residential"))]);
+    assert_eq!(colors_in(156..256), vec![blue]);
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_unclassed_ways_drawn_by_layer() -> Result<(), Box<dyn std::error::Error>> {
+    use rust_osm_renderer::style::line_colors::LineColors;
+
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    // A motorway across the tile, then a river crossing it
+    let mut temp_file = data_file()?;
+    let way = |from: Point, to: Point, tags: &[(&str, &str)]| MapObject {
+        bounding_box: BoundingBox::new(
+            Point::new(from.lon.min(to.lon), from.lat.min(to.lat)),
+            Point::new(from.lon.max(to.lon), from.lat.max(to.lat)),
+        ),
+        points: vec![from, to],
+        kind: ObjectKind::Line,
+        tags: tags.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect(),
+    };
+    let motorway = way(Point::new(-170.0, 0.0), Point::new(170.0, 0.0), &[("highway", "motorway")]);
+    let river = way(Point::new(0.0, -80.0), Point::new(0.0, 80.0), &[("waterway", "river")]);
+    let motorway = write_map_object(temp_file.as_file_mut(), &motorway)?;
+    let river = write_map_object(temp_file.as_file_mut(), &river)?;
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let tile = Tile::new(0, 0, 0);
+    let mut tile_index = TileIndex::new();
+    tile_index.insert(tile, motorway);
+    tile_index.insert(tile, river);
+    tile_index.max_points = 2;
+
+    let blue = [0, 0, 255, 255];
+    let line_colors = LineColors::highway(blue);
+    let options = RendererOptions {
+        line_colors: Some(std::sync::Arc::new(line_colors.clone())),
+        ..Default::default()
+    };
+    let mut renderer = VulkanRenderer::new_with_options(2, ShaderType::Mercator, 256, options)
+        .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+    let image = renderer.render_tile(&tile, &tile_index, &mmap_data)
+        .map_err(|e| format!("Failed to render tile: {}", e))?;
+
+    // The river comes later in the index but draws under the motorway
+    let motorway_color = line_colors.color(Some("motorway"));
+    let row = (0..256).find(|&y| image.get_pixel(20, y).0 == motorway_color).ok_or("motorway not drawn")?;
+    let crossing: Vec<[u8; 4]> = (120..136).map(|x| image.get_pixel(x, row).0).collect();
+    assert!(crossing.iter().all(|p| *p == motorway_color), "{:?}", crossing);
+    assert!((0..256).any(|x| image.get_pixel(x, 20).0 == blue), "river not drawn");
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_poi_points_drawn_and_decimated() -> Result<(), Box<dyn std::error::Error>> {
+    use rust_osm_renderer::data::types::Pixel;
+    use rust_osm_renderer::projection::{get_bounding_box, pixel_to_tile};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    // Two POIs 3px apart and one far away, without any ways
+    let tile = Tile::new(0, 0, 0);
+    let bbox = get_bounding_box(&tile);
+    let at = |x: f64, y: f64| MapObject::point(pixel_to_tile(&Pixel { x, y }, &bbox, 256));
+    let mut temp_file = data_file()?;
+    let mut tile_index = TileIndex::new();
+    for object in [at(100.5, 100.5), at(103.5, 100.5), at(200.5, 50.5)] {
+        tile_index.insert(tile, write_map_object(temp_file.as_file_mut(), &object)?);
+    }
+    tile_index.max_points = 1;
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let mut renderer = VulkanRenderer::new_with_options(1, ShaderType::Mercator, 256, RendererOptions::default())
+        .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+    let image = renderer.render_tile(&tile, &tile_index, &mmap_data)
+        .map_err(|e| format!("Failed to render tile: {}", e))?;
+    assert_eq!(renderer.last_vertex_count(), 3);
+    for (x, y) in [(100, 100), (103, 100), (200, 50)] {
+        assert_eq!(image.get_pixel(x, y).0, LINE_COLOR, "point at {}, {}", x, y);
+    }
+    assert_eq!(image.get_pixel(150, 150).0, BACKGROUND_COLOR);
+
+    // The close pair shares a 16px cell
+    let options = RendererOptions { point_decimation_px: 16.0, ..Default::default() };
+    let mut renderer = VulkanRenderer::new_with_options(1, ShaderType::Mercator, 256, options)
+        .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+    let image = renderer.render_tile(&tile, &tile_index, &mmap_data)
+        .map_err(|e| format!("Failed to render tile: {}", e))?;
+    assert_eq!(renderer.last_vertex_count(), 2);
+    assert_eq!(image.get_pixel(100, 100).0, LINE_COLOR);
+    assert_eq!(image.get_pixel(103, 100).0, BACKGROUND_COLOR);
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_metatile_slices_match_single_tiles() -> Result<(), Box<dyn std::error::Error>> {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    // One diagonal way through all four zoom 1 tiles, indexed in each
+    let points = vec![Point::new(-170.0, 60.0), Point::new(170.0, -60.0)];
+    let line = MapObject::new(BoundingBox::new(Point::new(-170.0, -60.0), Point::new(170.0, 60.0)), points);
+    let mut temp_file = data_file()?;
+    let offset = write_map_object(temp_file.as_file_mut(), &line)?;
+    let mut tile_index = TileIndex::new();
+    let tiles: Vec<Tile> = [(0, 0), (1, 0), (0, 1), (1, 1)].iter().map(|&(x, y)| Tile::new(x, y, 1)).collect();
+    for tile in &tiles {
+        tile_index.insert(*tile, offset);
+    }
+    tile_index.max_points = 2;
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let mut metatile_renderer = VulkanRenderer::new_with_options(2, ShaderType::Mercator, 512, RendererOptions::default())
+        .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+    let images = metatile_renderer.render_metatile(&tiles[0], 2, &tile_index, &mmap_data)
+        .map_err(|e| format!("Failed to render metatile: {}", e))?;
+    // Drawn once although all four tiles list it
+    assert_eq!(metatile_renderer.last_vertex_count(), 2);
+    assert_eq!(images.len(), 4);
+
+    let mut renderer = VulkanRenderer::new_with_options(2, ShaderType::Mercator, 256, RendererOptions::default())
+        .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+    for (tile, slice) in tiles.iter().zip(&images) {
+        let single = renderer.render_tile(tile, &tile_index, &mmap_data)
+            .map_err(|e| format!("Failed to render tile: {}", e))?;
+        assert_eq!(slice.dimensions(), single.dimensions());
+        // Rasterization at the seams may differ by a pixel here and there
+        let differing = slice.pixels().zip(single.pixels()).filter(|(a, b)| a != b).count();
+        assert!(differing < 32, "tile {:?}: {} pixels differ", tile, differing);
+        assert!(slice.pixels().any(|pixel| pixel.0 == LINE_COLOR), "tile {:?} has the line", tile);
+    }
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_overzoomed_tile_renders_from_ancestor() -> Result<(), Box<dyn std::error::Error>> {
+    use rust_osm_renderer::data::types::Pixel;
+    use rust_osm_renderer::projection::{get_bounding_box, pixel_to_tile};
+
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    // A horizontal line across the middle of a z16 tile, indexed at z15 only
+    let tile = Tile::new(34603, 21156, 16);
+    let bbox = get_bounding_box(&tile);
+    let lat = pixel_to_tile(&Pixel { x: 0.0, y: 128.0 }, &bbox, 256).lat;
+    let (west, east) = (bbox.min.lon - 0.01, bbox.max.lon + 0.01);
+    let mut temp_file = data_file()?;
+    let line = MapObject {
+        bounding_box: BoundingBox::new(Point::new(west, lat), Point::new(east, lat)),
+        points: vec![Point::new(west, lat), Point::new(east, lat)],
+        kind: ObjectKind::Line,
+        tags: Vec::new(),
+    };
+    let offset = write_map_object(temp_file.as_file_mut(), &line)?;
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let mut tile_index = TileIndex::new();
+    tile_index.insert(tile.get_ancestor(15).unwrap(), offset);
+    tile_index.max_points = 2;
+
+    let mut renderer = VulkanRenderer::new(2, ShaderType::Mercator)
+        .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+    let rows = |image: &image::RgbaImage| -> Vec<u32> {
+        (0..256).filter(|&y| (0..256).any(|x| image.get_pixel(x, y).0 != BACKGROUND_COLOR)).collect()
+    };
+
+    // Drawn in the requested tile's bounding box, not the ancestor's
+    let image = renderer.render_tile(&tile, &tile_index, &mmap_data)
+        .map_err(|e| format!("Failed to render tile: {}", e))?;
+    let drawn = rows(&image);
+    assert!(!drawn.is_empty(), "over-zoomed line not drawn");
+    assert!(drawn.iter().all(|y| (126..=130).contains(y)), "line drawn at rows {:?}", drawn);
+
+    // Zooms missing from the index fall back to the nearest indexed ancestor
+    let mut sparse = TileIndex::new();
+    sparse.insert(tile.get_ancestor(12).unwrap(), offset);
+    sparse.max_points = 2;
+    let image = renderer.render_tile(&tile, &sparse, &mmap_data)
+        .map_err(|e| format!("Failed to render tile: {}", e))?;
+    assert_eq!(rows(&image), drawn);
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_renderer_resizes_between_tile_sizes() -> Result<(), Box<dyn std::error::Error>> {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    let mut temp_file = data_file()?;
+    let line = MapObject {
+        bounding_box: BoundingBox::new(Point::new(-60.0, -30.0), Point::new(60.0, 30.0)),
+        points: vec![Point::new(-60.0, -30.0), Point::new(60.0, 30.0)],
+        kind: ObjectKind::Line,
+        tags: Vec::new(),
+    };
+    let offset = write_map_object(temp_file.as_file_mut(), &line)?;
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let tile = Tile::new(0, 0, 0);
+    let mut tile_index = TileIndex::new();
+    tile_index.insert(tile, offset);
+    tile_index.max_points = 2;
+
+    // One renderer switching sizes draws what a renderer created for each size draws
+    let mut resized = VulkanRenderer::new(2, ShaderType::Mercator)
+        .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+    for size in [512, 256, 1024] {
+        resized.set_tile_size(size);
+        assert_eq!(resized.tile_size(), size);
+        let image = resized.render_tile(&tile, &tile_index, &mmap_data)
+            .map_err(|e| format!("Failed to render tile: {}", e))?;
+        let mut fresh = VulkanRenderer::new_with_tile_size(2, ShaderType::Mercator, size)
+            .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+        let expected = fresh.render_tile(&tile, &tile_index, &mmap_data)
+            .map_err(|e| format!("Failed to render tile: {}", e))?;
+        assert_eq!(image.dimensions(), (size, size));
+        assert!(image.pixels().any(|pixel| pixel.0 == LINE_COLOR), "{}px tile has the line", size);
+        assert_eq!(image.as_raw(), expected.as_raw(), "{}px tile differs", size);
+    }
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_renderer_with_config() -> Result<(), Box<dyn std::error::Error>> {
+    use rust_osm_renderer::renderer::RendererConfig;
+
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    let mut temp_file = data_file()?;
+    let line = MapObject {
+        bounding_box: BoundingBox::new(Point::new(-60.0, 0.0), Point::new(60.0, 0.0)),
+        points: vec![Point::new(-60.0, 0.0), Point::new(60.0, 0.0)],
+        kind: ObjectKind::Line,
+        tags: Vec::new(),
+    };
+    let offset = write_map_object(temp_file.as_file_mut(), &line)?;
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let tile = Tile::new(0, 0, 0);
+    let mut tile_index = TileIndex::new();
+    tile_index.insert(tile, offset);
+    tile_index.max_points = 2;
+
+    let background = [16, 32, 64, 255];
+    let mut renderer = VulkanRenderer::with_config(RendererConfig {
+        max_points: tile_index.max_points,
+        tile_size: 512,
+        background: Some(background),
+        ..RendererConfig::default()
+    })
+    .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+    let image = renderer.render_tile(&tile, &tile_index, &mmap_data)
+        .map_err(|e| format!("Failed to render tile: {}", e))?;
+
+    assert_eq!(image.dimensions(), (512, 512));
+    assert_eq!(image.get_pixel(0, 0).0, background);
+    assert!(image.pixels().any(|pixel| pixel.0 == LINE_COLOR), "line drawn");
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_vertex_buffer_grows_for_dense_tiles() -> Result<(), Box<dyn std::error::Error>> {
+    use rust_osm_renderer::renderer::renderer::VERTEX_BUFFER_CAPACITY;
+
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    // A zigzag inside tile 0/0/0 with more segments than the initial buffer holds vertices for
+    let segments = VERTEX_BUFFER_CAPACITY / 2 + 1000;
+    let points: Vec<Point> = (0..=segments)
+        .map(|i| Point::new(-60.0 + 120.0 * i as f64 / segments as f64, if i % 2 == 0 { -30.0 } else { 30.0 }))
+        .collect();
+    let mut temp_file = data_file()?;
+    let zigzag = MapObject {
+        bounding_box: BoundingBox::from_points(&points).unwrap(),
+        points,
+        kind: ObjectKind::Line,
+        tags: Vec::new(),
+    };
+    let offset = write_map_object(temp_file.as_file_mut(), &zigzag)?;
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    let tile = Tile::new(0, 0, 0);
+    let mut tile_index = TileIndex::new();
+    tile_index.insert(tile, offset);
+    tile_index.max_points = segments + 1;
+
+    // Grown: every segment is drawn
+    let mut renderer = VulkanRenderer::new(tile_index.max_points, ShaderType::Mercator)
+        .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+    renderer.render_tile(&tile, &tile_index, &mmap_data)
+        .map_err(|e| format!("Failed to render tile: {}", e))?;
+    assert_eq!(renderer.last_vertex_count(), 2 * segments);
+
+    // Capped at the initial size: truncated to what fits
+    let options = RendererOptions { max_vertex_buffer_capacity: Some(VERTEX_BUFFER_CAPACITY), ..RendererOptions::default() };
+    let mut capped = VulkanRenderer::new_with_options(tile_index.max_points, ShaderType::Mercator, 256, options)
+        .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+    capped.render_tile(&tile, &tile_index, &mmap_data)
+        .map_err(|e| format!("Failed to render tile: {}", e))?;
+    assert_eq!(capped.last_vertex_count(), VERTEX_BUFFER_CAPACITY);
+
+    Ok(())
+}
+
+#[test]
+#[ignore] // Ignore by default since it requires Vulkan
+fn test_precise_overlap_skips_ways_missing_the_tile() -> Result<(), Box<dyn std::error::Error>> {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    // Tile 2/2/1 spans 0..90°E, 0..66.5°N. An L around its south-western
+    // corner, whose bounding box overlaps it, and a line through it
+    let tile = Tile::new(2, 1, 2);
+    let mut temp_file = data_file()?;
+    let mut tile_index = TileIndex::new();
+    let l_shape = vec![Point::new(-10.0, 10.0), Point::new(-10.0, -10.0), Point::new(10.0, -10.0)];
+    let through = vec![Point::new(20.0, 20.0), Point::new(60.0, 40.0)];
+    for points in [l_shape, through] {
+        let object = MapObject {
+            bounding_box: BoundingBox::from_points(&points).unwrap(),
+            points,
+            kind: ObjectKind::Line,
+            tags: Vec::new(),
+        };
+        let offset = write_map_object(temp_file.as_file_mut(), &object)?;
+        tile_index.insert(tile, offset);
+    }
+    use std::io::Write;
+    temp_file.as_file_mut().flush()?;
+    tile_index.max_points = 3;
+    let mmap_data = MappedData::new(temp_file.path())?;
+
+    // With a budget of one object, the L uses it up unless it's found to miss the tile
+    let vertices = |precise_overlap: bool| -> Result<usize, Box<dyn std::error::Error>> {
+        let options = RendererOptions {
+            vertex_budgets: "0=*/1".parse::<VertexBudgets>()?,
+            precise_overlap,
+            ..Default::default()
+        };
+        let mut renderer = VulkanRenderer::new_with_options(3, ShaderType::Mercator, 256, options)
+            .map_err(|e| format!("Failed to create Vulkan renderer: {}", e))?;
+        renderer.render_tile(&tile, &tile_index, &mmap_data)
+            .map_err(|e| format!("Failed to render tile: {}", e))?;
+        Ok(renderer.last_vertex_count())
+    };
+    assert_eq!(vertices(false)?, 0);
+    assert_eq!(vertices(true)?, 2);
+
+    Ok(())
+}